@@ -0,0 +1,228 @@
+//! Conditional assembly.
+//!
+//! `.if NAME` (or `.if value`) keeps the instructions up to its `.else` or `.endif` only if the
+//! value is nonzero, and `.ifdef NAME` only if the name is defined. `.else` keeps the instructions
+//! up to `.endif` if the condition was false, and conditionals can be nested.
+//!
+//! Names are defined with `.set NAME, value`, which can be repeated to change the value, by earlier
+//! `.equ` constants, and by [`Assembler::define`](crate::Assembler::define) for the CLI's
+//! `-D NAME=value`. Conditions are evaluated before anything else is assembled, so values given
+//! with `.set` or `-D` can only be used in conditions.
+
+use crate::assembler::errors::AssemblerError;
+use crate::parser::directive::Directive;
+use crate::parser::instruction::{AssemblerInstruction, DirectiveInstruction};
+use crate::parser::operand::Operand;
+use std::collections::HashMap;
+
+/// A `.if` or `.ifdef` whose `.endif` hasn't been reached
+struct Conditional {
+    /// Whether the instructions in the current branch are kept
+    active: bool,
+    /// Whether the enclosing branch is kept, without which neither branch is
+    enclosing: bool,
+    /// Whether `.else` has been reached
+    in_else: bool,
+}
+
+/// Removes conditional directives and the instructions in branches whose condition doesn't hold,
+/// along with `.set` directives. `defines` gives the names defined before the program starts
+pub(super) fn evaluate_conditionals(
+    program: &mut Vec<AssemblerInstruction>,
+    defines: &HashMap<String, i32>,
+) -> Result<(), AssemblerError> {
+    let mut symbols = defines.clone();
+    let mut stack: Vec<Conditional> = Vec::new();
+    let mut kept = Vec::with_capacity(program.len());
+
+    for instruction in program.drain(..) {
+        let active = stack.last().is_none_or(|conditional| conditional.active);
+        let AssemblerInstruction::Directive(directive) = &instruction else {
+            if active {
+                kept.push(instruction);
+            }
+            continue;
+        };
+
+        match directive.directive {
+            Directive::If | Directive::Ifdef => {
+                // conditions in branches that aren't kept may use names that aren't defined
+                let holds = active && condition(directive, &symbols)?;
+                stack.push(Conditional {
+                    active: holds,
+                    enclosing: active,
+                    in_else: false,
+                });
+            }
+            Directive::Else => match stack.last_mut() {
+                Some(conditional) if !conditional.in_else => {
+                    conditional.active = conditional.enclosing && !conditional.active;
+                    conditional.in_else = true;
+                }
+                _ => {
+                    return Err(AssemblerError::UnmatchedConditional {
+                        directive: Directive::Else,
+                    })
+                }
+            },
+            Directive::Endif => {
+                stack.pop().ok_or(AssemblerError::UnmatchedConditional {
+                    directive: Directive::Endif,
+                })?;
+            }
+            Directive::Set if active => {
+                let [Operand::Constant(name), value] = &directive.operands[..] else {
+                    return Err(AssemblerError::IncorrectOperand);
+                };
+                let value = value_of(value, &symbols)?;
+                symbols.insert(name.name.clone(), value);
+            }
+            Directive::Equ if active => {
+                // constants defined from other constants are left for the assembler to report
+                if let [Operand::Constant(name), value] = &directive.operands[..] {
+                    if let Ok(value) = value_of(value, &symbols) {
+                        symbols.insert(name.name.clone(), value);
+                    }
+                }
+                kept.push(instruction);
+            }
+            _ if active => kept.push(instruction),
+            _ => {}
+        }
+    }
+    *program = kept;
+
+    match stack.is_empty() {
+        true => Ok(()),
+        false => Err(AssemblerError::UnterminatedConditional),
+    }
+}
+
+/// Whether the condition of a `.if` or `.ifdef` holds
+fn condition(
+    directive: &DirectiveInstruction,
+    symbols: &HashMap<String, i32>,
+) -> Result<bool, AssemblerError> {
+    match (directive.directive, &directive.operands[..]) {
+        (Directive::Ifdef, [Operand::Constant(name)]) => Ok(symbols.contains_key(&name.name)),
+        (Directive::If, [value]) => Ok(value_of(value, symbols)? != 0),
+        _ => Err(AssemblerError::IncorrectOperand),
+    }
+}
+
+/// Value of a number or defined name
+fn value_of(operand: &Operand, symbols: &HashMap<String, i32>) -> Result<i32, AssemblerError> {
+    match operand {
+        Operand::Value(value) => Ok(*value),
+        Operand::Constant(name) => {
+            symbols
+                .get(&name.name)
+                .copied()
+                .ok_or_else(|| AssemblerError::UndefinedSymbol {
+                    name: name.name.clone(),
+                    location: Some(name.span.location),
+                })
+        }
+        _ => Err(AssemblerError::IncorrectOperand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    /// Opcodes left after evaluating the conditionals in source
+    fn evaluate(source: &str, defines: &[(&str, i32)]) -> Result<Vec<String>, AssemblerError> {
+        let mut program = Program::parse(source).unwrap().instructions;
+        let defines = defines
+            .iter()
+            .map(|&(name, value)| (name.to_owned(), value))
+            .collect();
+        evaluate_conditionals(&mut program, &defines)?;
+
+        Ok(program
+            .iter()
+            .filter_map(|instruction| match instruction {
+                AssemblerInstruction::Opcode(opcode) => Some(format!("{:?}", opcode.opcode)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    #[test]
+    fn test_conditionals() {
+        let source = ".code
+            .ifdef DEBUG
+                prti $0
+                .if VERBOSE
+                    prti $1
+                .else
+                    prti $2
+                .endif
+            .else
+                nop
+            .endif
+            hlt";
+
+        assert_eq!(evaluate(source, &[]).unwrap(), ["NOP", "HLT"]);
+        assert_eq!(
+            evaluate(source, &[("DEBUG", 0), ("VERBOSE", 0)]).unwrap(),
+            ["PRTI", "PRTI", "HLT"]
+        );
+        assert_eq!(
+            evaluate(source, &[("DEBUG", 1), ("VERBOSE", 2)]).unwrap(),
+            ["PRTI", "PRTI", "HLT"]
+        );
+        // VERBOSE isn't needed when DEBUG isn't defined, since its branch is skipped
+        assert!(evaluate(source, &[("DEBUG", 1)]).is_err());
+    }
+
+    #[test]
+    fn test_set() {
+        let source = ".code
+            .set LEVEL, 0
+            .if LEVEL
+                nop
+            .endif
+            .set LEVEL, 2
+            .equ SIZE, 4
+            .if LEVEL
+                .if SIZE
+                    hlt
+                .endif
+            .endif
+            .if 0
+                .set LEVEL, 0
+            .endif
+            .if LEVEL
+                inc $0
+            .endif";
+
+        assert_eq!(evaluate(source, &[]).unwrap(), ["HLT", "INC"]);
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        assert!(matches!(
+            evaluate(".code\n.endif", &[]),
+            Err(AssemblerError::UnmatchedConditional {
+                directive: Directive::Endif
+            })
+        ));
+        assert!(matches!(
+            evaluate(".code\n.if 1\n.else\n.else\n.endif", &[]),
+            Err(AssemblerError::UnmatchedConditional {
+                directive: Directive::Else
+            })
+        ));
+        assert!(matches!(
+            evaluate(".code\n.if 1\nhlt", &[]),
+            Err(AssemblerError::UnterminatedConditional)
+        ));
+        assert!(matches!(
+            evaluate(".code\n.if MISSING\n.endif", &[]),
+            Err(AssemblerError::UndefinedSymbol { .. })
+        ));
+    }
+}
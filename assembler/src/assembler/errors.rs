@@ -1,11 +1,118 @@
+use crate::assembler::lint::AbiWarning;
+use crate::parser::directive::Directive;
+use crate::parser::Location;
+use shared::{Opcode, OperandKind};
+
 #[derive(thiserror::Error, Debug, Clone)]
 pub enum AssemblerError {
     #[error("instruction/directive not in a segment")]
     NoSegmentDeclarationFound,
-    #[error("symbol already declared")]
-    SymbolAlreadyDeclared,
-    #[error("failed to parse: {error}")]
-    ParseError { error: String },
+    #[error("{location}: symbol {name} already declared")]
+    SymbolAlreadyDeclared { name: String, location: Location },
+    #[error("{location}: failed to parse '{token}'")]
+    ParseError { location: Location, token: String },
+    #[error("{location}: unknown mnemonic '{mnemonic}'")]
+    UnknownMnemonic {
+        location: Location,
+        mnemonic: String,
+    },
+    #[error("invalid label name '{name}'")]
+    InvalidLabelName { name: String },
+    #[error("failed to include {path}: {error}")]
+    IncludeFailed { path: String, error: String },
+    #[error("{path} includes itself")]
+    IncludeCycle { path: String },
     #[error("incorrect operand for instruction/directive")]
     IncorrectOperand,
+    #[error("can't assemble for {size} byte words, only 4 and 8 byte words")]
+    UnsupportedWordSize { size: usize },
+    #[error("{value} doesn't fit in the immediate operand of {opcode:?}, which takes 0 to {max}")]
+    ImmediateOutOfRange {
+        opcode: Opcode,
+        value: i32,
+        max: i32,
+    },
+    #[error(
+        "{opcode:?} can branch {} to {} bytes, but its target is {offset} bytes away",
+        i16::MIN,
+        i16::MAX
+    )]
+    BranchOutOfRange { opcode: Opcode, offset: i32 },
+    #[error("{opcode:?} takes {expected} operands, but was given {found}")]
+    OperandCount {
+        opcode: Opcode,
+        expected: usize,
+        found: usize,
+    },
+    #[error("operand {position} of {opcode:?} should be a {expected}")]
+    OperandMismatch {
+        opcode: Opcode,
+        /// Position of the operand, starting from 1
+        position: usize,
+        expected: OperandKind,
+    },
+    #[error("{mnemonic} has no addressing mode taking {operand} as its last operand")]
+    NoAddressingMode {
+        mnemonic: String,
+        /// Kind of operand given, such as "a register"
+        operand: &'static str,
+    },
+    #[error("{location}: entry point already declared")]
+    EntryAlreadyDeclared { location: Location },
+    #[error("entry point {name} isn't an instruction in the code section")]
+    EntryNotInCode { name: String },
+    #[error("only .space and .align can be used in the .bss section, found {directive:?}")]
+    InitializedBss { directive: Directive },
+    #[error("{directive:?} without a matching .if")]
+    UnmatchedConditional { directive: Directive },
+    #[error(".if without a matching .endif")]
+    UnterminatedConditional,
+    #[error(".endr without a matching .rept")]
+    UnmatchedRepeat,
+    #[error(".rept without a matching .endr")]
+    UnterminatedRepeat,
+    #[error("{location}: a labelled .rept must start with an unlabelled instruction")]
+    LabelledRepeat { location: Location },
+    #[error(".pad_to {target:#X} is behind the section's current offset of {offset:#X}")]
+    PadBackwards { offset: u32, target: u32 },
+    #[error("{}symbol {name} is not declared", prefix(location))]
+    UndefinedSymbol {
+        name: String,
+        /// Where the symbol is used, if it appears in the source
+        location: Option<Location>,
+    },
+    #[error("symbols are not declared: {}", list(symbols))]
+    UndefinedSymbols {
+        /// Name of each symbol and where it's first used, in source order
+        symbols: Vec<(String, Option<Location>)>,
+    },
+}
+
+/// Problem found while assembling that doesn't stop the program being assembled
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum AssemblerWarning {
+    #[error("{0}")]
+    Abi(AbiWarning),
+    #[error(
+        "{len} bytes of zero padding at {address:#06X} are in the code section, where they run as \
+         HLT; use .nopfill or .pad_to to pad with NOPs instead"
+    )]
+    ZeroPadding { address: u32, len: usize },
+}
+
+/// Location prefix for errors that may not point at the source
+fn prefix(location: &Option<Location>) -> String {
+    location.map_or_else(String::new, |location| format!("{location}: "))
+}
+
+/// Comma separated symbol names, each followed by its location if it has one
+fn list(symbols: &[(String, Option<Location>)]) -> String {
+    symbols
+        .iter()
+        .map(|(name, location)| match location {
+            Some(location) => format!("{name} ({location})"),
+            None => name.clone(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ")
 }
@@ -0,0 +1,247 @@
+//! Multi-file programs.
+//!
+//! `.include "path"` is replaced by the instructions of the file at path, which is resolved
+//! relative to the directory of the file containing the directive. Files are read through a
+//! [`SourceLoader`], so programs can be assembled from somewhere other than the filesystem. A file
+//! including itself, directly or through other files, is an error.
+//!
+//! `.incbin "path"` finds its file the same way, and stores the file's bytes as they are.
+//!
+//! Each instruction records the file it came from along with its line, for debugging. Source
+//! generated from another language can use `.line n` or `.line n, "path"` to record the
+//! following instructions in the file as coming from line n of the original source instead.
+
+use crate::assembler::errors::AssemblerError;
+use crate::parser::directive::Directive;
+use crate::parser::instruction::{AssemblerInstruction, DirectiveInstruction};
+use crate::parser::operand::Operand;
+use crate::parser::{Program, SourceLine};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::path::{Component, Path, PathBuf};
+use std::rc::Rc;
+
+/// Reads the source of included files, and the contents of files embedded with `.incbin`
+pub trait SourceLoader: Debug {
+    fn load(&self, path: &Path) -> std::io::Result<String>;
+
+    /// Reads a file embedded with `.incbin`, which defaults to its source as bytes
+    fn load_bytes(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        self.load(path).map(String::into_bytes)
+    }
+}
+
+/// Reads included files from the filesystem
+#[derive(Debug, Default, Clone, Copy)]
+pub struct FileSystemLoader;
+
+impl SourceLoader for FileSystemLoader {
+    fn load(&self, path: &Path) -> std::io::Result<String> {
+        std::fs::read_to_string(path)
+    }
+
+    fn load_bytes(&self, path: &Path) -> std::io::Result<Vec<u8>> {
+        std::fs::read(path)
+    }
+}
+
+/// Reads included files from memory, by path
+impl SourceLoader for HashMap<PathBuf, String> {
+    fn load(&self, path: &Path) -> std::io::Result<String> {
+        self.get(path)
+            .cloned()
+            .ok_or_else(|| std::io::ErrorKind::NotFound.into())
+    }
+}
+
+/// Replaces every `.include` directive with the instructions of the included file, and reads the
+/// file embedded by every `.incbin` directive. `path` is the path of the program itself, if it has
+/// one
+pub(super) fn expand_includes(
+    program: Vec<AssemblerInstruction>,
+    path: Option<&Path>,
+    loader: &dyn SourceLoader,
+) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let mut stack = path.map(normalize).into_iter().collect::<Vec<_>>();
+
+    expand(program, loader, &mut stack)
+}
+
+/// Expands includes in the file at the top of the stack, or in source without a path if the
+/// stack is empty
+fn expand(
+    program: Vec<AssemblerInstruction>,
+    loader: &dyn SourceLoader,
+    stack: &mut Vec<PathBuf>,
+) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+    let directory = stack
+        .last()
+        .and_then(|path| path.parent())
+        .map(Path::to_path_buf)
+        .unwrap_or_default();
+
+    let file = stack
+        .last()
+        .map(|path| Rc::from(path.display().to_string()));
+    // line given by the last `.line` directive
+    let mut line = None;
+
+    let mut expanded = Vec::with_capacity(program.len());
+    for mut instruction in program {
+        let directive = match &mut instruction {
+            AssemblerInstruction::Opcode(opcode) => {
+                if let Some(source) = &mut opcode.source {
+                    *source = line.clone().unwrap_or(SourceLine {
+                        file: file.clone(),
+                        line: source.line,
+                    });
+                }
+                expanded.push(instruction);
+                continue;
+            }
+            AssemblerInstruction::Directive(directive) => directive,
+        };
+        if directive.directive == Directive::Line {
+            line = Some(line_directive(directive, &file)?);
+            continue;
+        }
+        if !matches!(directive.directive, Directive::Include | Directive::Incbin) {
+            expanded.push(instruction);
+            continue;
+        }
+
+        let [Operand::String(included)] = &directive.operands[..] else {
+            return Err(AssemblerError::IncorrectOperand);
+        };
+        let path = normalize(&directory.join(included));
+        let failed = |error: std::io::Error| AssemblerError::IncludeFailed {
+            path: path.display().to_string(),
+            error: error.to_string(),
+        };
+
+        if directive.directive == Directive::Incbin {
+            let bytes = loader.load_bytes(&path).map_err(failed)?;
+            expanded.push(AssemblerInstruction::Directive(DirectiveInstruction {
+                operands: vec![Operand::Bytes(bytes)],
+                ..directive.clone()
+            }));
+            continue;
+        }
+
+        if stack.contains(&path) {
+            return Err(AssemblerError::IncludeCycle {
+                path: path.display().to_string(),
+            });
+        }
+
+        let source = loader.load(&path).map_err(failed)?;
+
+        stack.push(path);
+        expanded.extend(expand(
+            Program::parse(&source)?.instructions,
+            loader,
+            stack,
+        )?);
+        stack.pop();
+    }
+
+    Ok(expanded)
+}
+
+/// Source line given by a `.line n` or `.line n, "path"` directive in file
+fn line_directive(
+    directive: &DirectiveInstruction,
+    file: &Option<Rc<str>>,
+) -> Result<SourceLine, AssemblerError> {
+    let (line, path) = match &directive.operands[..] {
+        [Operand::Value(line)] => (*line, None),
+        [Operand::Value(line), Operand::String(path)] => (*line, Some(Rc::from(path.as_str()))),
+        _ => return Err(AssemblerError::IncorrectOperand),
+    };
+
+    Ok(SourceLine {
+        file: path.or_else(|| file.clone()),
+        line: usize::try_from(line)
+            .ok()
+            .filter(|&line| line > 0)
+            .ok_or(AssemblerError::IncorrectOperand)?,
+    })
+}
+
+/// Removes `.` and `..` components without touching the filesystem, so the same file is always
+/// found at the same path
+fn normalize(path: &Path) -> PathBuf {
+    let mut normalized = PathBuf::new();
+
+    for component in path.components() {
+        match component {
+            Component::CurDir => {}
+            Component::ParentDir
+                if matches!(
+                    normalized.components().next_back(),
+                    Some(Component::Normal(_))
+                ) =>
+            {
+                normalized.pop();
+            }
+            component => normalized.push(component),
+        }
+    }
+
+    normalized
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn loader(files: &[(&str, &str)]) -> HashMap<PathBuf, String> {
+        files
+            .iter()
+            .map(|&(path, source)| (PathBuf::from(path), source.to_owned()))
+            .collect()
+    }
+
+    fn expand_file(path: &str, loader: &HashMap<PathBuf, String>) -> Result<usize, AssemblerError> {
+        let program = Program::parse(&loader.load(Path::new(path)).unwrap())?;
+
+        Ok(expand_includes(program.instructions, Some(Path::new(path)), loader)?.len())
+    }
+
+    #[test]
+    fn test_include() {
+        let files = loader(&[
+            ("src/main.asm", ".include 'lib/io.asm'\n.code\nhlt"),
+            (
+                "src/lib/io.asm",
+                ".include \"../common.asm\"\n.code\nprint: ret",
+            ),
+            ("src/common.asm", ".data\nmessage: .asciiz 'hi'"),
+        ]);
+
+        assert_eq!(expand_file("src/main.asm", &files).unwrap(), 6);
+    }
+
+    #[test]
+    fn test_include_errors() {
+        let files = loader(&[
+            ("a.asm", ".include 'b.asm'"),
+            ("b.asm", ".include './a.asm'"),
+            ("c.asm", ".include 'missing.asm'"),
+            ("d.asm", ".include 1"),
+        ]);
+
+        assert!(matches!(
+            expand_file("a.asm", &files),
+            Err(AssemblerError::IncludeCycle { path }) if path == "a.asm"
+        ));
+        assert!(matches!(
+            expand_file("c.asm", &files),
+            Err(AssemblerError::IncludeFailed { path, .. }) if path == "missing.asm"
+        ));
+        assert!(matches!(
+            expand_file("d.asm", &files),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+}
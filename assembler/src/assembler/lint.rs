@@ -0,0 +1,214 @@
+//! Calling convention lint.
+//!
+//! Every label used as a `CALLI` target is treated as a routine, running up to its last `RET` before
+//! the next routine or section directive. A routine that writes a callee-saved register must save it with `PUSH` before
+//! the write and restore it with `POP` before returning.
+
+use crate::parser::instruction::{AssemblerInstruction, OpcodeInstruction};
+use crate::parser::operand::Operand;
+use shared::abi::is_callee_saved;
+use shared::Opcode;
+use std::collections::HashSet;
+use std::fmt::{Display, Formatter};
+
+/// Calling convention violation found in a routine
+#[derive(Debug, Clone, PartialEq)]
+pub enum AbiWarning {
+    /// A callee-saved register is written without being saved first
+    Clobbered { routine: String, register: u8 },
+    /// A callee-saved register is saved but never restored
+    NotRestored { routine: String, register: u8 },
+}
+
+impl Display for AbiWarning {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AbiWarning::Clobbered { routine, register } => write!(
+                f,
+                "routine {routine} clobbers callee-saved register ${register} without saving it"
+            ),
+            AbiWarning::NotRestored { routine, register } => write!(
+                f,
+                "routine {routine} saves callee-saved register ${register} but never restores it"
+            ),
+        }
+    }
+}
+
+/// Checks every routine in the program follows the calling convention
+pub(super) fn lint_abi(program: &[AssemblerInstruction]) -> Vec<AbiWarning> {
+    routines(program)
+        .into_iter()
+        .flat_map(|(routine, body)| lint_routine(routine, body))
+        .collect()
+}
+
+/// Finds every routine in the program, along with the instructions in its body
+pub(super) fn routines(program: &[AssemblerInstruction]) -> Vec<(&str, Vec<&OpcodeInstruction>)> {
+    let names = program
+        .iter()
+        .filter_map(|instruction| match instruction {
+            AssemblerInstruction::Opcode(OpcodeInstruction {
+                opcode: Opcode::CALLI,
+                operands,
+                ..
+            }) => match operands.first() {
+                Some(Operand::Label(label)) => Some(label.name.as_str()),
+                _ => None,
+            },
+            _ => None,
+        })
+        .collect::<HashSet<_>>();
+
+    let mut routines = Vec::new();
+    for (start, instruction) in program.iter().enumerate() {
+        let Some(routine) = instruction
+            .label()
+            .filter(|label| names.contains(label.name.as_str()))
+        else {
+            continue;
+        };
+
+        // the routine runs until the next routine or section directive
+        let mut body = program[start..]
+            .iter()
+            .enumerate()
+            .take_while(|&(index, instruction)| {
+                index == 0
+                    || match instruction {
+                        AssemblerInstruction::Opcode(_) => !instruction
+                            .label()
+                            .is_some_and(|label| names.contains(label.name.as_str())),
+                        AssemblerInstruction::Directive(directive) => {
+                            !directive.operands.is_empty()
+                        }
+                    }
+            })
+            .filter_map(|(_, instruction)| match instruction {
+                AssemblerInstruction::Opcode(opcode) => Some(opcode),
+                _ => None,
+            })
+            .collect::<Vec<_>>();
+
+        // anything after the last return isn't part of the routine
+        if let Some(end) = body.iter().rposition(|opcode| opcode.opcode == Opcode::RET) {
+            body.truncate(end + 1);
+        }
+
+        routines.push((routine.name.as_str(), body));
+    }
+
+    routines
+}
+
+fn lint_routine(routine: &str, body: Vec<&OpcodeInstruction>) -> Vec<AbiWarning> {
+    let mut saved = Vec::new();
+    let mut restored = HashSet::new();
+    let mut clobbered = Vec::new();
+
+    for instruction in body {
+        let Some(Operand::Register(register)) = instruction.operands.first() else {
+            continue;
+        };
+        let register = *register;
+        if !is_callee_saved(register) {
+            continue;
+        }
+
+        match instruction.opcode {
+            Opcode::PUSH if !saved.contains(&register) => saved.push(register),
+            Opcode::POP if saved.contains(&register) => {
+                restored.insert(register);
+            }
+            opcode
+                if writes_first_operand(opcode)
+                    && !saved.contains(&register)
+                    && !clobbered.contains(&register) =>
+            {
+                clobbered.push(register)
+            }
+            _ => {}
+        }
+    }
+
+    let clobbered = clobbered.into_iter().map(|register| AbiWarning::Clobbered {
+        routine: routine.to_owned(),
+        register,
+    });
+    let not_restored = saved
+        .into_iter()
+        .filter(|register| !restored.contains(register))
+        .map(|register| AbiWarning::NotRestored {
+            routine: routine.to_owned(),
+            register,
+        });
+
+    clobbered.chain(not_restored).collect()
+}
+
+/// Checks if the opcode writes to the register given as its first operand
+fn writes_first_operand(opcode: Opcode) -> bool {
+    use Opcode::*;
+
+    matches!(
+        opcode,
+        LDBI | LDBD
+            | LDBR
+            | LDHI
+            | LDHD
+            | LDHR
+            | LDWD
+            | LDWR
+            | MOV
+            | ALOCI
+            | ALOCR
+            | POP
+            | ADDR
+            | ADDI
+            | SUBR
+            | SUBI
+            | MULR
+            | MULI
+            | DIVR
+            | DIVI
+            | RECV
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    #[test]
+    fn test_lint_abi() {
+        let program = r#".code
+                                    calli @good
+                                    calli @bad
+                                    hlt
+                                    good: push $16
+                                    ldbi $16, 1
+                                    addi $0, 1
+                                    pop $16
+                                    ret
+                                    bad: addi $17, 1
+                                    push $18
+                                    ret
+                                    notcalled: ldbi $20, 1"#;
+        let program = Program::parse(program).unwrap().instructions;
+
+        assert_eq!(
+            lint_abi(&program),
+            vec![
+                AbiWarning::Clobbered {
+                    routine: "bad".to_owned(),
+                    register: 17
+                },
+                AbiWarning::NotRestored {
+                    routine: "bad".to_owned(),
+                    register: 18
+                }
+            ]
+        );
+    }
+}
@@ -0,0 +1,158 @@
+//! Literal pools.
+//!
+//! `ldwd $0, =12345678` and `ldwd $0, =@label` load a word too large for an immediate. Each distinct
+//! literal is placed once in a pool at the end of the data section, and the operand is rewritten
+//! to the address of its pool entry.
+//!
+//! `ldhi $0, =value` loads any word, assembling to a plain `LDHI` when the value fits in its
+//! immediate and to an `LDWD` from the pool otherwise.
+
+use crate::assembler::errors::AssemblerError;
+use crate::parser::directive::Directive;
+use crate::parser::instruction::AssemblerInstruction;
+use crate::parser::operand::{Literal, Operand};
+use crate::parser::Label;
+use shared::Opcode;
+
+/// Moves literal operands into a pool, rewriting them to direct loads from it
+pub(super) fn place_literals(
+    program: &mut Vec<AssemblerInstruction>,
+) -> Result<(), AssemblerError> {
+    // pool entries are named after their literal, which can't clash with user labels
+    let mut pool: Vec<(String, Operand)> = Vec::new();
+
+    for instruction in program.iter_mut() {
+        let AssemblerInstruction::Opcode(instruction) = instruction else {
+            continue;
+        };
+
+        for operand in &mut instruction.operands {
+            let Operand::Literal(literal) = operand else {
+                continue;
+            };
+            if instruction.opcode == Opcode::LDHI {
+                // addresses always fit in an immediate
+                match literal {
+                    Literal::Value(value) if u16::try_from(*value).is_ok() => {
+                        *operand = Operand::Value(*value);
+                        continue;
+                    }
+                    Literal::Label(label) => {
+                        *operand = Operand::Label(label.clone());
+                        continue;
+                    }
+                    Literal::Value(_) => instruction.opcode = Opcode::LDWD,
+                }
+            }
+            if instruction.opcode != Opcode::LDWD {
+                return Err(AssemblerError::IncorrectOperand);
+            }
+
+            let (name, word) = match literal {
+                Literal::Value(value) => (format!("={value}"), Operand::Value(*value)),
+                Literal::Label(label) => {
+                    (format!("=@{}", label.name), Operand::Label(label.clone()))
+                }
+            };
+            if !pool.iter().any(|(existing, _)| *existing == name) {
+                pool.push((name.clone(), word));
+            }
+
+            *operand = Operand::Label(Label::from(name.as_str()));
+        }
+    }
+
+    if pool.is_empty() {
+        return Ok(());
+    }
+
+    // data is laid out before code wherever it's declared, so the pool can go at the end
+    program.push(AssemblerInstruction::new_directive(
+        None,
+        Directive::Data,
+        &[],
+    ));
+    program.extend(pool.into_iter().map(|(name, word)| {
+        AssemblerInstruction::new_directive(Some(&name), Directive::Word, &[word])
+    }));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    #[test]
+    fn test_place_literals() {
+        let program = r#".data
+                                    a: .word 1
+                                .code
+                                    ldwd $0, =12345678
+                                    ldwd $1, =@a
+                                    ldwd $2, =12345678"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+        place_literals(&mut program).unwrap();
+
+        let expected = vec![
+            AssemblerInstruction::new_directive(None, Directive::Data, &[]),
+            AssemblerInstruction::new_directive(
+                Some("=12345678"),
+                Directive::Word,
+                &[Operand::Value(12345678)],
+            ),
+            AssemblerInstruction::new_directive(
+                Some("=@a"),
+                Directive::Word,
+                &[Operand::Label("a".into())],
+            ),
+        ];
+        assert_eq!(program[6..], expected);
+        assert_eq!(
+            program[3],
+            AssemblerInstruction::new_opcode(
+                None,
+                Opcode::LDWD,
+                &[Operand::Register(0), Operand::Label("=12345678".into())]
+            )
+        );
+    }
+
+    #[test]
+    fn test_immediate_loads() {
+        let program = r#".code
+                                    ldhi $0, =0xFFFF
+                                    ldhi $1, =-1
+                                    ldhi $2, =@start"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+        place_literals(&mut program).unwrap();
+
+        let expected = [
+            (Opcode::LDHI, Operand::Value(0xFFFF)),
+            (Opcode::LDWD, Operand::Label("=-1".into())),
+            (Opcode::LDHI, Operand::Label("start".into())),
+        ];
+        for (index, (opcode, operand)) in expected.into_iter().enumerate() {
+            assert_eq!(
+                program[index + 1],
+                AssemblerInstruction::new_opcode(
+                    None,
+                    opcode,
+                    &[Operand::Register(index as u8), operand]
+                )
+            );
+        }
+        assert_eq!(program.len(), 6);
+    }
+
+    #[test]
+    fn test_literal_needs_load() {
+        let mut program = Program::parse(".code\naddi $0, =1").unwrap().instructions;
+
+        assert!(matches!(
+            place_literals(&mut program),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+}
@@ -1,56 +1,318 @@
 //! BYTECODE FORMAT
 //! ```norun
-//! <EPIE magic number>     00 00 00 00
+//! <EPIE magic number>    <format version> 00 00 00
 //! <data section offset>  <data section length>
 //! <code section offset>  <code section length>
+//! <symbol section offset> <symbol section length>
+//! <entry point offset>  <rodata section offset> <rodata section length>
+//! <bss address>          <bss length>
 //! ```
+//!
+//! The symbol section is only written if enabled with [`Assembler::debug_symbols`], and both its
+//! fields are zero otherwise. See [`shared::symbols`] for its encoding. The entry point is given
+//! relative to the start of the code section, so it's zero unless declared with `.entry`. The bss
+//! section isn't stored in the program, and is instead addressed from the first word boundary after
+//! it, including the symbol section.
 
-use crate::assembler::errors::AssemblerError;
+pub use crate::assembler::errors::{AssemblerError, AssemblerWarning};
+pub use crate::assembler::include::{FileSystemLoader, SourceLoader};
+pub use crate::assembler::lint::AbiWarning;
 use crate::assembler::section::AssemblerSection;
+pub use crate::assembler::stats::{AddressingMode, Immediate, RoutineStats, Statistics};
+pub use crate::assembler::symbols::DataLayout;
 use crate::assembler::symbols::{Symbol, SymbolTable, SymbolType};
 use crate::parser::directive::Directive;
 use crate::parser::instruction::{AssemblerInstruction, DirectiveInstruction, OpcodeInstruction};
 use crate::parser::operand::Operand;
-use crate::parser::Program;
-use shared::{PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
+use crate::parser::{Label, Location, Program, SourceLine};
+use shared::lines::{encode_lines, LineEntry, LineTable, LINE_SECTION_FIELD};
+use shared::symbols::{encode_symbols, DebugSymbol, SymbolKind, SYMBOL_SECTION_FIELD};
+use shared::{
+    Opcode, OperandKind, BSS_SECTION_FIELD, ENTRY_POINT_FIELD, PIE_FORMAT_VERSION,
+    PIE_HEADER_LENGTH, PIE_HEADER_PREFIX, PIE_WORD_SIZE, RODATA_SECTION_FIELD,
+};
+use std::collections::HashMap;
+use std::path::PathBuf;
 
+mod conditional;
 mod errors;
+mod include;
+mod lint;
+mod literals;
+mod rept;
 mod section;
+mod stats;
+mod strip;
 mod symbols;
+mod weak;
 
 /// Stores information used during assembly
 #[derive(Default, Debug)]
 pub struct Assembler {
     data_section: Vec<u8>,
+    rodata_section: Vec<u8>,
     code_section: Vec<u8>,
     symbols: SymbolTable,
     current_section: Option<AssemblerSection>,
     next_alignment: Option<usize>,
+    strip_unused: bool,
+    stripped: Vec<String>,
+    lint_abi: bool,
+    warnings: Vec<AssemblerWarning>,
+    collect_stats: bool,
+    stats: Option<Statistics>,
+    /// Path of the source being assembled, which included files are found relative to
+    source_path: Option<PathBuf>,
+    /// Reads included files, or the filesystem if None
+    loader: Option<Box<dyn SourceLoader>>,
+    /// Whether a symbol section is written after the code section
+    debug_symbols: bool,
+    /// Address of the code section, known once the first pass has laid out the data section
+    code_start: u32,
+    /// Largest alignment of the `.align` directives in the code section, which the code section
+    /// itself is aligned to so they align addresses rather than offsets
+    code_alignment: u32,
+    /// Zero bytes after the read-only data that align the code section
+    code_padding: u32,
+    /// Label execution starts at, declared with `.entry`
+    entry: Option<Label>,
+    /// Address and length of the bss section, known once the first pass has laid out everything
+    /// before it
+    bss_start: u32,
+    bss_len: u32,
+    /// Names defined before assembly starts, which `.if` and `.ifdef` can test
+    defines: HashMap<String, i32>,
+    /// Source line of each instruction, known once the first pass has laid out the code section
+    lines: LineTable,
+    /// Bytes in each word, declared in the header and used for `.word`, or None for 4 byte words
+    word_size: Option<u8>,
 }
 
 impl Assembler {
+    /// Sets whether labelled code and data unreachable from the start of the program is removed
+    pub fn strip_unused(mut self, strip: bool) -> Self {
+        self.strip_unused = strip;
+        self
+    }
+
+    /// Labels removed by dead symbol stripping during the last assembly
+    pub fn stripped_symbols(&self) -> &[String] {
+        &self.stripped
+    }
+
+    /// Sets whether routines are checked against the calling convention while assembling
+    pub fn lint_abi(mut self, lint: bool) -> Self {
+        self.lint_abi = lint;
+        self
+    }
+
+    /// Calling convention violations and zero padding in the code section found during the last
+    /// assembly
+    pub fn warnings(&self) -> &[AssemblerWarning] {
+        &self.warnings
+    }
+
+    /// Sets whether register pressure and encoding statistics are collected while assembling
+    pub fn collect_stats(mut self, collect: bool) -> Self {
+        self.collect_stats = collect;
+        self
+    }
+
+    /// Statistics collected during the last assembly, if enabled
+    pub fn stats(&self) -> Option<&Statistics> {
+        self.stats.as_ref()
+    }
+
+    /// Sets the path of the source being assembled, so included files can be found relative to it.
+    /// Without one, they're found relative to the current directory
+    pub fn source_path(mut self, path: impl Into<PathBuf>) -> Self {
+        self.source_path = Some(path.into());
+        self
+    }
+
+    /// Sets how included files are read, instead of from the filesystem
+    pub fn loader(mut self, loader: impl SourceLoader + 'static) -> Self {
+        self.loader = Some(Box::new(loader));
+        self
+    }
+
+    /// Defines a name for `.if` and `.ifdef` to test, as if the program started with
+    /// `.set name, value`
+    pub fn define(mut self, name: impl Into<String>, value: i32) -> Self {
+        self.defines.insert(name.into(), value);
+        self
+    }
+
+    /// Sets how many bytes each word is, either 4 or 8, instead of 4. Programs assembled for 8 byte
+    /// words declare it in their header, so only run in VMs with 8 byte registers, and their
+    /// `.word` directives store 8 bytes per value, sign extended
+    pub fn word_size(mut self, bytes: u8) -> Self {
+        self.word_size = Some(bytes);
+        self
+    }
+
+    /// Sets whether a symbol section naming every label and constant is written after the code
+    /// section, so addresses can be resolved back to names when debugging
+    pub fn debug_symbols(mut self, enabled: bool) -> Self {
+        self.debug_symbols = enabled;
+        self
+    }
+
+    /// Symbols written to the symbol section, sorted by value then name
+    pub fn debug_symbol_table(&self) -> Vec<DebugSymbol> {
+        let data_end = (self.data_section.len() + self.rodata_section.len()) as u32;
+        let code = data_end..data_end + self.code_section.len() as u32;
+        let mut symbols = self
+            .symbols
+            .iter()
+            .map(|(name, symbol)| {
+                let (kind, value) = match symbol.constant() {
+                    Some(value) => (SymbolKind::Constant, value as u32),
+                    None => {
+                        let kind = match code.contains(&symbol.offset) {
+                            true => SymbolKind::Code,
+                            false => SymbolKind::Data,
+                        };
+                        (kind, symbol.offset + PIE_HEADER_LENGTH as u32)
+                    }
+                };
+
+                DebugSymbol {
+                    name: name.to_owned(),
+                    kind,
+                    value,
+                }
+            })
+            .collect::<Vec<_>>();
+        symbols.sort_by(|a, b| (a.value, &a.name).cmp(&(b.value, &b.name)));
+
+        symbols
+    }
+
+    /// Source file and line each instruction in the last assembled program came from, written
+    /// after the symbol section along with it
+    pub fn line_table(&self) -> &LineTable {
+        &self.lines
+    }
+
     /// Assembles an assembly string into bytecode
     pub fn assemble(&mut self, data: &str) -> Result<Vec<u8>, AssemblerError> {
-        let program = Program::parse(data).ok_or(AssemblerError::ParseError {
-            error: "failed to parse assembly".to_string(),
-        })?;
+        if !matches!(self.word_bytes(), 4 | 8) {
+            return Err(AssemblerError::UnsupportedWordSize {
+                size: self.word_bytes(),
+            });
+        }
+        let mut program = Program::parse(data)?;
+        program.instructions = include::expand_includes(
+            program.instructions,
+            self.source_path.as_deref(),
+            self.loader.as_deref().unwrap_or(&FileSystemLoader),
+        )?;
+
+        conditional::evaluate_conditionals(&mut program.instructions, &self.defines)?;
+        rept::expand_repeats(&mut program.instructions)?;
+        weak::resolve_weak(&mut program.instructions)?;
+        literals::place_literals(&mut program.instructions)?;
+        if self.strip_unused {
+            self.stripped = strip::strip_unused(&mut program.instructions)?;
+        }
+        self.warnings.clear();
+        if self.lint_abi {
+            let warnings = lint::lint_abi(&program.instructions);
+            self.warnings
+                .extend(warnings.into_iter().map(AssemblerWarning::Abi));
+        }
+        if self.collect_stats {
+            self.stats = Some(stats::collect_stats(&program.instructions));
+        }
 
         self.first_pass(&program.instructions)?;
+        self.check_defined(&program.instructions)?;
         self.second_pass(&program.instructions)?;
 
-        let mut out = self.create_header();
+        let (symbol_section, line_section) = match self.debug_symbols {
+            true => (
+                encode_symbols(&self.debug_symbol_table()),
+                encode_lines(&self.lines),
+            ),
+            false => (Vec::new(), Vec::new()),
+        };
+
+        let mut out = self.create_header(
+            symbol_section.len(),
+            line_section.len(),
+            self.entry_offset()?,
+        );
         out.extend_from_slice(&self.data_section);
+        out.extend_from_slice(&self.rodata_section);
         out.extend_from_slice(&self.code_section);
+        out.extend_from_slice(&symbol_section);
+        out.extend_from_slice(&line_section);
 
         Ok(out)
     }
 
+    /// Returns the address a label resolves to in the assembled program, if it was declared
+    pub fn label_address(&self, name: &str) -> Option<u32> {
+        self.symbols
+            .get_symbol(name)
+            .filter(|symbol| symbol.constant().is_none())
+            .map(|symbol| symbol.offset + PIE_HEADER_LENGTH as u32)
+    }
+
+    /// Returns the value of a constant defined with `.equ`
+    pub fn constant(&self, name: &str) -> Option<i32> {
+        self.symbols.get_symbol(name)?.constant()
+    }
+
+    /// Returns the element size and dimensions of data declared at a label with `.fill` or
+    /// `.matrix`
+    pub fn label_layout(&self, name: &str) -> Option<&DataLayout> {
+        self.symbols.get_symbol(name)?.layout.as_ref()
+    }
+
+    /// Iterates over every label and the address it resolves to, in no particular order
+    pub fn labels(&self) -> impl Iterator<Item = (&str, u32)> {
+        self.symbols
+            .iter()
+            .filter(|(_, symbol)| symbol.constant().is_none())
+            .map(|(name, symbol)| (name, symbol.offset + PIE_HEADER_LENGTH as u32))
+    }
+
+    /// Iterates over every constant and its value, in no particular order
+    pub fn constants(&self) -> impl Iterator<Item = (&str, i32)> {
+        self.symbols
+            .iter()
+            .filter_map(|(name, symbol)| Some((name, symbol.constant()?)))
+    }
+
     /// First pass of assembler
-    /// Scans for symbols and builds the symbol table
+    /// Scans for symbols and builds the symbol table. Data, read-only data, code and bss are laid
+    /// out separately, in that order, so sections can appear in any order and labels can be used
+    /// before they're declared
     fn first_pass(&mut self, program: &[AssemblerInstruction]) -> Result<(), AssemblerError> {
-        let mut offset = 0;
+        let mut data_offset = 0;
+        let mut rodata_offset = 0;
+        let mut code_offset = 0;
+        let mut bss_offset = 0;
+        // read-only data, code and bss labels, offset by the size of the sections before them once
+        // they're known
+        let mut rodata_labels = Vec::new();
+        let mut code_labels = Vec::new();
+        let mut bss_labels = Vec::new();
+        let mut sources = Vec::new();
+        self.entry = None;
+        self.code_alignment = 1;
 
         for instruction in program {
+            if let AssemblerInstruction::Opcode(OpcodeInstruction {
+                source: Some(source),
+                ..
+            }) = instruction
+            {
+                sources.push((code_offset, source));
+            }
+
             match instruction {
                 AssemblerInstruction::Opcode(OpcodeInstruction {
                     label: Option::None,
@@ -58,7 +320,7 @@ impl Assembler {
                 }) => {
                     // simplest case: instruction with no label
                     // simply move offset by size of instruction (4 bytes)
-                    offset += 4;
+                    code_offset += 4;
                 }
                 AssemblerInstruction::Opcode(OpcodeInstruction {
                     label: Some(label), ..
@@ -71,16 +333,68 @@ impl Assembler {
                     // then add the symbol, returning error if it already exists
                     if !self
                         .symbols
-                        .add_symbol(label, Symbol::new(offset, SymbolType::Label))
+                        .add_symbol(&label.name, Symbol::new(code_offset, SymbolType::Label))
                     {
-                        return Err(AssemblerError::SymbolAlreadyDeclared);
+                        return Err(AssemblerError::SymbolAlreadyDeclared {
+                            name: label.name.clone(),
+                            location: label.span.location,
+                        });
                     }
+                    code_labels.push(&label.name);
 
                     // finally move offset by size of instruction (4 bytes)
-                    offset += 4;
+                    code_offset += 4;
                 }
                 AssemblerInstruction::Directive(directive) => {
-                    self.handle_directive_first_pass(directive, &mut offset)?;
+                    // directives are written to whichever section they're in
+                    let (offset, labels) = match self.current_section {
+                        Some(AssemblerSection::Code) => (&mut code_offset, Some(&mut code_labels)),
+                        Some(AssemblerSection::Rodata) => {
+                            (&mut rodata_offset, Some(&mut rodata_labels))
+                        }
+                        Some(AssemblerSection::Bss) => (&mut bss_offset, Some(&mut bss_labels)),
+                        _ => (&mut data_offset, None),
+                    };
+                    let label = directive.label.as_ref().map(|label| &label.name);
+                    let undeclared = label.filter(|name| self.symbols.get_symbol(name).is_none());
+
+                    self.handle_directive_first_pass(directive, offset)?;
+                    if let Some(labels) = labels {
+                        labels.extend(
+                            undeclared.filter(|name| self.symbols.get_symbol(name).is_some()),
+                        );
+                    }
+                }
+            }
+        }
+
+        let code_start = PIE_HEADER_LENGTH as u32 + data_offset + rodata_offset;
+        self.code_padding = code_start.next_multiple_of(self.code_alignment) - code_start;
+        rodata_offset += self.code_padding;
+        self.code_start = code_start + self.code_padding;
+        self.lines = line_table(self.code_start, sources);
+
+        // the symbol section's length only depends on the names in it, so the end of the program
+        // is known before any symbol has its final value
+        let debug_sections_len = match self.debug_symbols {
+            true => {
+                encode_symbols(&self.debug_symbol_table()).len() + encode_lines(&self.lines).len()
+            }
+            false => 0,
+        };
+        let image_len = self.code_start + code_offset + debug_sections_len as u32;
+        self.bss_start = image_len.next_multiple_of(4);
+        self.bss_len = bss_offset;
+
+        let moved = [
+            (rodata_labels, data_offset),
+            (code_labels, data_offset + rodata_offset),
+            (bss_labels, self.bss_start - PIE_HEADER_LENGTH as u32),
+        ];
+        for (labels, by) in moved {
+            for name in labels {
+                if let Some(symbol) = self.symbols.get_symbol_mut(name) {
+                    symbol.offset += by;
                 }
             }
         }
@@ -94,6 +408,14 @@ impl Assembler {
         directive: &DirectiveInstruction,
         offset: &mut u32,
     ) -> Result<(), AssemblerError> {
+        // constants aren't part of any section
+        if directive.directive == Directive::Equ {
+            return self.define_constant(directive);
+        }
+        if directive.directive == Directive::Entry {
+            return self.declare_entry(directive);
+        }
+
         // no operands, so treat as section
         if directive.operands.is_empty() {
             self.current_section = Some(AssemblerSection::from(directive.directive));
@@ -105,29 +427,60 @@ impl Assembler {
             return Err(AssemblerError::NoSegmentDeclarationFound);
         }
 
+        // bss is only reserved, so there are no values to store
+        if self.current_section == Some(AssemblerSection::Bss)
+            && !matches!(directive.directive, Directive::Space | Directive::Align)
+        {
+            return Err(AssemblerError::InitializedBss {
+                directive: directive.directive,
+            });
+        }
+
+        // sizes and shapes can be given by constants, so they must be defined before this
+        let directive = &DirectiveInstruction {
+            operands: self.resolve_constants(&directive.operands)?,
+            ..directive.clone()
+        };
+
         match directive.directive {
-            Directive::Align => {
+            Directive::Align => match self.code_alignment(directive) {
+                Some(alignment) => {
+                    self.code_alignment = self.code_alignment.max(alignment);
+                    *offset = offset.next_multiple_of(alignment);
+                }
                 // if alignment, set the next alignment value to first argument
-                if let Some(&Operand::Value(value)) = directive.operands.first() {
-                    self.next_alignment = Some(value as usize);
+                None => {
+                    if let Some(&Operand::Value(value)) = directive.operands.first() {
+                        self.next_alignment = Some(value as usize);
+                    }
                 }
+            },
+            Directive::PadTo | Directive::Nopfill => {
+                let end = self.padded_offset(directive, *offset)?;
+                self.declare_label(directive, *offset, None)?;
+
+                self.next_alignment = None;
+                *offset = end;
+                return Ok(());
             }
             Directive::Ascii
             | Directive::Asciiz
             | Directive::Byte
             | Directive::Half
             | Directive::Word
-            | Directive::Space => {
-                // add label if it exists
-                if let Some(label) = &directive.label {
-                    // then add the symbol, returning error if it already exists
-                    if !self
-                        .symbols
-                        .add_symbol(label, Symbol::new(*offset, SymbolType::Label))
-                    {
-                        return Err(AssemblerError::SymbolAlreadyDeclared);
-                    }
+            | Directive::Space
+            | Directive::Fill
+            | Directive::Matrix
+            | Directive::Incbin => {
+                // structured data must have a valid shape
+                let layout = directive.layout();
+                if matches!(directive.directive, Directive::Fill | Directive::Matrix)
+                    && layout.is_none()
+                {
+                    return Err(AssemblerError::IncorrectOperand);
                 }
+
+                self.declare_label(directive, *offset, layout)?;
             }
             _ => {}
         }
@@ -135,36 +488,226 @@ impl Assembler {
         // skip align directive since works different
         if directive.directive != Directive::Align {
             // finally move offset by size of directive
-            *offset += directive.size(self.next_alignment.take()) as u32;
+            *offset += directive.size(self.next_alignment.take(), self.word_bytes()) as u32;
+        }
+
+        Ok(())
+    }
+
+    /// Bytes in each word of the program being assembled
+    fn word_bytes(&self) -> usize {
+        self.word_size.unwrap_or(PIE_WORD_SIZE) as usize
+    }
+
+    /// Alignment of an `.align` directive that pads the code section with NOPs, which it does when
+    /// it's in the code section and aligns to whole instructions. Other `.align` directives only
+    /// align the next data directive
+    fn code_alignment(&self, directive: &DirectiveInstruction) -> Option<u32> {
+        let Some(&Operand::Value(value)) = directive.operands.first() else {
+            return None;
+        };
+
+        u32::try_from(value).ok().filter(|&alignment| {
+            self.current_section == Some(AssemblerSection::Code)
+                && alignment > 0
+                && alignment.is_multiple_of(4)
+        })
+    }
+
+    /// Adds the directive's label at offset, if it has one
+    fn declare_label(
+        &mut self,
+        directive: &DirectiveInstruction,
+        offset: u32,
+        layout: Option<DataLayout>,
+    ) -> Result<(), AssemblerError> {
+        if let Some(label) = &directive.label {
+            // then add the symbol, returning error if it already exists
+            let symbol = Symbol::new(offset, SymbolType::Label).with_layout(layout);
+            if !self.symbols.add_symbol(&label.name, symbol) {
+                return Err(AssemblerError::SymbolAlreadyDeclared {
+                    name: label.name.clone(),
+                    location: label.span.location,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Offset a `.nopfill count` or `.pad_to offset` directive at offset in the current section
+    /// pads up to. Code can only be padded by whole instructions
+    fn padded_offset(
+        &self,
+        directive: &DirectiveInstruction,
+        offset: u32,
+    ) -> Result<u32, AssemblerError> {
+        let [Operand::Value(value)] = directive.operands[..] else {
+            return Err(AssemblerError::IncorrectOperand);
+        };
+        let value = u32::try_from(value).map_err(|_| AssemblerError::IncorrectOperand)?;
+
+        let end = match directive.directive {
+            Directive::Nopfill => offset + value * 4,
+            _ => value,
+        };
+        if end < offset {
+            return Err(AssemblerError::PadBackwards {
+                offset,
+                target: end,
+            });
+        }
+        if self.current_section == Some(AssemblerSection::Code) && !(end - offset).is_multiple_of(4)
+        {
+            return Err(AssemblerError::IncorrectOperand);
         }
 
+        Ok(end)
+    }
+
+    /// Adds a constant defined with `.equ NAME, value`, where the value can be an earlier constant
+    fn define_constant(&mut self, directive: &DirectiveInstruction) -> Result<(), AssemblerError> {
+        let Some((Operand::Constant(name), value)) = directive.operands.split_first() else {
+            return Err(AssemblerError::IncorrectOperand);
+        };
+        let [Operand::Value(value)] = self.resolve_constants(value)?[..] else {
+            return Err(AssemblerError::IncorrectOperand);
+        };
+
+        let symbol = Symbol::new(0, SymbolType::Constant(value));
+        if !self.symbols.add_symbol(&name.name, symbol) {
+            return Err(AssemblerError::SymbolAlreadyDeclared {
+                name: name.name.clone(),
+                location: name.span.location,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Checks every label and constant used has been declared, reporting all that haven't rather
+    /// than just the first
+    fn check_defined(&self, program: &[AssemblerInstruction]) -> Result<(), AssemblerError> {
+        let mut undefined: Vec<(String, Option<Location>)> = Vec::new();
+        for label in program.iter().flat_map(AssemblerInstruction::labels) {
+            if self.symbols.get_symbol(&label.name).is_none()
+                && !undefined.iter().any(|(name, _)| *name == label.name)
+            {
+                undefined.push((label.name.clone(), Some(label.span.location)));
+            }
+        }
+
+        match undefined.len() {
+            0 => Ok(()),
+            1 => {
+                let (name, location) = undefined.remove(0);
+                Err(AssemblerError::UndefinedSymbol { name, location })
+            }
+            _ => Err(AssemblerError::UndefinedSymbols { symbols: undefined }),
+        }
+    }
+
+    /// Records the label execution starts at, which can only be declared once
+    fn declare_entry(&mut self, directive: &DirectiveInstruction) -> Result<(), AssemblerError> {
+        let [Operand::Label(label)] = &directive.operands[..] else {
+            return Err(AssemblerError::IncorrectOperand);
+        };
+        if self.entry.is_some() {
+            return Err(AssemblerError::EntryAlreadyDeclared {
+                location: label.span.location,
+            });
+        }
+
+        self.entry = Some(label.clone());
         Ok(())
     }
 
+    /// Offset of the entry point from the start of the code section, or 0 if there isn't one
+    fn entry_offset(&self) -> Result<u32, AssemblerError> {
+        let Some(label) = &self.entry else {
+            return Ok(0);
+        };
+        let address =
+            self.label_address(&label.name)
+                .ok_or_else(|| AssemblerError::UndefinedSymbol {
+                    name: label.name.clone(),
+                    location: Some(label.span.location),
+                })?;
+
+        address
+            .checked_sub(self.code_start)
+            .filter(|&offset| {
+                (offset as usize) < self.code_section.len() && offset.is_multiple_of(4)
+            })
+            .ok_or_else(|| AssemblerError::EntryNotInCode {
+                name: label.name.clone(),
+            })
+    }
+
     /// Generates data and code section from program
     fn second_pass(&mut self, program: &[AssemblerInstruction]) -> Result<(), AssemblerError> {
         for instruction in program {
             match instruction {
                 AssemblerInstruction::Opcode(opcode) => {
+                    let opcode = &*opcode.select_mode()?;
+                    Self::check_operands(opcode)?;
+
                     // instructions are all 4 bytes
                     let mut buf = Vec::with_capacity(4);
 
                     // write opcode, and then up to 3 operands
                     buf.push(opcode.opcode as u8);
-                    for operand in opcode.operands.iter().take(3) {
+                    for (index, operand) in opcode.operands.iter().take(3).enumerate() {
+                        // log levels are a single byte, leaving room for the message address
+                        if index == 0 && matches!(opcode.opcode, Opcode::LOGD | Opcode::LOGR) {
+                            buf.push(self.log_level(operand)?);
+                            continue;
+                        }
+
+                        // other single byte literals, such as the kind of object to create
+                        if opcode.opcode.operand_kinds()[index] == OperandKind::Byte {
+                            buf.push(self.byte(opcode.opcode, operand)?);
+                            continue;
+                        }
+
+                        // branches hold the distance to their target rather than its address
+                        if opcode.opcode.operand_kinds()[index] == OperandKind::Offset {
+                            let pc = self.code_start + self.code_section.len() as u32;
+                            let offset = self.branch_offset(opcode.opcode, operand, pc)?;
+                            buf.extend_from_slice(&offset.to_be_bytes());
+                            continue;
+                        }
+
                         match operand {
                             Operand::Register(reg) => buf.push(*reg),
-                            Operand::Value(value) => {
-                                buf.extend_from_slice(&(*value as u16).to_be_bytes())
-                            }
-                            Operand::Label(label) => match self.symbols.get_symbol(label) {
-                                None => return Err(AssemblerError::IncorrectOperand),
+                            Operand::Value(value) => buf.extend_from_slice(
+                                &Self::immediate(opcode.opcode, *value)?.to_be_bytes(),
+                            ),
+                            Operand::Label(label) => match self.symbols.get_symbol(&label.name) {
+                                None => {
+                                    return Err(AssemblerError::UndefinedSymbol {
+                                        name: label.name.clone(),
+                                        location: Some(label.span.location),
+                                    })
+                                }
                                 Some(symbol) => {
                                     let offset = symbol.offset as u16 + PIE_HEADER_LENGTH as u16;
 
                                     buf.extend_from_slice(&offset.to_be_bytes())
                                 }
                             },
+                            Operand::Constant(name) => {
+                                let value = self.constant_value(name)?;
+
+                                buf.extend_from_slice(
+                                    &Self::immediate(opcode.opcode, value)?.to_be_bytes(),
+                                )
+                            }
+                            // literals are moved into the pool before assembling, and only
+                            // .incbin holds bytes
+                            Operand::Literal(_) | Operand::Bytes(_) => {
+                                return Err(AssemblerError::IncorrectOperand)
+                            }
                             Operand::String(string) => {
                                 // if more than two bytes, we can't use it
                                 if string.len() > 2 {
@@ -180,9 +723,10 @@ impl Assembler {
                                 bytes.resize(2, 0);
                                 bytes.reverse();
 
-                                dbg!(&bytes);
-
-                                buf.extend_from_slice(&bytes);
+                                let value = u16::from_be_bytes([bytes[0], bytes[1]]) as i32;
+                                buf.extend_from_slice(
+                                    &Self::immediate(opcode.opcode, value)?.to_be_bytes(),
+                                );
                             }
                         }
                     }
@@ -200,6 +744,10 @@ impl Assembler {
                 }
             }
         }
+
+        let len = self.rodata_section.len() + self.code_padding as usize;
+        self.rodata_section.resize(len, 0);
+
         Ok(())
     }
 
@@ -213,11 +761,51 @@ impl Assembler {
 
             return Ok(());
         }
+        // bss is laid out by the first pass and isn't written to the program
+        if self.current_section == Some(AssemblerSection::Bss) {
+            return Ok(());
+        }
 
         match directive.directive {
-            Directive::Align => {
-                if let Some(&Operand::Value(value)) = directive.operands.first() {
-                    self.next_alignment = Some(value as usize);
+            Directive::Align => match self.code_alignment(directive) {
+                Some(alignment) => {
+                    // zeroes first, in case data left the section part way through an instruction
+                    let len = self.code_section.len();
+                    self.code_section.resize(len.next_multiple_of(4), 0);
+                    let len = self.code_section.len();
+                    let padding = len.next_multiple_of(alignment as usize) - len;
+                    self.code_section
+                        .extend([Opcode::NOP as u8, 0, 0, 0].repeat(padding / 4));
+                }
+                None => {
+                    if let Some(&Operand::Value(value)) = directive.operands.first() {
+                        self.next_alignment = Some(value as usize);
+                    }
+                }
+            },
+            Directive::PadTo | Directive::Nopfill => {
+                let directive = self.resolve_labels(directive)?;
+                self.next_alignment = None;
+
+                let code = self.current_section == Some(AssemblerSection::Code);
+                let offset = match self.current_section {
+                    Some(AssemblerSection::Data) => self.data_section.len() as u32,
+                    Some(AssemblerSection::Rodata) => self.rodata_section.len() as u32,
+                    Some(AssemblerSection::Code) => self.code_section.len() as u32,
+                    _ => return Err(AssemblerError::NoSegmentDeclarationFound),
+                };
+                let len = (self.padded_offset(&directive, offset)? - offset) as usize;
+
+                let section = match self.current_section {
+                    Some(AssemblerSection::Data) => &mut self.data_section,
+                    Some(AssemblerSection::Rodata) => &mut self.rodata_section,
+                    _ => &mut self.code_section,
+                };
+
+                // data is only padded with NOPs if asked for, since it isn't run
+                match code || directive.directive == Directive::Nopfill {
+                    true => section.extend([Opcode::NOP as u8, 0, 0, 0].repeat(len / 4)),
+                    false => section.resize(section.len() + len, 0),
                 }
             }
             Directive::Ascii
@@ -225,14 +813,39 @@ impl Assembler {
             | Directive::Byte
             | Directive::Half
             | Directive::Word
-            | Directive::Space => {
-                let bytes = directive.aligned_bytes(self.next_alignment.take());
+            | Directive::Space
+            | Directive::Fill
+            | Directive::Matrix
+            | Directive::Incbin => {
+                // words can hold label addresses, so resolve them first
+                let directive = self.resolve_labels(directive)?;
+                let bytes = directive.aligned_bytes(self.next_alignment.take(), self.word_bytes());
 
                 match (&self.current_section, bytes) {
                     (Some(AssemblerSection::Data), Some(bytes)) => {
                         self.data_section.extend_from_slice(&bytes)
                     }
+                    (Some(AssemblerSection::Rodata), Some(bytes)) => {
+                        self.rodata_section.extend_from_slice(&bytes)
+                    }
                     (Some(AssemblerSection::Code), Some(bytes)) => {
+                        // anything past the directive's own bytes is zero padding, which would
+                        // halt the program if it ran into it
+                        let padding = match directive.directive {
+                            Directive::Space => bytes.len(),
+                            _ => bytes
+                                .len()
+                                .saturating_sub(directive.size(Some(1), self.word_bytes())),
+                        };
+                        if padding > 0 {
+                            let end =
+                                self.code_start + (self.code_section.len() + bytes.len()) as u32;
+                            self.warnings.push(AssemblerWarning::ZeroPadding {
+                                address: end - padding as u32,
+                                len: padding,
+                            });
+                        }
+
                         self.code_section.extend_from_slice(&bytes)
                     }
                     _ => return Err(AssemblerError::NoSegmentDeclarationFound),
@@ -245,19 +858,211 @@ impl Assembler {
         Ok(())
     }
 
+    /// Replaces label operands with the address they resolve to, and constants with their value
+    fn resolve_labels(
+        &self,
+        directive: &DirectiveInstruction,
+    ) -> Result<DirectiveInstruction, AssemblerError> {
+        let mut directive = directive.clone();
+        directive.operands = self.resolve_constants(&directive.operands)?;
+        for operand in &mut directive.operands {
+            if let Operand::Label(label) = operand {
+                let symbol = self.symbols.get_symbol(&label.name).ok_or_else(|| {
+                    AssemblerError::UndefinedSymbol {
+                        name: label.name.clone(),
+                        location: Some(label.span.location),
+                    }
+                })?;
+
+                *operand = Operand::Value((symbol.offset as usize + PIE_HEADER_LENGTH) as i32);
+            }
+        }
+
+        Ok(directive)
+    }
+
+    /// Replaces constant operands with their value
+    fn resolve_constants(&self, operands: &[Operand]) -> Result<Vec<Operand>, AssemblerError> {
+        operands
+            .iter()
+            .map(|operand| match operand {
+                Operand::Constant(name) => Ok(Operand::Value(self.constant_value(name)?)),
+                operand => Ok(operand.clone()),
+            })
+            .collect()
+    }
+
+    /// Looks up the value of a constant, which must have been defined with `.equ`
+    fn constant_value(&self, name: &Label) -> Result<i32, AssemblerError> {
+        match self.symbols.get_symbol(&name.name) {
+            Some(symbol) => symbol.constant().ok_or(AssemblerError::IncorrectOperand),
+            None => Err(AssemblerError::UndefinedSymbol {
+                name: name.name.clone(),
+                location: Some(name.span.location),
+            }),
+        }
+    }
+
+    /// Checks an instruction has as many operands as its opcode takes, and each is of a kind that
+    /// can be encoded as the opcode expects
+    fn check_operands(instruction: &OpcodeInstruction) -> Result<(), AssemblerError> {
+        let opcode = instruction.opcode;
+        let kinds = opcode.operand_kinds();
+        if instruction.operands.len() != kinds.len() {
+            return Err(AssemblerError::OperandCount {
+                opcode,
+                expected: kinds.len(),
+                found: instruction.operands.len(),
+            });
+        }
+
+        for (index, (operand, &expected)) in instruction.operands.iter().zip(kinds).enumerate() {
+            let matches = match expected {
+                OperandKind::Register => matches!(operand, Operand::Register(_)),
+                OperandKind::Byte => matches!(operand, Operand::Value(_) | Operand::Constant(_)),
+                OperandKind::Value => !matches!(operand, Operand::Register(_)),
+                OperandKind::Address | OperandKind::Offset => matches!(
+                    operand,
+                    Operand::Value(_) | Operand::Label(_) | Operand::Constant(_)
+                ),
+            };
+
+            if !matches {
+                return Err(AssemblerError::OperandMismatch {
+                    opcode,
+                    position: index + 1,
+                    expected,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks a value fits in an opcode's immediate operand, which the VM zero extends to a word
+    fn immediate(opcode: Opcode, value: i32) -> Result<u16, AssemblerError> {
+        let max = match opcode {
+            Opcode::LDBI => u8::MAX as i32,
+            _ => u16::MAX as i32,
+        };
+
+        match (0..=max).contains(&value) {
+            true => Ok(value as u16),
+            false => Err(AssemblerError::ImmediateOutOfRange { opcode, value, max }),
+        }
+    }
+
+    /// Resolves the operand of a branch at pc to the signed distance from pc to its target
+    fn branch_offset(
+        &self,
+        opcode: Opcode,
+        operand: &Operand,
+        pc: u32,
+    ) -> Result<i16, AssemblerError> {
+        let offset = match operand {
+            Operand::Value(offset) => *offset,
+            Operand::Constant(name) => self.constant_value(name)?,
+            Operand::Label(label) => {
+                let target = self.label_address(&label.name).ok_or_else(|| {
+                    AssemblerError::UndefinedSymbol {
+                        name: label.name.clone(),
+                        location: Some(label.span.location),
+                    }
+                })?;
+
+                target as i32 - pc as i32
+            }
+            _ => return Err(AssemblerError::IncorrectOperand),
+        };
+
+        i16::try_from(offset).map_err(|_| AssemblerError::BranchOutOfRange { opcode, offset })
+    }
+
+    /// Resolves the level operand of a log instruction, from 0 (error) to 4 (trace)
+    fn log_level(&self, operand: &Operand) -> Result<u8, AssemblerError> {
+        let level = match operand {
+            Operand::Value(value) => *value,
+            Operand::Constant(name) => self.constant_value(name)?,
+            _ => return Err(AssemblerError::IncorrectOperand),
+        };
+
+        u8::try_from(level)
+            .ok()
+            .filter(|&level| level <= 4)
+            .ok_or(AssemblerError::IncorrectOperand)
+    }
+
+    /// Resolves a single byte literal operand
+    fn byte(&self, opcode: Opcode, operand: &Operand) -> Result<u8, AssemblerError> {
+        let value = match operand {
+            Operand::Value(value) => *value,
+            Operand::Constant(name) => self.constant_value(name)?,
+            _ => return Err(AssemblerError::IncorrectOperand),
+        };
+
+        u8::try_from(value).map_err(|_| AssemblerError::ImmediateOutOfRange {
+            opcode,
+            value,
+            max: u8::MAX as i32,
+        })
+    }
+
     /// Creates 64 byte header
-    fn create_header(&self) -> Vec<u8> {
+    fn create_header(
+        &self,
+        symbol_section_len: usize,
+        line_section_len: usize,
+        entry_offset: u32,
+    ) -> Vec<u8> {
         let mut out = Vec::with_capacity(PIE_HEADER_LENGTH);
 
         out.extend_from_slice(&PIE_HEADER_PREFIX);
-        out.extend_from_slice(&[0, 0, 0, 0]);
+        out.extend_from_slice(&[PIE_FORMAT_VERSION, self.word_bytes() as u8, 0, 0]);
+
+        let rodata_start = PIE_HEADER_LENGTH + self.data_section.len();
+        let code_start = rodata_start + self.rodata_section.len();
 
         out.extend_from_slice(&64u32.to_be_bytes());
         out.extend_from_slice(&(self.data_section.len() as u32).to_be_bytes());
 
-        out.extend_from_slice(&(64 + self.data_section.len() as u32).to_be_bytes());
+        out.extend_from_slice(&(code_start as u32).to_be_bytes());
         out.extend_from_slice(&(self.code_section.len() as u32).to_be_bytes());
 
+        debug_assert_eq!(out.len(), SYMBOL_SECTION_FIELD);
+        let symbol_section_start = match symbol_section_len {
+            0 => 0,
+            _ => code_start + self.code_section.len(),
+        };
+        out.extend_from_slice(&(symbol_section_start as u32).to_be_bytes());
+        out.extend_from_slice(&(symbol_section_len as u32).to_be_bytes());
+
+        debug_assert_eq!(out.len(), ENTRY_POINT_FIELD);
+        out.extend_from_slice(&entry_offset.to_be_bytes());
+
+        debug_assert_eq!(out.len(), RODATA_SECTION_FIELD);
+        let rodata_start = match self.rodata_section.len() {
+            0 => 0,
+            _ => rodata_start,
+        };
+        out.extend_from_slice(&(rodata_start as u32).to_be_bytes());
+        out.extend_from_slice(&(self.rodata_section.len() as u32).to_be_bytes());
+
+        debug_assert_eq!(out.len(), BSS_SECTION_FIELD);
+        let bss_start = match self.bss_len {
+            0 => 0,
+            _ => self.bss_start,
+        };
+        out.extend_from_slice(&bss_start.to_be_bytes());
+        out.extend_from_slice(&self.bss_len.to_be_bytes());
+
+        debug_assert_eq!(out.len(), LINE_SECTION_FIELD);
+        let line_section_start = match line_section_len {
+            0 => 0,
+            _ => code_start + self.code_section.len() + symbol_section_len,
+        };
+        out.extend_from_slice(&(line_section_start as u32).to_be_bytes());
+        out.extend_from_slice(&(line_section_len as u32).to_be_bytes());
+
         // then pad to final length
         if out.len() < PIE_HEADER_LENGTH {
             out.resize(PIE_HEADER_LENGTH, 0);
@@ -267,6 +1072,30 @@ impl Assembler {
     }
 }
 
+/// Builds a line table from the offset into the code section and source line of each instruction
+fn line_table(code_start: u32, sources: Vec<(u32, &SourceLine)>) -> LineTable {
+    let mut table = LineTable::default();
+
+    for (offset, source) in sources {
+        let name = source.file.as_deref().unwrap_or_default();
+        let file = match table.files.iter().position(|file| file == name) {
+            Some(index) => index,
+            None => {
+                table.files.push(name.to_owned());
+                table.files.len() - 1
+            }
+        };
+
+        table.entries.push(LineEntry {
+            address: code_start + offset,
+            file: file as u16,
+            line: source.line as u32,
+        });
+    }
+
+    table
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -283,7 +1112,7 @@ mod tests {
                                     addi $5,1
                                     jmpi @loop"#;
         let expected_header = [
-            69, 80, 73, 69, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 12, 0, 0, 0, 76, 0, 0, 0, 12, 0, 0,
+            69, 80, 73, 69, 1, 4, 0, 0, 0, 0, 0, 64, 0, 0, 0, 12, 0, 0, 0, 76, 0, 0, 0, 12, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0,
         ];
@@ -292,12 +1121,509 @@ mod tests {
 
         let expected: Vec<u8> = expected_header
             .into_iter()
-            .chain(expected_data.into_iter())
-            .chain(expected_code.into_iter())
+            .chain(expected_data)
+            .chain(expected_code)
             .collect();
 
         let program = asm.assemble(program).unwrap();
         assert_eq!(program, expected);
+
+        assert_eq!(asm.label_address("world"), Some(68));
+        assert_eq!(asm.label_address("loop"), Some(80));
+        assert_eq!(asm.label_address("missing"), None);
+        assert_eq!(asm.labels().count(), 3);
+    }
+
+    #[test]
+    fn test_forward_labels() {
+        let mut asm = Assembler::default();
+        let program = r#".code
+                                    jmpi @end
+                                    prtsd @text
+                                .data
+                                    text: .asciiz 'hi'
+                                .code
+                                    end: ldwd $0, @value
+                                .data
+                                    value: .word 7"#;
+        let program = asm.assemble(program).unwrap();
+
+        // data is laid out first regardless of where it's declared
+        assert_eq!(asm.label_address("text"), Some(64));
+        assert_eq!(asm.label_address("value"), Some(68));
+        assert_eq!(asm.label_address("end"), Some(80));
+        assert_eq!(program[64..72], [104, 105, 0, 0, 0, 0, 0, 7]);
+        assert_eq!(
+            program[72..84],
+            [160, 0, 80, 0, 193, 0, 64, 0, 13, 0, 0, 68]
+        );
+    }
+
+    #[test]
+    fn test_debug_symbols() {
+        let source = ".equ SIZE, 4\n.data\nvalue: .word SIZE\n.code\nmain: ldwd $0, @value\nhlt";
+        let program = Assembler::default().assemble(source).unwrap();
+        assert_eq!(program[24..32], [0; 8]);
+        assert_eq!(program.len(), 76);
+
+        let mut asm = Assembler::default().debug_symbols(true);
+        let program = asm.assemble(source).unwrap();
+        assert_eq!(program[24..32], [0, 0, 0, 76, 0, 0, 0, 34]);
+        assert_eq!(
+            shared::symbols::read_symbols(&program).unwrap(),
+            [
+                ("SIZE", SymbolKind::Constant, 4),
+                ("value", SymbolKind::Data, 64),
+                ("main", SymbolKind::Code, 68),
+            ]
+            .map(|(name, kind, value)| DebugSymbol {
+                name: name.to_owned(),
+                kind,
+                value
+            })
+        );
+    }
+
+    #[test]
+    fn test_constants() {
+        let mut asm = Assembler::default();
+        let program = r#".equ SIZE, 3
+                                .equ LAST, SIZE
+                                .data
+                                    values: .fill SIZE, 1, LAST
+                                .code
+                                    ldbi $0, SIZE
+                                    addi $0, LATER
+                                .equ LATER, 0x1234"#;
+        let program = asm.assemble(program).unwrap();
+
+        assert_eq!(asm.constant("SIZE"), Some(3));
+        assert_eq!(asm.label_address("SIZE"), None);
+        assert_eq!(asm.labels().count(), 1);
+        let mut constants: Vec<_> = asm.constants().collect();
+        constants.sort();
+        assert_eq!(constants, [("LAST", 3), ("LATER", 0x1234), ("SIZE", 3)]);
+        assert_eq!(program[64..68], [3, 3, 3, 0]);
+        assert_eq!(program[68..76], [4, 0, 0, 3, 64, 0, 0x12, 0x34]);
+
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".code\nldbi $0, SIZE"),
+            Err(AssemblerError::UndefinedSymbol { name, .. }) if name == "SIZE"
+        ));
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".equ A, 1\n.equ A, 2"),
+            Err(AssemblerError::SymbolAlreadyDeclared { .. })
+        ));
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".code\nloop: ldbi $0, loop"),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+
+    #[test]
+    fn test_include() {
+        let files = [(
+            PathBuf::from("lib/data.asm"),
+            ".data\nvalue: .word 7".to_owned(),
+        )];
+        let mut asm = Assembler::default().source_path("main.asm").loader(
+            files
+                .into_iter()
+                .collect::<std::collections::HashMap<_, _>>(),
+        );
+
+        let program = asm
+            .assemble(".include 'lib/data.asm'\n.code\nldwd $0, @value")
+            .unwrap();
+        assert_eq!(program[64..72], [0, 0, 0, 7, 13, 0, 0, 64]);
+    }
+
+    #[test]
+    fn test_incbin() {
+        let files = [(
+            PathBuf::from("assets/sprite.bin"),
+            "\x01\x02\x03\x04\x05".to_owned(),
+        )];
+        let mut asm = Assembler::default().source_path("main.asm").loader(
+            files
+                .into_iter()
+                .collect::<std::collections::HashMap<_, _>>(),
+        );
+
+        let program = asm
+            .assemble(".data\nsprite: .incbin 'assets/sprite.bin'\nafter: .byte 9\n.code\nhlt")
+            .unwrap();
+        assert_eq!(program[64..73], [1, 2, 3, 4, 5, 0, 0, 0, 9]);
+        assert_eq!(
+            asm.label_address("after"),
+            Some(asm.label_address("sprite").unwrap() + 8)
+        );
+    }
+
+    #[test]
+    fn test_line_table() {
+        let files = [(PathBuf::from("lib/io.asm"), ".code\nprint: ret".to_owned())];
+        let mut asm = Assembler::default().source_path("main.asm").loader(
+            files
+                .into_iter()
+                .collect::<std::collections::HashMap<_, _>>(),
+        );
+
+        asm.assemble(
+            ".include 'lib/io.asm'\n.code\nstart:\n    inc $0\n.line 40, \"gen.c\"\nhlt\nnop",
+        )
+        .unwrap();
+        let lines = asm.line_table();
+        assert_eq!(lines.lookup(64), Some(("lib/io.asm", 2)));
+        assert_eq!(lines.lookup(68), Some(("main.asm", 4)));
+        assert_eq!(lines.lookup(72), Some(("gen.c", 40)));
+        assert_eq!(lines.lookup(76), Some(("gen.c", 40)));
+
+        // the section is only written with debug symbols
+        let program = Assembler::default().assemble(".code\nhlt").unwrap();
+        assert_eq!(program[52..60], [0; 8]);
+        let program = Assembler::default()
+            .debug_symbols(true)
+            .assemble(".code\n\nhlt")
+            .unwrap();
+        let lines = shared::lines::read_lines(&program).unwrap();
+        assert_eq!(lines.lookup(64), Some(("", 3)));
+    }
+
+    #[test]
+    fn test_branch_offsets() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(".code\nstart: nop\nbra @start\nbre @end\nend: hlt")
+            .unwrap();
+        assert_eq!(program[68..76], [0xE0, 0xFF, 0xFC, 0, 0xE4, 0, 4, 0]);
+
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".code\nbra 40000"),
+            Err(AssemblerError::BranchOutOfRange {
+                opcode: Opcode::BRA,
+                offset: 40000
+            })
+        ));
+    }
+
+    #[test]
+    fn test_error_locations() {
+        let mut asm = Assembler::default();
+        let error = asm.assemble(".code\nloop: hlt\n    loop: hlt").unwrap_err();
+        assert_eq!(error.to_string(), "3:5: symbol loop already declared");
+
+        // a copy could otherwise be stripped as unused before the symbol table sees both
+        let mut asm = Assembler::default().strip_unused(true);
+        let error = asm.assemble(".code\njmpi @x\nx: hlt\nx: hlt").unwrap_err();
+        assert_eq!(error.to_string(), "4:1: symbol x already declared");
+        let mut asm = Assembler::default().strip_unused(true);
+        let error = asm.assemble(".code\n.rept 2\nx: hlt\n.endr").unwrap_err();
+        assert!(matches!(error, AssemblerError::SymbolAlreadyDeclared { name, .. } if name == "x"));
+
+        let mut asm = Assembler::default();
+        let error = asm.assemble(".code\n  jmpi @missing").unwrap_err();
+        assert_eq!(error.to_string(), "2:9: symbol missing is not declared");
+
+        // every undefined symbol is reported, once each
+        let mut asm = Assembler::default();
+        let error = asm
+            .assemble(".code\n  jmpi @a\n  calli @b\n  jmpi @a\n  ldbi $0, C")
+            .unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "symbols are not declared: a (2:9), b (3:10), C (5:12)"
+        );
+
+        let mut asm = Assembler::default();
+        let error = asm.assemble(".code\n  jmpi @loop\n  !hlt").unwrap_err();
+        assert_eq!(error.to_string(), "3:3: failed to parse '!hlt'");
+    }
+
+    #[test]
+    fn test_literal_pool() {
+        let mut asm = Assembler::default();
+        let program = r#".code
+                                    ldwd $0, =0x12345678
+                                    ldwd $1, =@end
+                                    end: hlt"#;
+        let program = asm.assemble(program).unwrap();
+
+        // pool is placed in a new data section, before the code
+        assert_eq!(program[64..72], [0x12, 0x34, 0x56, 0x78, 0, 0, 0, 80]);
+        assert_eq!(program[72..80], [13, 0, 0, 64, 13, 1, 0, 68]);
+    }
+
+    #[test]
+    fn test_log_levels() {
+        let mut asm = Assembler::default();
+        let program = r#".equ WARN, 1
+                                .data
+                                    message: .asciiz 'hi'
+                                .code
+                                    logd WARN, @message
+                                    logr 4, $2"#;
+        let program = asm.assemble(program).unwrap();
+        assert_eq!(program[68..76], [0xC5, 1, 0, 64, 0xC6, 4, 2, 0]);
+
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".code\nlogr 5, $0"),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+
+    #[test]
+    fn test_register_pairs() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(
+                ".data\nvalue: .word 0, 1\n.code\nld64d $2, @value\nadd64 $2, $4\nst64r $2, $0",
+            )
+            .unwrap();
+        assert_eq!(
+            program[72..84],
+            [0x31, 2, 0, 64, 0x52, 2, 4, 0, 0x36, 2, 0, 0]
+        );
+    }
+
+    #[test]
+    fn test_original_spellings() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(".code\nloop: inc $5\ndec $6\nloadm $1, $2\nsetm $1, $2\ndjmp @loop")
+            .unwrap();
+        assert_eq!(
+            program[64..],
+            [0x5A, 5, 0, 0, 0x5E, 6, 0, 0, 0x0E, 1, 2, 0, 0x1A, 1, 2, 0, 0xA0, 0, 64, 0]
+        );
+    }
+
+    #[test]
+    fn test_block_opcodes() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(".code\nmemcpy $1, $2, $3\nmemset $1, $0, $3\nstrcmp $4, $5, $6")
+            .unwrap();
+        assert_eq!(program[64..], [0x3A, 1, 2, 3, 0x3E, 1, 0, 3, 0x9A, 4, 5, 6]);
+    }
+
+    #[test]
+    fn test_escapes() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(".data\n.asciiz 'a\\tb\\n'\n.code\nldbi $0, '\\n'\ngti $1, 'z'")
+            .unwrap();
+        assert_eq!(program[64..70], [b'a', b'\t', b'b', b'\n', 0, 0]);
+        assert_eq!(program[74..76], [0, b'\n']);
+        assert_eq!(program[78..80], [0, b'z']);
+    }
+
+    #[test]
+    fn test_immediate_range() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(".code\nldbi $0, 255\naddi $0, 0xFFFF\nldhi $1, =0x12345")
+            .unwrap();
+        assert_eq!(
+            program[68..80],
+            [4, 0, 0, 255, 64, 0, 255, 255, 13, 1, 0, 64]
+        );
+
+        for source in [
+            "ldbi $0, -1",
+            "ldbi $0, 256",
+            "ldbi $0, 'ab'",
+            "addi $0, 65536",
+        ] {
+            assert!(matches!(
+                asm.assemble(&format!(".code\n{source}")),
+                Err(AssemblerError::ImmediateOutOfRange { .. })
+            ));
+        }
+        assert!(matches!(
+            asm.assemble(".code\n.equ BIG, 70000\nsubi $0, BIG"),
+            Err(AssemblerError::ImmediateOutOfRange {
+                opcode: Opcode::SUBI,
+                value: 70000,
+                max: 0xFFFF
+            })
+        ));
+    }
+
+    #[test]
+    fn test_operand_signatures() {
+        let mut asm = Assembler::default();
+        let mut assemble = |source: &str| asm.assemble(&format!(".code\n{source}"));
+
+        assert!(matches!(
+            assemble("addr $1, $2"),
+            Err(AssemblerError::OperandCount {
+                opcode: Opcode::ADDR,
+                expected: 3,
+                found: 2
+            })
+        ));
+        assert!(matches!(
+            assemble("hlt $0"),
+            Err(AssemblerError::OperandCount { found: 1, .. })
+        ));
+        assert!(matches!(
+            assemble("addr $1, $2, 'a'"),
+            Err(AssemblerError::OperandMismatch {
+                opcode: Opcode::ADDR,
+                position: 3,
+                expected: OperandKind::Register
+            })
+        ));
+        assert!(matches!(
+            assemble("jmpi $0"),
+            Err(AssemblerError::OperandMismatch {
+                expected: OperandKind::Address,
+                ..
+            })
+        ));
+        assert!(matches!(
+            assemble("logd 'hi', 64"),
+            Err(AssemblerError::OperandMismatch { position: 1, .. })
+        ));
+
+        assert!(assemble("loop: addi $1, 'a'\njmpi @loop").is_ok());
+    }
+
+    #[test]
+    fn test_generic_mnemonics() {
+        let data = ".data\nvalue: .word 7\n.code\n";
+        let generic = Assembler::default()
+            .assemble(&format!(
+                "{data}ld.b $0, 10\nld.b $0, @value\nld.b $0, $1\nld.w $2, =0x12345\n\
+                 st.w $2, @value\nst.h $2, $1\nld.64 $4, $1\nst.64 $4, @value"
+            ))
+            .unwrap();
+        let explicit = Assembler::default()
+            .assemble(&format!(
+                "{data}ldbi $0, 10\nldbd $0, @value\nldbr $0, $1\nldwd $2, =0x12345\n\
+                 strwi $2, @value\nstrhr $2, $1\nld64r $4, $1\nst64i $4, @value"
+            ))
+            .unwrap();
+        assert_eq!(generic, explicit);
+
+        assert!(matches!(
+            Assembler::default().assemble(".code\nld.w $0, 10"),
+            Err(AssemblerError::NoAddressingMode { mnemonic, operand: "a value" })
+                if mnemonic == "ld.w"
+        ));
+        assert!(matches!(
+            Assembler::default().assemble(".code\nld.b $0"),
+            Err(AssemblerError::OperandCount {
+                opcode: Opcode::LDBR,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn test_entry_point() {
+        let mut asm = Assembler::default();
+        let program = asm
+            .assemble(".data\n.word 0\n.code\nhelper: ret\nmain: calli @helper\n.entry @main")
+            .unwrap();
+        assert_eq!(
+            program[ENTRY_POINT_FIELD..ENTRY_POINT_FIELD + 4],
+            [0, 0, 0, 4]
+        );
+
+        let program = asm.assemble(".code\nhlt").unwrap();
+        assert_eq!(program[ENTRY_POINT_FIELD..ENTRY_POINT_FIELD + 4], [0; 4]);
+
+        assert!(matches!(
+            asm.assemble(".data\nvalue: .word 0\n.code\nhlt\n.entry @value"),
+            Err(AssemblerError::EntryNotInCode { name }) if name == "value"
+        ));
+        assert!(matches!(
+            asm.assemble(".code\na: hlt\n.entry @a\n.entry @a"),
+            Err(AssemblerError::EntryAlreadyDeclared { location }) if location.line == 4
+        ));
+    }
+
+    #[test]
+    fn test_rodata() {
+        let mut asm = Assembler::default();
+        let program = r#".rodata
+                                    message: .asciiz 'hi'
+                                .data
+                                    count: .word 1
+                                .code
+                                    prtsd @message
+                                    ldwd $0, @count"#;
+        let program = asm.assemble(program).unwrap();
+
+        assert_eq!(
+            program[8..24],
+            [0, 0, 0, 64, 0, 0, 0, 4, 0, 0, 0, 72, 0, 0, 0, 8]
+        );
+        assert_eq!(
+            program[RODATA_SECTION_FIELD..RODATA_SECTION_FIELD + 8],
+            [0, 0, 0, 68, 0, 0, 0, 4]
+        );
+        assert_eq!(program[64..72], [0, 0, 0, 1, b'h', b'i', 0, 0]);
+        assert_eq!(program[72..80], [0xC1, 0, 68, 0, 0x0D, 0, 0, 64]);
+        assert_eq!(asm.label_address("message"), Some(68));
+
+        // programs without read-only data leave its fields zeroed
+        let program = Assembler::default().assemble(".code\nhlt").unwrap();
+        assert_eq!(
+            program[RODATA_SECTION_FIELD..RODATA_SECTION_FIELD + 8],
+            [0; 8]
+        );
+    }
+
+    #[test]
+    fn test_bss() {
+        let mut asm = Assembler::default();
+        let program = r#".bss
+                                    buffer: .space 16
+                                    .align 1
+                                    flag: .space 1
+                                .code
+                                    ldwd $0, @buffer
+                                    hlt"#;
+        let program = asm.assemble(program).unwrap();
+
+        // nothing is written after the code, and the bss is addressed from the end of the program
+        assert_eq!(program.len(), 72);
+        assert_eq!(
+            program[BSS_SECTION_FIELD..BSS_SECTION_FIELD + 8],
+            [0, 0, 0, 72, 0, 0, 0, 17]
+        );
+        assert_eq!(program[64..68], [0x0D, 0, 0, 72]);
+        assert_eq!(asm.label_address("flag"), Some(88));
+
+        // the symbol and line sections come before the bss
+        let mut asm = Assembler::default().debug_symbols(true);
+        let program = asm.assemble(".bss\nbuffer: .space 4\n.code\nhlt").unwrap();
+        assert_eq!(program.len(), 64 + 4 + 13 + 14);
+        assert_eq!(asm.label_address("buffer"), Some(96));
+        assert_eq!(
+            program[BSS_SECTION_FIELD..BSS_SECTION_FIELD + 8],
+            [0, 0, 0, 96, 0, 0, 0, 4]
+        );
+
+        // only space can be reserved
+        assert!(matches!(
+            Assembler::default().assemble(".bss\nvalue: .word 1"),
+            Err(AssemblerError::InitializedBss {
+                directive: Directive::Word
+            })
+        ));
+
+        // programs without bss leave its fields zeroed
+        let program = Assembler::default().assemble(".code\nhlt").unwrap();
+        assert_eq!(program[BSS_SECTION_FIELD..BSS_SECTION_FIELD + 8], [0; 8]);
     }
 
     #[test]
@@ -311,16 +1637,13 @@ mod tests {
                                     c: .ascii 'ab'
                                 .code"#;
         let expected_header = [
-            69, 80, 73, 69, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 14, 0, 0, 0, 78, 0, 0, 0, 0, 0, 0, 0,
+            69, 80, 73, 69, 1, 4, 0, 0, 0, 0, 0, 64, 0, 0, 0, 14, 0, 0, 0, 78, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0,
         ];
         let expected_data = [97, 0, 0, 0, 0, 0, 0, 0, 97, 0, 97, 98, 0, 0];
 
-        let expected: Vec<u8> = expected_header
-            .into_iter()
-            .chain(expected_data.into_iter())
-            .collect();
+        let expected: Vec<u8> = expected_header.into_iter().chain(expected_data).collect();
 
         let program = asm.assemble(program).unwrap();
         assert_eq!(program, expected);
@@ -336,16 +1659,13 @@ mod tests {
                                 .code"#;
 
         let expected_header = [
-            69, 80, 73, 69, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 10, 0, 0, 0, 74, 0, 0, 0, 0, 0, 0, 0,
+            69, 80, 73, 69, 1, 4, 0, 0, 0, 0, 0, 64, 0, 0, 0, 10, 0, 0, 0, 74, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0,
         ];
         let expected_data = [1, 2, 3, 4, 5, 0, 0, 0, 1, 0];
 
-        let expected: Vec<u8> = expected_header
-            .into_iter()
-            .chain(expected_data.into_iter())
-            .collect();
+        let expected: Vec<u8> = expected_header.into_iter().chain(expected_data).collect();
 
         let program = asm.assemble(program).unwrap();
         assert_eq!(program, expected);
@@ -360,16 +1680,13 @@ mod tests {
                                     b: .half 256
                                 .code"#;
         let expected_header = [
-            69, 80, 73, 69, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 10, 0, 0, 0, 74, 0, 0, 0, 0, 0, 0, 0,
+            69, 80, 73, 69, 1, 4, 0, 0, 0, 0, 0, 64, 0, 0, 0, 10, 0, 0, 0, 74, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0,
         ];
         let expected_data = [0, 100, 0, 200, 1, 44, 0, 0, 1, 0];
 
-        let expected: Vec<u8> = expected_header
-            .into_iter()
-            .chain(expected_data.into_iter())
-            .collect();
+        let expected: Vec<u8> = expected_header.into_iter().chain(expected_data).collect();
 
         let program = asm.assemble(program).unwrap();
         assert_eq!(program, expected);
@@ -384,7 +1701,7 @@ mod tests {
                                     b: .word 2147483647
                                 .code"#;
         let expected_header = [
-            69, 80, 73, 69, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 16, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 0,
+            69, 80, 73, 69, 1, 4, 0, 0, 0, 0, 0, 64, 0, 0, 0, 16, 0, 0, 0, 80, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0,
         ];
@@ -392,15 +1709,60 @@ mod tests {
             128, 0, 0, 0, 127, 255, 255, 255, 127, 255, 255, 255, 0, 0, 0, 0,
         ];
 
-        let expected: Vec<u8> = expected_header
-            .into_iter()
-            .chain(expected_data.into_iter())
-            .collect();
+        let expected: Vec<u8> = expected_header.into_iter().chain(expected_data).collect();
 
         let program = asm.assemble(program).unwrap();
         assert_eq!(program, expected);
     }
 
+    #[test]
+    fn test_word_size() {
+        let program = ".data\n a: .word -2, 7\n .byte 1\n b: .word 3\n.code";
+        let mut asm = Assembler::default().word_size(8);
+        let program = asm.assemble(program).unwrap();
+
+        // words are 8 bytes and aligned to 8 bytes, sign extended
+        assert_eq!(program[5], 8);
+        assert_eq!(asm.label_address("b"), Some(PIE_HEADER_LENGTH as u32 + 24));
+        let mut expected = [0xFF; 8].to_vec();
+        expected[7] = 0xFE;
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 7, 1, 0, 0, 0, 0, 0, 0, 0]);
+        expected.extend([0, 0, 0, 0, 0, 0, 0, 3]);
+        assert_eq!(program[PIE_HEADER_LENGTH..], expected);
+
+        assert!(matches!(
+            Assembler::default().word_size(2).assemble(".code\nhlt"),
+            Err(AssemblerError::UnsupportedWordSize { size: 2 })
+        ));
+    }
+
+    #[test]
+    fn test_structured_data() {
+        let mut asm = Assembler::default();
+        let program = r#".data
+                                    row: .fill 3, 1, 7
+                                    grid: .matrix 2, 2, 2, 1, 2, 3
+                                .code"#;
+        let program = asm.assemble(program).unwrap();
+
+        assert_eq!(program[64..76], [7, 7, 7, 0, 0, 1, 0, 2, 0, 3, 0, 0]);
+        assert_eq!(asm.label_address("grid"), Some(68));
+        assert_eq!(
+            asm.label_layout("row"),
+            DataLayout::new(1, vec![3]).as_ref()
+        );
+        assert_eq!(
+            asm.label_layout("grid"),
+            DataLayout::new(2, vec![2, 2]).as_ref()
+        );
+
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".data\na: .fill 2, 3, 0\n.code"),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+
     #[test]
     fn test_space() {
         let mut asm = Assembler::default();
@@ -412,18 +1774,117 @@ mod tests {
                                     b: .byte 1
                                 .code"#;
         let expected_header = [
-            69, 80, 73, 69, 0, 0, 0, 0, 0, 0, 0, 64, 0, 0, 0, 8, 0, 0, 0, 72, 0, 0, 0, 0, 0, 0, 0,
+            69, 80, 73, 69, 1, 4, 0, 0, 0, 0, 0, 64, 0, 0, 0, 8, 0, 0, 0, 72, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
             0, 0, 0, 0, 0, 0, 0, 0,
         ];
         let expected_data = [1, 0, 0, 0, 0, 0, 0, 1];
 
-        let expected: Vec<u8> = expected_header
-            .into_iter()
-            .chain(expected_data.into_iter())
-            .collect();
+        let expected: Vec<u8> = expected_header.into_iter().chain(expected_data).collect();
 
         let program = asm.assemble(program).unwrap();
         assert_eq!(program, expected);
     }
+
+    #[test]
+    fn test_padding() {
+        let mut asm = Assembler::default();
+        let program = r#".data
+                                    .align 1
+                                    a: .byte 1
+                                    .pad_to 3
+                                    .nopfill 1
+                                .code
+                                    nop
+                                    sled: .nopfill 2
+                                    .pad_to 16
+                                    hlt"#;
+
+        let program = asm.assemble(program).unwrap();
+        assert_eq!(program[64..71], [1, 0, 0, 0xF8, 0, 0, 0]);
+        assert_eq!(program[71..87], [0xF8, 0, 0, 0].repeat(4));
+        assert_eq!(program[87..], [0, 0, 0, 0]);
+        assert_eq!(asm.label_address("sled"), Some(75));
+        assert!(asm.warnings().is_empty());
+
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".code\nhlt\nhlt\n.pad_to 4"),
+            Err(AssemblerError::PadBackwards {
+                offset: 8,
+                target: 4
+            })
+        ));
+        let mut asm = Assembler::default();
+        assert!(matches!(
+            asm.assemble(".code\nhlt\n.pad_to 6"),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+
+    #[test]
+    fn test_code_align() {
+        let mut asm = Assembler::default();
+        let program = r#".data
+                                    .align 1
+                                    a: .byte 1
+                                .code
+                                    hlt
+                                    .align 16
+                                    loop: jmpi @loop
+                                    .align 1
+                                    .byte 1
+                                    .align 8
+                                    hlt"#;
+
+        // the code section is moved to a multiple of 16 so the loop is too
+        let program = asm.assemble(program).unwrap();
+        assert_eq!(u32::from_be_bytes(program[16..20].try_into().unwrap()), 80);
+        assert_eq!(program[65..80], [0; 15]);
+        assert_eq!(program[84..96], [0xF8, 0, 0, 0].repeat(3));
+        assert_eq!(asm.label_address("loop"), Some(96));
+        assert_eq!(program[100..104], [1, 0, 0, 0]);
+        assert_eq!(program[104..108], [0; 4]);
+        assert_eq!(program.len(), 108);
+        assert!(asm.warnings().is_empty());
+    }
+
+    #[test]
+    fn test_zero_padding_warning() {
+        let mut asm = Assembler::default();
+        asm.assemble(".data\n.byte 1\n.code\nhlt\n.space 8\n.byte 1\nhlt")
+            .unwrap();
+
+        assert_eq!(
+            asm.warnings(),
+            [
+                AssemblerWarning::ZeroPadding {
+                    address: 72,
+                    len: 8
+                },
+                AssemblerWarning::ZeroPadding {
+                    address: 81,
+                    len: 3
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn test_define() {
+        let source = ".code\n.ifdef DEBUG\nprti $0\n.endif\n.if SIZE\nhlt\n.endif";
+
+        let plain = Assembler::default()
+            .define("SIZE", 0)
+            .assemble(source)
+            .unwrap();
+        assert_eq!(plain.len(), PIE_HEADER_LENGTH);
+
+        let debug = Assembler::default()
+            .define("DEBUG", 1)
+            .define("SIZE", 4)
+            .assemble(source)
+            .unwrap();
+        assert_eq!(debug.len(), PIE_HEADER_LENGTH + 8);
+    }
 }
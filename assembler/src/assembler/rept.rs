@@ -0,0 +1,177 @@
+//! Repeated blocks.
+//!
+//! `.rept n` repeats the instructions up to its `.endr` n times, and blocks can be nested. Blocks
+//! are expanded before anything else is assembled, so sizes and bytes are worked out for each copy
+//! as if it had been written out by hand. A label on `.rept` names the first copy, while labels
+//! declared inside a block are declared once per copy, so only blocks repeated once can declare
+//! them.
+
+use crate::assembler::errors::AssemblerError;
+use crate::parser::directive::Directive;
+use crate::parser::instruction::{AssemblerInstruction, DirectiveInstruction};
+use crate::parser::operand::Operand;
+use crate::parser::Label;
+
+/// A `.rept` block whose `.endr` hasn't been reached
+struct Block {
+    count: usize,
+    /// Label on the `.rept`, which names the first copy
+    label: Option<Label>,
+    body: Vec<AssemblerInstruction>,
+}
+
+/// Replaces each `.rept` block with its instructions repeated
+pub(super) fn expand_repeats(
+    program: &mut Vec<AssemblerInstruction>,
+) -> Result<(), AssemblerError> {
+    // the outermost entry holds the expanded program, and the rest each block being read
+    let mut blocks = vec![Block {
+        count: 1,
+        label: None,
+        body: Vec::with_capacity(program.len()),
+    }];
+
+    for instruction in program.drain(..) {
+        match &instruction {
+            AssemblerInstruction::Directive(directive)
+                if directive.directive == Directive::Rept =>
+            {
+                blocks.push(Block {
+                    count: repeat_count(directive)?,
+                    label: directive.label.clone(),
+                    body: Vec::new(),
+                });
+            }
+            AssemblerInstruction::Directive(directive)
+                if directive.directive == Directive::Endr =>
+            {
+                if blocks.len() == 1 {
+                    return Err(AssemblerError::UnmatchedRepeat);
+                }
+
+                let block = blocks.pop().unwrap();
+                let outer = &mut blocks.last_mut().unwrap().body;
+                let first = outer.len();
+                for _ in 0..block.count {
+                    outer.extend(block.body.iter().cloned());
+                }
+
+                if let Some(label) = block.label {
+                    match outer.get_mut(first) {
+                        Some(AssemblerInstruction::Opcode(instruction))
+                            if instruction.label.is_none() =>
+                        {
+                            instruction.label = Some(label)
+                        }
+                        Some(AssemblerInstruction::Directive(instruction))
+                            if instruction.label.is_none() =>
+                        {
+                            instruction.label = Some(label)
+                        }
+                        _ => {
+                            return Err(AssemblerError::LabelledRepeat {
+                                location: label.span.location,
+                            })
+                        }
+                    }
+                }
+            }
+            _ => blocks.last_mut().unwrap().body.push(instruction),
+        }
+    }
+
+    if blocks.len() > 1 {
+        return Err(AssemblerError::UnterminatedRepeat);
+    }
+    *program = blocks.pop().unwrap().body;
+
+    Ok(())
+}
+
+/// Number of times a `.rept` block is repeated
+fn repeat_count(directive: &DirectiveInstruction) -> Result<usize, AssemblerError> {
+    match directive.operands[..] {
+        [Operand::Value(count)] => {
+            usize::try_from(count).map_err(|_| AssemblerError::IncorrectOperand)
+        }
+        _ => Err(AssemblerError::IncorrectOperand),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    /// Opcodes left after expanding the repeated blocks in source
+    fn expand(source: &str) -> Result<Vec<String>, AssemblerError> {
+        let mut program = Program::parse(source).unwrap().instructions;
+        expand_repeats(&mut program)?;
+
+        Ok(program
+            .iter()
+            .filter_map(|instruction| match instruction {
+                AssemblerInstruction::Opcode(opcode) => Some(format!("{:?}", opcode.opcode)),
+                _ => None,
+            })
+            .collect())
+    }
+
+    #[test]
+    fn test_expand_repeats() {
+        let source = ".code
+            .rept 2
+                inc $0
+                .rept 3
+                    nop
+                .endr
+            .endr
+            .rept 0
+                dec $0
+            .endr
+            hlt";
+
+        assert_eq!(
+            expand(source).unwrap(),
+            ["INC", "NOP", "NOP", "NOP", "INC", "NOP", "NOP", "NOP", "HLT"]
+        );
+    }
+
+    #[test]
+    fn test_labelled_repeat() {
+        let mut program = Program::parse(".data\ntable: .rept 3\n.word 7\n.endr")
+            .unwrap()
+            .instructions;
+        expand_repeats(&mut program).unwrap();
+
+        let labels = program
+            .iter()
+            .map(|instruction| instruction.label().map(|label| label.name.as_str()))
+            .collect::<Vec<_>>();
+        assert_eq!(labels, [None, Some("table"), None, None]);
+    }
+
+    #[test]
+    fn test_unbalanced() {
+        assert!(matches!(
+            expand(".code\n.endr"),
+            Err(AssemblerError::UnmatchedRepeat)
+        ));
+        assert!(matches!(
+            expand(".code\n.rept 2\nnop"),
+            Err(AssemblerError::UnterminatedRepeat)
+        ));
+        assert!(matches!(
+            expand(".code\nloop: .rept 2\nend: nop\n.endr"),
+            Err(AssemblerError::LabelledRepeat { .. })
+        ));
+        assert!(matches!(
+            expand(".code\nloop: .rept 0\nnop\n.endr"),
+            Err(AssemblerError::LabelledRepeat { .. })
+        ));
+        assert!(matches!(
+            expand(".code\n.rept -1\nnop\n.endr"),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+}
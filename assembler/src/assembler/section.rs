@@ -1,22 +1,23 @@
 use crate::parser::directive::Directive;
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy, Default)]
 pub(super) enum AssemblerSection {
     Data,
+    /// Data that can't be written once the program is running
+    Rodata,
+    /// Zeroed space reserved when the program is loaded, which takes up no bytes in the program
+    Bss,
     Code,
+    #[default]
     Unknown,
 }
 
-impl Default for AssemblerSection {
-    fn default() -> Self {
-        Self::Unknown
-    }
-}
-
 impl From<Directive> for AssemblerSection {
     fn from(value: Directive) -> Self {
         match value {
             Directive::Data => Self::Data,
+            Directive::Rodata => Self::Rodata,
+            Directive::Bss => Self::Bss,
             Directive::Code => Self::Code,
             _ => Self::Unknown,
         }
@@ -0,0 +1,224 @@
+//! Register pressure and encoding statistics.
+//!
+//! Routines are found the same way as the calling convention lint. Immediates are value operands
+//! of instructions, which are encoded in 16 bits, so anything close to that is near the limit of
+//! the ISA.
+
+use crate::assembler::lint::routines;
+use crate::parser::instruction::AssemblerInstruction;
+use crate::parser::operand::Operand;
+use shared::abi::REGISTER_COUNT;
+use shared::Opcode;
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::{Display, Formatter};
+
+/// Number of immediates kept, largest first
+const LARGEST_IMMEDIATES: usize = 5;
+
+/// How an instruction finds its operand, from the lower 2 bits of the opcode
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum AddressingMode {
+    /// No operands, such as `HLT` or `RET`
+    Implied,
+    /// Literal value encoded in the instruction
+    Immediate,
+    /// Value read from a memory address encoded in the instruction
+    Direct,
+    /// Value read from a register
+    Register,
+}
+
+impl AddressingMode {
+    fn of(opcode: Opcode, operands: &[Operand]) -> Self {
+        match opcode as u8 & 0b11 {
+            _ if operands.is_empty() => AddressingMode::Implied,
+            0b01 => AddressingMode::Direct,
+            0b10 => AddressingMode::Register,
+            _ => AddressingMode::Immediate,
+        }
+    }
+}
+
+impl Display for AddressingMode {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            AddressingMode::Implied => "implied",
+            AddressingMode::Immediate => "immediate",
+            AddressingMode::Direct => "direct",
+            AddressingMode::Register => "register",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+/// Distinct registers a routine touches
+#[derive(Debug, Clone, PartialEq)]
+pub struct RoutineStats {
+    pub name: String,
+    /// Registers used as operands, in ascending order
+    pub registers: Vec<u8>,
+}
+
+/// Immediate value along with the instruction using it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Immediate {
+    pub opcode: Opcode,
+    pub value: i32,
+}
+
+/// Statistics about how close a program is to the limits of the ISA
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Statistics {
+    /// Routines in source order
+    pub routines: Vec<RoutineStats>,
+    /// Largest immediates by magnitude, largest first
+    pub immediates: Vec<Immediate>,
+    /// Number of instructions using each addressing mode
+    pub addressing_modes: BTreeMap<AddressingMode, usize>,
+}
+
+impl Display for Statistics {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        writeln!(f, "registers per routine:")?;
+        for routine in &self.routines {
+            let registers = routine
+                .registers
+                .iter()
+                .map(|register| format!("${register}"))
+                .collect::<Vec<_>>()
+                .join(" ");
+
+            writeln!(
+                f,
+                "  {}: {}/{REGISTER_COUNT} ({registers})",
+                routine.name,
+                routine.registers.len()
+            )?;
+        }
+
+        writeln!(f, "largest immediates:")?;
+        for immediate in &self.immediates {
+            writeln!(f, "  {:?} {}", immediate.opcode, immediate.value)?;
+        }
+
+        writeln!(f, "addressing modes:")?;
+        for (mode, count) in &self.addressing_modes {
+            writeln!(f, "  {mode}: {count}")?;
+        }
+
+        Ok(())
+    }
+}
+
+/// Collects statistics for every instruction in the program
+pub(super) fn collect_stats(program: &[AssemblerInstruction]) -> Statistics {
+    let routines = routines(program)
+        .into_iter()
+        .map(|(name, body)| RoutineStats {
+            name: name.to_owned(),
+            registers: body
+                .iter()
+                .flat_map(|instruction| &instruction.operands)
+                .filter_map(|operand| match operand {
+                    Operand::Register(register) => Some(*register),
+                    _ => None,
+                })
+                .collect::<BTreeSet<_>>()
+                .into_iter()
+                .collect(),
+        })
+        .collect();
+
+    let mut immediates = Vec::new();
+    let mut addressing_modes = BTreeMap::new();
+    for instruction in program {
+        let AssemblerInstruction::Opcode(instruction) = instruction else {
+            continue;
+        };
+        // generic mnemonics without an addressing mode fail to assemble later
+        let Ok(instruction) = instruction.select_mode() else {
+            continue;
+        };
+
+        *addressing_modes
+            .entry(AddressingMode::of(
+                instruction.opcode,
+                &instruction.operands,
+            ))
+            .or_default() += 1;
+
+        immediates.extend(
+            instruction
+                .operands
+                .iter()
+                .filter_map(|operand| match operand {
+                    Operand::Value(value) => Some(Immediate {
+                        opcode: instruction.opcode,
+                        value: *value,
+                    }),
+                    _ => None,
+                }),
+        );
+    }
+
+    // stable sort, so equally large immediates stay in source order
+    immediates.sort_by_key(|immediate| std::cmp::Reverse(immediate.value.unsigned_abs()));
+    immediates.truncate(LARGEST_IMMEDIATES);
+
+    Statistics {
+        routines,
+        immediates,
+        addressing_modes,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    #[test]
+    fn test_collect_stats() {
+        let program = r#".code
+                                    calli @square
+                                    ldhi $2, -300
+                                    hlt
+                                    square: mulr $0, $0, $3
+                                    ldbd $4, @square
+                                    addi $0, 1000
+                                    ret"#;
+        let program = Program::parse(program).unwrap().instructions;
+        let stats = collect_stats(&program);
+
+        assert_eq!(
+            stats.routines,
+            vec![RoutineStats {
+                name: "square".to_owned(),
+                registers: vec![0, 3, 4]
+            }]
+        );
+        assert_eq!(
+            stats.immediates,
+            vec![
+                Immediate {
+                    opcode: Opcode::ADDI,
+                    value: 1000
+                },
+                Immediate {
+                    opcode: Opcode::LDHI,
+                    value: -300
+                }
+            ]
+        );
+        assert_eq!(
+            stats.addressing_modes.into_iter().collect::<Vec<_>>(),
+            vec![
+                (AddressingMode::Implied, 2),
+                (AddressingMode::Immediate, 3),
+                (AddressingMode::Direct, 1),
+                (AddressingMode::Register, 1)
+            ]
+        );
+    }
+}
@@ -0,0 +1,326 @@
+//! Dead symbol stripping.
+//!
+//! The program is split into units, each starting at a label (along with any `.align` directly
+//! before it) or section directive, and running until the next one. Units starting at a section
+//! directive can't be referenced by name so are always kept, and execution begins by falling
+//! through from the start of the code section. Units holding an `.entry` directive are kept too,
+//! along with the entry point it names.
+//! Any unit referenced by a kept unit is kept, as is a code unit that a kept code unit can fall
+//! through into. Data can be reached by address arithmetic from an earlier label, so every data
+//! unit following a kept one in the same section is kept too. Everything else is removed.
+//!
+//! Labels are checked for duplicates before anything is removed, since the first pass would
+//! otherwise only see whichever copy of a duplicated label was kept.
+
+use crate::assembler::errors::AssemblerError;
+use crate::assembler::section::AssemblerSection;
+use crate::parser::directive::Directive;
+use crate::parser::instruction::{AssemblerInstruction, OpcodeInstruction};
+use crate::parser::Label;
+use shared::Opcode;
+use std::collections::HashMap;
+use std::ops::Range;
+
+pub(super) struct Unit<'a> {
+    pub(super) instructions: Range<usize>,
+    label: Option<&'a Label>,
+    section: Option<AssemblerSection>,
+}
+
+/// Removes labelled code and data that can't be reached from unlabelled code, returning the names
+/// of the removed labels. Fails if a label is declared more than once
+pub(super) fn strip_unused(
+    program: &mut Vec<AssemblerInstruction>,
+) -> Result<Vec<String>, AssemblerError> {
+    let units = split_units(program);
+
+    let mut unit_by_label = HashMap::new();
+    for (index, unit) in units.iter().enumerate() {
+        let Some(label) = unit.label else { continue };
+        if unit_by_label.insert(label.name.as_str(), index).is_some() {
+            return Err(AssemblerError::SymbolAlreadyDeclared {
+                name: label.name.clone(),
+                location: label.span.location,
+            });
+        }
+    }
+
+    // walk from every unlabelled unit and the entry point, following references and fall through
+    let mut kept = vec![false; units.len()];
+    let mut queue = units
+        .iter()
+        .enumerate()
+        .filter(|(_, unit)| {
+            unit.label.is_none() || declares_entry(&program[unit.instructions.clone()])
+        })
+        .map(|(index, _)| index)
+        .collect::<Vec<_>>();
+
+    while let Some(index) = queue.pop() {
+        if kept[index] {
+            continue;
+        }
+        kept[index] = true;
+
+        let unit = &units[index];
+        let instructions = &program[unit.instructions.clone()];
+
+        queue.extend(
+            instructions
+                .iter()
+                .flat_map(AssemblerInstruction::labels)
+                .filter_map(|label| unit_by_label.get(label.name.as_str())),
+        );
+
+        // data after a kept label can be reached by offsetting its address, so is kept too
+        let next = units.get(index + 1);
+        let continues = match unit.section {
+            Some(AssemblerSection::Code) => falls_through(instructions),
+            Some(_) => unit.label.is_some(),
+            None => false,
+        };
+        if continues && next.is_some_and(|next| next.section == unit.section) {
+            queue.push(index + 1);
+        }
+    }
+
+    let removed = units
+        .iter()
+        .zip(&kept)
+        .filter(|(_, &kept)| !kept)
+        .filter_map(|(unit, _)| unit.label.map(|label| label.name.clone()))
+        .collect();
+
+    let mut stripped = vec![false; program.len()];
+    for (unit, _) in units.iter().zip(&kept).filter(|(_, &kept)| !kept) {
+        stripped[unit.instructions.clone()].fill(true);
+    }
+
+    // constants can be used from anywhere, so are kept even under a removed label
+    let mut index = 0;
+    program.retain(|instruction| {
+        let keep = !stripped[index] || is_constant(instruction);
+        index += 1;
+        keep
+    });
+
+    Ok(removed)
+}
+
+/// Checks if any of the instructions is an `.entry` directive
+fn declares_entry(instructions: &[AssemblerInstruction]) -> bool {
+    instructions.iter().any(|instruction| {
+        matches!(instruction, AssemblerInstruction::Directive(directive) if directive.directive == Directive::Entry)
+    })
+}
+
+/// Splits the program into units, each beginning at a label or section directive
+pub(super) fn split_units(program: &[AssemblerInstruction]) -> Vec<Unit<'_>> {
+    let mut units: Vec<Unit> = Vec::new();
+    let mut section = None;
+
+    for (index, instruction) in program.iter().enumerate() {
+        let (label, directive) = match instruction {
+            AssemblerInstruction::Opcode(opcode) => (&opcode.label, None),
+            AssemblerInstruction::Directive(directive) => (&directive.label, Some(directive)),
+        };
+
+        // section directives start an unlabelled unit, so are always kept and fall through into
+        // the start of the code section
+        if let Some(directive) = directive.filter(|directive| directive.operands.is_empty()) {
+            section = Some(AssemblerSection::from(directive.directive));
+            units.push(Unit {
+                instructions: index..index + 1,
+                label: None,
+                section,
+            });
+            continue;
+        }
+
+        let Some(label) = label else {
+            match units.last_mut() {
+                Some(unit) => unit.instructions.end = index + 1,
+                None => units.push(Unit {
+                    instructions: index..index + 1,
+                    label: None,
+                    section,
+                }),
+            }
+            continue;
+        };
+
+        // alignment directly before a label belongs with it
+        let mut start = index;
+        while start > 0 && is_align(&program[start - 1]) {
+            start -= 1;
+        }
+        if let Some(unit) = units.last_mut() {
+            unit.instructions.end = start;
+        }
+
+        units.push(Unit {
+            instructions: start..index + 1,
+            label: Some(label),
+            section,
+        });
+    }
+
+    units
+}
+
+fn is_align(instruction: &AssemblerInstruction) -> bool {
+    matches!(instruction, AssemblerInstruction::Directive(directive) if directive.directive == Directive::Align)
+}
+
+fn is_constant(instruction: &AssemblerInstruction) -> bool {
+    matches!(
+        instruction,
+        AssemblerInstruction::Directive(directive) if directive.directive == Directive::Equ
+    )
+}
+
+/// Checks if execution can continue past the end of a unit
+fn falls_through(instructions: &[AssemblerInstruction]) -> bool {
+    let last_opcode = instructions
+        .iter()
+        .rev()
+        .find_map(|instruction| match instruction {
+            AssemblerInstruction::Opcode(OpcodeInstruction { opcode, .. }) => Some(*opcode),
+            _ => None,
+        });
+
+    !matches!(
+        last_opcode,
+        Some(
+            Opcode::HLT
+                | Opcode::EXIT
+                | Opcode::JMPI
+                | Opcode::JMPD
+                | Opcode::JMPR
+                | Opcode::BRA
+                | Opcode::RET
+                | Opcode::IRET
+                | Opcode::IGL
+        )
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    #[test]
+    fn test_strip_unused() {
+        let program = r#".data
+                                    unused: .asciiz 'b'
+                                    .align 8
+                                    used: .asciiz 'a'
+                                .code
+                                    prtsd @used
+                                    jmpi @main
+                                    helper: addi $0, 1
+                                    main: hlt
+                                    dead: jmpi @helper
+                                    deadtoo: hlt"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        let mut removed = strip_unused(&mut program).unwrap();
+        removed.sort();
+        assert_eq!(removed, vec!["dead", "deadtoo", "helper", "unused"]);
+
+        let labels = program
+            .iter()
+            .flat_map(AssemblerInstruction::labels)
+            .map(|label| label.name.as_str())
+            .collect::<Vec<_>>();
+        assert_eq!(labels, vec!["used", "used", "main", "main"]);
+        assert_eq!(program.len(), 7);
+    }
+
+    #[test]
+    fn test_keep_data_after_referenced() {
+        let program = r#".data
+                                    unused: .word 4
+                                    x: .word 5
+                                    y: .word 6
+                                .code
+                                    ldhi $1, @x
+                                    addi $1, 4
+                                    ldwr $2, $1
+                                    hlt"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        assert_eq!(strip_unused(&mut program).unwrap(), vec!["unused"]);
+        assert!(program
+            .iter()
+            .any(|instruction| matches!(instruction, AssemblerInstruction::Directive(directive) if directive.label.as_ref().is_some_and(|label| label.name == "y"))));
+    }
+
+    #[test]
+    fn test_keep_entry() {
+        let program = r#".code
+                                    main: jmpi @main
+                                    dead: hlt"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        assert_eq!(strip_unused(&mut program).unwrap(), vec!["dead"]);
+        assert_eq!(program.len(), 2);
+    }
+
+    #[test]
+    fn test_keep_entry_directive() {
+        let program = r#".code
+                                    helper: addi $0, 1
+                                    ret
+                                    main: calli @helper
+                                    hlt
+                                    .entry @main
+                                    dead: hlt"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        assert_eq!(strip_unused(&mut program).unwrap(), vec!["dead"]);
+        assert_eq!(program.len(), 6);
+    }
+
+    #[test]
+    fn test_keep_fall_through() {
+        let program = r#".code
+                                    addi $0, 1
+                                    next: addi $0, 1
+                                    last: hlt"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        assert!(strip_unused(&mut program).unwrap().is_empty());
+        assert_eq!(program.len(), 4);
+    }
+
+    #[test]
+    fn test_keep_constants() {
+        let program = r#".code
+                                    hlt
+                                    dead: addi $0, 1
+                                    .equ SIZE, 4
+                                    ldbi $0, SIZE"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        assert_eq!(strip_unused(&mut program).unwrap(), vec!["dead"]);
+        assert!(matches!(
+            &program[2],
+            AssemblerInstruction::Directive(directive) if directive.directive == Directive::Equ
+        ));
+        assert_eq!(program.len(), 3);
+    }
+
+    #[test]
+    fn test_duplicate_label() {
+        let program = r#".code
+                                    jmpi @x
+                                    x: hlt
+                                    x: addi $0, 1"#;
+        let mut program = Program::parse(program).unwrap().instructions;
+
+        let error = strip_unused(&mut program).unwrap_err();
+        assert_eq!(error.to_string(), "4:37: symbol x already declared");
+    }
+}
@@ -14,6 +14,17 @@ impl SymbolTable {
     pub fn get_symbol(&self, name: &str) -> Option<&Symbol> {
         self.symbols.get(name)
     }
+
+    pub fn get_symbol_mut(&mut self, name: &str) -> Option<&mut Symbol> {
+        self.symbols.get_mut(name)
+    }
+
+    /// Iterates over every symbol and its name, in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Symbol)> {
+        self.symbols
+            .iter()
+            .map(|(name, symbol)| (name.as_str(), symbol))
+    }
 }
 
 #[derive(Debug, PartialEq)]
@@ -21,6 +32,8 @@ pub struct Symbol {
     /// Offset from start of data section (in terms of bytes)
     pub offset: u32,
     symbol_type: SymbolType,
+    /// Shape of the data declared at the symbol, if it was declared as structured data
+    pub layout: Option<DataLayout>,
 }
 
 impl Symbol {
@@ -28,13 +41,65 @@ impl Symbol {
         Self {
             offset,
             symbol_type,
+            layout: None,
+        }
+    }
+
+    /// Value of the symbol if it's a constant defined with `.equ`
+    pub fn constant(&self) -> Option<i32> {
+        match self.symbol_type {
+            SymbolType::Constant(value) => Some(value),
+            SymbolType::Label => None,
         }
     }
+
+    /// Attaches the shape of the data declared at the symbol
+    pub fn with_layout(mut self, layout: Option<DataLayout>) -> Self {
+        self.layout = layout;
+        self
+    }
+}
+
+/// Element size and dimensions of data declared with `.fill` or `.matrix`, so it can be
+/// interpreted as an array or grid rather than raw bytes
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DataLayout {
+    /// Size of each element in bytes, one of 1, 2 or 4
+    pub element_size: usize,
+    /// Number of elements along each dimension, outermost first
+    pub dimensions: Vec<usize>,
+}
+
+impl DataLayout {
+    /// Creates a layout, returning None if the element size isn't 1, 2 or 4 bytes
+    pub fn new(element_size: usize, dimensions: Vec<usize>) -> Option<Self> {
+        matches!(element_size, 1 | 2 | 4).then_some(Self {
+            element_size,
+            dimensions,
+        })
+    }
+
+    /// Total number of elements
+    pub fn len(&self) -> usize {
+        self.dimensions.iter().product()
+    }
+
+    /// Checks if there are no elements
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Total size in bytes, before alignment
+    pub fn size(&self) -> usize {
+        self.len() * self.element_size
+    }
 }
 
 #[derive(Debug, PartialEq)]
 pub enum SymbolType {
     Label,
+    /// Named value, which takes up no space in the program
+    Constant(i32),
 }
 
 #[cfg(test)]
@@ -56,5 +121,18 @@ mod tests {
 
         let v = sym.get_symbol("does_not_exist");
         assert!(v.is_none());
+
+        sym.add_symbol("SIZE", Symbol::new(0, SymbolType::Constant(16)));
+        assert_eq!(sym.get_symbol("SIZE").unwrap().constant(), Some(16));
+        assert_eq!(sym.get_symbol("test").unwrap().constant(), None);
+    }
+
+    #[test]
+    fn test_data_layout() {
+        let layout = DataLayout::new(2, vec![3, 4]).unwrap();
+        assert_eq!(layout.len(), 12);
+        assert_eq!(layout.size(), 24);
+
+        assert_eq!(DataLayout::new(3, vec![1]), None);
     }
 }
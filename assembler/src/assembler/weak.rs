@@ -0,0 +1,138 @@
+//! Weak symbols.
+//!
+//! `.weak @name` marks the next declaration of `name` as weak, so a default implementation can be
+//! overridden by another declaration without a multiple definition error. Overridden weak
+//! declarations are removed along with the code or data under them. If every declaration of a
+//! name is weak, the first is kept.
+
+use crate::assembler::errors::AssemblerError;
+use crate::assembler::strip::split_units;
+use crate::parser::directive::Directive;
+use crate::parser::instruction::AssemblerInstruction;
+use crate::parser::operand::Operand;
+
+/// Removes `.weak` directives, along with any weak declarations that are overridden
+pub(super) fn resolve_weak(program: &mut Vec<AssemblerInstruction>) -> Result<(), AssemblerError> {
+    let mut pending = Vec::new();
+    let mut weak = Vec::new();
+    let mut resolved = Vec::with_capacity(program.len());
+
+    for instruction in program.drain(..) {
+        if let AssemblerInstruction::Directive(directive) = &instruction {
+            if directive.directive == Directive::Weak {
+                for operand in &directive.operands {
+                    match operand {
+                        Operand::Label(label) => pending.push(label.clone()),
+                        _ => return Err(AssemblerError::IncorrectOperand),
+                    }
+                }
+
+                continue;
+            }
+        }
+
+        // the next declaration of a pending name is weak
+        let position = instruction
+            .label()
+            .and_then(|label| pending.iter().position(|pending| pending == label));
+        if let Some(position) = position {
+            pending.swap_remove(position);
+            weak.push(resolved.len());
+        }
+
+        resolved.push(instruction);
+    }
+    *program = resolved;
+
+    if let Some(label) = pending.pop() {
+        return Err(AssemblerError::UndefinedSymbol {
+            name: label.name,
+            location: Some(label.span.location),
+        });
+    }
+
+    // overridden by any other declaration that isn't weak, or by an earlier weak one
+    let overridden = weak
+        .iter()
+        .copied()
+        .filter(|&index| {
+            let name = &program[index].label().unwrap().name;
+
+            program.iter().enumerate().any(|(other, instruction)| {
+                other != index
+                    && instruction.label().is_some_and(|label| label.name == *name)
+                    && (other < index || !weak.contains(&other))
+            })
+        })
+        .collect::<Vec<_>>();
+
+    let ranges = split_units(program)
+        .into_iter()
+        .map(|unit| unit.instructions)
+        .filter(|range| overridden.iter().any(|index| range.contains(index)))
+        .collect::<Vec<_>>();
+    for range in ranges.into_iter().rev() {
+        program.drain(range);
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::parser::Program;
+
+    fn resolve(program: &str) -> Result<Vec<AssemblerInstruction>, AssemblerError> {
+        let mut program = Program::parse(program).unwrap().instructions;
+        resolve_weak(&mut program)?;
+
+        Ok(program)
+    }
+
+    #[test]
+    fn test_weak_overridden() {
+        let program = resolve(
+            r#".code
+                    jmpi @handler
+                    .weak @handler
+                    handler: addi $0, 1
+                    hlt
+                    handler: addi $0, 2"#,
+        )
+        .unwrap();
+
+        let expected = Program::parse(
+            r#".code
+                    jmpi @handler
+                    handler: addi $0, 2"#,
+        )
+        .unwrap();
+        assert_eq!(program, expected.instructions);
+    }
+
+    #[test]
+    fn test_weak_default() {
+        let program = resolve(
+            r#".code
+                    .weak @handler
+                    handler: hlt"#,
+        )
+        .unwrap();
+
+        assert_eq!(program.len(), 2);
+    }
+
+    #[test]
+    fn test_weak_undeclared() {
+        assert!(matches!(
+            resolve(".code\n.weak @handler"),
+            Err(AssemblerError::UndefinedSymbol { name, location: Some(location) })
+                if name == "handler" && location.line == 2
+        ));
+        assert!(matches!(
+            resolve(".code\n.weak 1\nhlt"),
+            Err(AssemblerError::IncorrectOperand)
+        ));
+    }
+}
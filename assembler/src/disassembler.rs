@@ -0,0 +1,435 @@
+//! Converts assembled bytecode back into assembly.
+//!
+//! Addresses used as operands are replaced with generated labels when they point into the program,
+//! named `L<address>` in the code section and `D<address>` in the data section. If the program has
+//! a symbol section, its names are used instead, every symbol is labelled whether or not anything
+//! refers to it, and constants are written as `.equ` directives. Data is written out as byte
+//! aligned `.byte` directives, so the output assembles back into the same program. Registers are
+//! written with their names from [`shared::abi::REGISTER_NAMES`]. The bss section is written as
+//! `.space` directives split at each label. If the program has a line section, `.line` directives
+//! are written wherever the source line changes, so the line section is kept too.
+
+use shared::abi::REGISTER_NAMES;
+use shared::lines::read_lines;
+use shared::symbols::{read_symbols, SymbolKind};
+use shared::{
+    Opcode, OperandKind, BSS_SECTION_FIELD, ENTRY_POINT_FIELD, PIE_HEADER_LENGTH,
+    PIE_HEADER_PREFIX, RODATA_SECTION_FIELD,
+};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// Number of bytes written on each `.byte` line
+const BYTES_PER_LINE: usize = 16;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum DisassemblerError {
+    #[error("program doesn't start with a valid header")]
+    InvalidHeader,
+}
+
+/// Disassembles a program, including its header, into assembly
+pub fn disassemble(program: &[u8]) -> Result<String, DisassemblerError> {
+    if program.len() < PIE_HEADER_LENGTH || program[..4] != PIE_HEADER_PREFIX {
+        return Err(DisassemblerError::InvalidHeader);
+    }
+
+    let field = |offset: usize| {
+        u32::from_be_bytes(program[offset..offset + 4].try_into().unwrap()) as usize
+    };
+    let section = |offset: usize| {
+        let start = field(offset);
+
+        start
+            .checked_add(field(offset + 4))
+            .filter(|&end| start >= PIE_HEADER_LENGTH && end <= program.len())
+            .map(|end| start..end)
+            .ok_or(DisassemblerError::InvalidHeader)
+    };
+    let data = section(8)?;
+    let code = section(16)?;
+    let rodata = match field(RODATA_SECTION_FIELD + 4) {
+        0 => data.end..data.end,
+        _ => section(RODATA_SECTION_FIELD)?,
+    };
+    // the bss isn't stored in the program, so only needs to come after it
+    let bss = match field(BSS_SECTION_FIELD + 4) {
+        0 => 0..0,
+        len => {
+            let start = field(BSS_SECTION_FIELD);
+            start
+                .checked_add(len)
+                .filter(|_| start >= program.len())
+                .map(|end| start..end)
+                .ok_or(DisassemblerError::InvalidHeader)?
+        }
+    };
+
+    let symbols = read_symbols(program).ok_or(DisassemblerError::InvalidHeader)?;
+    let lines = read_lines(program).ok_or(DisassemblerError::InvalidHeader)?;
+    // the first name at each address, which the assembler sorts alphabetically
+    let mut names = BTreeMap::new();
+    for symbol in symbols.iter().filter(|s| s.kind != SymbolKind::Constant) {
+        names
+            .entry(symbol.value as usize)
+            .or_insert(symbol.name.as_str());
+    }
+
+    let instructions = program[code.clone()].chunks_exact(4).collect::<Vec<_>>();
+    let code_labels = code.start..code.start + instructions.len() * 4;
+    let entry = Some(field(ENTRY_POINT_FIELD))
+        .filter(|&offset| offset != 0)
+        .map(|offset| code.start + offset);
+
+    // every address operand, branch target or symbol pointing at an instruction or into data gets
+    // a label
+    let labels = instructions
+        .iter()
+        .enumerate()
+        .filter_map(|(index, bytes)| Some((code.start + index * 4, decode(bytes)?.1)))
+        .flat_map(|(pc, operands)| {
+            operands
+                .into_iter()
+                .filter_map(move |(kind, value)| operand_target(pc, kind, value))
+        })
+        .chain(names.keys().copied())
+        .chain(entry)
+        .filter(|address| {
+            data.contains(address)
+                || rodata.contains(address)
+                || bss.contains(address)
+                || (code_labels.contains(address) && (address - code.start) % 4 == 0)
+        })
+        .collect::<BTreeSet<_>>();
+    let label = |address: usize| -> Option<String> {
+        labels.contains(&address).then(|| {
+            match (names.get(&address), code_labels.contains(&address)) {
+                (Some(name), _) => name.to_string(),
+                (None, false) => format!("D{address:04X}"),
+                (None, true) => format!("L{address:04X}"),
+            }
+        })
+    };
+
+    let mut out = String::new();
+
+    for symbol in symbols.iter().filter(|s| s.kind == SymbolKind::Constant) {
+        writeln!(out, ".equ {}, {}", symbol.name, symbol.value as i32).unwrap();
+    }
+
+    for (directive, section) in [(".data", &data), (".rodata", &rodata)] {
+        if directive == ".rodata" && section.is_empty() {
+            continue;
+        }
+
+        writeln!(out, "{directive}").unwrap();
+        let mut start = section.start;
+        for end in labels
+            .iter()
+            .copied()
+            .filter(|&address| address > section.start && address < section.end)
+            .chain([section.end])
+        {
+            write_bytes(&mut out, label(start), start, &program[start..end]);
+            start = end;
+        }
+    }
+
+    if !bss.is_empty() {
+        writeln!(out, ".bss").unwrap();
+        let mut start = bss.start;
+        for end in labels
+            .iter()
+            .copied()
+            .filter(|&address| address > bss.start && address < bss.end)
+            .chain([bss.end])
+        {
+            let prefix = label(start)
+                .map(|label| format!("{label}:"))
+                .unwrap_or_default();
+            writeln!(out, "        .align 1").unwrap();
+            writeln!(out, "{prefix:<8}.space {}", end - start).unwrap();
+            start = end;
+        }
+    }
+
+    writeln!(out, ".code").unwrap();
+    let mut source = None;
+    for (index, bytes) in instructions.iter().enumerate() {
+        let address = code.start + index * 4;
+        if let Some((file, line)) = lines.lookup(address as u32) {
+            if source != Some((file, line)) {
+                match file {
+                    "" => writeln!(out, "        .line {line}").unwrap(),
+                    _ => {
+                        let file = file.replace('\\', "\\\\").replace('"', "\\\"");
+                        writeln!(out, "        .line {line}, \"{file}\"").unwrap()
+                    }
+                }
+                source = Some((file, line));
+            }
+        }
+        let prefix = match label(address) {
+            Some(label) => format!("{label}:"),
+            None => String::new(),
+        };
+
+        let Some((opcode, operands)) = decode(bytes) else {
+            writeln!(
+                out,
+                "{prefix:<8}.word {}",
+                i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]])
+            )
+            .unwrap();
+            continue;
+        };
+
+        let instruction = format_instruction(address, opcode, operands, label);
+        writeln!(out, "{prefix:<8}{instruction}").unwrap();
+    }
+
+    // trailing bytes that don't make up a whole instruction
+    let trailing = code.start + instructions.len() * 4..code.end;
+    write_bytes(&mut out, None, trailing.start, &program[trailing]);
+
+    if let Some(label) = entry.and_then(label) {
+        writeln!(out, ".entry @{label}").unwrap();
+    }
+
+    Ok(out)
+}
+
+/// Disassembles a single instruction, such as `ldbi $a0, 10`, or None if its opcode isn't valid.
+/// Addresses and branch offsets are written as numbers, as there are no labels to give them
+pub fn disassemble_instruction(bytes: [u8; 4]) -> Option<String> {
+    let (opcode, operands) = decode(&bytes)?;
+
+    Some(format_instruction(0, opcode, operands, |_| None))
+}
+
+/// Writes the instruction at an address as assembly, naming the addresses its operands refer to
+/// with their labels
+fn format_instruction(
+    address: usize,
+    opcode: Opcode,
+    operands: Vec<(OperandKind, u16)>,
+    label: impl Fn(usize) -> Option<String>,
+) -> String {
+    let operands = operands
+        .into_iter()
+        .map(|(kind, value)| match kind {
+            OperandKind::Register => match REGISTER_NAMES.get(value as usize) {
+                Some(name) => format!("${name}"),
+                None => format!("${value}"),
+            },
+            OperandKind::Address => match label(value as usize) {
+                Some(label) => format!("@{label}"),
+                None => value.to_string(),
+            },
+            OperandKind::Offset => match operand_target(address, kind, value).and_then(&label) {
+                Some(label) => format!("@{label}"),
+                None => (value as i16).to_string(),
+            },
+            OperandKind::Value | OperandKind::Byte => value.to_string(),
+        })
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mnemonic = opcode.mnemonic().to_lowercase();
+
+    format!("{mnemonic} {operands}").trim_end().to_owned()
+}
+
+/// Writes bytes as byte aligned `.byte` directives, with the label on the first
+fn write_bytes(out: &mut String, label: Option<String>, address: usize, bytes: &[u8]) {
+    let mut prefix = label.map(|label| format!("{label}:")).unwrap_or_default();
+
+    for (index, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        let values = line
+            .iter()
+            .map(u8::to_string)
+            .collect::<Vec<_>>()
+            .join(", ");
+
+        writeln!(out, "        .align 1").unwrap();
+        writeln!(
+            out,
+            "{prefix:<8}.byte {values}    ; {:#06X}",
+            address + index * BYTES_PER_LINE
+        )
+        .unwrap();
+        prefix.clear();
+    }
+}
+
+/// Address an operand of the instruction at pc refers to, if it's an address or branch offset
+fn operand_target(pc: usize, kind: OperandKind, value: u16) -> Option<usize> {
+    match kind {
+        OperandKind::Address => Some(value as usize),
+        OperandKind::Offset => pc.checked_add_signed(value as i16 as isize),
+        _ => None,
+    }
+}
+
+/// Decodes an instruction into its opcode and operands, or None if the opcode isn't valid
+fn decode(bytes: &[u8]) -> Option<(Opcode, Vec<(OperandKind, u16)>)> {
+    let opcode = Opcode::from_encoding(bytes[0])?;
+
+    let mut offset = 1;
+    let operands = opcode
+        .operand_kinds()
+        .iter()
+        .map(|&kind| {
+            let value = match kind {
+                OperandKind::Register | OperandKind::Byte => bytes[offset] as u16,
+                _ => u16::from_be_bytes([bytes[offset], bytes[offset + 1]]),
+            };
+            offset += kind.size();
+
+            (kind, value)
+        })
+        .collect();
+
+    Some((opcode, operands))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Assembler;
+
+    #[test]
+    fn test_disassemble() {
+        let program = r#".data
+                                    hello: .asciiz 'hi'
+                                    count: .byte 3
+                                .code
+                                    loop: prtsd @hello
+                                    ldbd $0, @count
+                                    subi $0, 1
+                                    strbi $0, @count
+                                    gti $0, 0
+                                    jmpei @loop
+                                    hlt"#;
+        let bytes = Assembler::default().assemble(program).unwrap();
+        let disassembled = disassemble(&bytes).unwrap();
+
+        assert!(disassembled.contains("D0040:  .byte 104, 105, 0, 0"));
+        assert!(disassembled.contains("L0048:  prtsd @D0040"));
+        assert!(disassembled.contains("        ldbd $a0, @D0044"));
+        assert!(disassembled.contains("        jmpei @L0048"));
+
+        // output assembles back into the same program
+        let reassembled = Assembler::default().assemble(&disassembled).unwrap();
+        assert_eq!(reassembled, bytes);
+    }
+
+    #[test]
+    fn test_disassemble_branches() {
+        let program = ".code
+            loop: inc $0
+            lti $0, 3
+            bre @loop
+            brne 8
+            bra -100
+            hlt";
+        let bytes = Assembler::default().assemble(program).unwrap();
+        assert_eq!(bytes[72..76], [Opcode::BRE as u8, 0xFF, 0xF8, 0]);
+
+        let disassembled = disassemble(&bytes).unwrap();
+        assert!(disassembled.contains("L0040:  inc $a0"));
+        assert!(disassembled.contains("        bre @L0040"));
+        assert!(disassembled.contains("        brne @L0054"));
+        assert!(disassembled.contains("        bra -100"));
+
+        let reassembled = Assembler::default().assemble(&disassembled).unwrap();
+        assert_eq!(reassembled, bytes);
+    }
+
+    #[test]
+    fn test_disassemble_symbols() {
+        let program = r#".equ COUNT, 3
+                                .data
+                                    hello: .asciiz 'hi'
+                                .code
+                                    main: ldbi $0, COUNT
+                                    loop: prtsd @hello
+                                    subi $0, 1
+                                    gti $0, 0
+                                    jmpei @loop
+                                    done: hlt"#;
+        let mut assembler = Assembler::default().debug_symbols(true);
+        let bytes = assembler.assemble(program).unwrap();
+        let disassembled = disassemble(&bytes).unwrap();
+
+        assert!(disassembled.starts_with(".equ COUNT, 3\n"));
+        assert!(disassembled.contains("hello:  .byte 104, 105, 0"));
+        assert!(disassembled.contains("main:   ldbi $a0, 3"));
+        assert!(disassembled.contains("loop:   prtsd @hello"));
+        assert!(disassembled.contains("        jmpei @loop"));
+        assert!(disassembled.contains("done:   hlt"));
+        assert!(disassembled.contains("        .line 5\nmain:   ldbi $a0, 3"));
+
+        let reassembled = Assembler::default()
+            .debug_symbols(true)
+            .assemble(&disassembled)
+            .unwrap();
+        assert_eq!(reassembled, bytes);
+    }
+
+    #[test]
+    fn test_disassemble_entry() {
+        let program = ".code\nhelper: ret\nmain: calli @helper\nhlt\n.entry @main";
+        let bytes = Assembler::default().assemble(program).unwrap();
+        let disassembled = disassemble(&bytes).unwrap();
+
+        assert!(disassembled.ends_with("L0044:  calli @L0040\n        hlt\n.entry @L0044\n"));
+        assert_eq!(Assembler::default().assemble(&disassembled).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_disassemble_rodata() {
+        let program = ".rodata\nmessage: .asciiz 'hi'\n.code\nprtsd @message";
+        let bytes = Assembler::default().assemble(program).unwrap();
+        let disassembled = disassemble(&bytes).unwrap();
+
+        assert!(disassembled.contains(".rodata\n        .align 1\nD0040:  .byte 104, 105, 0, 0"));
+        assert_eq!(Assembler::default().assemble(&disassembled).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_disassemble_bss() {
+        let program = ".bss\nbuffer: .space 8\ncount: .space 4\n.code\nldwd $0, @count";
+        let bytes = Assembler::default().assemble(program).unwrap();
+        let disassembled = disassemble(&bytes).unwrap();
+
+        assert!(disassembled.contains(
+            ".bss\n        .align 1\n        .space 8\n        .align 1\nD004C:  .space 4"
+        ));
+        assert_eq!(Assembler::default().assemble(&disassembled).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_disassemble_without_data() {
+        let bytes = Assembler::default().assemble(".code\nhlt").unwrap();
+
+        assert_eq!(disassemble(&bytes).unwrap(), ".data\n.code\n        hlt\n");
+    }
+
+    #[test]
+    fn test_disassemble_instruction() {
+        assert_eq!(
+            disassemble_instruction([0x04, 0, 0, 10]).as_deref(),
+            Some("ldbi $a0, 10")
+        );
+        assert_eq!(
+            disassemble_instruction([0, 0, 0, 0]).as_deref(),
+            Some("hlt")
+        );
+        assert_eq!(disassemble_instruction([0x7C, 0, 0, 0]), None);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        assert_eq!(disassemble(&[0; 64]), Err(DisassemblerError::InvalidHeader));
+    }
+}
@@ -1,4 +1,12 @@
 mod assembler;
+mod disassembler;
 mod parser;
+mod rename;
 
-pub use assembler::Assembler;
+pub use assembler::{
+    AbiWarning, AddressingMode, Assembler, AssemblerError, AssemblerWarning, DataLayout,
+    FileSystemLoader, Immediate, RoutineStats, SourceLoader, Statistics,
+};
+pub use disassembler::{disassemble, disassemble_instruction, DisassemblerError};
+pub use parser::Location;
+pub use rename::rename_label;
@@ -1,6 +1,7 @@
 use nom::bytes::complete::take_while;
 use nom::character::complete::{char, multispace0};
 use nom::combinator::{map, opt};
+use nom::multi::many0;
 use nom::sequence::tuple;
 use nom::IResult;
 
@@ -12,6 +13,17 @@ pub(super) fn parse_comment(input: &str) -> IResult<&str, ()> {
     )(input)
 }
 
+/// Matches any whitespace and full-line comments, such as the lines between instructions
+pub(super) fn parse_blank(input: &str) -> IResult<&str, ()> {
+    map(
+        tuple((
+            many0(tuple((multispace0, char(';'), take_while(|c| c != '\n')))),
+            multispace0,
+        )),
+        |_| (),
+    )(input)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -22,4 +34,10 @@ mod tests {
         assert_eq!(parse_comment("    ; aaaaaaa"), Ok(("", ())));
         assert_eq!(parse_comment("     aaaaaaa"), Ok(("     aaaaaaa", ())));
     }
+
+    #[test]
+    fn test_blank_parser() {
+        assert_eq!(parse_blank("; a\n\n  ; b\n  hlt"), Ok(("hlt", ())));
+        assert_eq!(parse_blank("hlt ; a"), Ok(("hlt ; a", ())));
+    }
 }
@@ -1,5 +1,7 @@
+use nom::branch::alt;
 use nom::character::complete::{alpha1, char};
-use nom::combinator::map;
+use nom::combinator::{map, recognize};
+use nom::multi::many1;
 use nom::sequence::preceded;
 use nom::IResult;
 
@@ -12,8 +14,27 @@ pub enum Directive {
     Half,
     Word,
     Space,
+    Fill,
+    Matrix,
     Code,
     Data,
+    Rodata,
+    Bss,
+    Weak,
+    Equ,
+    Include,
+    Line,
+    Incbin,
+    PadTo,
+    Nopfill,
+    Entry,
+    If,
+    Ifdef,
+    Else,
+    Endif,
+    Set,
+    Rept,
+    Endr,
     Unknown,
 }
 
@@ -27,8 +48,27 @@ impl From<&str> for Directive {
             "half" => Self::Half,
             "word" => Self::Word,
             "space" => Self::Space,
+            "fill" => Self::Fill,
+            "matrix" => Self::Matrix,
             "code" => Self::Code,
             "data" => Self::Data,
+            "rodata" => Self::Rodata,
+            "bss" => Self::Bss,
+            "weak" => Self::Weak,
+            "equ" => Self::Equ,
+            "include" => Self::Include,
+            "line" => Self::Line,
+            "incbin" => Self::Incbin,
+            "pad_to" => Self::PadTo,
+            "nopfill" => Self::Nopfill,
+            "entry" => Self::Entry,
+            "if" => Self::If,
+            "ifdef" => Self::Ifdef,
+            "else" => Self::Else,
+            "endif" => Self::Endif,
+            "set" => Self::Set,
+            "rept" => Self::Rept,
+            "endr" => Self::Endr,
             _ => Self::Unknown,
         }
     }
@@ -36,7 +76,13 @@ impl From<&str> for Directive {
 
 /// Parses a directive of the form .<directive>
 pub(super) fn parse_directive(input: &str) -> IResult<&str, Directive> {
-    map(preceded(char('.'), alpha1), Directive::from)(input)
+    map(
+        preceded(
+            char('.'),
+            recognize(many1(alt((alpha1, recognize(char('_')))))),
+        ),
+        Directive::from,
+    )(input)
 }
 
 #[cfg(test)]
@@ -47,6 +93,7 @@ mod tests {
     fn test_parse_directive() {
         assert_eq!(parse_directive(".asciiz"), Ok(("", Directive::Asciiz)));
         assert_eq!(parse_directive(".code.a"), Ok((".a", Directive::Code)));
+        assert_eq!(parse_directive(".pad_to 8"), Ok((" 8", Directive::PadTo)));
         assert_eq!(
             parse_directive(".one@two"),
             Ok(("@two", Directive::Unknown))
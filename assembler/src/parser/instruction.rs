@@ -1,15 +1,18 @@
+use crate::assembler::{AssemblerError, DataLayout};
 use crate::parser::comment::parse_comment;
 use crate::parser::directive::{parse_directive, Directive};
 use crate::parser::label_declaration::parse_label_declaration;
 use crate::parser::opcode::parse_opcode;
-use crate::parser::operand::{parse_operand, Operand};
+use crate::parser::operand::{parse_operand, Literal, Operand};
+use crate::parser::{Label, SourceLine};
 use nom::branch::alt;
-use nom::character::complete::{char, multispace0};
+use nom::character::complete::{char, multispace0, space0};
 use nom::combinator::{map, opt};
 use nom::multi::many0;
 use nom::sequence::{delimited, tuple};
 use nom::IResult;
-use shared::Opcode;
+use shared::{AddressingMode, Opcode};
+use std::borrow::Cow;
 
 #[derive(PartialEq, Debug, Clone)]
 pub enum AssemblerInstruction {
@@ -22,21 +25,65 @@ impl AssemblerInstruction {
     #[allow(unused)]
     pub fn new_opcode(label: Option<&str>, opcode: Opcode, operands: &[Operand]) -> Self {
         Self::Opcode(OpcodeInstruction {
-            label: label.map(str::to_owned),
+            label: label.map(Label::from),
             opcode,
             operands: operands.to_vec(),
+            generic: None,
+            source: None,
         })
     }
 
-    /// Used in testing
-    #[allow(unused)]
     pub fn new_directive(label: Option<&str>, directive: Directive, operands: &[Operand]) -> Self {
         Self::Directive(DirectiveInstruction {
-            label: label.map(str::to_owned),
+            label: label.map(Label::from),
             directive,
             operands: operands.to_vec(),
         })
     }
+
+    /// Label declared by the instruction, if any
+    pub fn label(&self) -> Option<&Label> {
+        match self {
+            Self::Opcode(instruction) => instruction.label.as_ref(),
+            Self::Directive(instruction) => instruction.label.as_ref(),
+        }
+    }
+
+    /// Iterates over the label declared by the instruction and any labels or constants used as
+    /// operands
+    pub fn labels(&self) -> impl Iterator<Item = &Label> {
+        let (label, operands) = match self {
+            Self::Opcode(instruction) => (&instruction.label, &instruction.operands),
+            Self::Directive(instruction) => (&instruction.label, &instruction.operands),
+        };
+
+        label
+            .iter()
+            .chain(operands.iter().filter_map(|operand| match operand {
+                Operand::Label(label)
+                | Operand::Literal(Literal::Label(label))
+                | Operand::Constant(label) => Some(label),
+                _ => None,
+            }))
+    }
+
+    /// Iterates mutably over the label declared by the instruction and any labels or constants used
+    /// as operands
+    pub(super) fn labels_mut(&mut self) -> impl Iterator<Item = &mut Label> {
+        let (label, operands) = match self {
+            Self::Opcode(instruction) => (&mut instruction.label, &mut instruction.operands),
+            Self::Directive(instruction) => (&mut instruction.label, &mut instruction.operands),
+        };
+
+        label
+            .iter_mut()
+            .chain(operands.iter_mut().filter_map(|operand| match operand {
+                Operand::Label(label)
+                | Operand::Literal(Literal::Label(label))
+                | Operand::Constant(label) => Some(label),
+                _ => None,
+            }))
+    }
 }
 
 /// Parses an instruction of the form <label?> <opcode | directive> <operands?>
@@ -47,11 +94,59 @@ pub(super) fn parse_instruction(input: &str) -> IResult<&str, AssemblerInstructi
     ))(input)
 }
 
-#[derive(PartialEq, Debug, Clone)]
+#[derive(Debug, Clone)]
 pub struct OpcodeInstruction {
-    pub label: Option<String>,
+    pub label: Option<Label>,
     pub opcode: Opcode,
     pub operands: Vec<Operand>,
+    /// Generic mnemonic the instruction was written with, such as `ld.b`, if its opcode is chosen
+    /// from its operands
+    pub generic: Option<String>,
+    /// Where the instruction was written, if it was parsed from source
+    pub source: Option<SourceLine>,
+}
+
+/// Instructions are equal if they assemble the same, wherever they were written
+impl PartialEq for OpcodeInstruction {
+    fn eq(&self, other: &Self) -> bool {
+        self.label == other.label
+            && self.opcode == other.opcode
+            && self.operands == other.operands
+            && self.generic == other.generic
+    }
+}
+
+impl OpcodeInstruction {
+    /// Instruction with the opcode it assembles to, choosing the addressing mode of a generic
+    /// mnemonic from its last operand: registers select the register form, labels the direct form
+    /// (or the immediate form if there isn't one, as stores take their address as an immediate)
+    /// and anything else the immediate form
+    pub(crate) fn select_mode(&self) -> Result<Cow<'_, Self>, AssemblerError> {
+        let (Some(mnemonic), Some(operand)) = (&self.generic, self.operands.last()) else {
+            return Ok(Cow::Borrowed(self));
+        };
+
+        let (modes, kind): (&[_], _) = match operand {
+            Operand::Register(_) => (&[AddressingMode::Register], "a register"),
+            Operand::Label(_) | Operand::Literal(_) => (
+                &[AddressingMode::Direct, AddressingMode::Immediate],
+                "an address",
+            ),
+            _ => (&[AddressingMode::Immediate], "a value"),
+        };
+        let opcode = modes
+            .iter()
+            .find_map(|&mode| self.opcode.with_mode(mode))
+            .ok_or_else(|| AssemblerError::NoAddressingMode {
+                mnemonic: mnemonic.clone(),
+                operand: kind,
+            })?;
+
+        Ok(Cow::Owned(Self {
+            opcode,
+            ..self.clone()
+        }))
+    }
 }
 
 /// Parses an instruction of the form <label?> <opcode> <operands?>
@@ -61,59 +156,36 @@ fn parse_opcode_instruction(input: &str) -> IResult<&str, OpcodeInstruction> {
             opt(parse_label_declaration),
             multispace0,
             parse_opcode,
-            many0(delimited(multispace0, parse_operand, opt(char(',')))),
+            many0(delimited(space0, parse_operand, opt(char(',')))),
             parse_comment,
         )),
-        |(label, _, opcode, operands, _)| OpcodeInstruction {
-            label: label.map(str::to_owned),
+        |(label, _, (opcode, generic), operands, _)| OpcodeInstruction {
+            label: label.map(Label::from_token),
             opcode,
             operands,
+            generic,
+            source: None,
         },
     )(input)
 }
 
 #[derive(PartialEq, Debug, Clone)]
 pub struct DirectiveInstruction {
-    pub label: Option<String>,
+    pub label: Option<Label>,
     pub directive: Directive,
     pub operands: Vec<Operand>,
 }
 
 impl DirectiveInstruction {
-    /// Size of 4-byte aligned null terminated string. If alignment is None, default to 4 bytes.
-    pub(crate) fn size(&self, alignment: Option<usize>) -> usize {
-        let alignment = alignment.unwrap_or(4);
+    /// Size of the directive's bytes once aligned, with words of `word_size` bytes. If alignment is
+    /// None, default to the word size.
+    pub(crate) fn size(&self, alignment: Option<usize>, word_size: usize) -> usize {
+        let alignment = alignment.unwrap_or(word_size);
 
         match self.directive {
             Directive::Align => 0,
-            Directive::Ascii => self
-                .operands
-                .first()
-                .and_then(|operand| {
-                    if let Operand::String(string) = operand {
-                        let len = string.len();
-                        let len = Self::align(len, alignment);
-
-                        Some(len)
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or(0),
-            Directive::Asciiz => self
-                .operands
-                .first()
-                .and_then(|operand| {
-                    if let Operand::String(string) = operand {
-                        let len = string.len() + 1;
-                        let len = Self::align(len, alignment);
-
-                        Some(len)
-                    } else {
-                        None
-                    }
-                })
-                .unwrap_or(0),
+            Directive::Ascii => Self::align(self.string_bytes().len(), alignment),
+            Directive::Asciiz => Self::align(self.string_bytes().len() + 1, alignment),
             Directive::Byte => {
                 let count = self
                     .operands
@@ -136,11 +208,19 @@ impl DirectiveInstruction {
                 let count = self
                     .operands
                     .iter()
-                    .filter(|operand| matches!(operand, Operand::Value { .. }))
+                    .filter(|operand| matches!(operand, Operand::Value(_) | Operand::Label(_)))
                     .count();
 
-                Self::align(count * 4, alignment)
+                Self::align(count * word_size, alignment)
             }
+            Directive::Fill | Directive::Matrix => self
+                .layout()
+                .map(|layout| Self::align(layout.size(), alignment))
+                .unwrap_or(0),
+            Directive::Incbin => match self.operands.first() {
+                Some(Operand::Bytes(bytes)) => Self::align(bytes.len(), alignment),
+                _ => 0,
+            },
             Directive::Space => self
                 .operands
                 .first()
@@ -156,24 +236,22 @@ impl DirectiveInstruction {
         }
     }
 
-    /// Creates a null terminated string. If alignment is None, default to 4 bytes.
-    pub(crate) fn aligned_bytes(&self, alignment: Option<usize>) -> Option<Vec<u8>> {
-        let size = self.size(alignment);
+    /// Bytes of the directive, padded to its size. If alignment is None, default to the word size.
+    pub(crate) fn aligned_bytes(
+        &self,
+        alignment: Option<usize>,
+        word_size: usize,
+    ) -> Option<Vec<u8>> {
+        let size = self.size(alignment, word_size);
 
         let mut bytes = match self.directive {
-            Directive::Ascii => match self.operands.first() {
-                Some(Operand::String(string)) => string.as_bytes().to_vec(),
-                _ => vec![],
-            },
-            Directive::Asciiz => match self.operands.first() {
-                Some(Operand::String(string)) => {
-                    let mut string = string.clone();
-                    string.push('\0');
+            Directive::Ascii => self.string_bytes(),
+            Directive::Asciiz => {
+                let mut bytes = self.string_bytes();
+                bytes.push(0);
 
-                    string.as_bytes().to_vec()
-                }
-                _ => vec![],
-            },
+                bytes
+            }
             Directive::Byte => self
                 .operands
                 .iter()
@@ -201,14 +279,36 @@ impl DirectiveInstruction {
                 .operands
                 .iter()
                 .filter_map(|operand| {
+                    // sign extended to wider words
                     if let &Operand::Value(value) = operand {
-                        Some((value as u32).to_be_bytes())
+                        Some((value as i64).to_be_bytes()[8 - word_size..].to_vec())
                     } else {
                         None
                     }
                 })
                 .flatten()
                 .collect(),
+            Directive::Fill | Directive::Matrix => {
+                let (layout, values) = self.layout().zip(self.element_values())?;
+
+                // a fill repeats its single value, while a matrix is zeroed after its values
+                let values = match self.directive {
+                    Directive::Fill => vec![values.first().copied().unwrap_or(0); layout.len()],
+                    _ => values,
+                };
+
+                values
+                    .into_iter()
+                    .flat_map(|value| {
+                        let bytes = (value as u32).to_be_bytes();
+                        bytes[4 - layout.element_size..].to_vec()
+                    })
+                    .collect()
+            }
+            Directive::Incbin => match self.operands.first() {
+                Some(Operand::Bytes(bytes)) => bytes.clone(),
+                _ => vec![],
+            },
             _ => vec![],
         };
 
@@ -219,8 +319,63 @@ impl DirectiveInstruction {
         Some(bytes)
     }
 
+    /// Bytes of an `.ascii` or `.asciiz` directive's operands, which are strings and numeric bytes
+    /// stored one after another
+    fn string_bytes(&self) -> Vec<u8> {
+        self.operands
+            .iter()
+            .flat_map(|operand| match operand {
+                Operand::String(string) => string.as_bytes().to_vec(),
+                &Operand::Value(value) => vec![value as u8],
+                _ => Vec::new(),
+            })
+            .collect()
+    }
+
+    /// Element size and dimensions of a `.fill count, size, value` or
+    /// `.matrix rows, cols, size, values...` directive, or None if the directive isn't one of
+    /// these or its operands are invalid
+    pub(crate) fn layout(&self) -> Option<DataLayout> {
+        let dimension = |operand: &Operand| match operand {
+            &Operand::Value(value) => usize::try_from(value).ok(),
+            _ => None,
+        };
+
+        let (element_size, dimensions, values) = match (self.directive, &self.operands[..]) {
+            (Directive::Fill, [count, size, values @ ..]) if values.len() <= 1 => {
+                (dimension(size)?, vec![dimension(count)?], values)
+            }
+            (Directive::Matrix, [rows, columns, size, values @ ..]) => (
+                dimension(size)?,
+                vec![dimension(rows)?, dimension(columns)?],
+                values,
+            ),
+            _ => return None,
+        };
+
+        let layout = DataLayout::new(element_size, dimensions)?;
+        (values.len() <= layout.len() || self.directive == Directive::Fill).then_some(layout)
+    }
+
+    /// Initial element values given to a `.fill` or `.matrix` directive
+    fn element_values(&self) -> Option<Vec<i32>> {
+        let skip = match self.directive {
+            Directive::Fill => 2,
+            _ => 3,
+        };
+
+        self.operands
+            .iter()
+            .skip(skip)
+            .map(|operand| match operand {
+                &Operand::Value(value) => Some(value),
+                _ => None,
+            })
+            .collect()
+    }
+
     fn align(value: usize, alignment: usize) -> usize {
-        ((value + alignment - 1) / alignment) * alignment
+        value.div_ceil(alignment) * alignment
     }
 }
 
@@ -231,11 +386,11 @@ fn parse_directive_instruction(input: &str) -> IResult<&str, DirectiveInstructio
             opt(parse_label_declaration),
             multispace0,
             parse_directive,
-            many0(delimited(multispace0, parse_operand, opt(char(',')))),
+            many0(delimited(space0, parse_operand, opt(char(',')))),
             parse_comment,
         )),
         |(label, _, directive, operands, _)| DirectiveInstruction {
-            label: label.map(str::to_owned),
+            label: label.map(Label::from_token),
             directive: directive.to_owned(),
             operands,
         },
@@ -260,6 +415,8 @@ mod tests {
                         Operand::Register(4),
                         Operand::Register(0)
                     ],
+                    generic: None,
+                    source: None,
                 })
             ))
         );
@@ -287,6 +444,8 @@ mod tests {
                     label: None,
                     opcode: Opcode::HLT,
                     operands: vec![],
+                    generic: None,
+                    source: None,
                 }
             ))
         );
@@ -299,6 +458,8 @@ mod tests {
                     label: None,
                     opcode: Opcode::LDBI,
                     operands: vec![Operand::Register(0)],
+                    generic: None,
+                    source: None,
                 }
             ))
         );
@@ -311,6 +472,8 @@ mod tests {
                     label: None,
                     opcode: Opcode::LDBI,
                     operands: vec![Operand::Label("label".into())],
+                    generic: None,
+                    source: None,
                 }
             ))
         );
@@ -327,6 +490,8 @@ mod tests {
                         Operand::Register(0),
                         Operand::Register(0)
                     ],
+                    generic: None,
+                    source: None,
                 }
             ))
         );
@@ -343,6 +508,8 @@ mod tests {
                         Operand::Register(4),
                         Operand::Register(0)
                     ],
+                    generic: None,
+                    source: None,
                 }
             ))
         );
@@ -386,6 +553,39 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_structured_data() {
+        let fill = DirectiveInstruction {
+            label: None,
+            directive: Directive::Fill,
+            operands: vec![Operand::Value(3), Operand::Value(2), Operand::Value(0x102)],
+        };
+        assert_eq!(fill.layout(), DataLayout::new(2, vec![3]));
+        assert_eq!(
+            fill.aligned_bytes(None, 4),
+            Some(vec![1, 2, 1, 2, 1, 2, 0, 0])
+        );
+
+        let matrix = DirectiveInstruction {
+            label: None,
+            directive: Directive::Matrix,
+            operands: [2, 3, 1, 1, 2, 3, 4].map(Operand::Value).to_vec(),
+        };
+        assert_eq!(matrix.layout(), DataLayout::new(1, vec![2, 3]));
+        assert_eq!(
+            matrix.aligned_bytes(Some(1), 4),
+            Some(vec![1, 2, 3, 4, 0, 0])
+        );
+
+        // more values than elements, and an unsupported element size
+        let mut invalid = matrix.clone();
+        invalid.operands[0] = Operand::Value(1);
+        assert_eq!(invalid.layout(), None);
+        invalid.operands[0] = Operand::Value(2);
+        invalid.operands[2] = Operand::Value(3);
+        assert_eq!(invalid.layout(), None);
+    }
+
     #[test]
     fn test_string_alignment() {
         assert_eq!(
@@ -394,7 +594,7 @@ mod tests {
                 directive: Directive::Asciiz,
                 operands: vec![Operand::String("hi".to_owned())],
             }
-            .aligned_bytes(None),
+            .aligned_bytes(None, 4),
             Some("hi\0\0".as_bytes().to_vec())
         );
 
@@ -404,7 +604,7 @@ mod tests {
                 directive: Directive::Asciiz,
                 operands: vec![Operand::String("hey".to_owned())],
             }
-            .aligned_bytes(None),
+            .aligned_bytes(None, 4),
             Some("hey\0".as_bytes().to_vec())
         );
 
@@ -414,8 +614,34 @@ mod tests {
                 directive: Directive::Asciiz,
                 operands: vec![Operand::String("hiii".to_owned())],
             }
-            .aligned_bytes(None),
+            .aligned_bytes(None, 4),
             Some("hiii\0\0\0\0".as_bytes().to_vec())
         );
     }
+
+    #[test]
+    fn test_multiple_string_operands() {
+        let mut directive = DirectiveInstruction {
+            label: None,
+            directive: Directive::Ascii,
+            operands: vec![
+                Operand::String("ab".to_owned()),
+                Operand::Value(10),
+                Operand::String("cd".to_owned()),
+            ],
+        };
+        assert_eq!(directive.size(Some(1), 4), 5);
+        assert_eq!(
+            directive.aligned_bytes(None, 4),
+            Some(b"ab\ncd\0\0\0".to_vec())
+        );
+
+        // the null byte only follows the last operand
+        directive.directive = Directive::Asciiz;
+        assert_eq!(directive.size(Some(1), 4), 6);
+        assert_eq!(
+            directive.aligned_bytes(Some(1), 4),
+            Some(b"ab\ncd\0".to_vec())
+        );
+    }
 }
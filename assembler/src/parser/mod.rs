@@ -5,15 +5,19 @@ mod label_declaration;
 mod opcode;
 pub mod operand;
 
+use crate::assembler::AssemblerError;
+use crate::parser::comment::parse_blank;
 use crate::parser::instruction::parse_instruction;
 use instruction::AssemblerInstruction;
 use nom::branch::alt;
 use nom::bytes::complete::{is_a, tag, take_while};
-use nom::character::complete::{digit1, hex_digit1, multispace0};
-use nom::combinator::{map_res, opt};
+use nom::character::complete::{digit1, hex_digit1};
+use nom::combinator::{consumed, map_res, opt};
 use nom::multi::many0;
 use nom::sequence::{delimited, pair, separated_pair};
 use nom::IResult;
+use std::fmt::{Display, Formatter};
+use std::rc::Rc;
 
 #[derive(Debug)]
 pub struct Program {
@@ -21,11 +25,186 @@ pub struct Program {
 }
 
 impl Program {
-    pub fn parse(text: &str) -> Option<Self> {
-        let (_, instructions) =
-            many0(delimited(multispace0, parse_instruction, multispace0))(text).ok()?;
+    /// Parses assembly source, returning an error pointing at the first token that couldn't be
+    /// parsed
+    pub fn parse(text: &str) -> Result<Self, AssemblerError> {
+        let (rest, parsed) = many0(delimited(
+            parse_blank,
+            consumed(parse_instruction),
+            parse_blank,
+        ))(text)
+        .map_err(|error| match error {
+            nom::Err::Failure(error) => Self::unknown_mnemonic(text, error.input),
+            _ => Self::parse_error(text, text),
+        })?;
 
-        Some(Self { instructions })
+        // anything left over couldn't be parsed
+        if !rest.is_empty() {
+            return Err(Self::parse_error(text, rest));
+        }
+
+        // instructions are in source order, so lines are counted up to each in turn
+        let (mut line, mut counted) = (1, 0);
+        let mut instructions = Vec::with_capacity(parsed.len());
+        for (token, mut instruction) in parsed {
+            for label in instruction.labels_mut() {
+                label.span.rebase(text);
+            }
+
+            if let AssemblerInstruction::Opcode(opcode) = &mut instruction {
+                // the opcode can be on a line after its label
+                let start = match &opcode.label {
+                    Some(label) => text.len() - text[label.span.end + 1..].trim_start().len(),
+                    None => token.as_ptr() as usize - text.as_ptr() as usize,
+                };
+                line += text[counted..start].matches('\n').count();
+                counted = start;
+                opcode.source = Some(SourceLine { file: None, line });
+            }
+
+            instructions.push(instruction);
+        }
+
+        Ok(Self { instructions })
+    }
+
+    /// Error for the token at the start of the unparsed remainder of the source
+    fn parse_error(text: &str, rest: &str) -> AssemblerError {
+        let rest = rest.trim_start();
+        let token = rest.split_whitespace().next().unwrap_or_default();
+
+        AssemblerError::ParseError {
+            location: Location::of(text, text.len() - rest.len()),
+            token: token.to_owned(),
+        }
+    }
+
+    /// Error for the mnemonic at the start of the unparsed remainder of the source, which isn't one
+    /// the assembler knows
+    fn unknown_mnemonic(text: &str, rest: &str) -> AssemblerError {
+        let len = rest
+            .find(|c: char| !c.is_alphanumeric() && c != '.')
+            .unwrap_or(rest.len());
+
+        AssemblerError::UnknownMnemonic {
+            location: Location::of(text, text.len() - rest.len()),
+            mnemonic: rest[..len].to_owned(),
+        }
+    }
+
+    /// Spans of every declaration and usage of the label with the given name, in source order
+    pub fn label_spans(&self, name: &str) -> Vec<Span> {
+        let mut spans = self
+            .instructions
+            .iter()
+            .flat_map(AssemblerInstruction::labels)
+            .filter(|label| label.name == name)
+            .map(|label| label.span)
+            .collect::<Vec<_>>();
+        spans.sort_by_key(|span| span.start);
+
+        spans
+    }
+}
+
+/// File and line an instruction was written on, so code can be mapped back to its source
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SourceLine {
+    /// Path of the file, or None for source assembled without one
+    pub file: Option<Rc<str>>,
+    /// Line number, starting from 1
+    pub line: usize,
+}
+
+/// Line and column of a position in the source text, both starting from 1
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Location {
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Location {
+    /// Finds the location of a byte offset into the source
+    pub fn of(source: &str, offset: usize) -> Self {
+        let before = &source[..offset];
+        let line_start = before.rfind('\n').map_or(0, |newline| newline + 1);
+
+        Self {
+            line: before.matches('\n').count() + 1,
+            column: before[line_start..].chars().count() + 1,
+        }
+    }
+}
+
+impl Display for Location {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// Byte range of a token within the source text
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    /// Where the token starts, for reporting errors
+    pub location: Location,
+}
+
+impl Span {
+    /// Creates a span from a token sliced out of the source text. Parsers only see the remaining
+    /// input, so the span holds the token's address until [`Span::rebase`] makes it relative to
+    /// the start of the source.
+    fn from_token(token: &str) -> Self {
+        let start = token.as_ptr() as usize;
+
+        Self {
+            start,
+            end: start + token.len(),
+            location: Location::default(),
+        }
+    }
+
+    /// Makes a span created by [`Span::from_token`] relative to the source it was sliced from
+    fn rebase(&mut self, source: &str) {
+        let base = source.as_ptr() as usize;
+
+        self.start -= base;
+        self.end -= base;
+        self.location = Location::of(source, self.start);
+    }
+}
+
+/// Label declaration or usage, along with where it appears in the source
+#[derive(Debug, Clone, Default)]
+pub struct Label {
+    pub name: String,
+    pub span: Span,
+}
+
+impl Label {
+    /// Creates a label from a token sliced out of the source text
+    fn from_token(token: &str) -> Self {
+        Self {
+            name: token.to_owned(),
+            span: Span::from_token(token),
+        }
+    }
+}
+
+/// Labels are equal if their names match, wherever they appear in the source
+impl PartialEq for Label {
+    fn eq(&self, other: &Self) -> bool {
+        self.name == other.name
+    }
+}
+
+impl From<&str> for Label {
+    fn from(value: &str) -> Self {
+        Self {
+            name: value.to_owned(),
+            span: Span::default(),
+        }
     }
 }
 
@@ -113,7 +292,8 @@ mod tests {
 
     #[test]
     fn test_parse_program() {
-        let program = r#".data
+        let text = r#"; greetings
+                                .data
                                     hello: .asciiz 'Hello'
                                     world: .asciiz 'world!'
                                 .code
@@ -121,7 +301,7 @@ mod tests {
                                     ldbi 2,$0,$0
                                     ldbi @loop"#;
 
-        let program = Program::parse(&program).unwrap();
+        let program = Program::parse(text).unwrap();
 
         assert_eq!(
             program.instructions,
@@ -150,9 +330,48 @@ mod tests {
                 AssemblerInstruction::new_opcode(
                     None,
                     Opcode::LDBI,
-                    &[Operand::Label("loop".to_owned())]
+                    &[Operand::Label("loop".into())]
                 )
             ]
-        )
+        );
+
+        let spans = program.label_spans("loop");
+        assert_eq!(spans.len(), 2);
+        for span in spans {
+            assert_eq!(&text[span.start..span.end], "loop");
+        }
+        assert_eq!(
+            program.label_spans("world")[0].location,
+            Location {
+                line: 4,
+                column: 37
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_error_location() {
+        let error = Program::parse(".code\n    addi $0, 1\n    addi $0, %5 ; bad").unwrap_err();
+
+        assert!(matches!(
+            error,
+            AssemblerError::ParseError { location: Location { line: 3, column: 14 }, token }
+                if token == "%5"
+        ));
+    }
+
+    #[test]
+    fn test_unknown_mnemonic() {
+        let error = Program::parse(".code\n    addi $0, 1\n    loop: adi $0, 1").unwrap_err();
+
+        assert!(matches!(
+            error,
+            AssemblerError::UnknownMnemonic { location: Location { line: 3, column: 11 }, mnemonic }
+                if mnemonic == "adi"
+        ));
+        assert!(matches!(
+            Program::parse(".code\n    ld.q $0, 1"),
+            Err(AssemblerError::UnknownMnemonic { mnemonic, .. }) if mnemonic == "ld.q"
+        ));
     }
 }
@@ -1,11 +1,52 @@
-use nom::character::complete::alpha1;
-use nom::combinator::map;
+use nom::character::complete::{alpha1, alphanumeric0, alphanumeric1, char};
+use nom::combinator::{opt, recognize};
+use nom::error::{Error, ErrorKind};
+use nom::sequence::pair;
 use nom::IResult;
-use shared::Opcode;
+use shared::{AddressingMode, Opcode};
 
-/// Parses an opcode, such as LOAD
-pub(super) fn parse_opcode(input: &str) -> IResult<&str, Opcode> {
-    map(alpha1, Opcode::from)(input)
+/// Parses an opcode, such as LDBI or ADD64, or a generic mnemonic such as `ld.b`, which leaves the
+/// addressing mode to be chosen from the instruction's operands. A generic mnemonic is returned
+/// with one of the opcodes it can assemble to. Fails without trying anything else if the mnemonic
+/// isn't one, since nothing else starts with a letter
+pub(super) fn parse_opcode(input: &str) -> IResult<&str, (Opcode, Option<String>)> {
+    let (rest, mnemonic) = recognize(pair(
+        pair(alpha1, alphanumeric0),
+        opt(pair(char('.'), alphanumeric1)),
+    ))(input)?;
+
+    let opcode = match mnemonic.split_once('.') {
+        Some((name, suffix)) => {
+            generic_opcode(name, suffix).map(|opcode| (opcode, Some(mnemonic.to_lowercase())))
+        }
+        None => Opcode::from_mnemonic(mnemonic).map(|opcode| (opcode, None)),
+    };
+
+    match opcode {
+        Some(opcode) => Ok((rest, opcode)),
+        None => Err(nom::Err::Failure(Error::new(input, ErrorKind::Tag))),
+    }
+}
+
+/// Opcode of a generic mnemonic, found by appending the letter of an addressing mode to its name
+/// and suffix, so `ld.b` can assemble to LDBI, LDBD or LDBR. Stores can be written `st.w` as well
+/// as `str.w`
+fn generic_opcode(name: &str, suffix: &str) -> Option<Opcode> {
+    let stem = match name.to_lowercase().as_str() {
+        "st" if !suffix.starts_with(|c: char| c.is_ascii_digit()) => format!("str{suffix}"),
+        name => format!("{name}{suffix}"),
+    };
+
+    [
+        (AddressingMode::Immediate, 'i'),
+        (AddressingMode::Direct, 'd'),
+        (AddressingMode::Register, 'r'),
+    ]
+    .into_iter()
+    .find_map(|(mode, letter)| {
+        Opcode::from_mnemonic(&format!("{stem}{letter}"))
+            .filter(|opcode| opcode.mode() == Some(mode))
+    })
 }
 
 #[cfg(test)]
@@ -14,10 +55,29 @@ mod tests {
 
     #[test]
     fn test_parse_opcode() {
-        assert_eq!(parse_opcode("ldbi"), Ok(("", Opcode::LDBI)));
-        assert_eq!(parse_opcode("LdBi"), Ok(("", Opcode::LDBI)));
-        assert_eq!(parse_opcode("hlt"), Ok(("", Opcode::HLT)));
+        assert_eq!(parse_opcode("ldbi"), Ok(("", (Opcode::LDBI, None))));
+        assert_eq!(parse_opcode("LdBi"), Ok(("", (Opcode::LDBI, None))));
+        assert_eq!(parse_opcode("hlt"), Ok(("", (Opcode::HLT, None))));
+        assert_eq!(parse_opcode("add64 $0"), Ok((" $0", (Opcode::ADD64, None))));
+
+        let unknown = nom::Err::Failure(Error::new("unknown $0", ErrorKind::Tag));
+        assert_eq!(parse_opcode("unknown $0"), Err(unknown));
+    }
+
+    #[test]
+    fn test_parse_generic_opcode() {
+        let generic = |opcode, mnemonic: &str| Ok(("", (opcode, Some(mnemonic.to_owned()))));
+
+        assert_eq!(parse_opcode("ld.b"), generic(Opcode::LDBI, "ld.b"));
+        assert_eq!(parse_opcode("LD.W"), generic(Opcode::LDWD, "ld.w"));
+        assert_eq!(parse_opcode("st.h"), generic(Opcode::STRHI, "st.h"));
+        assert_eq!(parse_opcode("str.h"), generic(Opcode::STRHI, "str.h"));
+        assert_eq!(parse_opcode("ld.64"), generic(Opcode::LD64D, "ld.64"));
+        assert_eq!(
+            parse_opcode("ld.b $0, 1"),
+            Ok((" $0, 1", (Opcode::LDBI, Some("ld.b".to_owned()))))
+        );
 
-        assert_eq!(parse_opcode("unknown"), Ok(("", Opcode::IGL)));
+        assert!(parse_opcode("ld.q").is_err());
     }
 }
@@ -0,0 +1,24 @@
+use nom::character::complete::{alpha1, alphanumeric0, char};
+use nom::combinator::{not, recognize};
+use nom::sequence::{pair, terminated};
+use nom::IResult;
+
+/// Parses a constant usage of the form <name>, where name starts with a letter followed by
+/// alphanumeric characters. Names followed by a colon are label declarations, so aren't matched
+pub(super) fn parse_constant_usage(input: &str) -> IResult<&str, &str> {
+    terminated(recognize(pair(alpha1, alphanumeric0)), not(char(':')))(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_constant_usage() {
+        assert_eq!(parse_constant_usage("SIZE"), Ok(("", "SIZE")));
+        assert_eq!(parse_constant_usage("max2, 1"), Ok((", 1", "max2")));
+
+        assert!(parse_constant_usage("2max").is_err());
+        assert!(parse_constant_usage("loop:").is_err());
+    }
+}
@@ -0,0 +1,48 @@
+use crate::parser::operand::label::parse_label_usage;
+use crate::parser::{parse_number, Label};
+use nom::branch::alt;
+use nom::character::complete::char;
+use nom::combinator::map;
+use nom::sequence::preceded;
+use nom::IResult;
+
+/// Word placed in a literal pool, loaded from its pool entry
+#[derive(PartialEq, Debug, Clone)]
+pub enum Literal {
+    Value(i32),
+    Label(Label),
+}
+
+/// Parses a literal of the form =<number> or =@<label>
+pub(super) fn parse_literal(input: &str) -> IResult<&str, Literal> {
+    preceded(
+        char('='),
+        alt((
+            map(parse_number, Literal::Value),
+            map(parse_label_usage, |label| {
+                Literal::Label(Label::from_token(label))
+            }),
+        )),
+    )(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_literal() {
+        assert_eq!(
+            parse_literal("=12345678"),
+            Ok(("", Literal::Value(12345678)))
+        );
+        assert_eq!(parse_literal("=-0x10"), Ok(("", Literal::Value(-16))));
+        assert_eq!(
+            parse_literal("=@test"),
+            Ok(("", Literal::Label("test".into())))
+        );
+
+        assert!(parse_literal("12").is_err());
+        assert!(parse_literal("=$1").is_err());
+    }
+}
@@ -1,30 +1,48 @@
+use crate::parser::operand::constant::parse_constant_usage;
 use crate::parser::operand::label::parse_label_usage;
+use crate::parser::operand::literal::parse_literal;
 use crate::parser::operand::register::parse_register;
 use crate::parser::operand::string::parse_string;
-use crate::parser::parse_number;
+use crate::parser::{parse_number, Label};
 use nom::branch::alt;
 use nom::combinator::map;
 use nom::IResult;
 
+mod constant;
 mod label;
+mod literal;
 mod register;
 mod string;
 
+pub use literal::Literal;
+
 #[derive(PartialEq, Debug, Clone)]
 pub enum Operand {
     Register(u8),
     Value(i32),
-    Label(String),
+    Label(Label),
     String(String),
+    Literal(Literal),
+    /// Name of a constant defined with `.equ`
+    Constant(Label),
+    /// Contents of a file embedded with `.incbin`, read while includes are expanded
+    Bytes(Vec<u8>),
 }
 
-/// Parses an operand which can either be a register, value, or label usage
+/// Parses an operand which can either be a register, value, label usage, string, literal or
+/// constant
 pub(super) fn parse_operand(input: &str) -> IResult<&str, Operand> {
     alt((
         map(parse_register, Operand::Register),
         map(parse_number, Operand::Value),
-        map(parse_label_usage, |label| Operand::Label(label.to_owned())),
-        map(parse_string, |string| Operand::String(string.to_owned())),
+        map(parse_label_usage, |label| {
+            Operand::Label(Label::from_token(label))
+        }),
+        map(parse_string, Operand::String),
+        map(parse_literal, Operand::Literal),
+        map(parse_constant_usage, |name| {
+            Operand::Constant(Label::from_token(name))
+        }),
     ))(input)
 }
 
@@ -38,14 +56,24 @@ mod tests {
         assert_eq!(parse_operand("100"), Ok(("", Operand::Value(100))));
         assert_eq!(
             parse_operand("@test"),
-            Ok(("", Operand::Label("test".to_owned())))
+            Ok(("", Operand::Label("test".into())))
         );
         assert_eq!(
             parse_operand("'hi'"),
             Ok(("", Operand::String("hi".to_owned())))
         );
 
+        assert_eq!(
+            parse_operand("=@test"),
+            Ok(("", Operand::Literal(Literal::Label("test".into()))))
+        );
+
+        assert_eq!(
+            parse_operand("SIZE"),
+            Ok(("", Operand::Constant("SIZE".into())))
+        );
+
         assert!(parse_operand("@[]").is_err());
-        assert!(parse_operand("test").is_err());
+        assert!(parse_operand("test:").is_err());
     }
 }
@@ -1,12 +1,25 @@
 use crate::parser::parse_number;
-use nom::character::complete::char;
-use nom::combinator::map;
+use nom::branch::alt;
+use nom::character::complete::{alphanumeric1, char};
+use nom::combinator::{map, map_opt};
 use nom::sequence::preceded;
 use nom::IResult;
+use shared::abi::REGISTER_NAMES;
 
-/// Parses a register of the form $<number>
+/// Parses a register of the form $<number> or $<name>, such as $sp
 pub(super) fn parse_register(input: &str) -> IResult<&str, u8> {
-    map(preceded(char('$'), parse_number), |number| number as u8)(input)
+    preceded(
+        char('$'),
+        alt((
+            map(parse_number, |number| number as u8),
+            map_opt(alphanumeric1, |name| {
+                REGISTER_NAMES
+                    .iter()
+                    .position(|&known| known == name)
+                    .map(|index| index as u8)
+            }),
+        )),
+    )(input)
 }
 
 #[cfg(test)]
@@ -21,5 +34,12 @@ mod tests {
 
         assert_eq!(parse_register("$4a4"), Ok(("a4", 4)));
         assert!(parse_register("4a4").is_err());
+
+        assert_eq!(parse_register("$sp"), Ok(("", 31)));
+        assert_eq!(parse_register("$t0, $1"), Ok((", $1", 6)));
+        assert_eq!(parse_register("$s13"), Ok(("", 29)));
+        // unsupported, since there's no zero or return address register
+        assert!(parse_register("$zero").is_err());
+        assert!(parse_register("$ra").is_err());
     }
 }
@@ -1,19 +1,50 @@
 use nom::branch::alt;
-use nom::bytes::complete::take_until;
+use nom::bytes::complete::{escaped_transform, is_not, take_while_m_n};
 use nom::character::complete::char;
-use nom::combinator::map;
-use nom::sequence::tuple;
+use nom::combinator::{map, map_opt, opt, value};
+use nom::sequence::{delimited, preceded};
 use nom::IResult;
 
-/// Parses a string of the form "<string>" or '<string>'
-pub(super) fn parse_string(input: &str) -> IResult<&str, &str> {
+/// Parses a string of the form "<string>" or '<string>', replacing escape sequences with the
+/// characters they stand for
+pub(super) fn parse_string(input: &str) -> IResult<&str, String> {
+    alt((
+        delimited(char('\''), contents("\\'"), char('\'')),
+        delimited(char('\"'), contents("\\\""), char('\"')),
+    ))(input)
+}
+
+/// Characters up to the closing quote, where `terminators` is the quote and a backslash
+fn contents<'a>(terminators: &'static str) -> impl FnMut(&'a str) -> IResult<&'a str, String> {
     map(
-        alt((
-            tuple((char('\''), take_until("'"), char('\''))),
-            tuple((char('\"'), take_until("\""), char('\"'))),
-        )),
-        |(_, string, _)| string,
-    )(input)
+        opt(escaped_transform(is_not(terminators), '\\', parse_escape)),
+        Option::unwrap_or_default,
+    )
+}
+
+/// Parses the part of an escape sequence after the backslash: `n`, `t`, `0`, `\`, a quote, or `x`
+/// followed by two hex digits giving an ASCII character
+fn parse_escape(input: &str) -> IResult<&str, char> {
+    alt((
+        value('\n', char('n')),
+        value('\t', char('t')),
+        value('\0', char('0')),
+        value('\\', char('\\')),
+        value('\'', char('\'')),
+        value('"', char('"')),
+        map_opt(
+            preceded(
+                char('x'),
+                take_while_m_n(2, 2, |c: char| c.is_ascii_hexdigit()),
+            ),
+            |hex| {
+                u8::from_str_radix(hex, 16)
+                    .ok()
+                    .filter(u8::is_ascii)
+                    .map(char::from)
+            },
+        ),
+    ))(input)
 }
 
 #[cfg(test)]
@@ -22,10 +53,28 @@ mod tests {
 
     #[test]
     fn test_parse_string() {
-        assert_eq!(parse_string("'hi'"), Ok(("", "hi")));
-        assert_eq!(parse_string(r#""test""#), Ok(("", "test")));
+        assert_eq!(parse_string("'hi'"), Ok(("", "hi".to_owned())));
+        assert_eq!(parse_string(r#""test""#), Ok(("", "test".to_owned())));
+        assert_eq!(parse_string("''"), Ok(("", String::new())));
 
         assert!(parse_string(r#"'no worky""#).is_err());
         assert!(parse_string("test").is_err());
     }
+
+    #[test]
+    fn test_escapes() {
+        assert_eq!(
+            parse_string(r#""a\tb\n\0""#),
+            Ok(("", "a\tb\n\0".to_owned()))
+        );
+        assert_eq!(
+            parse_string(r#"'it\'s "\\" \x41'"#),
+            Ok(("", "it's \"\\\" A".to_owned()))
+        );
+        assert_eq!(parse_string(r"'\n'"), Ok(("", "\n".to_owned())));
+
+        assert!(parse_string(r"'\q'").is_err());
+        assert!(parse_string(r"'\x4'").is_err());
+        assert!(parse_string(r"'\xFF'").is_err());
+    }
 }
@@ -0,0 +1,69 @@
+use crate::assembler::AssemblerError;
+use crate::parser::Program;
+
+/// Renames every declaration and usage of a label, leaving the rest of the source untouched
+pub fn rename_label(source: &str, old: &str, new: &str) -> Result<String, AssemblerError> {
+    if new.is_empty() || !new.chars().all(|c| c.is_ascii_alphanumeric()) {
+        return Err(AssemblerError::InvalidLabelName {
+            name: new.to_owned(),
+        });
+    }
+
+    let program = Program::parse(source)?;
+
+    if let Some(existing) = program.label_spans(new).first() {
+        return Err(AssemblerError::SymbolAlreadyDeclared {
+            name: new.to_owned(),
+            location: existing.location,
+        });
+    }
+
+    let spans = program.label_spans(old);
+    if spans.is_empty() {
+        return Err(AssemblerError::UndefinedSymbol {
+            name: old.to_owned(),
+            location: None,
+        });
+    }
+
+    // replace from the end so earlier spans stay valid
+    let mut out = source.to_owned();
+    for span in spans.iter().rev() {
+        out.replace_range(span.start..span.end, new);
+    }
+
+    Ok(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_rename_label() {
+        let source = r#".data
+string: .asciiz "a"     ; string @string
+.code
+loop:   prtsd @string   ; prints string
+        jmpi @loop"#;
+
+        assert_eq!(
+            rename_label(source, "string", "text").unwrap(),
+            r#".data
+text: .asciiz "a"     ; string @string
+.code
+loop:   prtsd @text   ; prints string
+        jmpi @loop"#
+        );
+
+        assert!(matches!(
+            rename_label(source, "missing", "text"),
+            Err(AssemblerError::UndefinedSymbol { .. })
+        ));
+        assert!(matches!(
+            rename_label(source, "string", "loop"),
+            Err(AssemblerError::SymbolAlreadyDeclared { location, .. }) if location.line == 4
+        ));
+        assert!(rename_label(source, "string", "a b").is_err());
+    }
+}
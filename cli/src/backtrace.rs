@@ -0,0 +1,162 @@
+use shared::abi::{STACK_POINTER, STACK_TOP};
+use std::fmt::Write;
+use vm::VM;
+
+/// Most words of a stack frame printed by `stack_frame`
+const MAX_FRAME_WORDS: usize = 64;
+
+/// Lists the instruction the VM stopped at followed by every call that hasn't returned, innermost
+/// first, or returns None if the shadow stack isn't enabled. `context` describes an address, such
+/// as ` <main+8>`
+pub fn backtrace(vm: &VM, context: impl Fn(usize) -> String) -> Option<String> {
+    let frames = vm.backtrace()?;
+    let pc = vm.instruction_pc();
+
+    let mut out = format!("backtrace:\n  #0 {pc:#06X}{}\n", context(pc));
+    for (index, frame) in frames.iter().rev().enumerate() {
+        writeln!(
+            out,
+            "  #{} {:#06X}{} calling {:#06X}{}, returns to {:#06X}",
+            index + 1,
+            frame.call_site,
+            context(frame.call_site),
+            frame.routine,
+            context(frame.routine),
+            frame.return_address
+        )
+        .unwrap();
+    }
+
+    Some(out)
+}
+
+/// Lists the words on the stack frame of the routine the VM stopped in, from the stack pointer up
+/// to the return address its caller pushed, or the whole stack outside of any call. Returns None
+/// if the shadow stack isn't enabled. `context` describes an address, such as ` <main+8>`
+pub fn stack_frame(vm: &VM, context: impl Fn(usize) -> String) -> Option<String> {
+    let frames = vm.backtrace()?;
+    let stack_pointer = vm.registers()[STACK_POINTER as usize] as u32 as usize;
+
+    let (mut out, end) = match frames.last() {
+        Some(frame) => (
+            format!(
+                "frame of {:#06X}{}, returns to {:#06X}{}\n",
+                frame.routine,
+                context(frame.routine),
+                frame.return_address,
+                context(frame.return_address)
+            ),
+            frame.stack_pointer + 4,
+        ),
+        None => ("frame of the entry point\n".to_owned(), STACK_TOP),
+    };
+    if stack_pointer > end {
+        writeln!(out, "  $sp {stack_pointer:#010X} is above the frame").unwrap();
+        return Some(out);
+    }
+
+    let words = (stack_pointer..end).step_by(4);
+    let len = words.len();
+    for address in words.take(MAX_FRAME_WORDS) {
+        let word = match vm.memory().read_u32(address) {
+            Ok(word) => format!("{word:08X}"),
+            Err(_) => "????????".to_owned(),
+        };
+        let note = match address {
+            _ if address == stack_pointer => "  <- $sp",
+            _ if address + 4 == end && !frames.is_empty() => "  return address",
+            _ => "",
+        };
+
+        writeln!(out, "  {address:#010X}  {word}{note}").unwrap();
+    }
+    if len > MAX_FRAME_WORDS {
+        writeln!(out, "  ... {} more words", len - MAX_FRAME_WORDS).unwrap();
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::repl::label_context;
+    use assembler::Assembler;
+    use vm::Memory;
+
+    #[test]
+    fn test_backtrace() {
+        let mut assembler = Assembler::default();
+        let program = assembler
+            .assemble(
+                ".code
+                main: calli @outer
+                hlt
+                outer: calli @inner
+                ret
+                inner: free $0",
+            )
+            .unwrap();
+
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(program);
+        assert_eq!(backtrace(&vm, |_| String::new()), None);
+
+        vm.enable_shadow_stack(true);
+        assert!(vm.run().is_err());
+
+        let context = |address| {
+            let labels = assembler
+                .labels()
+                .map(|(name, label)| (name, label as usize));
+            label_context(labels, address)
+        };
+        assert_eq!(
+            backtrace(&vm, context).unwrap(),
+            "backtrace:
+  #0 0x0050 <inner+0>
+  #1 0x0048 <outer+0> calling 0x0050 <inner+0>, returns to 0x004C
+  #2 0x0040 <main+0> calling 0x0048 <outer+0>, returns to 0x0044
+"
+        );
+    }
+
+    #[test]
+    fn test_stack_frame() {
+        let program = Assembler::default()
+            .assemble(
+                ".code
+                main: ldbi $t0, 7
+                push $t0
+                calli @inner
+                hlt
+                inner: push $t0
+                push $t0
+                free $0",
+            )
+            .unwrap();
+
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(program);
+        assert_eq!(stack_frame(&vm, |_| String::new()), None);
+
+        vm.enable_shadow_stack(true);
+        vm.start().unwrap();
+        vm.run_once().unwrap();
+        vm.run_once().unwrap();
+        assert_eq!(
+            stack_frame(&vm, |_| String::new()).unwrap(),
+            "frame of the entry point\n  0x000FFFFC  00000007  <- $sp\n"
+        );
+
+        assert!(vm.run().is_err());
+        assert_eq!(
+            stack_frame(&vm, |_| String::new()).unwrap(),
+            "frame of 0x0050, returns to 0x004C
+  0x000FFFF0  00000007  <- $sp
+  0x000FFFF4  00000007
+  0x000FFFF8  0000004C  return address
+"
+        );
+    }
+}
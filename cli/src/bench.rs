@@ -0,0 +1,134 @@
+//! Benchmark programs bundled into the binary for `bench`, comparing how quickly `run` and
+//! `run --cached` execute them.
+//!
+//! Each program leaves a known result in `$a0`, which is checked after every run so a benchmark
+//! can't get faster by computing the wrong thing.
+
+use anyhow::bail;
+use assembler::Assembler;
+use std::time::{Duration, Instant};
+use vm::{Program, VM};
+
+/// Bundled program, with the value it leaves in `$a0`
+pub struct Benchmark {
+    pub name: &'static str,
+    source: &'static str,
+    result: i32,
+}
+
+pub const BENCHMARKS: [Benchmark; 3] = [
+    Benchmark {
+        name: "fibonacci",
+        source: include_str!("bench/fibonacci.asm"),
+        result: 46368,
+    },
+    Benchmark {
+        name: "memcpy",
+        source: include_str!("bench/memcpy.asm"),
+        result: 255,
+    },
+    Benchmark {
+        name: "sieve",
+        source: include_str!("bench/sieve.asm"),
+        result: 2262,
+    },
+];
+
+/// Way of running a program being compared
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Mode {
+    /// Fetching and decoding every instruction, as `run` does
+    Plain,
+    /// Executing hot loops from pre-decoded basic blocks, as `run --cached` does
+    Cached,
+}
+
+impl Mode {
+    pub const ALL: [Mode; 2] = [Mode::Plain, Mode::Cached];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Mode::Plain => "plain",
+            Mode::Cached => "cached",
+        }
+    }
+}
+
+/// Instructions executed by a benchmark and the fastest time it took to execute them
+pub struct Measurement {
+    pub steps: u64,
+    pub time: Duration,
+}
+
+impl Measurement {
+    pub fn instructions_per_second(&self) -> f64 {
+        self.steps as f64 / self.time.as_secs_f64()
+    }
+}
+
+impl Benchmark {
+    /// Runs the program the given number of times, keeping the fastest
+    pub fn measure(&self, mode: Mode, iterations: u32) -> anyhow::Result<Measurement> {
+        let program = Program::parse(Assembler::default().assemble(self.source)?)?;
+
+        let mut vm = VM::default();
+        vm.set_output(std::io::sink());
+        vm.load(program);
+
+        let mut time = Duration::MAX;
+        for _ in 0..iterations.max(1) {
+            let start = Instant::now();
+            match mode {
+                Mode::Plain => vm.run()?,
+                Mode::Cached => vm.run_cached()?,
+            }
+            time = time.min(start.elapsed());
+
+            if vm.registers()[0] != self.result {
+                bail!(
+                    "{} left {} in $a0, expected {}",
+                    self.name,
+                    vm.registers()[0],
+                    self.result
+                );
+            }
+        }
+
+        Ok(Measurement {
+            steps: vm.steps(),
+            time,
+        })
+    }
+}
+
+/// Formats a rate with a metric suffix, such as `12.3M`
+pub fn format_rate(rate: f64) -> String {
+    match rate {
+        rate if rate >= 1e9 => format!("{:.1}G", rate / 1e9),
+        rate if rate >= 1e6 => format!("{:.1}M", rate / 1e6),
+        rate if rate >= 1e3 => format!("{:.1}K", rate / 1e3),
+        rate => format!("{rate:.0}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_benchmarks() {
+        for benchmark in &BENCHMARKS {
+            let plain = benchmark.measure(Mode::Plain, 1).unwrap();
+            let cached = benchmark.measure(Mode::Cached, 1).unwrap();
+            assert_eq!(plain.steps, cached.steps, "{}", benchmark.name);
+            assert!(plain.steps > 100_000, "{}", benchmark.name);
+        }
+    }
+
+    #[test]
+    fn test_format_rate() {
+        assert_eq!(format_rate(12_345_678.0), "12.3M");
+        assert_eq!(format_rate(2.5e9), "2.5G");
+        assert_eq!(format_rate(999.0), "999");
+    }
+}
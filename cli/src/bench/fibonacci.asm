@@ -0,0 +1,22 @@
+; Computes the 24th Fibonacci number recursively, leaving it in $a0
+.code
+    main:   ldbi $a0, 24
+            calli @fib
+            hlt
+
+; $a0 <- fib($a0)
+    fib:    lti $a0, 2
+            jmpei @done
+            push $s0
+            push $s1
+            mov $s0, $a0
+            dec $a0
+            calli @fib
+            mov $s1, $a0
+            mov $a0, $s0
+            subi $a0, 2
+            calli @fib
+            addr $a0, $s1, $a0
+            pop $s1
+            pop $s0
+    done:   ret
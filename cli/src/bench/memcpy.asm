@@ -0,0 +1,29 @@
+; Copies a 4KB buffer a byte at a time, 64 times, leaving the last byte copied in $a0
+.equ SIZE, 4096
+.bss
+    source:         .space SIZE
+    destination:    .space SIZE
+.code
+            ldwd $t0, =@source
+            ldbi $t1, 0
+    fill:   addr $t2, $t0, $t1      ; source[i] <- i
+            strbr $t1, $t2
+            inc $t1
+            lti $t1, SIZE
+            jmpei @fill
+
+            ldbi $t5, 64            ; copies left
+    copy:   ldwd $t0, =@source
+            ldwd $t1, =@destination
+            ldwd $t2, =@destination
+            addi $t2, SIZE
+    byte:   ldbr $a0, $t0
+            strbr $a0, $t1
+            inc $t0
+            inc $t1
+            ltr $t1, $t2
+            jmpei @byte
+            dec $t5
+            gti $t5, 0
+            jmpei @copy
+            hlt
@@ -0,0 +1,25 @@
+; Counts the primes below 20000 with the sieve of Eratosthenes, leaving the count in $a0
+.equ LIMIT, 20000
+.bss
+    composite:  .space LIMIT
+.code
+            ldwd $t0, =@composite
+            ldbi $a0, 0
+            ldbi $t1, 2             ; candidate
+    next:   addr $t2, $t0, $t1
+            ldbr $t3, $t2
+            eqi $t3, 0
+            jmpnei @skip
+            inc $a0
+            addr $t4, $t1, $t1      ; cross off multiples, from 2 * candidate
+            ldbi $t5, 1
+    cross:  lti $t4, LIMIT
+            jmpnei @skip
+            addr $t2, $t0, $t4
+            strbr $t5, $t2
+            addr $t4, $t4, $t1
+            jmpi @cross
+    skip:   inc $t1
+            lti $t1, LIMIT
+            jmpei @next
+            hlt
@@ -0,0 +1,315 @@
+//! Converting programs to and from formats used by other toolchains.
+//!
+//! Raw binaries are a flat copy of the bytes, with nothing recording where they belong. Intel HEX
+//! files are text, one record per line, each giving a run of up to 16 bytes and the address they
+//! start at. Addresses above 0xFFFF are reached with extended linear address records, which set
+//! the upper 16 bits of the following records' addresses.
+//!
+//! Either can hold a whole EPIE program, header included, which is recognised by its magic once
+//! decoded and converted as a program rather than wrapped in another one.
+
+use anyhow::{bail, Context};
+use shared::{PIE_FORMAT_VERSION, PIE_HEADER_LENGTH, PIE_HEADER_PREFIX, PIE_WORD_SIZE};
+use std::fmt::Write;
+use std::path::Path;
+use vm::Memory;
+
+/// Bytes written in each Intel HEX data record
+const HEX_RECORD_LENGTH: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum ImageFormat {
+    /// Assembled program, with a header describing its sections
+    Epie,
+    /// Raw flat binary
+    Bin,
+    /// Intel HEX records
+    Hex,
+}
+
+impl ImageFormat {
+    /// Format of a file from its contents if it's an EPIE program, otherwise from its extension
+    pub fn guess(path: &Path, contents: Option<&[u8]>) -> Self {
+        if contents.is_some_and(|contents| contents.starts_with(&PIE_HEADER_PREFIX)) {
+            return ImageFormat::Epie;
+        }
+
+        match path.extension().and_then(|extension| extension.to_str()) {
+            Some("epie") => ImageFormat::Epie,
+            Some("hex" | "ihex") => ImageFormat::Hex,
+            _ => ImageFormat::Bin,
+        }
+    }
+}
+
+/// Converts an image between formats, taking the section from EPIE programs and placing payloads
+/// in it, or converting whole programs if there's no section. Anything that isn't a program is
+/// placed in the data section of EPIE programs unless the section is code
+pub fn convert(
+    bytes: Vec<u8>,
+    from: ImageFormat,
+    to: ImageFormat,
+    section: Option<Section>,
+    base: Option<u32>,
+) -> anyhow::Result<Vec<u8>> {
+    let (payload, address) = match from {
+        ImageFormat::Epie | ImageFormat::Bin => (bytes, 0),
+        ImageFormat::Hex => from_intel_hex(&String::from_utf8(bytes)?, base)?,
+    };
+
+    // programs decoded from other formats still start with their header
+    let program = from == ImageFormat::Epie || payload.starts_with(&PIE_HEADER_PREFIX);
+    let (payload, address) = match program {
+        true => {
+            let (payload, offset) = extract(&payload, section)?;
+            (payload, address + offset)
+        }
+        false => (payload, address),
+    };
+
+    Ok(match to {
+        // whole programs are already .epie
+        ImageFormat::Epie if program && section.is_none() => payload,
+        ImageFormat::Epie => wrap(&payload, section.unwrap_or(Section::Data)),
+        ImageFormat::Bin => payload,
+        ImageFormat::Hex => to_intel_hex(&payload, base.unwrap_or(address)).into_bytes(),
+    })
+}
+
+/// Section of an EPIE program a payload is taken from or placed in
+#[derive(Debug, Clone, Copy, PartialEq, clap::ValueEnum)]
+pub enum Section {
+    Data,
+    Code,
+}
+
+/// Bytes of an EPIE program along with the address of the first one. Without a section this is
+/// the whole program, header included
+pub fn extract(program: &[u8], section: Option<Section>) -> anyhow::Result<(Vec<u8>, u32)> {
+    let mut memory = Memory::new(program.to_vec());
+    memory.map_sections()?;
+
+    let range = match section {
+        None => 0..program.len(),
+        Some(Section::Data) => memory.data_section().unwrap(),
+        Some(Section::Code) => memory.code_section().unwrap(),
+    };
+
+    Ok((program[range.clone()].to_vec(), range.start as u32))
+}
+
+/// EPIE program holding the payload as its only section
+pub fn wrap(payload: &[u8], section: Section) -> Vec<u8> {
+    let (data, code) = match section {
+        Section::Data => (payload.len(), 0),
+        Section::Code => (0, payload.len()),
+    };
+
+    let mut out = Vec::with_capacity(PIE_HEADER_LENGTH + payload.len());
+    out.extend_from_slice(&PIE_HEADER_PREFIX);
+    out.extend_from_slice(&[PIE_FORMAT_VERSION, PIE_WORD_SIZE, 0, 0]);
+    out.extend_from_slice(&(PIE_HEADER_LENGTH as u32).to_be_bytes());
+    out.extend_from_slice(&(data as u32).to_be_bytes());
+    out.extend_from_slice(&((PIE_HEADER_LENGTH + data) as u32).to_be_bytes());
+    out.extend_from_slice(&(code as u32).to_be_bytes());
+    out.resize(PIE_HEADER_LENGTH, 0);
+
+    out.extend_from_slice(payload);
+    out
+}
+
+/// Intel HEX records placing the bytes at the base address, ending with an end of file record
+pub fn to_intel_hex(bytes: &[u8], base: u32) -> String {
+    let mut out = String::new();
+    let mut upper = 0;
+
+    let mut offset = 0;
+    while offset < bytes.len() {
+        let address = base.wrapping_add(offset as u32);
+        if address >> 16 != upper {
+            upper = address >> 16;
+            write_record(&mut out, 0, 4, &(upper as u16).to_be_bytes());
+        }
+
+        // records can't cross into the next 64KiB, since their addresses are only 16 bits
+        let len = HEX_RECORD_LENGTH
+            .min(bytes.len() - offset)
+            .min(0x10000 - (address & 0xFFFF) as usize);
+        write_record(&mut out, address as u16, 0, &bytes[offset..offset + len]);
+        offset += len;
+    }
+    write_record(&mut out, 0, 1, &[]);
+
+    out
+}
+
+/// Bytes described by Intel HEX records along with the address of the first one, which is the base
+/// address or the lowest address in the records if there's no base. Gaps between records are
+/// filled with zeroes
+pub fn from_intel_hex(text: &str, base: Option<u32>) -> anyhow::Result<(Vec<u8>, u32)> {
+    let mut runs = Vec::new();
+    let mut upper = 0;
+
+    for (line, record) in text.lines().map(str::trim).enumerate() {
+        let line = line + 1;
+        if record.is_empty() {
+            continue;
+        }
+
+        let bytes = record
+            .strip_prefix(':')
+            .filter(|hex| hex.len() % 2 == 0)
+            .and_then(|hex| {
+                (0..hex.len())
+                    .step_by(2)
+                    .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+                    .collect::<Option<Vec<_>>>()
+            })
+            .with_context(|| format!("line {line} isn't an Intel HEX record"))?;
+        if bytes.len() < 5 || bytes.len() != bytes[0] as usize + 5 {
+            bail!("record on line {line} has the wrong length");
+        }
+        if bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte)) != 0 {
+            bail!("record on line {line} has an incorrect checksum");
+        }
+
+        let address = u16::from_be_bytes([bytes[1], bytes[2]]) as u32;
+        let data = &bytes[4..bytes.len() - 1];
+        match (bytes[3], data) {
+            (0, _) => runs.push((upper + address, data.to_vec())),
+            (1, _) => break,
+            (2, &[high, low]) => upper = (u16::from_be_bytes([high, low]) as u32) << 4,
+            (4, &[high, low]) => upper = (u16::from_be_bytes([high, low]) as u32) << 16,
+            // start addresses don't say anything about where bytes go
+            (3 | 5, _) => {}
+            (kind, _) => bail!("record on line {line} has unsupported type {kind:02X}"),
+        }
+    }
+
+    let Some(start) = base.or_else(|| runs.iter().map(|&(address, _)| address).min()) else {
+        return Ok((Vec::new(), 0));
+    };
+    let mut out = Vec::new();
+    for (address, data) in runs {
+        let Some(offset) = address.checked_sub(start) else {
+            bail!("record at {address:#010X} is below the base address {start:#010X}");
+        };
+        let offset = offset as usize;
+
+        if out.len() < offset + data.len() {
+            out.resize(offset + data.len(), 0);
+        }
+        out[offset..offset + data.len()].copy_from_slice(&data);
+    }
+
+    Ok((out, start))
+}
+
+/// Writes a record of the given type, calculating its length and checksum
+fn write_record(out: &mut String, address: u16, kind: u8, data: &[u8]) {
+    let mut bytes = vec![data.len() as u8];
+    bytes.extend_from_slice(&address.to_be_bytes());
+    bytes.push(kind);
+    bytes.extend_from_slice(data);
+    let sum = bytes.iter().fold(0u8, |sum, &byte| sum.wrapping_add(byte));
+    bytes.push(sum.wrapping_neg());
+
+    out.push(':');
+    for byte in bytes {
+        write!(out, "{byte:02X}").unwrap();
+    }
+    out.push('\n');
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_intel_hex() {
+        let record = ":10010000214601360121470136007EFE09D2190140\n:00000001FF\n";
+        let (bytes, base) = from_intel_hex(record, None).unwrap();
+        assert_eq!(base, 0x100);
+        assert_eq!(bytes[..4], [0x21, 0x46, 0x01, 0x36]);
+        assert_eq!(to_intel_hex(&bytes, 0x100), record);
+
+        // crossing into the next 64KiB starts an extended linear address record
+        let bytes = (0..20).collect::<Vec<u8>>();
+        let hex = to_intel_hex(&bytes, 0xFFF8);
+        assert_eq!(hex.lines().nth(1), Some(":020000040001F9"));
+        assert_eq!(from_intel_hex(&hex, None).unwrap(), (bytes.clone(), 0xFFF8));
+        assert_eq!(from_intel_hex(&hex, Some(0xFFF0)).unwrap().0[8..], bytes);
+
+        assert!(from_intel_hex(&hex, Some(0x10000)).is_err());
+        assert!(from_intel_hex(":10010000214601360121470136007EFE09D2190141", None).is_err());
+        assert!(from_intel_hex("10010000", None).is_err());
+    }
+
+    #[test]
+    fn test_epie() {
+        let program = wrap(b"payload", Section::Data);
+        assert_eq!(
+            ImageFormat::guess(Path::new("a.bin"), Some(&program)),
+            ImageFormat::Epie
+        );
+
+        assert_eq!(
+            extract(&program, Some(Section::Data)).unwrap(),
+            (b"payload".to_vec(), 64)
+        );
+        assert_eq!(
+            extract(&program, Some(Section::Code)).unwrap(),
+            (vec![], 71)
+        );
+        assert_eq!(extract(&program, None).unwrap(), (program.clone(), 0));
+
+        assert!(extract(b"payload", None).is_err());
+    }
+
+    /// Bytes of a program in the given format, as `convert` would write them
+    fn encode(program: &[u8], format: ImageFormat) -> Vec<u8> {
+        match format {
+            ImageFormat::Epie | ImageFormat::Bin => program.to_vec(),
+            ImageFormat::Hex => to_intel_hex(program, 0).into_bytes(),
+        }
+    }
+
+    #[test]
+    fn test_round_trips() {
+        let formats = [ImageFormat::Epie, ImageFormat::Bin, ImageFormat::Hex];
+        let program = wrap(&[0x18, 1, 0, 0, 0, 0, 0, 0], Section::Code);
+
+        for from in formats {
+            for to in formats {
+                // whole programs come back unchanged, without being wrapped in another program
+                let original = encode(&program, from);
+                let converted = convert(original.clone(), from, to, None, None).unwrap();
+                assert_eq!(converted, encode(&program, to), "{from:?} to {to:?}");
+                let back = convert(converted, to, from, None, None).unwrap();
+                assert_eq!(back, original, "{from:?} to {to:?} and back");
+            }
+        }
+
+        // as does the code section, when it's the only one converted
+        let section = Some(Section::Code);
+        for format in formats {
+            let converted = convert(program.clone(), ImageFormat::Epie, format, section, None);
+            let back = convert(converted.unwrap(), format, ImageFormat::Epie, section, None);
+            assert_eq!(
+                back.unwrap(),
+                program,
+                "code section to {format:?} and back"
+            );
+        }
+
+        // payloads that aren't programs are wrapped in one
+        let epie = convert(
+            b"payload".to_vec(),
+            ImageFormat::Bin,
+            ImageFormat::Epie,
+            None,
+            None,
+        );
+        assert_eq!(epie.unwrap(), wrap(b"payload", Section::Data));
+    }
+}
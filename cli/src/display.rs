@@ -0,0 +1,95 @@
+//! Window showing the framebuffer for `run --display`.
+//!
+//! The program runs in slices of about a frame, with the window redrawn between them whenever it
+//! has drawn something, so it runs at full speed while staying responsive. The window stays open
+//! once the program halts, until it's closed or Escape is pressed. Keys pressed in the window go to
+//! a keyboard mapped alongside the framebuffer.
+
+use minifb::{Key, KeyRepeat, Scale, Window, WindowOptions};
+use shared::abi::{FRAMEBUFFER_BASE, KEYBOARD_BASE};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::time::{Duration, Instant};
+use vm::{
+    Framebuffer, Keyboard, Machine, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH, KEY_DOWN, KEY_LEFT,
+    KEY_RIGHT, KEY_UP,
+};
+
+/// Time spent running the program between redraws
+const FRAME_TIME: Duration = Duration::from_millis(16);
+/// Instructions run between checks of whether the frame is over
+const INSTRUCTIONS_PER_CHECK: usize = 1000;
+
+/// Maps a framebuffer and keyboard into the machine's memory and runs the program from the start,
+/// showing the framebuffer in a window until it's closed
+pub(crate) fn run(machine: &mut dyn Machine) -> anyhow::Result<()> {
+    let framebuffer = Rc::new(RefCell::new(Framebuffer::default()));
+    machine
+        .memory_mut()
+        .map_device(FRAMEBUFFER_BASE, framebuffer.clone())?;
+    let keyboard = Rc::new(RefCell::new(Keyboard::default()));
+    machine
+        .memory_mut()
+        .map_device(KEYBOARD_BASE, keyboard.clone())?;
+
+    let options = WindowOptions {
+        scale: Scale::X8,
+        ..WindowOptions::default()
+    };
+    let mut window = Window::new("rvm", FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT, options)?;
+
+    machine.start()?;
+    let mut running = true;
+    while window.is_open() && !window.is_key_down(Key::Escape) {
+        let shift = window.is_key_down(Key::LeftShift) || window.is_key_down(Key::RightShift);
+        for key in window.get_keys_pressed(KeyRepeat::Yes) {
+            if let Some(key) = key_code(key, shift) {
+                keyboard.borrow_mut().press(key);
+            }
+        }
+
+        let frame_end = Instant::now() + FRAME_TIME;
+        while running && Instant::now() < frame_end {
+            for _ in 0..INSTRUCTIONS_PER_CHECK {
+                if !machine.step()? {
+                    running = false;
+                    // only the window is left to update, so there's no need to spin
+                    window.set_target_fps(60);
+                    break;
+                }
+            }
+        }
+
+        let frame = framebuffer.borrow_mut().take_frame();
+        match frame {
+            Some(frame) => {
+                window.update_with_buffer(&frame, FRAMEBUFFER_WIDTH, FRAMEBUFFER_HEIGHT)?
+            }
+            None => window.update(),
+        }
+    }
+
+    Ok(())
+}
+
+/// Code the keyboard gives a key, which is its ASCII character where it has one
+fn key_code(key: Key, shift: bool) -> Option<u8> {
+    // digits are numbered from 0, followed by the letters
+    let index = key as u8;
+    let code = match key {
+        _ if index <= Key::Key9 as u8 => b'0' + index,
+        _ if index <= Key::Z as u8 && shift => b'A' + index - Key::A as u8,
+        _ if index <= Key::Z as u8 => b'a' + index - Key::A as u8,
+        Key::Space => b' ',
+        Key::Enter => b'\n',
+        Key::Tab => b'\t',
+        Key::Backspace => 8,
+        Key::Up => KEY_UP,
+        Key::Down => KEY_DOWN,
+        Key::Left => KEY_LEFT,
+        Key::Right => KEY_RIGHT,
+        _ => return None,
+    };
+
+    Some(code)
+}
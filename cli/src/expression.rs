@@ -0,0 +1,229 @@
+use anyhow::{anyhow, bail};
+use shared::abi::register_index;
+use std::fmt::{Display, Formatter};
+use vm::Machine;
+
+/// Expression over VM state, such as `$3`, `mem[@counter]` or `memw[$0 + 4]`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Expression {
+    Value(i32),
+    Register(usize),
+    Label(String),
+    /// Memory read of the given width (1, 2 or 4 bytes) at the address computed by the expression
+    Memory(usize, Box<Expression>),
+    Add(Box<Expression>, Box<Expression>),
+    Sub(Box<Expression>, Box<Expression>),
+}
+
+impl Expression {
+    /// Parses an expression of the form <term> ((+|-) <term>)*
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let mut parser = Parser {
+            input: input.trim(),
+        };
+        let expression = parser.expression()?;
+
+        if !parser.input.is_empty() {
+            bail!("unexpected input '{}'", parser.input);
+        }
+
+        Ok(expression)
+    }
+
+    /// Evaluates the expression against the machine, resolving labels with the given function
+    pub fn evaluate(
+        &self,
+        machine: &dyn Machine,
+        labels: &dyn Fn(&str) -> Option<u32>,
+    ) -> anyhow::Result<i32> {
+        Ok(match self {
+            Expression::Value(value) => *value,
+            Expression::Register(register) => *machine
+                .registers()
+                .get(*register)
+                .ok_or_else(|| anyhow!("no register ${register}"))?,
+            Expression::Label(label) => {
+                labels(label).ok_or_else(|| anyhow!("unknown label @{label}"))? as i32
+            }
+            Expression::Memory(width, address) => {
+                let address = address.evaluate(machine, labels)? as usize;
+                let bytes = machine.memory().read(address, *width)?;
+
+                match *width {
+                    1 => bytes[0] as i32,
+                    2 => i16::from_be_bytes([bytes[0], bytes[1]]) as i32,
+                    _ => i32::from_be_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]),
+                }
+            }
+            Expression::Add(a, b) => a
+                .evaluate(machine, labels)?
+                .wrapping_add(b.evaluate(machine, labels)?),
+            Expression::Sub(a, b) => a
+                .evaluate(machine, labels)?
+                .wrapping_sub(b.evaluate(machine, labels)?),
+        })
+    }
+}
+
+impl Display for Expression {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Expression::Value(value) => write!(f, "{value}"),
+            Expression::Register(register) => write!(f, "${register}"),
+            Expression::Label(label) => write!(f, "@{label}"),
+            Expression::Memory(width, address) => {
+                let suffix = match width {
+                    1 => "",
+                    2 => "h",
+                    _ => "w",
+                };
+                write!(f, "mem{suffix}[{address}]")
+            }
+            Expression::Add(a, b) => write!(f, "{a} + {b}"),
+            Expression::Sub(a, b) => write!(f, "{a} - {b}"),
+        }
+    }
+}
+
+/// Recursive descent parser, consuming the input as it goes
+struct Parser<'a> {
+    input: &'a str,
+}
+
+impl Parser<'_> {
+    fn expression(&mut self) -> anyhow::Result<Expression> {
+        let mut expression = self.term()?;
+
+        loop {
+            self.skip_whitespace();
+            if let Some(rest) = self.input.strip_prefix('+') {
+                self.input = rest;
+                expression = Expression::Add(Box::new(expression), Box::new(self.term()?));
+            } else if let Some(rest) = self.input.strip_prefix('-') {
+                self.input = rest;
+                expression = Expression::Sub(Box::new(expression), Box::new(self.term()?));
+            } else {
+                return Ok(expression);
+            }
+        }
+    }
+
+    fn term(&mut self) -> anyhow::Result<Expression> {
+        self.skip_whitespace();
+
+        if let Some(rest) = self.input.strip_prefix('$') {
+            self.input = rest;
+            if !self.input.starts_with(|c: char| c.is_ascii_alphabetic()) {
+                return Ok(Expression::Register(self.number()? as usize));
+            }
+
+            let name = self.take_while(|c| c.is_ascii_alphanumeric());
+            let register = register_index(name).ok_or_else(|| anyhow!("no register ${name}"))?;
+            return Ok(Expression::Register(register as usize));
+        }
+
+        if let Some(rest) = self.input.strip_prefix('@') {
+            self.input = rest;
+            let label = self.take_while(|c| c.is_ascii_alphanumeric());
+            if label.is_empty() {
+                bail!("expected label name after '@'");
+            }
+
+            return Ok(Expression::Label(label.to_owned()));
+        }
+
+        for (prefix, width) in [("memw[", 4), ("memh[", 2), ("mem[", 1)] {
+            if let Some(rest) = self.input.strip_prefix(prefix) {
+                self.input = rest;
+                let address = self.expression()?;
+
+                self.skip_whitespace();
+                self.input = self
+                    .input
+                    .strip_prefix(']')
+                    .ok_or_else(|| anyhow!("expected ']'"))?;
+
+                return Ok(Expression::Memory(width, Box::new(address)));
+            }
+        }
+
+        Ok(Expression::Value(self.number()?))
+    }
+
+    /// Parses a decimal or hexadecimal (0x prefixed) number
+    fn number(&mut self) -> anyhow::Result<i32> {
+        let negative = if let Some(rest) = self.input.strip_prefix('-') {
+            self.input = rest;
+            true
+        } else {
+            false
+        };
+
+        let value = if let Some(rest) = self.input.strip_prefix("0x") {
+            self.input = rest;
+            let digits = self.take_while(|c| c.is_ascii_hexdigit());
+            i64::from_str_radix(digits, 16)
+        } else {
+            self.take_while(|c| c.is_ascii_digit()).parse()
+        }
+        .map_err(|_| anyhow!("expected number"))?;
+
+        Ok(if negative { -value } else { value } as i32)
+    }
+
+    fn take_while(&mut self, predicate: impl Fn(char) -> bool) -> &str {
+        let end = self
+            .input
+            .find(|c| !predicate(c))
+            .unwrap_or(self.input.len());
+        let (taken, rest) = self.input.split_at(end);
+        self.input = rest;
+
+        taken
+    }
+
+    fn skip_whitespace(&mut self) {
+        self.input = self.input.trim_start();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::{Memory, VM};
+
+    #[test]
+    fn test_parse_expression() {
+        assert_eq!(Expression::parse("$3").unwrap(), Expression::Register(3));
+        assert_eq!(Expression::parse("$sp").unwrap(), Expression::Register(31));
+        assert_eq!(
+            Expression::parse("mem[@counter]").unwrap(),
+            Expression::Memory(1, Box::new(Expression::Label("counter".to_owned())))
+        );
+        assert_eq!(
+            Expression::parse("memw[$0 + 0x10]").unwrap().to_string(),
+            "memw[$0 + 16]"
+        );
+
+        assert!(Expression::parse("mem[$0").is_err());
+        assert!(Expression::parse("$").is_err());
+        assert!(Expression::parse("$zero").is_err());
+        assert!(Expression::parse("1 2").is_err());
+    }
+
+    #[test]
+    fn test_evaluate_expression() {
+        let mut vm = VM::default();
+        vm.registers_mut()[1] = 2;
+        *vm.memory_mut() = Memory::new(vec![0, 0, 0x12, 0x34, 0, 0, 0, 7]);
+        let labels = |label: &str| (label == "counter").then_some(4);
+
+        let evaluate = |input: &str| Expression::parse(input).unwrap().evaluate(&vm, &labels);
+
+        assert_eq!(evaluate("$1 - 3").unwrap(), -1);
+        assert_eq!(evaluate("memh[$1]").unwrap(), 0x1234);
+        assert_eq!(evaluate("memw[@counter]").unwrap(), 7);
+        assert!(evaluate("mem[100]").is_err());
+        assert!(evaluate("@missing").is_err());
+    }
+}
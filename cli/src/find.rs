@@ -0,0 +1,113 @@
+use anyhow::{anyhow, bail};
+use shared::Opcode;
+
+/// Pattern searched for by `.find`
+#[derive(Debug, PartialEq, Clone)]
+pub enum Query {
+    /// Raw bytes, such as `bytes DE AD BE EF`
+    Bytes(Vec<u8>),
+    /// String contents without a null terminator, such as `str 'hello'`
+    Str(String),
+    /// Instructions with the given opcode, such as `instr jmpi`
+    Instr(Opcode),
+}
+
+impl Query {
+    /// Parses a query of the form <bytes | str | instr> <pattern>
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let (kind, pattern) = input.trim().split_once(' ').unwrap_or((input, ""));
+        let pattern = pattern.trim();
+        if pattern.is_empty() {
+            bail!("expected a pattern to find");
+        }
+
+        Ok(match kind {
+            "bytes" => Query::Bytes(
+                pattern
+                    .split_whitespace()
+                    .map(|byte| u8::from_str_radix(byte, 16))
+                    .collect::<Result<_, _>>()
+                    .map_err(|_| anyhow!("invalid hex bytes '{pattern}'"))?,
+            ),
+            "str" => {
+                let string = ['\'', '"']
+                    .into_iter()
+                    .find_map(|quote| pattern.strip_prefix(quote)?.strip_suffix(quote))
+                    .ok_or_else(|| anyhow!("expected a quoted string"))?;
+
+                Query::Str(string.to_owned())
+            }
+            "instr" => match Opcode::from_mnemonic(pattern) {
+                Some(opcode) => Query::Instr(opcode),
+                None => bail!("unknown instruction '{pattern}'"),
+            },
+            kind => bail!("unknown search '{kind}', expected bytes, str or instr"),
+        })
+    }
+
+    /// Offsets into memory the query matches at. Instructions only match at multiples of 4 bytes
+    pub fn matches<'a>(&'a self, memory: &'a [u8]) -> Box<dyn Iterator<Item = usize> + 'a> {
+        let needle = match self {
+            Query::Bytes(bytes) => bytes.as_slice(),
+            Query::Str(string) => string.as_bytes(),
+            Query::Instr(opcode) => {
+                return Box::new(
+                    memory
+                        .chunks_exact(4)
+                        .enumerate()
+                        .filter(|(_, instruction)| instruction[0] == *opcode as u8)
+                        .map(|(index, _)| index * 4),
+                )
+            }
+        };
+
+        Box::new(
+            memory
+                .windows(needle.len().max(1))
+                .enumerate()
+                .filter(move |(_, window)| *window == needle)
+                .map(|(offset, _)| offset),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            Query::parse("bytes DE AD").unwrap(),
+            Query::Bytes(vec![0xDE, 0xAD])
+        );
+        assert_eq!(
+            Query::parse("str 'hi there'").unwrap(),
+            Query::Str("hi there".to_owned())
+        );
+        assert_eq!(
+            Query::parse("instr JMPI").unwrap(),
+            Query::Instr(Opcode::JMPI)
+        );
+
+        assert!(Query::parse("bytes XY").is_err());
+        assert!(Query::parse("str hi").is_err());
+        assert!(Query::parse("instr jump").is_err());
+        assert!(Query::parse("word 1").is_err());
+    }
+
+    #[test]
+    fn test_matches() {
+        let memory = [0xA0, 0, 0xA0, 0, 0xA0, 0, 0, 0, b'h', b'i', 0, 0];
+
+        let find = |query: &str| {
+            Query::parse(query)
+                .unwrap()
+                .matches(&memory)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(find("bytes A0 00"), vec![0, 2, 4]);
+        assert_eq!(find("instr jmpi"), vec![0, 4]);
+        assert_eq!(find("str 'hi'"), vec![8]);
+    }
+}
@@ -0,0 +1,217 @@
+//! Number formatting shared by every dump of registers and memory.
+//!
+//! Settings are changed in the REPL with `.set <setting> <value>`, or loaded from a config file
+//! passed with `--config`, which holds one `<setting> <value>` pair per line. Lines starting with
+//! `#` are comments.
+
+use anyhow::{anyhow, bail};
+use std::fmt::{Display, Formatter};
+use std::path::Path;
+
+/// Base values are shown in
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Radix {
+    /// Zero padded hexadecimal, two digits per byte
+    Hex,
+    Dec,
+    /// Hexadecimal followed by decimal in brackets
+    Both,
+}
+
+/// How values are formatted when dumped
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub struct NumberFormat {
+    pub radix: Radix,
+    /// Whether decimal values are shown as two's complement signed numbers
+    pub signed: bool,
+    /// Whether digits are grouped, in threes for decimal and fours for hexadecimal
+    pub separators: bool,
+}
+
+impl Default for NumberFormat {
+    fn default() -> Self {
+        Self {
+            radix: Radix::Hex,
+            signed: true,
+            separators: false,
+        }
+    }
+}
+
+impl NumberFormat {
+    /// Loads settings from a config file, on top of the defaults
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let mut format = Self::default();
+
+        let config = std::fs::read_to_string(path)?;
+        for line in config.lines().map(str::trim) {
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+
+            let (setting, value) = line.split_once(' ').unwrap_or((line, ""));
+            format.set(setting, value.trim())?;
+        }
+
+        Ok(format)
+    }
+
+    /// Changes a single setting, such as `format dec` or `separators on`
+    pub fn set(&mut self, setting: &str, value: &str) -> anyhow::Result<()> {
+        match setting {
+            "format" => {
+                self.radix = match value {
+                    "hex" => Radix::Hex,
+                    "dec" => Radix::Dec,
+                    "both" => Radix::Both,
+                    _ => bail!("unknown format '{value}', expected hex, dec or both"),
+                }
+            }
+            "signed" => self.signed = parse_switch(value)?,
+            "separators" => self.separators = parse_switch(value)?,
+            _ => bail!("unknown setting '{setting}', expected format, signed or separators"),
+        }
+
+        Ok(())
+    }
+
+    /// Formats the lowest `size` bytes of a value
+    pub fn format(&self, value: u32, size: usize) -> String {
+        match self.radix {
+            Radix::Hex => self.hex(value, size),
+            Radix::Dec => self.dec(value, size),
+            Radix::Both => format!("{} ({})", self.hex(value, size), self.dec(value, size)),
+        }
+    }
+
+    pub fn byte(&self, value: u8) -> String {
+        self.format(value as u32, 1)
+    }
+
+    pub fn word(&self, value: i32) -> String {
+        self.format(value as u32, 4)
+    }
+
+    fn hex(&self, value: u32, size: usize) -> String {
+        let digits = format!("{:01$X}", value & mask(size), size * 2);
+
+        if self.separators {
+            group(&digits, 4, '_')
+        } else {
+            digits
+        }
+    }
+
+    fn dec(&self, value: u32, size: usize) -> String {
+        let value = value & mask(size);
+        let shift = 32 - size as u32 * 8;
+
+        let (sign, magnitude) = if self.signed && (value >> (size * 8 - 1)) & 1 == 1 {
+            // sign extend, then take the magnitude
+            ("-", ((value << shift) as i32 >> shift).unsigned_abs())
+        } else {
+            ("", value)
+        };
+
+        let digits = magnitude.to_string();
+        if self.separators {
+            format!("{sign}{}", group(&digits, 3, ','))
+        } else {
+            format!("{sign}{digits}")
+        }
+    }
+
+    /// Formats values in rows of 8, in groups of 4
+    pub fn dump<T: Copy + Into<i64>>(&self, values: &[T], size: usize) -> String {
+        let mut out = String::new();
+        for row in values.chunks(8) {
+            let line = row
+                .chunks(4)
+                .map(|group| {
+                    group
+                        .iter()
+                        .map(|&value| self.format(value.into() as u32, size))
+                        .collect::<Vec<_>>()
+                        .join(" ")
+                })
+                .collect::<Vec<_>>()
+                .join("\t");
+
+            out.push_str(&line);
+            out.push('\n');
+        }
+
+        out
+    }
+}
+
+impl Display for NumberFormat {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let radix = match self.radix {
+            Radix::Hex => "hex",
+            Radix::Dec => "dec",
+            Radix::Both => "both",
+        };
+        let switch = |on| if on { "on" } else { "off" };
+
+        write!(
+            f,
+            "format {radix}\nsigned {}\nseparators {}",
+            switch(self.signed),
+            switch(self.separators)
+        )
+    }
+}
+
+fn parse_switch(value: &str) -> anyhow::Result<bool> {
+    match value {
+        "on" | "true" => Ok(true),
+        "off" | "false" => Ok(false),
+        _ => Err(anyhow!("expected on or off, got '{value}'")),
+    }
+}
+
+/// Mask of the lowest `size` bytes
+fn mask(size: usize) -> u32 {
+    u32::MAX >> (32 - size * 8)
+}
+
+/// Inserts a separator between every `width` digits, counting from the right
+fn group(digits: &str, width: usize, separator: char) -> String {
+    let mut out = String::new();
+
+    for (index, digit) in digits.chars().enumerate() {
+        if index > 0 && (digits.len() - index).is_multiple_of(width) {
+            out.push(separator);
+        }
+        out.push(digit);
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_format() {
+        let mut format = NumberFormat::default();
+        assert_eq!(format.word(-2), "FFFFFFFE");
+        assert_eq!(format.byte(0x2A), "2A");
+
+        format.set("format", "dec").unwrap();
+        assert_eq!(format.word(-2), "-2");
+        assert_eq!(format.byte(0xFF), "-1");
+        format.set("signed", "off").unwrap();
+        assert_eq!(format.byte(0xFF), "255");
+
+        format.set("separators", "on").unwrap();
+        assert_eq!(format.word(1234567), "1,234,567");
+        format.set("format", "both").unwrap();
+        assert_eq!(format.word(1234567), "0012_D687 (1,234,567)");
+
+        assert!(format.set("format", "oct").is_err());
+        assert!(format.set("colour", "on").is_err());
+    }
+}
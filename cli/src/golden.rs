@@ -0,0 +1,64 @@
+/// Line diff between expected and actual output, with removed lines prefixed by `-` and added
+/// lines by `+`. Returns None if they match
+pub fn diff(expected: &str, actual: &str) -> Option<String> {
+    if expected == actual {
+        return None;
+    }
+
+    let expected = expected.lines().collect::<Vec<_>>();
+    let actual = actual.lines().collect::<Vec<_>>();
+
+    // longest common subsequence lengths of every pair of suffixes
+    let mut common = vec![vec![0; actual.len() + 1]; expected.len() + 1];
+    for i in (0..expected.len()).rev() {
+        for j in (0..actual.len()).rev() {
+            common[i][j] = if expected[i] == actual[j] {
+                common[i + 1][j + 1] + 1
+            } else {
+                common[i + 1][j].max(common[i][j + 1])
+            };
+        }
+    }
+
+    let mut out = String::new();
+    let (mut i, mut j) = (0, 0);
+    while i < expected.len() || j < actual.len() {
+        if i < expected.len() && j < actual.len() && expected[i] == actual[j] {
+            out += &format!(" {}\n", expected[i]);
+            i += 1;
+            j += 1;
+        } else if j < actual.len() && (i == expected.len() || common[i][j + 1] >= common[i + 1][j])
+        {
+            out += &format!("+{}\n", actual[j]);
+            j += 1;
+        } else {
+            out += &format!("-{}\n", expected[i]);
+            i += 1;
+        }
+    }
+
+    // outputs can differ only in a trailing newline
+    if out.lines().all(|line| line.starts_with(' ')) {
+        out += "(trailing newline differs)\n";
+    }
+
+    Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff() {
+        assert_eq!(diff("a\nb\n", "a\nb\n"), None);
+        assert_eq!(
+            diff("a\nb\nc\n", "a\nx\nc\nd\n").unwrap(),
+            " a\n+x\n-b\n c\n+d\n"
+        );
+        assert_eq!(
+            diff("a\n", "a").unwrap(),
+            " a\n(trailing newline differs)\n"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use std::fmt::Write;
+use vm::{Allocation, Memory};
+
+/// Describes every live allocation along with the instruction that made it, the freed blocks
+/// waiting to be reused, and how fragmented the free space is. `context` describes an address,
+/// such as ` <main+8>`
+pub fn heap_report(memory: &Memory, context: impl Fn(usize) -> String) -> String {
+    let allocator = memory.allocator();
+    let mut out = String::new();
+
+    writeln!(out, "live allocations:").unwrap();
+    let mut live = 0;
+    for allocation in allocator.allocations() {
+        live += allocation.size;
+        writeln!(out, "  {}", describe(allocation, &context)).unwrap();
+    }
+
+    writeln!(out, "free blocks:").unwrap();
+    for block in allocator.free_blocks() {
+        writeln!(out, "  {:#06X} {} bytes", block.start, block.len()).unwrap();
+    }
+
+    let free = allocator.free_blocks().iter().map(|block| block.len());
+    let (total_free, largest_free) = (free.clone().sum::<usize>(), free.max().unwrap_or(0));
+    // share of free space that can't be used by a single allocation
+    let fragmentation = match total_free {
+        0 => 0,
+        _ => 100 - largest_free * 100 / total_free,
+    };
+    writeln!(
+        out,
+        "heap size {} bytes: {live} live, {total_free} free, largest free block {largest_free}, \
+         {fragmentation}% fragmented",
+        memory.heap_size()
+    )
+    .unwrap();
+
+    out
+}
+
+/// Describes how deep the stack grew, the most heap memory allocated at once and the highest data
+/// address written, to help choose resource limits
+pub fn usage_report(memory: &Memory, context: impl Fn(usize) -> String) -> String {
+    let marks = memory.high_water_marks();
+
+    let data = match marks.data_written {
+        Some(address) => format!("written up to {address:#06X}{}", context(address)),
+        None => "never written".to_owned(),
+    };
+    format!(
+        "stack: {} bytes deep\nheap: {} bytes allocated at peak, {} bytes in total\ndata: {data}\n",
+        marks.stack_depth,
+        marks.heap_peak,
+        memory.heap_size()
+    )
+}
+
+/// Lists allocations that were never freed, or returns None if there aren't any
+pub fn leak_report(memory: &Memory, context: impl Fn(usize) -> String) -> Option<String> {
+    let leaks = memory.allocator().allocations().collect::<Vec<_>>();
+    if leaks.is_empty() {
+        return None;
+    }
+
+    let plural = if leaks.len() == 1 { "" } else { "s" };
+    let mut out = format!("{} allocation{plural} never freed:\n", leaks.len());
+    for allocation in leaks {
+        writeln!(out, "  {}", describe(allocation, &context)).unwrap();
+    }
+
+    Some(out)
+}
+
+fn describe(allocation: &Allocation, context: impl Fn(usize) -> String) -> String {
+    format!(
+        "{:#06X} {} bytes, allocated at {:#06X}{}",
+        allocation.address,
+        allocation.size,
+        allocation.pc,
+        context(allocation.pc)
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::VM;
+
+    #[test]
+    fn test_heap_report() {
+        // allocates three 4 byte blocks, then frees the first and last
+        let code = [
+            32, 0, 0, 4, 32, 1, 0, 4, 32, 2, 0, 4, 46, 0, 0, 0, 46, 2, 0, 0, 0, 0, 0, 0,
+        ];
+        let mut program = vec![0; 64];
+        program[..4].copy_from_slice(b"EPIE");
+        program[4] = shared::PIE_FORMAT_VERSION;
+        program[8..12].copy_from_slice(&64u32.to_be_bytes());
+        program[16..20].copy_from_slice(&64u32.to_be_bytes());
+        program[20..24].copy_from_slice(&(code.len() as u32).to_be_bytes());
+        program.extend_from_slice(&code);
+
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(program);
+        vm.run().unwrap();
+
+        assert_eq!(
+            heap_report(vm.memory(), |pc| format!(" <main+{}>", pc - 64)),
+            "live allocations:
+  0x005C 4 bytes, allocated at 0x0044 <main+4>
+free blocks:
+  0x0058 4 bytes
+  0x0060 4 bytes
+heap size 12 bytes: 4 live, 8 free, largest free block 4, 50% fragmented
+"
+        );
+        assert_eq!(
+            leak_report(vm.memory(), |_| String::new()).unwrap(),
+            "1 allocation never freed:\n  0x005C 4 bytes, allocated at 0x0044\n"
+        );
+        assert_eq!(leak_report(&Memory::default(), |_| String::new()), None);
+        assert_eq!(
+            usage_report(vm.memory(), |_| String::new()),
+            "stack: 0 bytes deep\nheap: 12 bytes allocated at peak, 12 bytes in total\ndata: never written\n"
+        );
+    }
+}
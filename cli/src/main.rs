@@ -1,54 +1,322 @@
+mod backtrace;
+mod bench;
+mod convert;
+#[cfg(feature = "display")]
+mod display;
+mod expression;
+mod find;
+mod format;
+mod golden;
+mod heap;
+mod profile;
 mod repl;
+mod replay;
+mod report;
+#[cfg(feature = "server")]
+mod server;
+mod suite;
+mod summary;
+mod timeline;
+mod trace;
+mod view;
+mod watch;
 
-use assembler::Assembler;
+use anyhow::{bail, Context};
+use assembler::{disassemble, rename_label, Assembler};
 use clap::{Parser, Subcommand};
+use convert::{ImageFormat, Section};
+use format::NumberFormat;
 use repl::REPL;
+use report::Report;
+use shared::abi::KEYBOARD_BASE;
+use shared::symbols::SymbolKind;
+use shared::PIE_HEADER_PREFIX;
+use std::cell::RefCell;
 use std::fs::File;
-use std::io::Read;
-use std::path::PathBuf;
-use vm::VM;
+use std::io::{ErrorKind, Read, Write};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+use summary::OutputFormat;
+use timeline::Timeline;
+use trace::Trace;
+use vm::{
+    validate, Cached, Keyboard, LogLevel, LogRecord, Machine, Program, SharedBuffer, VMConfig,
+    VmError, XorShift, VM,
+};
 
-const PIE_HEADER_PREFIX: [u8; 4] = *b"EPIE";
-const PIE_HEADER_LENGTH: usize = 64;
+/// Number of opcodes listed by `run --timings`
+#[cfg(feature = "timing")]
+const TIMING_REPORT_LENGTH: usize = 10;
 
 #[derive(Parser)]
 struct Cli {
     #[command(subcommand)]
     command: Command,
+    /// Config file of `<setting> <value>` lines, such as `format dec`, controlling how values are
+    /// shown
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
+// only one is ever made, so there's nothing to gain from boxing `run`'s options
+#[allow(clippy::large_enum_variant)]
 enum Command {
     Repl {
         path: Option<PathBuf>,
     },
+    /// Runs an assembly file, or a program already assembled into an .epie file
     Run {
-        path: PathBuf,
+        #[arg(required_unless_present = "replay")]
+        path: Option<PathBuf>,
         #[arg(short = 'p', long)]
         print_program: bool,
         #[arg(short = 'r', long)]
         print_registers: bool,
+        /// Write a Chrome tracing timeline of the calls the program makes to this file
+        #[arg(long)]
+        timeline: Option<PathBuf>,
+        /// Keep labelled code and data even if nothing can reach it
+        #[arg(long)]
+        keep_all: bool,
+        /// Warn about routines that don't follow the calling convention
+        #[arg(long)]
+        lint_abi: bool,
+        /// Define a name for .if and .ifdef to test, as NAME or NAME=value, with a value of 1 if
+        /// not given
+        #[arg(short = 'D', long = "define", value_name = "NAME[=VALUE]", value_parser = parse_define)]
+        defines: Vec<(String, i32)>,
+        /// Maximum number of bytes the heap can grow to
+        #[arg(long)]
+        max_heap: Option<usize>,
+        /// Maximum size of the assembled program, in bytes
+        #[arg(long)]
+        max_program_size: Option<usize>,
+        /// Maximum number of instructions to execute
+        #[arg(long)]
+        max_steps: Option<u64>,
+        /// Write a zip archive with everything needed to reproduce the run to this file
+        #[arg(long)]
+        report: Option<PathBuf>,
+        /// Compare the program's output against this file, failing with a diff if they differ
+        #[arg(long)]
+        expect_output: Option<PathBuf>,
+        /// Also write the program's output to this file
+        #[arg(long)]
+        tee: Option<PathBuf>,
+        /// Print live allocations, freed blocks and heap fragmentation once the program stops
+        #[arg(long)]
+        heap_report: bool,
+        /// Warn about allocations that were never freed once the program halts
+        #[arg(long)]
+        check_leaks: bool,
+        /// Print the stack depth, peak heap usage and highest data address written once the
+        /// program stops
+        #[arg(long)]
+        usage: bool,
+        /// Print the calls that led to a fault if the program faults
+        #[arg(long)]
+        backtrace: bool,
+        /// Seed for the numbers `RAND` loads, such as the one in a `--report` bundle, so they're
+        /// the same as in that run. Chosen at random if not given
+        #[arg(long)]
+        seed: Option<u64>,
+        /// Count executions of each opcode and instruction address, printing the most executed
+        /// once the program stops
+        #[arg(long)]
+        profile: bool,
+        /// Fault on half-word and word accesses to addresses that aren't a multiple of their size
+        #[arg(long)]
+        check_alignment: bool,
+        /// Reject the program before running it if an immediate jump or call targets an address
+        /// that isn't an instruction in the code section
+        #[arg(long)]
+        verify_jumps: bool,
+        /// Run the program without first checking its header, opcodes and jump targets, such as
+        /// one that keeps data in its code section
+        #[arg(long)]
+        no_validate: bool,
+        /// Run hot loops from pre-decoded basic blocks, which is faster for loop-heavy programs
+        #[arg(long)]
+        cached: bool,
+        /// Most verbose level of messages logged by the program that are printed
+        #[arg(long, default_value = "info")]
+        log_level: LogLevel,
+        /// Write a binary trace of every instruction executed to this file, which `trace-dump` reads
+        #[arg(long, conflicts_with = "report")]
+        trace: Option<PathBuf>,
+        /// Only keep the last N instructions executed, writing them to the `--trace` file once the
+        /// program stops or printing them if there's no file
+        #[arg(long, value_name = "N", conflicts_with = "report")]
+        trace_last: Option<usize>,
+        /// Print the opcodes whose handlers took the most time once the program stops
+        #[cfg(feature = "timing")]
+        #[arg(long)]
+        timings: bool,
+        /// Map the keyboard at 0x101000 with these keys already pressed, for running interactive
+        /// programs without a display
+        #[arg(long)]
+        keys: Option<String>,
+        /// Let the program open files inside this directory, which can be given more than once.
+        /// Programs can't open any files otherwise
+        #[arg(long, value_name = "DIR")]
+        allow_dir: Vec<PathBuf>,
+        /// Print the program's output, final registers, equality flag, instruction count, exit
+        /// status and any fault as a single JSON object once it stops, instead of as text
+        #[arg(long, value_enum, default_value_t)]
+        output: OutputFormat,
+        /// Let the program create garbage collected objects with `NEWARR`
+        #[arg(long)]
+        object_heap: bool,
+        /// Write the program, its settings, the input it reads, its random numbers and times, and
+        /// how it ended to this file, so `--replay` can repeat the run exactly
+        #[arg(long, value_name = "FILE", conflicts_with_all = ["keys", "allow_dir"])]
+        record: Option<PathBuf>,
+        /// Run a program recorded with `--record` again, with the same input, random numbers and
+        /// times, failing if it doesn't end the same way
+        #[arg(long, value_name = "FILE", conflicts_with = "path")]
+        replay: Option<PathBuf>,
+        /// Map the framebuffer at 0x100000 and show it in a window while the program runs, which
+        /// stays open once it halts until it's closed. Keys pressed in the window go to the keyboard
+        /// at 0x101000
+        #[cfg(feature = "display")]
+        #[arg(long, conflicts_with_all = ["timeline", "cached", "keys", "record"])]
+        display: bool,
+        /// Arguments passed to the program, given after `--`. Their count is put in $a0 and the
+        /// address of an array of pointers to them, as null terminated strings, in $a1
+        #[arg(last = true)]
+        args: Vec<String>,
+    },
+    /// Assembles a file into an .epie program that can be run without reassembling
+    Assemble {
+        input: PathBuf,
+        /// Output path, defaulting to the input path with an .epie extension
+        #[arg(short = 'o', long)]
+        output: Option<PathBuf>,
+        /// Keep labelled code and data even if nothing can reach it
+        #[arg(long)]
+        keep_all: bool,
+        /// Warn about routines that don't follow the calling convention
+        #[arg(long)]
+        lint_abi: bool,
+        /// Define a name for .if and .ifdef to test, as NAME or NAME=value, with a value of 1 if
+        /// not given
+        #[arg(short = 'D', long = "define", value_name = "NAME[=VALUE]", value_parser = parse_define)]
+        defines: Vec<(String, i32)>,
+        /// Print register usage per routine, the largest immediates and addressing mode counts
+        #[arg(long)]
+        stats: bool,
+        /// Write a symbol section naming labels and constants, for debugging
+        #[arg(short = 'g', long)]
+        debug_symbols: bool,
+    },
+    /// Disassembles a program, assembling it first if given assembly source
+    Disasm {
+        path: PathBuf,
+    },
+    /// Converts between .epie programs, raw binaries and Intel HEX files
+    Convert {
+        input: PathBuf,
+        output: PathBuf,
+        /// Format of the input, guessed from its contents and extension by default
+        #[arg(long)]
+        from: Option<ImageFormat>,
+        /// Format of the output, guessed from its extension by default
+        #[arg(long)]
+        to: Option<ImageFormat>,
+        /// Section of .epie programs to convert, rather than the whole program. Payloads converted
+        /// into .epie programs are placed in the data section unless this is `code`
+        #[arg(long)]
+        section: Option<Section>,
+        /// Address of the first byte in Intel HEX files, defaulting to the address of the converted
+        /// bytes in the .epie program when writing and the lowest address in the file when reading
+        #[arg(long, value_parser = parse_address)]
+        base: Option<u32>,
+    },
+    /// Prints a binary trace written by `run --trace`, one instruction per line along with the
+    /// registers that changed before it ran
+    TraceDump {
+        path: PathBuf,
+    },
+    /// Listens for connections controlling a VM over TCP, one command per line
+    #[cfg(feature = "server")]
+    Serve {
+        /// Address to listen on
+        #[arg(default_value = "127.0.0.1:7878")]
+        address: String,
+        /// Maximum number of instructions each run of a program can execute, so one stuck in a
+        /// loop doesn't hold the server
+        #[arg(long, default_value_t = 10_000_000)]
+        max_steps: u64,
+    },
+    /// Renames a label across its declaration and usages, rewriting the file in place
+    Rename {
+        old: String,
+        new: String,
+        path: PathBuf,
+    },
+    /// Runs every .asm file in a directory, comparing what each prints against its .out file and
+    /// its final registers against its .regs file
+    Test {
+        directory: PathBuf,
+        /// Write each program's .out file from what it printed, instead of comparing against it
+        #[arg(long)]
+        bless: bool,
+        /// Maximum number of instructions each program can execute, so one stuck in a loop fails
+        /// rather than hanging
+        #[arg(long, default_value_t = 10_000_000)]
+        max_steps: u64,
+    },
+    /// Runs bundled benchmark programs with and without `--cached`, printing how many
+    /// instructions per second each executes
+    Bench {
+        /// Number of times to run each program, reporting the fastest
+        #[arg(long, default_value_t = 5)]
+        iterations: u32,
     },
 }
 
 fn main() -> anyhow::Result<()> {
-    let cli = Cli::parse();
+    match run(Cli::parse()) {
+        // output piped into a program that stops reading early, like `head`, isn't an error
+        Err(error) if is_broken_pipe(&error) => Ok(()),
+        result => result,
+    }
+}
+
+/// Checks if an error was caused by writing to a pipe that's been closed, either directly or by
+/// the program running on the VM
+fn is_broken_pipe(error: &anyhow::Error) -> bool {
+    error.chain().any(|cause| {
+        let kind = match (cause.downcast_ref::<std::io::Error>(), cause.downcast_ref()) {
+            (Some(error), _) => error.kind(),
+            (_, Some(VmError::OutputFailed { kind, .. })) => *kind,
+            _ => return false,
+        };
+        kind == ErrorKind::BrokenPipe
+    })
+}
+
+fn run(cli: Cli) -> anyhow::Result<()> {
+    let mut stdout = std::io::stdout();
+    let format = match &cli.config {
+        Some(path) => NumberFormat::load(path)?,
+        None => NumberFormat::default(),
+    };
 
     match cli.command {
         Command::Repl { path } => {
-            let mut repl = REPL::default();
+            let mut repl = REPL::new(format, || Box::new(VM::default()));
 
             if let Some(path) = path {
                 // read data
-                let mut file = File::open(path)?;
+                let mut file = File::open(&path)?;
                 let mut data = String::new();
                 file.read_to_string(&mut data)?;
 
-                // construct vm and set memory to assembled program
-                let mut vm = VM::default();
-                vm.program = Assembler::default().assemble(&data)?;
-
-                repl.set_vm(vm);
+                // set vm memory to assembled program
+                repl.load_program(&data, Some(&path))?;
             }
 
             repl.run();
@@ -57,30 +325,524 @@ fn main() -> anyhow::Result<()> {
             path,
             print_program,
             print_registers,
+            timeline,
+            keep_all,
+            lint_abi,
+            defines,
+            max_heap,
+            max_program_size,
+            max_steps,
+            report,
+            expect_output,
+            tee,
+            heap_report,
+            check_leaks,
+            usage,
+            backtrace,
+            seed,
+            profile,
+            check_alignment,
+            verify_jumps,
+            no_validate,
+            cached,
+            log_level,
+            trace,
+            trace_last,
+            #[cfg(feature = "timing")]
+            timings,
+            keys,
+            allow_dir,
+            output,
+            object_heap,
+            record,
+            replay,
+            #[cfg(feature = "display")]
+            display,
+            args,
         } => {
+            if let Some(replay) = replay {
+                return replay::replay(&replay);
+            }
+            let path = path.unwrap();
+
+            // anything else printed to stdout would stop it being read as JSON
+            #[allow(unused_mut)]
+            let mut stdout_reports =
+                print_program || print_registers || heap_report || usage || profile;
+            #[cfg(feature = "timing")]
+            {
+                stdout_reports |= timings;
+            }
+            if output == OutputFormat::Json && stdout_reports {
+                bail!("--output json can't be combined with reports printed to stdout");
+            }
+
             // read data
-            let mut file = File::open(path)?;
-            let mut data = String::new();
-            file.read_to_string(&mut data)?;
+            let data = std::fs::read(&path)?;
+
+            // construct and run vm, running pre-assembled programs as they are
+            let mut assembler = Assembler::default()
+                .source_path(path)
+                .strip_unused(!keep_all)
+                .lint_abi(lint_abi);
+            for (name, value) in defines {
+                assembler = assembler.define(name, value);
+            }
+            let (source, program) = if data.starts_with(&PIE_HEADER_PREFIX) {
+                (None, data)
+            } else {
+                let source = String::from_utf8(data)?;
+                let program = assemble(&mut assembler, &source)?;
+                (Some(source), program)
+            };
 
-            // construct and run vm
-            let mut vm = VM::default();
-            vm.program = Assembler::default().assemble(&data)?;
-            vm.run();
+            // pre-assembled programs could come from anywhere, so are checked before running
+            if !no_validate {
+                if let Err(errors) = validate(&program) {
+                    for error in errors {
+                        eprintln!("error: {error}");
+                    }
+                    bail!("program isn't valid, pass --no-validate to run it anyway");
+                }
+            }
+
+            let config = VMConfig {
+                max_heap,
+                max_program_size,
+                max_steps,
+            };
+            let recorder = record.as_ref().map(|_| {
+                let settings = replay::Settings {
+                    config,
+                    args: args.clone(),
+                    check_alignment,
+                    verify_jumps,
+                    object_heap,
+                };
+                replay::Recorder::new(program.clone(), settings)
+            });
+            let mut vm = VM::with_config(config);
+            vm.load(Program::parse(program.clone())?);
+            if !args.is_empty() {
+                vm.set_args(&args)?;
+            }
+            vm.memory_mut().set_alignment_checked(check_alignment);
+            // the timeline follows calls through the shadow stack
+            vm.enable_shadow_stack(backtrace || timeline.is_some());
+            vm.enable_profiling(profile);
+            vm.enable_jump_verification(verify_jumps);
+            vm.enable_object_heap(object_heap);
+            // the seed is picked here rather than by the VM, so reports can say what it was
+            let seed = seed.unwrap_or_else(XorShift::entropy_seed);
+            vm.set_random(XorShift::new(seed));
+            for directory in allow_dir {
+                vm.allow_directory(&directory)
+                    .with_context(|| format!("couldn't allow {}", directory.display()))?;
+            }
+            vm.set_logger(move |record: &LogRecord| {
+                if record.level <= log_level {
+                    eprintln!("{record}");
+                }
+            });
+
+            let bundle = report
+                .as_ref()
+                .map(|_| Report::new(source, program, config, seed, format));
+            if let Some(bundle) = &bundle {
+                bundle.attach(&mut vm);
+            }
+            let trace = Trace::new(trace, trace_last)?;
+            if let Some(trace) = &trace {
+                trace.attach(&mut vm);
+            }
+            if let Some(keys) = keys {
+                let keyboard = Keyboard::scripted(keys.bytes().map(|key| (0, key)));
+                vm.memory_mut()
+                    .map_device(KEYBOARD_BASE, Rc::new(RefCell::new(keyboard)))?;
+            }
+
+            let capture = SharedBuffer::default();
+            match output {
+                OutputFormat::Json => vm.set_output(capture.clone()),
+                OutputFormat::Text if expect_output.is_some() => vm.add_output(capture.clone()),
+                OutputFormat::Text => {}
+            }
+            if let Some(tee) = tee {
+                vm.add_output(File::create(tee)?);
+            }
+            if let Some(recorder) = &recorder {
+                recorder.attach(&mut vm, seed);
+            }
+
+            let mut machine: Box<dyn Machine> = if cached {
+                Box::new(Cached(vm))
+            } else {
+                Box::new(vm)
+            };
+            let result = match timeline {
+                Some(timeline_path) => {
+                    run_with_timeline(machine.as_mut(), &assembler, &timeline_path)
+                }
+                #[cfg(feature = "display")]
+                None if display => display::run(machine.as_mut()),
+                None => machine.run().map_err(Into::into),
+            };
+            // reports are made from the VM the program ran on
+            let backend = machine.name();
+            let vm = machine
+                .vm_mut()
+                .with_context(|| format!("the {backend} backend has no VM to report on"))?;
+
+            // reports are written even if the run faulted, since that's when they're most useful
+            if let (Some(path), Some(bundle)) = (report, bundle) {
+                bundle.write(&path, vm, result.as_ref().err())?;
+            }
+            // as are recordings and traces
+            if let (Some(path), Some(recorder)) = (record, recorder) {
+                recorder.write(&path, vm, result.as_ref().err())?;
+            }
+            if let Some(trace) = trace {
+                trace.finish(vm)?;
+            }
+            // the program's output, ending with its halt message, comes before any reports
+            vm.flush_output()?;
+            // labels come from the assembler, or the symbol section of pre-assembled programs
+            let code = vm.memory().code_section().unwrap_or_default();
+            let context = |pc| {
+                let labels = assembler
+                    .labels()
+                    .map(|(name, label)| (name, label as usize))
+                    .chain(vm.memory().symbols().iter().filter_map(|symbol| {
+                        (symbol.kind != SymbolKind::Constant)
+                            .then_some((symbol.name.as_str(), symbol.value as usize))
+                    }));
+                code_context(labels, &code, pc)
+            };
+            #[cfg(feature = "timing")]
+            if timings {
+                write!(stdout, "{}", timing_report(vm))?;
+            }
+            if heap_report {
+                write!(stdout, "{}", heap::heap_report(vm.memory(), context))?;
+            }
+            if usage {
+                write!(stdout, "{}", heap::usage_report(vm.memory(), context))?;
+            }
+            if let Some(profile) = vm.profile() {
+                write!(stdout, "{}", profile::profile_report(profile, context))?;
+            }
+            if result.is_err() {
+                if let Some(backtrace) = backtrace::backtrace(vm, context) {
+                    eprint!("{backtrace}");
+                }
+            }
+            if output == OutputFormat::Json {
+                let output = capture.to_string_lossy();
+                writeln!(
+                    stdout,
+                    "{}",
+                    summary::json_summary(vm, &output, result.as_ref().err())
+                )?;
+            }
+            result?;
+
+            if check_leaks {
+                if let Some(leaks) = heap::leak_report(vm.memory(), context) {
+                    eprint!("warning: {leaks}");
+                }
+            }
+
+            if let Some(expected_path) = expect_output {
+                let expected = std::fs::read_to_string(&expected_path)?;
+                let actual = capture.to_string_lossy();
+
+                if let Some(diff) = golden::diff(&expected, &actual) {
+                    eprint!("{diff}");
+                    bail!("output doesn't match {}", expected_path.display());
+                }
+            }
 
             // then dump program/registers
             if print_program {
-                println!("\nfinal program:");
-                repl::pretty_print_hex(&vm.program, 2);
+                writeln!(stdout, "\nfinal program:")?;
+                write!(stdout, "{}", format.dump(vm.memory().image(), 1))?;
             }
 
             if print_registers {
-                println!("\nfinal registers:");
-                repl::pretty_print_hex(&vm.registers, 8);
-                println!("Equality register: {}", vm.equality_flag);
+                writeln!(stdout, "\nfinal registers:")?;
+                write!(stdout, "{}", format.dump(vm.registers(), 4))?;
+                writeln!(stdout, "Equality register: {}", vm.equality_flag())?;
+            }
+
+            // programs halting with EXIT pass their status on, so they can be used in scripts
+            if vm.exit_status() != 0 {
+                std::process::exit(process_status(vm.exit_status()));
             }
         }
+        Command::Assemble {
+            input,
+            output,
+            keep_all,
+            lint_abi,
+            defines,
+            stats,
+            debug_symbols,
+        } => {
+            let source = std::fs::read_to_string(&input)?;
+            let mut assembler = Assembler::default()
+                .source_path(&input)
+                .strip_unused(!keep_all)
+                .lint_abi(lint_abi)
+                .collect_stats(stats)
+                .debug_symbols(debug_symbols);
+            for (name, value) in defines {
+                assembler = assembler.define(name, value);
+            }
+            let program = assemble(&mut assembler, &source)?;
+            if let Some(stats) = assembler.stats() {
+                write!(stdout, "{stats}")?;
+            }
+
+            let output = output.unwrap_or_else(|| input.with_extension("epie"));
+            std::fs::write(output, program)?;
+        }
+        Command::Disasm { path } => {
+            let bytes = std::fs::read(&path)?;
+
+            // source is assembled first, showing exactly what the assembler produced
+            let assembly = match disassemble(&bytes) {
+                Ok(assembly) => assembly,
+                Err(_) => {
+                    let source = String::from_utf8(bytes)?;
+                    let mut assembler = Assembler::default().source_path(&path);
+                    disassemble(&assembler.assemble(&source)?)?
+                }
+            };
+            write!(stdout, "{assembly}")?;
+        }
+        Command::Convert {
+            input,
+            output,
+            from,
+            to,
+            section,
+            base,
+        } => {
+            let bytes = std::fs::read(&input)?;
+            let from = from.unwrap_or_else(|| ImageFormat::guess(&input, Some(&bytes)));
+            let to = to.unwrap_or_else(|| ImageFormat::guess(&output, None));
+
+            std::fs::write(output, convert::convert(bytes, from, to, section, base)?)?;
+        }
+        Command::TraceDump { path } => {
+            write!(stdout, "{}", trace::dump_file(&std::fs::read(path)?)?)?;
+        }
+        #[cfg(feature = "server")]
+        Command::Serve { address, max_steps } => server::serve(&address, max_steps)?,
+        Command::Rename { old, new, path } => {
+            let data = std::fs::read_to_string(&path)?;
+            std::fs::write(&path, rename_label(&data, &old, &new)?)?;
+        }
+        Command::Test {
+            directory,
+            bless,
+            max_steps,
+        } => {
+            let cases = suite::run_suite(&directory, bless, max_steps)?;
+            let mut failed = 0;
+            for case in &cases {
+                match &case.failure {
+                    None => writeln!(stdout, "ok      {}", case.path.display())?,
+                    Some(failure) => {
+                        writeln!(stdout, "FAILED  {}\n{failure}", case.path.display())?;
+                        failed += 1;
+                    }
+                }
+            }
+
+            writeln!(stdout, "\n{} passed, {failed} failed", cases.len() - failed)?;
+            if failed > 0 {
+                bail!("{failed} of {} programs failed", cases.len());
+            }
+        }
+        Command::Bench { iterations } => {
+            writeln!(
+                stdout,
+                "{:<12}{:<8}{:>14}{:>12}{:>16}",
+                "benchmark", "mode", "instructions", "time", "instructions/s"
+            )?;
+            for benchmark in &bench::BENCHMARKS {
+                for mode in bench::Mode::ALL {
+                    let measurement = benchmark.measure(mode, iterations)?;
+                    writeln!(
+                        stdout,
+                        "{:<12}{:<8}{:>14}{:>12}{:>16}",
+                        benchmark.name,
+                        mode.name(),
+                        measurement.steps,
+                        format!("{:.2?}", measurement.time),
+                        bench::format_rate(measurement.instructions_per_second())
+                    )?;
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Runs the program one instruction at a time, writing a timeline of the calls it makes. The calls
+/// are read from the VM's shadow stack, which must be enabled
+fn run_with_timeline(
+    machine: &mut dyn Machine,
+    assembler: &Assembler,
+    path: &Path,
+) -> anyhow::Result<()> {
+    machine.start()?;
+
+    // only code labels can be called
+    let code = machine.memory().code_section().unwrap();
+    let mut timeline = Timeline::new(
+        assembler
+            .labels()
+            .filter(|&(_, address)| code.contains(&(address as usize))),
+    );
+
+    let entry = machine.pc();
+    let mut routines = Vec::new();
+    let mut steps = 0;
+    loop {
+        let frames = machine
+            .vm()
+            .and_then(VM::backtrace)
+            .context("timelines need a VM with its shadow stack enabled")?;
+        routines.clear();
+        routines.push(entry);
+        routines.extend(frames.iter().map(|frame| frame.routine));
+        timeline.record(&routines, steps);
+        steps += 1;
+
+        if !machine.step()? {
+            break;
+        }
     }
+    timeline.finish(steps);
+
+    std::fs::write(path, timeline.to_chrome_json())?;
 
     Ok(())
 }
+
+/// Opcodes whose handlers took the most time, with how often they ran and their share of the time
+#[cfg(feature = "timing")]
+fn timing_report(vm: &VM) -> String {
+    use std::fmt::Write;
+
+    let total = vm.timings().total().as_secs_f64();
+    let mut out = format!(
+        "\n{:<8} {:>10} {:>12} {:>10} {:>6}\n",
+        "opcode", "count", "total", "mean", "share"
+    );
+    for timing in vm.timings().top(TIMING_REPORT_LENGTH) {
+        let share = timing.total.as_secs_f64() / total.max(f64::MIN_POSITIVE) * 100.0;
+        writeln!(out, "{timing} {share:>5.1}%").unwrap();
+    }
+
+    out
+}
+
+/// Assembles source, printing any warnings and stripped labels
+fn assemble(assembler: &mut Assembler, source: &str) -> anyhow::Result<Vec<u8>> {
+    let program = assembler.assemble(source)?;
+
+    for warning in assembler.warnings() {
+        eprintln!("warning: {warning}");
+    }
+    if !assembler.stripped_symbols().is_empty() {
+        eprintln!(
+            "stripped unused: {}",
+            assembler.stripped_symbols().join(", ")
+        );
+    }
+
+    Ok(program)
+}
+
+/// Status the process exits with for a program's exit status. Processes can only exit with 0 to
+/// 255, so nonzero statuses outside that are clamped into it rather than truncated, which could
+/// turn a failure into 0
+fn process_status(status: i32) -> i32 {
+    match status {
+        0 => 0,
+        status => status.clamp(1, 255),
+    }
+}
+
+/// Describes a pc relative to the closest code label at or before it. Data labels are skipped, since
+/// the pc is never under one
+fn code_context<'a>(
+    labels: impl Iterator<Item = (&'a str, usize)>,
+    code: &Range<usize>,
+    pc: usize,
+) -> String {
+    repl::label_context(labels.filter(|(_, label)| code.contains(label)), pc)
+}
+
+/// Parses an address in decimal, or hex with a `0x` prefix
+fn parse_address(value: &str) -> Result<u32, std::num::ParseIntError> {
+    match value.strip_prefix("0x") {
+        Some(hex) => u32::from_str_radix(hex, 16),
+        None => value.parse(),
+    }
+}
+
+/// Parses a name defined with -D, as NAME or NAME=value
+fn parse_define(value: &str) -> Result<(String, i32), std::num::ParseIntError> {
+    match value.split_once('=') {
+        Some((name, value)) => Ok((name.to_owned(), value.parse()?)),
+        None => Ok((value.to_owned(), 1)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_process_status() {
+        assert_eq!(process_status(0), 0);
+        assert_eq!(process_status(3), 3);
+        assert_eq!(process_status(255), 255);
+        assert_eq!(process_status(256), 255);
+        assert_eq!(process_status(512), 255);
+        assert_eq!(process_status(-1), 1);
+    }
+
+    #[test]
+    fn test_code_context() {
+        let labels = [("greeting", 0x40), ("main", 0x50), ("loop", 0x58)];
+        assert_eq!(code_context(labels.into_iter(), &(0x50..0x60), 0x48), "");
+        assert_eq!(
+            code_context(labels.into_iter(), &(0x50..0x60), 0x5C),
+            " <loop+4>"
+        );
+    }
+
+    #[test]
+    fn test_is_broken_pipe() {
+        let closed = std::io::Error::from(ErrorKind::BrokenPipe);
+        assert!(is_broken_pipe(&anyhow::Error::from(closed)));
+
+        let printed = VmError::OutputFailed {
+            error: "Broken pipe".to_owned(),
+            kind: ErrorKind::BrokenPipe,
+        };
+        assert!(is_broken_pipe(
+            &anyhow::Error::from(printed).context("program faulted")
+        ));
+
+        let full = std::io::Error::from(ErrorKind::StorageFull);
+        assert!(!is_broken_pipe(&anyhow::Error::from(full)));
+        assert!(!is_broken_pipe(&anyhow::anyhow!("broken pipe")));
+    }
+}
@@ -0,0 +1,76 @@
+use std::fmt::Write;
+use vm::Profile;
+
+/// Number of opcodes and addresses listed in a profile report
+const PROFILE_REPORT_LENGTH: usize = 10;
+
+/// Lists the most executed opcodes and instruction addresses, with each one's share of every
+/// instruction executed. `context` describes an address, such as ` <loop+4>`
+pub fn profile_report(profile: &Profile, context: impl Fn(usize) -> String) -> String {
+    let total = profile.total();
+    let share = |count: u64| count as f64 / total.max(1) as f64 * 100.0;
+
+    let mut out = format!("\n{total} instructions executed\n");
+    writeln!(out, "{:<8} {:>10} {:>6}", "opcode", "count", "share").unwrap();
+    for (opcode, count) in profile.top_opcodes(PROFILE_REPORT_LENGTH) {
+        let opcode = format!("{opcode:?}");
+        writeln!(out, "{opcode:<8} {count:>10} {:>5.1}%", share(count)).unwrap();
+    }
+
+    writeln!(out, "hot spots:").unwrap();
+    for (pc, count) in profile.hot_spots(PROFILE_REPORT_LENGTH) {
+        writeln!(
+            out,
+            "  {pc:#06X} {count:>10} {:>5.1}%{}",
+            share(count),
+            context(pc)
+        )
+        .unwrap();
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use vm::{Program, VM};
+
+    #[test]
+    fn test_profile_report() {
+        let program = Assembler::default()
+            .assemble(
+                ".code
+                loop: inc $0
+                lti $0, 3
+                jmpei @loop
+                hlt",
+            )
+            .unwrap();
+
+        let mut vm = VM::default();
+        vm.load(Program::parse(program).unwrap());
+        vm.set_output(std::io::sink());
+        vm.enable_profiling(true);
+        vm.run().unwrap();
+
+        let report = profile_report(vm.profile().unwrap(), |pc| format!(" <+{}>", pc - 64));
+        assert_eq!(
+            report,
+            "
+10 instructions executed
+opcode        count  share
+INC               3  30.0%
+LTI               3  30.0%
+JMPEI             3  30.0%
+HLT               1  10.0%
+hot spots:
+  0x0040          3  30.0% <+0>
+  0x0044          3  30.0% <+4>
+  0x0048          3  30.0% <+8>
+  0x004C          1  10.0% <+12>
+"
+        );
+    }
+}
@@ -1,22 +1,133 @@
-use assembler::Assembler;
-use std::fmt::UpperHex;
+use crate::backtrace::{backtrace, stack_frame};
+use crate::expression::Expression;
+use crate::find::Query;
+use crate::format::NumberFormat;
+use crate::heap::heap_report;
+use crate::profile::profile_report;
+use crate::view::ViewType;
+use crate::watch::{Watch, Watchpoint};
+use anyhow::{anyhow, bail};
+use assembler::{disassemble, disassemble_instruction, Assembler, AssemblerError};
+use shared::abi::{register_index, STACK_TOP};
 use std::fs::File;
 use std::io;
 use std::io::{Read, Write};
 use std::num::ParseIntError;
-use std::path::Path;
-use vm::VM;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+use vm::{LogRecord, Machine, Program, Region, Snapshot, VmError, VM};
+
+/// Most matches printed by `.find`
+const MAX_FIND_MATCHES: usize = 100;
+
+/// Instructions recorded for `.back` to undo, until changed with `.record`
+const RECORDED_STEPS: usize = 10_000;
+
+/// Source file reassembled and loaded by `.watch_file` whenever it changes
+struct WatchedFile {
+    path: PathBuf,
+    /// When the file was last modified as of the last time it was loaded, or None if it couldn't
+    /// be read
+    modified: Option<SystemTime>,
+    /// Whether registers and the heap are kept when it's reloaded
+    keep_state: bool,
+}
 
-#[derive(Default)]
 #[allow(clippy::upper_case_acronyms)]
 pub struct REPL {
-    vm: VM,
+    machine: Box<dyn Machine>,
+    /// Makes the machine programs are run on, whenever it's reset
+    backend: fn() -> Box<dyn Machine>,
     command_buffer: Vec<String>,
+    /// Assembler used for the last loaded program, kept so labels can be resolved
+    assembler: Option<Assembler>,
+    /// Address the last loaded program was placed at
+    program_base: usize,
+    /// Source of the last loaded program, for showing the line being run
+    source: String,
+    /// Expressions printed after every step
+    displays: Vec<Expression>,
+    /// Locations execution stops at once they change
+    watchpoints: Vec<Watchpoint>,
+    /// How register and memory values are shown
+    format: NumberFormat,
+    /// File reloaded whenever it changes
+    watched_file: Option<WatchedFile>,
 }
 
 impl REPL {
-    pub fn set_vm(&mut self, vm: VM) {
-        self.vm = vm;
+    /// Creates a REPL showing values with the given format, running programs on machines made by
+    /// `backend`
+    pub fn new(format: NumberFormat, backend: fn() -> Box<dyn Machine>) -> Self {
+        let mut repl = Self {
+            machine: backend(),
+            backend,
+            command_buffer: Vec::new(),
+            assembler: None,
+            program_base: 0,
+            source: String::new(),
+            displays: Vec::new(),
+            watchpoints: Vec::new(),
+            format,
+            watched_file: None,
+        };
+        repl.reset_machine();
+
+        repl
+    }
+
+    /// Replaces the machine with a new one. If it's a VM, calls are recorded for backtraces and
+    /// instructions for `.back`, and every message the program logs is printed
+    fn reset_machine(&mut self) {
+        self.machine = (self.backend)();
+        if let Some(vm) = self.machine.vm_mut() {
+            vm.enable_shadow_stack(true);
+            vm.set_recording(Some(RECORDED_STEPS));
+            vm.set_logger(|record: &LogRecord| println!("{record}"));
+        }
+    }
+
+    /// VM behind the machine, for commands only a VM supports, or None after saying the command
+    /// isn't supported
+    fn debugger(&self) -> Option<&VM> {
+        let vm = self.machine.vm();
+        if vm.is_none() {
+            println!("not supported by the {} backend", self.machine.name());
+        }
+
+        vm
+    }
+
+    fn debugger_mut(&mut self) -> Option<&mut VM> {
+        let name = self.machine.name();
+        let vm = self.machine.vm_mut();
+        if vm.is_none() {
+            println!("not supported by the {name} backend");
+        }
+
+        vm
+    }
+
+    /// Assembles a program and appends it to the machine's program. `path` is the path of the source,
+    /// if it has one, so included files can be found
+    pub fn load_program(
+        &mut self,
+        source: &str,
+        path: Option<&Path>,
+    ) -> Result<(), AssemblerError> {
+        let mut assembler = match path {
+            Some(path) => Assembler::default().source_path(path),
+            None => Assembler::default(),
+        };
+        let bytes = assembler.assemble(source)?;
+
+        self.program_base = self.machine.memory().image().len();
+        self.machine.memory_mut().extend(&bytes);
+        self.assembler = Some(assembler);
+        self.source = source.to_owned();
+
+        Ok(())
     }
 
     /// Starts interactive REPL session
@@ -35,8 +146,10 @@ impl REPL {
                 .expect("Couldn't read from stdin");
             let command = buffer.trim();
             self.command_buffer.push(command.to_string());
+            self.check_watched_file();
+            let (name, args) = command.split_once(' ').unwrap_or((command, ""));
 
-            match command {
+            match name {
                 ".quit" | ".exit" => {
                     // quits
                     println!("quitting");
@@ -50,25 +163,367 @@ impl REPL {
                 }
                 ".program" => {
                     // dumps VMs program bytecode
-                    pretty_print_hex(&self.vm.program, 2);
+                    print!("{}", self.format.dump(self.machine.memory().image(), 1));
+                }
+                ".disassemble" => {
+                    // disassembles the last loaded program
+                    if self.assembler.is_none() {
+                        println!("no program loaded");
+                        continue;
+                    }
+
+                    match disassemble(&self.machine.memory().image()[self.program_base..]) {
+                        Ok(assembly) => print!("{assembly}"),
+                        Err(e) => println!("couldn't disassemble program: {e}"),
+                    }
+                }
+                ".symbols" => {
+                    // lists the labels and constants of the last loaded program
+                    self.print_symbols();
+                }
+                ".sections" => {
+                    // lists the sections described by the last loaded program's header
+                    self.print_sections();
                 }
                 ".registers" => {
                     // dumps VMs registers + equality flag
-                    pretty_print_hex(&self.vm.registers, 8);
-                    println!("Equality register: {}", self.vm.equality_flag);
+                    print!("{}", self.format.dump(self.machine.registers(), 4));
+                    println!("Equality register: {}", self.machine.equality_flag());
+                }
+                ".heap" => {
+                    // lists live allocations and freed blocks
+                    print!(
+                        "{}",
+                        heap_report(self.machine.memory(), |pc| self.symbol_context(pc))
+                    );
+                }
+                ".frame" => {
+                    // lists the words pushed by the routine the program is in
+                    let Some(vm) = self.debugger() else {
+                        continue;
+                    };
+                    match stack_frame(vm, |address| self.symbol_context(address)) {
+                        Some(frame) => print!("{frame}"),
+                        None => println!("calls aren't being recorded"),
+                    }
+                }
+                ".profile" => {
+                    // counts executed instructions, or prints the counts so far
+                    let Some(vm) = self.debugger_mut() else {
+                        continue;
+                    };
+                    match args {
+                        "on" => vm.enable_profiling(true),
+                        "off" => vm.enable_profiling(false),
+                        "" => match self.machine.vm().and_then(VM::profile) {
+                            Some(profile) => {
+                                print!("{}", profile_report(profile, |pc| self.symbol_context(pc)))
+                            }
+                            None => println!("profiling is off, enable it with .profile on"),
+                        },
+                        _ => println!("usage: .profile [on|off]"),
+                    }
+                }
+                ".set_register" => {
+                    // sets a register to the value of an expression
+                    let (register, value) = args.split_once(' ').unwrap_or((args, ""));
+                    let index = register_index(register.trim_start_matches('$'));
+                    let Some(index) = index.map(usize::from) else {
+                        println!("invalid register {register}");
+                        continue;
+                    };
+
+                    match Expression::parse(value).and_then(|expression| {
+                        expression.evaluate(self.machine.as_ref(), &self.labels())
+                    }) {
+                        Ok(value) => self.machine.registers_mut()[index] = value,
+                        Err(e) => println!("invalid value: {e}"),
+                    }
+                }
+                ".read" => {
+                    // dumps len bytes of memory starting at an address
+                    let (address, len) = args.rsplit_once(' ').unwrap_or((args, ""));
+                    let Ok(len) = len.parse::<usize>() else {
+                        println!("invalid length {len}");
+                        continue;
+                    };
+
+                    let bytes = Expression::parse(address)
+                        .and_then(|expression| {
+                            expression.evaluate(self.machine.as_ref(), &self.labels())
+                        })
+                        .and_then(|address| {
+                            Ok(self.machine.memory().read(address as usize, len)?)
+                        });
+                    match bytes {
+                        Ok(bytes) => print!("{}", self.format.dump(bytes, 1)),
+                        Err(e) => println!("invalid read: {e}"),
+                    }
+                }
+                ".find" => {
+                    // prints addresses matching a pattern, along with the label they're under
+                    match Query::parse(args) {
+                        Ok(query) => self.find(&query),
+                        Err(e) => println!("invalid search: {e}"),
+                    }
+                }
+                ".view" => {
+                    // renders memory as a type, taken from the label's declaration if not given
+                    let (address, ty) = match args.split_once(" as ") {
+                        Some((address, ty)) => (address, Some(ty)),
+                        None => (args, None),
+                    };
+
+                    if let Err(e) = self.view(address, ty) {
+                        println!("invalid view: {e}");
+                    }
+                }
+                ".write" => {
+                    // writes hex bytes to memory starting at an address, even if it's read-only
+                    let (address, bytes) = args.split_once(' ').unwrap_or((args, ""));
+                    let Ok(bytes) = parse_hex(bytes) else {
+                        println!("invalid bytes {bytes}");
+                        continue;
+                    };
+
+                    let written = Expression::parse(address)
+                        .and_then(|expression| {
+                            expression.evaluate(self.machine.as_ref(), &self.labels())
+                        })
+                        .and_then(|address| Ok(self.machine.poke(address as usize, &bytes)?));
+                    if let Err(e) = written {
+                        println!("invalid write: {e}");
+                    }
+                }
+                ".set" => {
+                    // changes how values are shown, or lists the current settings if none given
+                    if args.is_empty() {
+                        println!("{}", self.format);
+                        continue;
+                    }
+
+                    let (setting, value) = args.split_once(' ').unwrap_or((args, ""));
+                    if let Err(e) = self.format.set(setting, value.trim()) {
+                        println!("invalid setting: {e}");
+                    }
+                }
+                ".snapshot" | ".save_state" => {
+                    // saves the VM's state to a file
+                    let Some(vm) = self.debugger() else {
+                        continue;
+                    };
+                    if let Err(e) = std::fs::write(args, vm.snapshot().as_bytes()) {
+                        println!("couldn't write snapshot: {e}");
+                    }
+                }
+                ".restore" | ".load_state" => {
+                    // replaces the VM's state with a snapshot saved by .snapshot
+                    let Some(vm) = self.debugger_mut() else {
+                        continue;
+                    };
+                    let restored = std::fs::read(args)
+                        .map_err(anyhow::Error::from)
+                        .and_then(|bytes| Ok(Snapshot::from_bytes(bytes)?))
+                        .and_then(|snapshot| Ok(vm.restore(&snapshot)?));
+                    match restored {
+                        Ok(()) => println!("pc = {:#06X}", vm.pc()),
+                        Err(e) => println!("couldn't restore snapshot: {e}"),
+                    }
                 }
                 ".reset" => {
-                    // resets VM to default state
-                    self.vm = VM::default();
-                    // assembler = Assembler::new();
+                    // resets the machine to its default state
+                    self.reset_machine();
+                    self.assembler = None;
+                    self.program_base = 0;
                 }
                 ".run" => {
-                    // runs VM until completion
-                    self.vm.run();
+                    // runs the program from the start until completion or a breakpoint
+                    match self.machine.start() {
+                        Ok(()) => self.resume(),
+                        Err(e) => self.report_fault(e),
+                    }
+                }
+                ".continue" => {
+                    // runs the program until completion or the next breakpoint, starting it at its
+                    // entry point if it hasn't been started yet
+                    if self.machine.memory().code_section().is_none() {
+                        if let Err(e) = self.machine.start() {
+                            self.report_fault(e);
+                            continue;
+                        }
+                    }
+                    self.resume();
+                }
+                ".run_once" | ".step" => {
+                    // runs a single instruction
+                    if let Err(e) = self.machine.step() {
+                        self.report_fault(e);
+                    }
+                    self.check_watchpoints();
+                    println!("pc = {:#06X}", self.machine.pc());
+                    self.print_source_line();
+                    self.print_displays();
                 }
-                ".run_once" => {
-                    // runs VM once
-                    self.vm.run_once();
+                ".back" => {
+                    // undoes the last instruction, or the last n
+                    let count = match args {
+                        "" => Ok(1),
+                        count => count.parse::<usize>(),
+                    };
+                    let Ok(count) = count else {
+                        println!("invalid count: {args}");
+                        continue;
+                    };
+
+                    let Some(vm) = self.debugger_mut() else {
+                        continue;
+                    };
+                    let undone = (0..count).take_while(|_| vm.step_back()).count();
+                    if undone < count {
+                        println!("no more recorded instructions to step back through");
+                    }
+                    self.check_watchpoints();
+                    println!("pc = {:#06X}", self.machine.pc());
+                    self.print_source_line();
+                    self.print_displays();
+                }
+                ".record" => {
+                    // changes how many instructions are recorded for .back, or stops recording
+                    let capacity = match args {
+                        "off" => None,
+                        capacity => match capacity.parse::<usize>() {
+                            Ok(capacity) => Some(capacity),
+                            Err(_) => {
+                                println!("expected a number of instructions or off, got '{args}'");
+                                continue;
+                            }
+                        },
+                    };
+
+                    if let Some(vm) = self.debugger_mut() {
+                        vm.set_recording(capacity);
+                    }
+                }
+                ".break" => {
+                    // adds a breakpoint at an address, or lists them if none given
+                    let address = Expression::parse(args).and_then(|expression| {
+                        expression.evaluate(self.machine.as_ref(), &self.labels())
+                    });
+                    let Some(vm) = self.debugger_mut() else {
+                        continue;
+                    };
+                    if args.is_empty() {
+                        for (index, address) in vm.breakpoints().iter().enumerate() {
+                            println!("{}: {address:#06X}", index + 1);
+                        }
+                        continue;
+                    }
+
+                    match address {
+                        Ok(address) if vm.add_breakpoint(address as usize) => {
+                            let number = vm.breakpoints().len();
+                            println!("breakpoint {number} at {address:#06X}");
+                        }
+                        Ok(address) => println!("breakpoint already set at {address:#06X}"),
+                        Err(e) => println!("invalid address: {e}"),
+                    }
+                }
+                ".delete" => {
+                    // removes a breakpoint by its number
+                    let Some(vm) = self.debugger_mut() else {
+                        continue;
+                    };
+                    let removed = args
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|n| vm.remove_breakpoint(n.checked_sub(1)?));
+                    if removed.is_none() {
+                        println!("no breakpoint number {args}");
+                    }
+                }
+                ".display" => {
+                    // adds an expression to print after every step, or lists them if none given
+                    if args.is_empty() {
+                        self.print_displays();
+                        continue;
+                    }
+
+                    match Expression::parse(args) {
+                        Ok(expression) => {
+                            self.displays.push(expression);
+                            self.print_display(self.displays.len() - 1);
+                        }
+                        Err(e) => println!("invalid expression: {e}"),
+                    }
+                }
+                ".watch" => {
+                    // stops execution once a location changes, or lists watchpoints if none given
+                    if args.is_empty() {
+                        for (index, watchpoint) in self.watchpoints.iter().enumerate() {
+                            println!("{}: {}", index + 1, watchpoint.describe(&self.format));
+                        }
+                        continue;
+                    }
+
+                    let watch = Watch::parse(args, |address| {
+                        let expression = Expression::parse(address)?;
+                        Ok(expression.evaluate(self.machine.as_ref(), &self.labels())? as usize)
+                    });
+                    match watch {
+                        Ok(watch) => {
+                            self.watchpoints
+                                .push(Watchpoint::new(watch, self.machine.as_ref()));
+                            let watchpoint = self.watchpoints.last().unwrap();
+                            let number = self.watchpoints.len();
+                            println!("watchpoint {number}: {}", watchpoint.describe(&self.format));
+                        }
+                        Err(e) => println!("invalid watch: {e}"),
+                    }
+                }
+                ".unwatch" => {
+                    // removes a watchpoint by its number
+                    match args.parse::<usize>() {
+                        Ok(n) if (1..=self.watchpoints.len()).contains(&n) => {
+                            self.watchpoints.remove(n - 1);
+                        }
+                        _ => println!("no watchpoint number {args}"),
+                    }
+                }
+                ".watch_file" => {
+                    // reloads a source file whenever it changes, or shows the watched file if none
+                    // given
+                    if args.is_empty() {
+                        match &self.watched_file {
+                            Some(watched) => println!("watching {}", watched.path.display()),
+                            None => println!("no file watched"),
+                        }
+                        continue;
+                    }
+
+                    let (path, keep_state) = match args.strip_suffix(" keep") {
+                        Some(path) => (path.trim(), true),
+                        None => (args, false),
+                    };
+                    let path = PathBuf::from(path);
+                    self.watched_file = Some(WatchedFile {
+                        modified: modified_time(&path),
+                        path: path.clone(),
+                        keep_state,
+                    });
+                    self.reload_file(&path, keep_state);
+                }
+                ".unwatch_file" => {
+                    // stops reloading the watched file
+                    self.watched_file = None;
+                }
+                ".undisplay" => {
+                    // removes a display by its number
+                    match args.parse::<usize>() {
+                        Ok(n) if (1..=self.displays.len()).contains(&n) => {
+                            self.displays.remove(n - 1);
+                        }
+                        _ => println!("no display number {args}"),
+                    }
                 }
                 ".load_file" => {
                     print!("file path: ");
@@ -85,60 +540,472 @@ impl REPL {
                     file.read_to_string(&mut file_content)
                         .expect("Couldn't read file");
 
-                    match Assembler::default().assemble(&file_content) {
-                        Ok(bytes) => self.vm.program.extend_from_slice(&bytes),
-                        Err(e) => {
-                            println!("Couldn't parse input program: {e:?}");
-                            continue;
-                        }
+                    if let Err(e) = self.load_program(&file_content, Some(path)) {
+                        println!("Couldn't parse input program: {e:?}");
+                        continue;
                     }
                 }
                 _ => {
-                    // tries and parses input, pushes to program, and executes once
-                    let bytecode = match Assembler::default().assemble(command) {
+                    // tries and parses input, pushes its code to the program, and executes it
+                    let bytecode = match assemble_code(command) {
                         Ok(bytes) => bytes,
                         Err(_) => {
-                            // otherwise treat as hex
-                            match parse_hex(command) {
-                                Ok(bytes) => bytes,
-                                Err(_) => {
-                                    println!("invalid command");
-                                    continue;
+                            // otherwise treat as hex, which must be whole instructions
+                            let Ok(bytes) = parse_hex(command) else {
+                                println!("invalid command");
+                                continue;
+                            };
+                            if bytes.len() % 4 != 0 {
+                                println!(
+                                    "instructions are 4 bytes each, but {} bytes were entered",
+                                    bytes.len()
+                                );
+                                continue;
+                            }
+
+                            // echoes what's about to run, so mistyped bytes are easy to spot
+                            for instruction in bytes.chunks_exact(4) {
+                                match disassemble_instruction(instruction.try_into().unwrap()) {
+                                    Some(instruction) => println!("{instruction}"),
+                                    None => println!("illegal opcode {:#04X}", instruction[0]),
                                 }
                             }
+
+                            bytes
                         }
                     };
 
-                    self.vm.program.extend_from_slice(&bytecode);
-                    self.vm.run_once();
+                    self.run_entered(&bytecode);
+                    self.print_displays();
+                }
+            }
+        }
+    }
+
+    /// Appends instructions entered at the prompt to the program and runs from the first of them
+    /// until they've all been executed, unless they jump elsewhere or stop first
+    fn run_entered(&mut self, bytecode: &[u8]) {
+        let start = self.machine.memory().image().len();
+        self.machine.memory_mut().extend(bytecode);
+        self.machine.set_pc(start);
+        for _ in 0..bytecode.len() / 4 {
+            match self.machine.step() {
+                Ok(true) => {}
+                Ok(false) => break,
+                Err(e) => {
+                    self.report_fault(e);
+                    break;
                 }
             }
         }
     }
-}
 
-/// Pretty prints array of types that can be represented in hex
-/// Size is how much to pad each hex value
-pub(crate) fn pretty_print_hex<T: UpperHex>(bytes: &[T], size: usize) {
-    let byte_chunks = bytes.chunks(4).collect::<Vec<_>>();
+    /// Reloads the watched file if it has been modified since it was last loaded
+    fn check_watched_file(&mut self) {
+        let Some(watched) = &mut self.watched_file else {
+            return;
+        };
+        let modified = modified_time(&watched.path);
+        if modified == watched.modified {
+            return;
+        }
+
+        watched.modified = modified;
+        let (path, keep_state) = (watched.path.clone(), watched.keep_state);
+        self.reload_file(&path, keep_state);
+    }
+
+    /// Assembles a file and replaces the VM's program with it, reporting whether it could be
+    fn reload_file(&mut self, path: &Path, keep_state: bool) {
+        let had_heap = self.machine.memory().heap_size() > 0;
+        let Some(vm) = self.debugger_mut() else {
+            return;
+        };
+        let mut reload = || -> anyhow::Result<(Assembler, String, bool)> {
+            let source = std::fs::read_to_string(path)?;
+            let mut assembler = Assembler::default().source_path(path);
+            let bytes = assembler.assemble(&source)?;
+            let heap_kept = vm.reload(Program::parse(bytes)?, keep_state)?;
+
+            Ok((assembler, source, heap_kept))
+        };
+
+        match reload() {
+            Ok((assembler, source, heap_kept)) => {
+                self.assembler = Some(assembler);
+                self.program_base = 0;
+                self.source = source;
+
+                println!("reloaded {}", path.display());
+                if keep_state && had_heap && !heap_kept {
+                    println!("heap not kept, since the program changed size");
+                }
+            }
+            Err(e) => println!("couldn't reload {}: {e}", path.display()),
+        }
+    }
 
-    for line in byte_chunks.chunks(2) {
-        for chunk in line {
-            for byte in *chunk {
-                print!("{byte:00$X} ", size);
+    /// Resumes the program, reporting where it stopped
+    fn resume(&mut self) {
+        let vm = match self.machine.vm_mut() {
+            Some(vm) if self.watchpoints.is_empty() => vm,
+            _ => return self.resume_stepping(),
+        };
+
+        match vm.resume() {
+            Ok(true) => {
+                let pc = vm.pc();
+                let number = vm.breakpoints().iter().position(|&b| b == pc).unwrap() + 1;
+                println!("breakpoint {number} hit at {pc:#06X}");
+                self.print_source_line();
+                self.print_displays();
             }
-            print!("\t");
+            Ok(false) => {}
+            Err(e) => self.report_fault(e),
         }
-        println!();
     }
 
-    io::stdout().flush().expect("Couldn't flush stdout");
+    /// Resumes the program one instruction at a time, stopping once a watched location changes as
+    /// well as at breakpoints. Used while watching, and for machines that can't stop at breakpoints
+    /// themselves
+    fn resume_stepping(&mut self) {
+        // locations changed by commands since they were last checked don't count
+        for watchpoint in &mut self.watchpoints {
+            *watchpoint = Watchpoint::new(watchpoint.watch, self.machine.as_ref());
+        }
+
+        loop {
+            match self.machine.step() {
+                Ok(true) => {}
+                Ok(false) => return,
+                Err(e) => return self.report_fault(e),
+            }
+
+            let pc = self.machine.pc();
+            if self.check_watchpoints() {
+                println!("pc = {pc:#06X}");
+                self.print_source_line();
+                self.print_displays();
+                return;
+            }
+            let breakpoints = self.machine.vm().map_or(&[][..], VM::breakpoints);
+            if let Some(index) = breakpoints.iter().position(|&b| b == pc) {
+                println!("breakpoint {} hit at {pc:#06X}", index + 1);
+                self.print_source_line();
+                self.print_displays();
+                return;
+            }
+        }
+    }
+
+    /// Prints every watched location that has changed since it was last checked, returning whether
+    /// any had
+    fn check_watchpoints(&mut self) -> bool {
+        let mut changed = false;
+        for (index, watchpoint) in self.watchpoints.iter_mut().enumerate() {
+            if let Some(change) = watchpoint.update(self.machine.as_ref(), &self.format) {
+                println!("watchpoint {}: {change}", index + 1);
+                changed = true;
+            }
+        }
+
+        changed
+    }
+
+    /// Prints a fault along with the calls that led to it
+    fn report_fault(&self, error: VmError) {
+        println!("VM fault: {error}");
+        let backtrace = self
+            .machine
+            .vm()
+            .and_then(|vm| backtrace(vm, |address| self.symbol_context(address)));
+        if let Some(backtrace) = backtrace {
+            print!("{backtrace}");
+        }
+    }
+
+    /// Prints memory at the address an expression evaluates to as the given type, or as the type
+    /// its label was declared with
+    fn view(&self, address: &str, ty: Option<&str>) -> anyhow::Result<()> {
+        let expression = Expression::parse(address)?;
+        let ty = match (ty, &expression) {
+            (Some(ty), _) => ViewType::parse(ty)?,
+            (None, Expression::Label(label)) => self
+                .assembler
+                .as_ref()
+                .and_then(|assembler| assembler.label_layout(label))
+                .map(ViewType::from_layout)
+                .ok_or_else(|| anyhow!("no type known for @{label}, use `as <type>`"))?,
+            (None, _) => bail!("no type known for {expression}, use `as <type>`"),
+        };
+
+        let address = expression.evaluate(self.machine.as_ref(), &self.labels())?;
+        println!("{}", ty.render(self.machine.memory(), address as usize)?);
+
+        Ok(())
+    }
+
+    /// Prints every address in memory matching the query. Instructions are only searched for in the
+    /// code section
+    fn find(&self, query: &Query) {
+        let memory = self.machine.memory();
+        let regions = match query {
+            Query::Instr(_) => self
+                .code_section()
+                .map(|code| (code.start, &memory.image()[code]))
+                .into_iter()
+                .collect(),
+            _ => vec![
+                (0, memory.image()),
+                (
+                    memory.heap_start(),
+                    memory
+                        .read(memory.heap_start(), memory.heap_size())
+                        .unwrap_or_default(),
+                ),
+                (
+                    memory.stack_start(),
+                    memory
+                        .read(memory.stack_start(), STACK_TOP - memory.stack_start())
+                        .unwrap_or_default(),
+                ),
+            ],
+        };
+
+        let mut matches = regions
+            .into_iter()
+            .flat_map(|(start, bytes)| query.matches(bytes).map(move |offset| start + offset))
+            .peekable();
+        if matches.peek().is_none() {
+            println!("no matches");
+        }
+
+        for (count, address) in matches.enumerate() {
+            if count == MAX_FIND_MATCHES {
+                println!("... more matches not shown");
+                break;
+            }
+
+            println!("{address:#06X}{}", self.symbol_context(address));
+        }
+    }
+
+    /// Header of the last loaded program, placed at its address in memory
+    fn loaded_program(&self) -> Option<Program> {
+        self.assembler.as_ref()?;
+
+        Program::parse(self.machine.memory().image()[self.program_base..].to_vec()).ok()
+    }
+
+    /// Prints every label in the last loaded program by address, along with the section it's in,
+    /// followed by its constants by name
+    fn print_symbols(&self) {
+        let (Some(assembler), Some(program)) = (&self.assembler, self.loaded_program()) else {
+            println!("no program loaded");
+            return;
+        };
+        let sections = sections(&program);
+
+        let mut labels: Vec<_> = assembler.labels().collect();
+        labels.sort_by_key(|&(name, address)| (address, name));
+        for (name, address) in labels {
+            let section = sections
+                .iter()
+                .find(|(_, range)| range.contains(&(address as usize)))
+                .map_or("", |&(section, _)| section);
+            let address = address as usize + self.program_base;
+
+            println!("{address:#06X}  {section:<6}  {name}");
+        }
+
+        let mut constants: Vec<_> = assembler.constants().collect();
+        constants.sort();
+        for (name, value) in constants {
+            println!("        const   {name} = {value}");
+        }
+    }
+
+    /// Prints the address and length of each section of the last loaded program
+    fn print_sections(&self) {
+        let Some(program) = self.loaded_program() else {
+            println!("no program loaded");
+            return;
+        };
+        let sections = sections(&program);
+
+        println!("section  start   end     length");
+        for (name, range) in sections {
+            // empty sections are recorded as starting at 0, wherever they would have been
+            if range.is_empty() {
+                println!("{name:<7}  -       -       0");
+                continue;
+            }
+            let (start, end) = (
+                range.start + self.program_base,
+                range.end + self.program_base,
+            );
+
+            println!("{name:<7}  {start:#06X}  {end:#06X}  {}", range.len());
+        }
+        println!("entry    {:#06X}", program.entry() + self.program_base);
+    }
+
+    /// Code section of the running program, or of the last loaded program if it hasn't started
+    fn code_section(&self) -> Option<Range<usize>> {
+        if let Some(code) = self.machine.memory().code_section() {
+            return Some(code);
+        }
+
+        self.assembler.as_ref()?;
+        let field = |offset: usize| {
+            let bytes = self
+                .machine
+                .memory()
+                .read(self.program_base + offset, 4)
+                .ok()?;
+            Some(u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+        };
+        let start = self.program_base + field(16)?;
+
+        Some(start..start + field(20)?)
+    }
+
+    /// Describes where an address is, relative to the closest label before it or the start of the
+    /// heap or stack, such as ` <loop+4>`
+    fn symbol_context(&self, address: usize) -> String {
+        let memory = self.machine.memory();
+        match memory.region(address) {
+            Some(Region::Heap) => return format!(" <heap+{}>", address - memory.heap_start()),
+            Some(Region::Stack) => return format!(" <stack+{}>", address - memory.stack_start()),
+            _ => {}
+        }
+
+        let labels = self
+            .assembler
+            .iter()
+            .flat_map(|assembler| assembler.labels())
+            .map(|(name, label)| (name, label as usize + self.program_base));
+
+        label_context(labels, address)
+    }
+
+    /// Resolves labels from the last loaded program to their address in memory
+    fn labels(&self) -> impl Fn(&str) -> Option<u32> + '_ {
+        |label: &str| {
+            let address = self.assembler.as_ref()?.label_address(label)?;
+            Some(address + self.program_base as u32)
+        }
+    }
+
+    /// Prints the source line the next instruction was assembled from, such as
+    /// `main.asm:12: inc $0`, if it's in the last loaded program. Lines from included files are read
+    /// from disk
+    fn print_source_line(&self) {
+        let Some(assembler) = &self.assembler else {
+            return;
+        };
+        let source = self
+            .machine
+            .pc()
+            .checked_sub(self.program_base)
+            .and_then(|address| assembler.line_table().lookup(address as u32));
+        let Some((file, line)) = source else {
+            return;
+        };
+
+        let text = match file {
+            "" => Some(self.source.clone()),
+            _ => std::fs::read_to_string(file).ok(),
+        };
+        let text = text
+            .as_deref()
+            .and_then(|text| text.lines().nth(line as usize - 1))
+            .unwrap_or_default();
+        let file = if file.is_empty() { "<input>" } else { file };
+
+        println!("{file}:{line}: {}", text.trim());
+    }
+
+    /// Prints every display expression with its current value
+    fn print_displays(&self) {
+        for index in 0..self.displays.len() {
+            self.print_display(index);
+        }
+    }
+
+    fn print_display(&self, index: usize) {
+        let expression = &self.displays[index];
+
+        match expression.evaluate(self.machine.as_ref(), &self.labels()) {
+            Ok(value) => println!("{}: {expression} = {}", index + 1, self.format.word(value)),
+            Err(e) => println!("{}: {expression} = <{e}>", index + 1),
+        }
+    }
+}
+
+/// Name and addresses of each section of a program
+fn sections(program: &Program) -> [(&'static str, Range<usize>); 4] {
+    [
+        ("data", program.data()),
+        ("rodata", program.rodata()),
+        ("code", program.code()),
+        ("bss", program.bss()),
+    ]
+}
+
+/// Assembles instructions entered at the prompt, returning only their code, without the header
+/// of the program they're assembled into
+fn assemble_code(source: &str) -> Result<Vec<u8>, AssemblerError> {
+    let program = Program::parse(Assembler::default().assemble(source)?).unwrap();
+
+    Ok(program.image()[program.code()].to_vec())
+}
+
+/// When a file was last modified, or None if that can't be found
+fn modified_time(path: &Path) -> Option<SystemTime> {
+    std::fs::metadata(path)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+}
+
+/// Describes an address relative to the closest label at or before it, such as ` <loop+4>`, or
+/// nothing if there isn't one
+pub(crate) fn label_context<'a>(
+    labels: impl Iterator<Item = (&'a str, usize)>,
+    address: usize,
+) -> String {
+    labels
+        .filter(|&(_, label)| label <= address)
+        .max_by_key(|&(_, label)| label)
+        .map(|(name, label)| format!(" <{name}+{}>", address - label))
+        .unwrap_or_default()
 }
 
 /// Parses a hex string into a list of bytes, such as "00 01 03 E8"
-fn parse_hex(string: &str) -> Result<Vec<u8>, ParseIntError> {
+pub(crate) fn parse_hex(string: &str) -> Result<Vec<u8>, ParseIntError> {
     string
         .split(' ')
         .map(|hex_string| u8::from_str_radix(hex_string, 16))
         .collect()
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_run_entered() {
+        let mut repl = REPL::new(NumberFormat::default(), || Box::new(VM::default()));
+        let code = assemble_code("ldbi $1, 5").unwrap();
+        assert_eq!(code.len(), 4);
+        repl.run_entered(&code);
+
+        // later instructions run after the earlier ones, however many are entered at once
+        let mut code = assemble_code("addi $1, 2").unwrap();
+        code.extend(assemble_code("inc $1").unwrap());
+        repl.run_entered(&code);
+        assert_eq!(repl.machine.registers()[1], 8);
+        assert_eq!(repl.machine.pc(), 12);
+        assert_eq!(repl.machine.memory().image().len(), 12);
+    }
+}
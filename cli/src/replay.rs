@@ -0,0 +1,535 @@
+//! Recording a run with `run --record` so `run --replay` can repeat it exactly, for bug reports
+//! against the VM itself.
+//!
+//! A recording holds the program and everything else the run depended on: its arguments, the
+//! settings that change how it runs, the input it read, the numbers `RAND` produced and the times
+//! `TIME` read. It also holds how the run ended, so a replay can check it ended the same way.
+//! Files and keyboard presses aren't recorded, so runs using them can't be recorded.
+//!
+//! Recordings start with the `EPRP` magic and a one byte format version, followed by each field in
+//! order. Integers are big endian, optional values are preceded by a byte that's 1 if they're
+//! present, and byte strings and lists are preceded by their length as a `u32`.
+
+use crate::golden;
+use anyhow::{anyhow, bail};
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::io::{BufRead, Cursor, Read, Write};
+use std::path::Path;
+use std::rc::Rc;
+use vm::{Clock, Program, Random, SharedBuffer, VMConfig, VM};
+
+const REPLAY_MAGIC: [u8; 4] = *b"EPRP";
+/// Version of the recording format, bumped whenever the layout changes
+const REPLAY_VERSION: u8 = 1;
+
+/// Settings of a recorded run that change how it runs
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Settings {
+    pub config: VMConfig,
+    pub args: Vec<String>,
+    pub check_alignment: bool,
+    pub verify_jumps: bool,
+    pub object_heap: bool,
+}
+
+impl Settings {
+    /// Creates a VM running the program with these settings
+    fn create_vm(&self, program: &[u8]) -> anyhow::Result<VM> {
+        let mut vm = VM::with_config(self.config);
+        vm.load(Program::parse(program.to_vec())?);
+        if !self.args.is_empty() {
+            vm.set_args(&self.args)?;
+        }
+        vm.memory_mut().set_alignment_checked(self.check_alignment);
+        vm.enable_jump_verification(self.verify_jumps);
+        vm.enable_object_heap(self.object_heap);
+
+        Ok(vm)
+    }
+}
+
+/// What the run read from outside the VM, in the order it was read
+#[derive(Debug, Default, PartialEq)]
+struct Events {
+    input: Vec<u8>,
+    random: Vec<u32>,
+    clock: Vec<u64>,
+}
+
+/// How a run ended
+#[derive(Debug, PartialEq)]
+struct Outcome {
+    registers: [i32; 32],
+    equality_flag: bool,
+    pc: usize,
+    steps: u64,
+    exit_status: i32,
+    output: Vec<u8>,
+    fault: Option<String>,
+}
+
+impl Outcome {
+    fn new(vm: &VM, output: &SharedBuffer, fault: Option<String>) -> Self {
+        Self {
+            registers: *vm.registers(),
+            equality_flag: vm.equality_flag(),
+            pc: vm.pc(),
+            steps: vm.steps(),
+            exit_status: vm.exit_status(),
+            output: output.contents(),
+            fault,
+        }
+    }
+
+    /// Describes the first difference from the recorded outcome, or None if there is none
+    fn difference(&self, recorded: &Outcome) -> Option<String> {
+        if self.output != recorded.output {
+            let diff = golden::diff(
+                &String::from_utf8_lossy(&recorded.output),
+                &String::from_utf8_lossy(&self.output),
+            );
+            return Some(format!("output differs:\n{}", diff.unwrap_or_default()));
+        }
+        if self.fault != recorded.fault {
+            return Some(format!(
+                "fault was {:?}, recorded {:?}",
+                self.fault, recorded.fault
+            ));
+        }
+        if self.steps != recorded.steps {
+            return Some(format!(
+                "{} instructions ran, recorded {}",
+                self.steps, recorded.steps
+            ));
+        }
+        if self.pc != recorded.pc {
+            return Some(format!(
+                "pc is {:#06X}, recorded {:#06X}",
+                self.pc, recorded.pc
+            ));
+        }
+        if let Some(index) = (0..32).find(|&i| self.registers[i] != recorded.registers[i]) {
+            return Some(format!(
+                "${index} is {}, recorded {}",
+                self.registers[index], recorded.registers[index]
+            ));
+        }
+        if self.equality_flag != recorded.equality_flag {
+            return Some(format!(
+                "equality flag is {}, recorded {}",
+                self.equality_flag, recorded.equality_flag
+            ));
+        }
+        if self.exit_status != recorded.exit_status {
+            return Some(format!(
+                "exit status is {}, recorded {}",
+                self.exit_status, recorded.exit_status
+            ));
+        }
+
+        None
+    }
+}
+
+/// Run being recorded, attached to its VM with `attach` and written out with `write` once it stops
+pub struct Recorder {
+    program: Vec<u8>,
+    settings: Settings,
+    events: Rc<RefCell<Events>>,
+    output: SharedBuffer,
+}
+
+impl Recorder {
+    pub fn new(program: Vec<u8>, settings: Settings) -> Self {
+        Self {
+            program,
+            settings,
+            events: Rc::default(),
+            output: SharedBuffer::default(),
+        }
+    }
+
+    /// Records the input the VM reads from stdin, its random numbers from a generator with the
+    /// given seed and the times it reads, and its output
+    pub fn attach(&self, vm: &mut VM, seed: u64) {
+        self.attach_with(
+            vm,
+            std::io::stdin().lock(),
+            vm::XorShift::new(seed),
+            vm::SystemClock::default(),
+        );
+    }
+
+    fn attach_with(
+        &self,
+        vm: &mut VM,
+        input: impl BufRead + 'static,
+        mut random: impl Random + 'static,
+        mut clock: impl Clock + 'static,
+    ) {
+        vm.set_input(RecordedInput {
+            inner: input,
+            events: Rc::clone(&self.events),
+        });
+
+        let events = Rc::clone(&self.events);
+        vm.set_random(move || {
+            let value = random.next_u32();
+            events.borrow_mut().random.push(value);
+            value
+        });
+
+        let events = Rc::clone(&self.events);
+        vm.set_clock(move || {
+            let now = clock.now_ms();
+            events.borrow_mut().clock.push(now);
+            now
+        });
+
+        vm.add_output(self.output.clone());
+    }
+
+    /// Writes the recording, along with how the run ended
+    pub fn write(&self, path: &Path, vm: &VM, fault: Option<&anyhow::Error>) -> anyhow::Result<()> {
+        std::fs::write(path, self.recording(vm, fault.map(ToString::to_string)))?;
+
+        Ok(())
+    }
+
+    /// Encodes the recording, along with how the run ended
+    fn recording(&self, vm: &VM, fault: Option<String>) -> Vec<u8> {
+        let outcome = Outcome::new(vm, &self.output, fault);
+        let events = self.events.borrow();
+
+        encode(&self.program, &self.settings, &events, &outcome)
+    }
+}
+
+/// Input that records every byte the program reads from it
+struct RecordedInput<R> {
+    inner: R,
+    events: Rc<RefCell<Events>>,
+}
+
+impl<R: BufRead> Read for RecordedInput<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.events.borrow_mut().input.extend(&buf[..read]);
+
+        Ok(read)
+    }
+}
+
+impl<R: BufRead> BufRead for RecordedInput<R> {
+    fn fill_buf(&mut self) -> std::io::Result<&[u8]> {
+        self.inner.fill_buf()
+    }
+
+    fn consume(&mut self, amt: usize) {
+        // the bytes being consumed are still at the start of the buffer
+        if let Ok(buffer) = self.inner.fill_buf() {
+            self.events.borrow_mut().input.extend(&buffer[..amt]);
+        }
+        self.inner.consume(amt);
+    }
+}
+
+/// Runs a recording again, printing its output as it goes, and fails if it ends differently than
+/// it did when recorded. Fails with the recorded fault if it ended with one
+pub fn replay(path: &Path) -> anyhow::Result<()> {
+    replay_recording(&std::fs::read(path)?, std::io::stdout())
+}
+
+fn replay_recording(recording: &[u8], printed: impl Write + 'static) -> anyhow::Result<()> {
+    let (program, settings, events, recorded) = decode(recording)?;
+
+    let mut vm = settings.create_vm(&program)?;
+    vm.set_output(printed);
+    let output = SharedBuffer::default();
+    replay_events(&mut vm, events, &output);
+    let result = vm.run();
+
+    let outcome = Outcome::new(&vm, &output, result.as_ref().err().map(ToString::to_string));
+    if let Some(difference) = outcome.difference(&recorded) {
+        bail!("replay diverged from the recording: {difference}");
+    }
+
+    Ok(result?)
+}
+
+/// Feeds the VM the recorded events instead of reading them from outside, capturing its output.
+/// Once the recorded random numbers or times run out, the run has diverged, so zeroes or the last
+/// time are read instead
+fn replay_events(vm: &mut VM, events: Events, output: &SharedBuffer) {
+    vm.set_input(Cursor::new(events.input));
+
+    let mut random = VecDeque::from(events.random);
+    vm.set_random(move || random.pop_front().unwrap_or(0));
+
+    let last = events.clock.last().copied().unwrap_or(0);
+    let mut clock = VecDeque::from(events.clock);
+    vm.set_clock(move || clock.pop_front().unwrap_or(last));
+
+    vm.add_output(output.clone());
+}
+
+fn encode(program: &[u8], settings: &Settings, events: &Events, outcome: &Outcome) -> Vec<u8> {
+    let mut out = REPLAY_MAGIC.to_vec();
+    out.push(REPLAY_VERSION);
+
+    write_bytes(&mut out, program);
+    for limit in [
+        settings.config.max_heap.map(|limit| limit as u64),
+        settings.config.max_program_size.map(|limit| limit as u64),
+        settings.config.max_steps,
+    ] {
+        write_option(&mut out, limit);
+    }
+    write_u32(&mut out, settings.args.len() as u32);
+    for arg in &settings.args {
+        write_bytes(&mut out, arg.as_bytes());
+    }
+    out.push(settings.check_alignment as u8);
+    out.push(settings.verify_jumps as u8);
+    out.push(settings.object_heap as u8);
+
+    write_bytes(&mut out, &events.input);
+    write_u32(&mut out, events.random.len() as u32);
+    for &value in &events.random {
+        write_u32(&mut out, value);
+    }
+    write_u32(&mut out, events.clock.len() as u32);
+    for &value in &events.clock {
+        out.extend(value.to_be_bytes());
+    }
+
+    for register in outcome.registers {
+        write_u32(&mut out, register as u32);
+    }
+    out.push(outcome.equality_flag as u8);
+    write_u32(&mut out, outcome.pc as u32);
+    out.extend(outcome.steps.to_be_bytes());
+    write_u32(&mut out, outcome.exit_status as u32);
+    write_bytes(&mut out, &outcome.output);
+    match &outcome.fault {
+        Some(fault) => {
+            out.push(1);
+            write_bytes(&mut out, fault.as_bytes());
+        }
+        None => out.push(0),
+    }
+
+    out
+}
+
+fn decode(bytes: &[u8]) -> anyhow::Result<(Vec<u8>, Settings, Events, Outcome)> {
+    let Some(rest) = bytes.strip_prefix(&REPLAY_MAGIC) else {
+        bail!("not a replay recording");
+    };
+    let mut reader = Reader(rest);
+    let version = reader.u8()?;
+    if version != REPLAY_VERSION {
+        bail!(
+            "recording format version {version} isn't supported, expected version {REPLAY_VERSION}"
+        );
+    }
+
+    let program = reader.bytes()?.to_vec();
+    let config = VMConfig {
+        max_heap: reader.option()?.map(|limit| limit as usize),
+        max_program_size: reader.option()?.map(|limit| limit as usize),
+        max_steps: reader.option()?,
+    };
+    let args = (0..reader.u32()?)
+        .map(|_| Ok(String::from_utf8(reader.bytes()?.to_vec())?))
+        .collect::<anyhow::Result<_>>()?;
+    let settings = Settings {
+        config,
+        args,
+        check_alignment: reader.bool()?,
+        verify_jumps: reader.bool()?,
+        object_heap: reader.bool()?,
+    };
+
+    let input = reader.bytes()?.to_vec();
+    let random = (0..reader.u32()?)
+        .map(|_| reader.u32())
+        .collect::<anyhow::Result<_>>()?;
+    let clock = (0..reader.u32()?)
+        .map(|_| reader.u64())
+        .collect::<anyhow::Result<_>>()?;
+    let events = Events {
+        input,
+        random,
+        clock,
+    };
+
+    let mut registers = [0; 32];
+    for register in &mut registers {
+        *register = reader.u32()? as i32;
+    }
+    let outcome = Outcome {
+        registers,
+        equality_flag: reader.bool()?,
+        pc: reader.u32()? as usize,
+        steps: reader.u64()?,
+        exit_status: reader.u32()? as i32,
+        output: reader.bytes()?.to_vec(),
+        fault: match reader.bool()? {
+            true => Some(String::from_utf8(reader.bytes()?.to_vec())?),
+            false => None,
+        },
+    };
+    if !reader.0.is_empty() {
+        bail!("recording has trailing bytes");
+    }
+
+    Ok((program, settings, events, outcome))
+}
+
+fn write_u32(out: &mut Vec<u8>, value: u32) {
+    out.extend(value.to_be_bytes());
+}
+
+fn write_bytes(out: &mut Vec<u8>, bytes: &[u8]) {
+    write_u32(out, bytes.len() as u32);
+    out.extend(bytes);
+}
+
+fn write_option(out: &mut Vec<u8>, value: Option<u64>) {
+    match value {
+        Some(value) => {
+            out.push(1);
+            out.extend(value.to_be_bytes());
+        }
+        None => out.push(0),
+    }
+}
+
+/// Reads the fields of a recording in order
+struct Reader<'a>(&'a [u8]);
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> anyhow::Result<&'a [u8]> {
+        if self.0.len() < len {
+            return Err(anyhow!("recording is truncated"));
+        }
+        let (bytes, rest) = self.0.split_at(len);
+        self.0 = rest;
+
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> anyhow::Result<u8> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn bool(&mut self) -> anyhow::Result<bool> {
+        Ok(self.u8()? != 0)
+    }
+
+    fn u32(&mut self) -> anyhow::Result<u32> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> anyhow::Result<u64> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self) -> anyhow::Result<&'a [u8]> {
+        let len = self.u32()? as usize;
+        self.take(len)
+    }
+
+    fn option(&mut self) -> anyhow::Result<Option<u64>> {
+        match self.bool()? {
+            true => Ok(Some(self.u64()?)),
+            false => Ok(None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    /// Reads a name, then prints it along with a random number and the time
+    const PROGRAM: &str = r#"
+.bss
+    name:   .space 16
+.code
+            ldwd $1, =@name
+            reads $1, 16
+            prtsr $1
+            rand $2
+            prti $2
+            time $3
+            prti $3
+            hlt
+"#;
+
+    fn record(random: u32) -> Vec<u8> {
+        let program = Assembler::default().assemble(PROGRAM).unwrap();
+        let settings = Settings {
+            config: VMConfig {
+                max_steps: Some(100),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        let recorder = Recorder::new(program.clone(), settings.clone());
+
+        let mut vm = settings.create_vm(&program).unwrap();
+        vm.set_output(std::io::sink());
+        recorder.attach_with(
+            &mut vm,
+            Cursor::new(b"Ada\nunread\n".to_vec()),
+            move || random,
+            || 1234,
+        );
+        let result = vm.run();
+
+        recorder.recording(&vm, result.err().map(|e| e.to_string()))
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let bytes = record(42);
+        let (program, settings, events, outcome) = decode(&bytes).unwrap();
+        assert_eq!(
+            events,
+            Events {
+                input: b"Ada\n".to_vec(),
+                random: vec![42],
+                clock: vec![1234, 1234, 1234],
+            }
+        );
+        assert_eq!(settings.config.max_steps, Some(100));
+        assert_eq!(outcome.output, b"Ada\n42\n0\nHalting!\n");
+        assert_eq!(encode(&program, &settings, &events, &outcome), bytes);
+
+        assert!(decode(&bytes[..bytes.len() - 1]).is_err());
+        assert!(decode(b"EPTR").is_err());
+    }
+
+    #[test]
+    fn test_replay() {
+        let printed = SharedBuffer::default();
+        replay_recording(&record(42), printed.clone()).unwrap();
+        assert_eq!(printed.contents(), b"Ada\n42\n0\nHalting!\n");
+
+        // a recording ending differently than the replay does is reported
+        let mut bytes = record(42);
+        let at = bytes
+            .windows(3)
+            .position(|window| window == b"42\n")
+            .unwrap();
+        bytes[at] = b'7';
+        let error = replay_recording(&bytes, std::io::sink()).unwrap_err();
+        assert!(error
+            .to_string()
+            .starts_with("replay diverged from the recording: output differs"));
+    }
+}
@@ -0,0 +1,204 @@
+use crate::format::NumberFormat;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::Write;
+use std::path::Path;
+use std::rc::Rc;
+use vm::{TraceStep, VMConfig, VM};
+use zip::write::SimpleFileOptions;
+use zip::ZipWriter;
+
+/// Number of instructions kept at the end of the trace
+const TRACE_LENGTH: usize = 64;
+
+/// Everything needed to reproduce a run, written to a zip archive to attach to bug reports
+pub struct Report {
+    /// Assembly source, if the program wasn't pre-assembled
+    source: Option<String>,
+    program: Vec<u8>,
+    config: VMConfig,
+    /// Seed of the generator `RAND` loaded its numbers from
+    seed: u64,
+    format: NumberFormat,
+    /// Most recently executed instructions, oldest first
+    trace: Rc<RefCell<VecDeque<String>>>,
+}
+
+impl Report {
+    pub fn new(
+        source: Option<String>,
+        program: Vec<u8>,
+        config: VMConfig,
+        seed: u64,
+        format: NumberFormat,
+    ) -> Self {
+        Self {
+            source,
+            program,
+            config,
+            seed,
+            format,
+            trace: Rc::default(),
+        }
+    }
+
+    /// Records the last instructions the VM executes into the report
+    pub fn attach(&self, vm: &mut VM) {
+        let trace = Rc::clone(&self.trace);
+        let format = self.format;
+
+        vm.set_tracer(move |step: &TraceStep| {
+            let mut trace = trace.borrow_mut();
+            if trace.len() == TRACE_LENGTH {
+                trace.pop_front();
+            }
+
+            let operands = step
+                .operands
+                .iter()
+                .map(|&operand| format.byte(operand))
+                .collect::<Vec<_>>();
+            trace.push_back(format!(
+                "{:#06X} {:?} {}",
+                step.pc,
+                step.opcode,
+                operands.join(" ")
+            ));
+        });
+    }
+
+    /// Writes the report along with the final state of the VM and the fault the run ended with,
+    /// if any
+    pub fn write(&self, path: &Path, vm: &VM, fault: Option<&anyhow::Error>) -> anyhow::Result<()> {
+        let mut zip = ZipWriter::new(File::create(path)?);
+        let options = SimpleFileOptions::default();
+
+        if let Some(source) = &self.source {
+            zip.start_file("source.asm", options)?;
+            zip.write_all(source.as_bytes())?;
+        }
+
+        zip.start_file("program.epie", options)?;
+        zip.write_all(&self.program)?;
+
+        zip.start_file("config.txt", options)?;
+        let command = std::env::args().collect::<Vec<_>>().join(" ");
+        write!(zip, "{}", self.config_text(&command))?;
+
+        zip.start_file("state.txt", options)?;
+        zip.write_all(self.state(vm).as_bytes())?;
+
+        zip.start_file("trace.txt", options)?;
+        for line in self.trace.borrow().iter() {
+            writeln!(zip, "{line}")?;
+        }
+
+        if let Some(fault) = fault {
+            zip.start_file("fault.txt", options)?;
+            writeln!(zip, "{fault:#}")?;
+        }
+
+        zip.finish()?;
+
+        Ok(())
+    }
+
+    /// Describes how the program was run, with the command to run it again with the same random
+    /// numbers
+    fn config_text(&self, command: &str) -> String {
+        let replay = match command.contains("--seed") {
+            true => command.to_owned(),
+            false => format!("{command} --seed {}", self.seed),
+        };
+
+        format!(
+            "command: {command}\nseed: {}\nreplay: {replay}\n{:#?}\n",
+            self.seed, self.config
+        )
+    }
+
+    /// Describes the registers and flags of the VM
+    fn state(&self, vm: &VM) -> String {
+        let mut state = format!(
+            "pc: {:#06X}\nequality flag: {}\nheap size: {}\n",
+            vm.pc(),
+            vm.equality_flag(),
+            vm.memory().heap_size()
+        );
+
+        for (index, value) in vm.registers().iter().enumerate() {
+            writeln!(state, "${index}: {}", self.format.word(*value)).unwrap();
+        }
+
+        state
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Read;
+    use vm::{Memory, VmError};
+    use zip::ZipArchive;
+
+    #[test]
+    fn test_write_report() {
+        // header with an empty data section, and a code section holding a single store to 0
+        let mut program = vec![0; 68];
+        program[..4].copy_from_slice(b"EPIE");
+        program[4] = shared::PIE_FORMAT_VERSION;
+        program[8..12].copy_from_slice(&64u32.to_be_bytes());
+        program[16..20].copy_from_slice(&64u32.to_be_bytes());
+        program[20..24].copy_from_slice(&4u32.to_be_bytes());
+        program[64..].copy_from_slice(&[24, 0, 0, 0]);
+
+        let report = Report::new(
+            None,
+            program.clone(),
+            VMConfig::default(),
+            7,
+            NumberFormat::default(),
+        );
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(program);
+        report.attach(&mut vm);
+
+        let fault = vm.run().unwrap_err();
+        assert_eq!(fault, VmError::WriteProtected { address: 0 });
+
+        let path = std::env::temp_dir().join(format!("report-{}.zip", std::process::id()));
+        report.write(&path, &vm, Some(&fault.into())).unwrap();
+
+        let mut archive = ZipArchive::new(File::open(&path).unwrap()).unwrap();
+        let mut names = archive.file_names().collect::<Vec<_>>();
+        names.sort();
+        assert_eq!(
+            names,
+            vec![
+                "config.txt",
+                "fault.txt",
+                "program.epie",
+                "state.txt",
+                "trace.txt"
+            ]
+        );
+
+        let mut trace = String::new();
+        archive
+            .by_name("trace.txt")
+            .unwrap()
+            .read_to_string(&mut trace)
+            .unwrap();
+        assert_eq!(trace, "0x0040 STRWI 00 00 00\n");
+
+        let config = report.config_text("cli run program.epie");
+        assert!(config.starts_with("command: cli run program.epie\nseed: 7\n"));
+        assert!(config.contains("replay: cli run program.epie --seed 7\n"));
+        let config = report.config_text("cli run program.epie --seed 7");
+        assert!(config.contains("replay: cli run program.epie --seed 7\n"));
+
+        std::fs::remove_file(path).unwrap();
+    }
+}
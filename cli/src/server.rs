@@ -0,0 +1,356 @@
+//! Remote control of a VM over TCP, for driving it from other tools and tests.
+//!
+//! Clients send one command per line, and every command is answered with a line starting with
+//! `ok`, followed by any result, or `error` followed by what went wrong. Programs run in the
+//! background, so commands such as `stop` and `registers` are still answered while they do, and
+//! once a run ends a `halted`, `fault <error>` or `breakpoint <address>` line is sent. Anything the
+//! program prints is sent as `output` followed by the text as a quoted, escaped string.
+//!
+//! | command                    | effect                                               |
+//! |----------------------------|------------------------------------------------------|
+//! | `load <hex bytes>`         | replaces the VM with one ready to run a program      |
+//! | `run`                      | starts the program from the beginning                |
+//! | `continue`                 | resumes the program from where it stopped            |
+//! | `stop`                     | stops the program, answering with where it stopped   |
+//! | `step`                     | executes one instruction, answering with the next pc |
+//! | `status`                   | answers `running` or `stopped`, with the pc          |
+//! | `registers`                | answers every register then the equality flag        |
+//! | `set_register <n> <value>` | sets a register                                      |
+//! | `read <address> <len>`     | answers the bytes in hex                             |
+//! | `write <address> <hex>`    | writes bytes to memory, even if it's read-only       |
+//! | `break <address>`          | adds a breakpoint                                    |
+//! | `quit`                     | closes the connection                                |
+//!
+//! Addresses are decimal, or hex with a `0x` prefix. Connections are served one at a time, each
+//! with a VM of its own. Programs read nothing from `READI` and `READS`, as if their input was
+//! empty, and each run faults once it has executed the server's step limit, so one client's program
+//! can't hold the server forever.
+
+use crate::parse_address;
+use crate::repl::parse_hex;
+use anyhow::{anyhow, bail, Context};
+use shared::abi::register_index;
+use std::io::{self, BufRead, BufReader, Write};
+use std::net::{TcpListener, TcpStream};
+use std::sync::mpsc::{channel, Receiver, TryRecvError};
+use vm::{Program, SharedBuffer, VMConfig, VmError, VM};
+
+/// Instructions executed between checks for commands while a program runs
+const BATCH_LENGTH: usize = 10_000;
+
+/// Accepts connections on the address, serving each until it closes. Each run of a program can
+/// execute at most max_steps instructions
+pub fn serve(address: &str, max_steps: u64) -> anyhow::Result<()> {
+    let listener = TcpListener::bind(address)?;
+    eprintln!("listening on {}", listener.local_addr()?);
+
+    for stream in listener.incoming() {
+        let stream = stream?;
+        let peer = stream.peer_addr()?;
+        eprintln!("{peer} connected");
+
+        let mut session = Session::new(stream.try_clone()?, max_steps);
+        if let Err(e) = session.serve(commands(stream)) {
+            eprintln!("{peer} disconnected: {e}");
+        }
+    }
+
+    Ok(())
+}
+
+/// Lines read from the stream on a thread of their own, so they can be checked for between batches
+/// of instructions without blocking
+fn commands(stream: TcpStream) -> Receiver<String> {
+    let (sender, receiver) = channel();
+    std::thread::spawn(move || {
+        for line in BufReader::new(stream).lines() {
+            let Ok(line) = line else { break };
+            if sender.send(line).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+/// One client's connection, along with the VM it controls
+struct Session<W: Write> {
+    out: W,
+    vm: VM,
+    /// Captures what the program prints, to be sent on to the client
+    output: SharedBuffer,
+    /// Whether the program is running in the background
+    running: bool,
+    /// Instructions each run can execute
+    max_steps: u64,
+}
+
+impl<W: Write> Session<W> {
+    fn new(out: W, max_steps: u64) -> Self {
+        let output = SharedBuffer::default();
+        let mut session = Self {
+            out,
+            vm: VM::default(),
+            output,
+            running: false,
+            max_steps,
+        };
+        session.vm = session.new_vm();
+
+        session
+    }
+
+    /// VM limited to the step limit, printing to the captured output and reading empty input
+    fn new_vm(&self) -> VM {
+        let mut vm = VM::with_config(VMConfig {
+            max_steps: Some(self.max_steps),
+            ..VMConfig::default()
+        });
+        vm.set_output(self.output.clone());
+        vm.set_input(io::empty());
+
+        vm
+    }
+
+    /// Handles commands until the client quits or disconnects, running the program between them
+    fn serve(&mut self, commands: Receiver<String>) -> anyhow::Result<()> {
+        loop {
+            let command = match self.running {
+                true => match commands.try_recv() {
+                    Ok(command) => Some(command),
+                    Err(TryRecvError::Empty) => None,
+                    Err(TryRecvError::Disconnected) => return Ok(()),
+                },
+                false => match commands.recv() {
+                    Ok(command) => Some(command),
+                    Err(_) => return Ok(()),
+                },
+            };
+
+            if let Some(command) = command {
+                if !self.handle(command.trim())? {
+                    return Ok(());
+                }
+            }
+            if self.running {
+                self.run_batch()?;
+            }
+        }
+    }
+
+    /// Handles a command, answering it. Returns false once the client quits
+    fn handle(&mut self, command: &str) -> anyhow::Result<bool> {
+        let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+        if name == "quit" {
+            writeln!(self.out, "ok")?;
+            return Ok(false);
+        }
+
+        match self.execute(name, args.trim()) {
+            Ok(Some(result)) => writeln!(self.out, "ok {result}")?,
+            Ok(None) => writeln!(self.out, "ok")?,
+            Err(e) => writeln!(self.out, "error {e}")?,
+        }
+        self.send_output()?;
+
+        Ok(true)
+    }
+
+    /// Carries out a command, returning any result to answer with
+    fn execute(&mut self, name: &str, args: &str) -> anyhow::Result<Option<String>> {
+        match name {
+            "load" => {
+                let bytes = parse_hex(args).map_err(|_| anyhow!("invalid bytes {args}"))?;
+                self.load(bytes)?;
+            }
+            "run" => {
+                self.vm.start()?;
+                self.running = true;
+            }
+            "continue" => self.running = true,
+            "stop" => {
+                self.running = false;
+                return Ok(Some(format!("{:#06X}", self.vm.pc())));
+            }
+            "step" => {
+                if self.running {
+                    bail!("program is running");
+                }
+                self.vm.run_once()?;
+                return Ok(Some(format!("{:#06X}", self.vm.pc())));
+            }
+            "status" => {
+                let state = if self.running { "running" } else { "stopped" };
+                return Ok(Some(format!("{state} {:#06X}", self.vm.pc())));
+            }
+            "registers" => {
+                let registers = self.vm.registers().map(|register| register.to_string());
+                let flag = self.vm.equality_flag() as u8;
+                return Ok(Some(format!("{} {flag}", registers.join(" "))));
+            }
+            "set_register" => {
+                let (register, value) = args.split_once(' ').unwrap_or((args, ""));
+                let index = register_index(register.trim_start_matches('$'));
+                let register = index
+                    .and_then(|index| self.vm.registers_mut().get_mut(index as usize))
+                    .with_context(|| format!("invalid register {register}"))?;
+                *register = value.trim().parse()?;
+            }
+            "read" => {
+                let (address, len) = args.split_once(' ').unwrap_or((args, ""));
+                let bytes = self
+                    .vm
+                    .memory()
+                    .read(parse_address(address)? as usize, len.parse()?)?;
+                let hex = bytes.iter().map(|byte| format!("{byte:02X}"));
+                return Ok(Some(hex.collect::<Vec<_>>().join(" ")));
+            }
+            "write" => {
+                let (address, bytes) = args.split_once(' ').unwrap_or((args, ""));
+                let bytes = parse_hex(bytes).map_err(|_| anyhow!("invalid bytes {bytes}"))?;
+                self.vm.poke(parse_address(address)? as usize, &bytes)?;
+            }
+            "break" => {
+                let address = parse_address(args)? as usize;
+                if !self.vm.add_breakpoint(address) {
+                    bail!("breakpoint already set at {address:#06X}");
+                }
+            }
+            _ => bail!("unknown command {name}"),
+        }
+
+        Ok(None)
+    }
+
+    /// Replaces the VM with one holding the program, capturing its output, and readies it to run
+    fn load(&mut self, program: Vec<u8>) -> Result<(), VmError> {
+        let mut vm = self.new_vm();
+        vm.load(Program::parse(program)?);
+        vm.start()?;
+
+        self.vm = vm;
+        self.running = false;
+
+        Ok(())
+    }
+
+    /// Runs the program for a batch of instructions, telling the client if it stops
+    fn run_batch(&mut self) -> anyhow::Result<()> {
+        let stopped = (0..BATCH_LENGTH).find_map(|_| match self.vm.run_once() {
+            Ok(true) if self.vm.breakpoints().contains(&self.vm.pc()) => {
+                Some(Ok(Some(self.vm.pc())))
+            }
+            Ok(true) => None,
+            Ok(false) => Some(Ok(None)),
+            Err(e) => Some(Err(e)),
+        });
+        self.send_output()?;
+
+        if let Some(stopped) = stopped {
+            self.running = false;
+            self.report(stopped)?;
+        }
+
+        Ok(())
+    }
+
+    /// Tells the client why the program stopped
+    fn report(&mut self, stopped: Result<Option<usize>, VmError>) -> anyhow::Result<()> {
+        match stopped {
+            Ok(Some(breakpoint)) => writeln!(self.out, "breakpoint {breakpoint:#06X}")?,
+            Ok(None) => writeln!(self.out, "halted")?,
+            Err(e) => writeln!(self.out, "fault {e}")?,
+        }
+
+        Ok(())
+    }
+
+    /// Sends on anything the program has printed since it was last sent
+    fn send_output(&mut self) -> anyhow::Result<()> {
+        let output = self.output.take();
+        if !output.is_empty() {
+            writeln!(self.out, "output {:?}", String::from_utf8_lossy(&output))?;
+        }
+        self.out.flush()?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+
+    #[test]
+    fn test_session() {
+        let program = Assembler::default()
+            .assemble(".code\nldbi $1, 7\nloop: inc $2\njmpi @loop")
+            .unwrap();
+        let hex = program.iter().map(|byte| format!("{byte:02X}"));
+
+        let (sender, receiver) = channel();
+        for command in [
+            format!("load {}", hex.collect::<Vec<_>>().join(" ")),
+            "step".into(),
+            "set_register $3 -4".into(),
+            "read 0x40 2".into(),
+            "frobnicate".into(),
+            "break 0x48".into(),
+            "continue".into(),
+            "status".into(),
+            "quit".into(),
+        ] {
+            sender.send(command).unwrap();
+        }
+
+        let mut session = Session::new(Vec::new(), 1000);
+        session.serve(receiver).unwrap();
+
+        let out = String::from_utf8(session.out).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            [
+                "ok",
+                "ok 0x0044",
+                "ok",
+                "ok 04 01",
+                "error unknown command frobnicate",
+                "ok",
+                "ok",
+                "breakpoint 0x0048",
+                "ok stopped 0x0048",
+                "ok",
+            ]
+        );
+        assert_eq!(session.vm.registers()[1..4], [7, 1, -4]);
+    }
+
+    #[test]
+    fn test_session_limits() {
+        let program = Assembler::default()
+            .assemble(".code\nreadi $1\nloop: jmpi @loop")
+            .unwrap();
+        let hex = program.iter().map(|byte| format!("{byte:02X}"));
+
+        let (sender, receiver) = channel();
+        for command in [
+            format!("load {}", hex.collect::<Vec<_>>().join(" ")),
+            "run".into(),
+        ] {
+            sender.send(command).unwrap();
+        }
+        drop(sender);
+
+        // input is empty rather than the server's own, and the loop stops at the step limit
+        let mut session = Session::new(Vec::new(), 100);
+        session.serve(receiver).unwrap();
+
+        let out = String::from_utf8(session.out).unwrap();
+        assert_eq!(
+            out.lines().collect::<Vec<_>>(),
+            ["ok", "ok", "fault step limit exceeded"]
+        );
+    }
+}
@@ -0,0 +1,125 @@
+//! Runs a directory of assembly programs against golden files, for `test` and the examples
+//! directory.
+//!
+//! Each `name.asm` is assembled and run, reading its input from `name.in` if there is one. What it
+//! prints is compared against `name.out`, and its final registers against `name.regs`, which holds
+//! `$register = value` lines so only the registers that matter are checked. Programs with neither
+//! file only have to run without faulting. With `--bless`, `name.out` is written from what the
+//! program printed instead of being compared.
+
+use crate::golden;
+use anyhow::{anyhow, bail};
+use assembler::Assembler;
+use shared::abi::register_index;
+use std::io::Cursor;
+use std::path::{Path, PathBuf};
+use vm::{Program, SharedBuffer, VMConfig, VM};
+
+/// Program in a suite and why it failed, if it did
+pub struct Case {
+    pub path: PathBuf,
+    pub failure: Option<String>,
+}
+
+/// Runs every program in a directory, in order of name
+pub fn run_suite(directory: &Path, bless: bool, max_steps: u64) -> anyhow::Result<Vec<Case>> {
+    let mut paths = std::fs::read_dir(directory)?
+        .map(|entry| Ok(entry?.path()))
+        .collect::<std::io::Result<Vec<_>>>()?;
+    paths.retain(|path| path.extension().is_some_and(|extension| extension == "asm"));
+    paths.sort();
+
+    Ok(paths
+        .into_iter()
+        .map(|path| {
+            let failure = run_case(&path, bless, max_steps).err();
+
+            Case {
+                path,
+                failure: failure.map(|e| e.to_string()),
+            }
+        })
+        .collect())
+}
+
+/// Runs one program, failing if it doesn't assemble, faults or doesn't match its golden files
+fn run_case(path: &Path, bless: bool, max_steps: u64) -> anyhow::Result<()> {
+    let source = std::fs::read_to_string(path)?;
+    let program = Assembler::default().source_path(path).assemble(&source)?;
+
+    let mut vm = VM::with_config(VMConfig {
+        max_steps: Some(max_steps),
+        ..Default::default()
+    });
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+    if let Ok(input) = std::fs::read(path.with_extension("in")) {
+        vm.set_input(Cursor::new(input));
+    }
+    vm.load(Program::parse(program)?);
+    vm.run()?;
+
+    let actual = output.to_string_lossy();
+    let expected_path = path.with_extension("out");
+    if bless {
+        std::fs::write(&expected_path, &actual)?;
+    } else if let Ok(expected) = std::fs::read_to_string(&expected_path) {
+        if let Some(diff) = golden::diff(&expected, &actual) {
+            bail!("output doesn't match {}:\n{diff}", expected_path.display());
+        }
+    }
+
+    if let Ok(registers) = std::fs::read_to_string(path.with_extension("regs")) {
+        for (register, expected) in parse_registers(&registers)? {
+            let actual = vm.registers()[register as usize];
+            if actual != expected {
+                bail!("${register} is {actual}, expected {expected}");
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Reads `$register = value` lines, skipping blank lines and `;` comments
+fn parse_registers(text: &str) -> anyhow::Result<Vec<(u8, i32)>> {
+    text.lines()
+        .map(|line| line.split(';').next().unwrap().trim())
+        .filter(|line| !line.is_empty())
+        .map(|line| {
+            let invalid = || anyhow!("expected `$register = value`, got '{line}'");
+            let (register, value) = line.split_once('=').ok_or_else(invalid)?;
+            let register = register
+                .trim()
+                .strip_prefix('$')
+                .and_then(register_index)
+                .ok_or_else(invalid)?;
+
+            Ok((register, value.trim().parse().map_err(|_| invalid())?))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_registers() {
+        let registers = parse_registers("$a0 = 5 ; result\n\n$31=-1\n").unwrap();
+        assert_eq!(registers, [(0, 5), (31, -1)]);
+        assert!(parse_registers("a0 = 5").is_err());
+        assert!(parse_registers("$a0 = five").is_err());
+    }
+
+    #[test]
+    fn test_examples() {
+        let examples = Path::new(env!("CARGO_MANIFEST_DIR")).join("../examples");
+        let cases = run_suite(&examples, false, 1_000_000).unwrap();
+        assert!(!cases.is_empty());
+
+        for case in cases {
+            assert_eq!(case.failure, None, "{}", case.path.display());
+        }
+    }
+}
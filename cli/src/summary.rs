@@ -0,0 +1,52 @@
+/// How `run` reports the result of a program
+#[derive(Debug, Clone, Copy, PartialEq, Default, clap::ValueEnum)]
+pub enum OutputFormat {
+    /// The program's output as it's printed, followed by any reports asked for
+    #[default]
+    Text,
+    /// A single JSON object once the program stops, holding its output, final state and any fault
+    Json,
+}
+
+/// JSON object describing how a program finished, for test harnesses and other tools to read
+pub fn json_summary(vm: &vm::VM, output: &str, fault: Option<&anyhow::Error>) -> String {
+    serde_json::json!({
+        "registers": vm.registers(),
+        "equality_flag": vm.equality_flag(),
+        "pc": vm.pc(),
+        "steps": vm.steps(),
+        "exit_status": vm.exit_status(),
+        "output": output,
+        "fault": fault.map(ToString::to_string),
+    })
+    .to_string()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use assembler::Assembler;
+    use vm::{Program, VM};
+
+    #[test]
+    fn test_json_summary() {
+        let program = Assembler::default()
+            .assemble(".code\nldbi $1, 7\nexit $1")
+            .unwrap();
+        let mut vm = VM::default();
+        vm.set_output(std::io::sink());
+        vm.load(Program::parse(program).unwrap());
+        vm.run().unwrap();
+
+        let fault = anyhow::anyhow!("division by zero");
+        let summary: serde_json::Value =
+            serde_json::from_str(&json_summary(&vm, "hi\n", Some(&fault))).unwrap();
+        assert_eq!(summary["registers"][1], 7);
+        assert_eq!(summary["registers"].as_array().unwrap().len(), 32);
+        assert_eq!(summary["equality_flag"], false);
+        assert_eq!(summary["steps"], 2);
+        assert_eq!(summary["exit_status"], 7);
+        assert_eq!(summary["output"], "hi\n");
+        assert_eq!(summary["fault"], "division by zero");
+    }
+}
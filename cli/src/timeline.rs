@@ -0,0 +1,178 @@
+//! Records the calls a program makes, for viewing in flamegraph-style profilers.
+//!
+//! A span is opened for the routine the program starts in, and for every routine it calls, named
+//! after the closest label declared at or before the routine's address. Spans are closed when the
+//! routine returns, so they nest the same way the calls do. Timestamps are instruction counts rather
+//! than wall time, so timelines are deterministic. The output uses the Chrome tracing JSON format,
+//! which both `chrome://tracing` and speedscope can import.
+
+use std::fmt::Write;
+
+#[derive(Debug, PartialEq)]
+enum Phase {
+    Enter,
+    Exit,
+}
+
+#[derive(Debug, PartialEq)]
+struct Event {
+    /// Address of the routine entered or exited
+    routine: usize,
+    phase: Phase,
+    timestamp: u64,
+}
+
+#[derive(Debug, Default)]
+pub struct Timeline {
+    /// Label names and addresses, sorted by address
+    labels: Vec<(String, u32)>,
+    /// Addresses of the routines with open spans, outermost first
+    open: Vec<usize>,
+    events: Vec<Event>,
+}
+
+impl Timeline {
+    /// Creates a timeline naming routines after the given labels
+    pub fn new<'a>(labels: impl Iterator<Item = (&'a str, u32)>) -> Self {
+        let mut labels = labels
+            .map(|(name, address)| (name.to_owned(), address))
+            .collect::<Vec<_>>();
+        labels.sort_by_key(|(_, address)| *address);
+
+        Self {
+            labels,
+            ..Default::default()
+        }
+    }
+
+    /// Records the routines being executed at the given timestamp, outermost first, closing the
+    /// spans of any that have returned and opening spans for any newly called
+    pub fn record(&mut self, routines: &[usize], timestamp: u64) {
+        let common = self
+            .open
+            .iter()
+            .zip(routines)
+            .take_while(|(open, routine)| open == routine)
+            .count();
+
+        self.exit_to(common, timestamp);
+        for &routine in &routines[common..] {
+            self.events.push(Event {
+                routine,
+                phase: Phase::Enter,
+                timestamp,
+            });
+            self.open.push(routine);
+        }
+    }
+
+    /// Closes every open span
+    pub fn finish(&mut self, timestamp: u64) {
+        self.exit_to(0, timestamp);
+    }
+
+    /// Closes spans, innermost first, until only depth are left open
+    fn exit_to(&mut self, depth: usize, timestamp: u64) {
+        while self.open.len() > depth {
+            let routine = self.open.pop().unwrap();
+            self.events.push(Event {
+                routine,
+                phase: Phase::Exit,
+                timestamp,
+            });
+        }
+    }
+
+    /// Name of the closest label at or before a routine, or its address if there isn't one
+    fn name(&self, routine: usize) -> String {
+        let label = self
+            .labels
+            .partition_point(|(_, address)| *address as usize <= routine)
+            .checked_sub(1);
+
+        match label {
+            Some(label) => self.labels[label].0.clone(),
+            None => format!("{routine:#06X}"),
+        }
+    }
+
+    /// Serialises the recorded events in the Chrome tracing JSON format
+    pub fn to_chrome_json(&self) -> String {
+        let mut out = String::from("{\"traceEvents\":[");
+
+        for (i, event) in self.events.iter().enumerate() {
+            if i > 0 {
+                out.push(',');
+            }
+
+            let phase = match event.phase {
+                Phase::Enter => "B",
+                Phase::Exit => "E",
+            };
+
+            // label names are alphanumeric, so need no escaping
+            write!(
+                out,
+                "{{\"name\":\"{}\",\"ph\":\"{phase}\",\"ts\":{},\"pid\":1,\"tid\":1}}",
+                self.name(event.routine),
+                event.timestamp
+            )
+            .unwrap();
+        }
+
+        out.push_str("]}");
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timeline() {
+        let mut timeline = Timeline::new([("main", 64), ("outer", 80), ("inner", 96)].into_iter());
+
+        // main calls outer, which calls inner twice before returning
+        let calls: [&[usize]; 7] = [
+            &[64],
+            &[64, 80],
+            &[64, 80, 96],
+            &[64, 80],
+            &[64, 80, 96],
+            &[64],
+            &[64],
+        ];
+        for (timestamp, routines) in calls.into_iter().enumerate() {
+            timeline.record(routines, timestamp as u64);
+        }
+        timeline.finish(7);
+
+        assert_eq!(
+            timeline.to_chrome_json(),
+            concat!(
+                r#"{"traceEvents":["#,
+                r#"{"name":"main","ph":"B","ts":0,"pid":1,"tid":1},"#,
+                r#"{"name":"outer","ph":"B","ts":1,"pid":1,"tid":1},"#,
+                r#"{"name":"inner","ph":"B","ts":2,"pid":1,"tid":1},"#,
+                r#"{"name":"inner","ph":"E","ts":3,"pid":1,"tid":1},"#,
+                r#"{"name":"inner","ph":"B","ts":4,"pid":1,"tid":1},"#,
+                r#"{"name":"inner","ph":"E","ts":5,"pid":1,"tid":1},"#,
+                r#"{"name":"outer","ph":"E","ts":5,"pid":1,"tid":1},"#,
+                r#"{"name":"main","ph":"E","ts":7,"pid":1,"tid":1}"#,
+                "]}"
+            )
+        );
+    }
+
+    #[test]
+    fn test_unlabelled_routine() {
+        let mut timeline = Timeline::new([("helper", 80)].into_iter());
+        timeline.record(&[64, 80], 0);
+        timeline.finish(1);
+
+        assert!(timeline
+            .to_chrome_json()
+            .contains(r#""name":"0x0040","ph":"B""#));
+    }
+}
@@ -0,0 +1,122 @@
+//! Recording every instruction a run executes, or only the last ones, and reading recordings back.
+
+use std::cell::RefCell;
+use std::fmt::Write as _;
+use std::fs::File;
+use std::io::BufWriter;
+use std::path::PathBuf;
+use std::rc::Rc;
+use vm::{decode_trace, RingTracer, TraceEncoder, TraceEntry, TraceStep, Tracer, VM};
+
+/// Trace being recorded for a run
+pub enum Trace {
+    /// Every instruction, written to the file as it's executed
+    Full(Rc<RefCell<TraceEncoder<BufWriter<File>>>>),
+    /// Only the last instructions, written to the file once the run stops or printed if there's
+    /// no file
+    Last(Rc<RefCell<RingTracer>>, Option<PathBuf>),
+}
+
+impl Trace {
+    pub fn new(path: Option<PathBuf>, last: Option<usize>) -> anyhow::Result<Option<Self>> {
+        Ok(match (path, last) {
+            (path, Some(last)) => Some(Trace::Last(
+                Rc::new(RefCell::new(RingTracer::new(last))),
+                path,
+            )),
+            (Some(path), None) => Some(Trace::Full(Rc::new(RefCell::new(TraceEncoder::new(
+                BufWriter::new(File::create(path)?),
+            )?)))),
+            (None, None) => None,
+        })
+    }
+
+    /// Records the instructions the VM executes into the trace
+    pub fn attach(&self, vm: &mut VM) {
+        match self {
+            Trace::Full(encoder) => attach(vm, Rc::clone(encoder)),
+            Trace::Last(ring, _) => attach(vm, Rc::clone(ring)),
+        }
+    }
+
+    /// Detaches the trace from the VM once the run has stopped, writing out whatever hasn't been yet
+    pub fn finish(self, vm: &mut VM) -> anyhow::Result<()> {
+        drop(vm.take_tracer());
+
+        match self {
+            Trace::Full(encoder) => {
+                let encoder = Rc::into_inner(encoder).expect("the VM's tracer has been dropped");
+                encoder.into_inner().finish()?;
+            }
+            Trace::Last(ring, Some(path)) => {
+                ring.borrow()
+                    .write_binary(BufWriter::new(File::create(path)?))?;
+            }
+            Trace::Last(ring, None) => {
+                eprintln!("last instructions:");
+                eprint!("{}", dump(ring.borrow().entries()));
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn attach(vm: &mut VM, tracer: Rc<RefCell<impl Tracer + 'static>>) {
+    vm.set_tracer(move |step: &TraceStep| tracer.borrow_mut().trace(step));
+}
+
+/// One line per instruction, along with the registers that changed since the previous one
+pub fn dump<'a>(entries: impl Iterator<Item = &'a TraceEntry>) -> String {
+    let mut out = String::new();
+    let mut registers = [0; 32];
+
+    for entry in entries {
+        write!(out, "{entry}").unwrap();
+        for (index, &value) in entry.registers.iter().enumerate() {
+            if value != registers[index] {
+                write!(out, " ${index}={value}").unwrap();
+            }
+        }
+        out.push('\n');
+        registers = entry.registers;
+    }
+
+    out
+}
+
+/// Reads a binary trace back into text
+pub fn dump_file(bytes: &[u8]) -> anyhow::Result<String> {
+    let entries = decode_trace(bytes).ok_or_else(|| anyhow::anyhow!("not a valid trace file"))?;
+
+    Ok(dump(entries.iter()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Opcode;
+
+    #[test]
+    fn test_dump() {
+        let mut registers = [0; 32];
+        let mut ring = RingTracer::new(4);
+        for (pc, value) in [(64, 0), (68, 7), (72, 7)] {
+            registers[2] = value;
+            ring.trace(&TraceStep {
+                pc,
+                opcode: Opcode::INC,
+                operands: [2, 0, 0],
+                registers: &registers,
+            });
+        }
+
+        let mut binary = Vec::new();
+        ring.write_binary(&mut binary).unwrap();
+        assert_eq!(
+            dump_file(&binary).unwrap(),
+            "0x0040 INC 02 00 00\n0x0044 INC 02 00 00 $2=7\n0x0048 INC 02 00 00\n"
+        );
+        assert!(dump_file(b"nope").is_err());
+    }
+}
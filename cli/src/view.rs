@@ -0,0 +1,202 @@
+use anyhow::{anyhow, bail};
+use assembler::DataLayout;
+use std::fmt::{Display, Formatter};
+use vm::Memory;
+
+/// Type of a single element rendered by `.view`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ElementType {
+    Byte,
+    Half,
+    Word,
+    Float,
+    /// Null terminated string
+    String,
+}
+
+impl ElementType {
+    /// Size of the element in bytes, or None if it isn't fixed
+    fn size(self) -> Option<usize> {
+        match self {
+            ElementType::Byte => Some(1),
+            ElementType::Half => Some(2),
+            ElementType::Word | ElementType::Float => Some(4),
+            ElementType::String => None,
+        }
+    }
+}
+
+/// Type memory is rendered as, such as `word`, `half[10]` or `byte[4][4]`
+#[derive(Debug, PartialEq, Clone)]
+pub struct ViewType {
+    element: ElementType,
+    /// Number of elements along each dimension, outermost first. Empty for a single element
+    dimensions: Vec<usize>,
+}
+
+impl ViewType {
+    /// Parses a type of the form <element> ([<count>])*
+    pub fn parse(input: &str) -> anyhow::Result<Self> {
+        let input = input.trim();
+        let (name, mut rest) = input.split_at(input.find('[').unwrap_or(input.len()));
+
+        let element = match name.trim() {
+            "byte" => ElementType::Byte,
+            "half" => ElementType::Half,
+            "word" => ElementType::Word,
+            "float" => ElementType::Float,
+            "string" => ElementType::String,
+            name => bail!("unknown type '{name}'"),
+        };
+
+        let mut dimensions = Vec::new();
+        while let Some(dimension) = rest.strip_prefix('[') {
+            let (count, remaining) = dimension
+                .split_once(']')
+                .ok_or_else(|| anyhow!("expected ']'"))?;
+            dimensions.push(
+                count
+                    .trim()
+                    .parse()
+                    .map_err(|_| anyhow!("invalid count '{count}'"))?,
+            );
+            rest = remaining;
+        }
+
+        if !rest.is_empty() {
+            bail!("unexpected input '{rest}'");
+        }
+        if element == ElementType::String && !dimensions.is_empty() {
+            bail!("strings can't have dimensions");
+        }
+        if dimensions.len() > 2 {
+            bail!("at most 2 dimensions are supported");
+        }
+
+        Ok(Self {
+            element,
+            dimensions,
+        })
+    }
+
+    /// Type described by the layout of a `.fill` or `.matrix` declaration
+    pub fn from_layout(layout: &DataLayout) -> Self {
+        let element = match layout.element_size {
+            1 => ElementType::Byte,
+            2 => ElementType::Half,
+            _ => ElementType::Word,
+        };
+
+        Self {
+            element,
+            dimensions: layout.dimensions.clone(),
+        }
+    }
+
+    /// Renders memory starting at address as this type. Arrays are rendered as a list, and
+    /// two dimensional arrays as a grid with one row per line
+    pub fn render(&self, memory: &Memory, address: usize) -> anyhow::Result<String> {
+        let Some(size) = self.element.size() else {
+            let bytes = memory.read_to_region_end(address)?;
+            let end = bytes
+                .iter()
+                .position(|&byte| byte == 0)
+                .ok_or_else(|| anyhow!("string at {address:#06X} isn't null terminated"))?;
+
+            return Ok(format!("{:?}", String::from_utf8_lossy(&bytes[..end])));
+        };
+
+        let count = self.dimensions.iter().product::<usize>();
+        let elements = memory
+            .read(address, count * size)?
+            .chunks(size)
+            .map(|bytes| self.render_element(bytes))
+            .collect::<Vec<_>>();
+
+        Ok(match self.dimensions[..] {
+            [] => elements[0].clone(),
+            [_] => format!("[{}]", elements.join(", ")),
+            [_, columns] => {
+                let width = elements.iter().map(String::len).max().unwrap_or(0);
+
+                elements
+                    .chunks(columns.max(1))
+                    .map(|row| {
+                        row.iter()
+                            .map(|element| format!("{element:>width$}"))
+                            .collect::<Vec<_>>()
+                            .join(" ")
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+            }
+            _ => unreachable!("at most 2 dimensions are supported"),
+        })
+    }
+
+    fn render_element(&self, bytes: &[u8]) -> String {
+        match self.element {
+            ElementType::Byte => bytes[0].to_string(),
+            ElementType::Half => i16::from_be_bytes([bytes[0], bytes[1]]).to_string(),
+            ElementType::Word => i32::from_be_bytes(bytes.try_into().unwrap()).to_string(),
+            ElementType::Float => f32::from_be_bytes(bytes.try_into().unwrap()).to_string(),
+            ElementType::String => unreachable!("strings aren't fixed size"),
+        }
+    }
+}
+
+impl Display for ViewType {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self.element {
+            ElementType::Byte => "byte",
+            ElementType::Half => "half",
+            ElementType::Word => "word",
+            ElementType::Float => "float",
+            ElementType::String => "string",
+        };
+        write!(f, "{name}")?;
+
+        for dimension in &self.dimensions {
+            write!(f, "[{dimension}]")?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_view_type() {
+        assert_eq!(ViewType::parse("word[10]").unwrap().to_string(), "word[10]");
+        assert_eq!(
+            ViewType::parse("byte[2][3]").unwrap().dimensions,
+            vec![2, 3]
+        );
+        assert_eq!(
+            ViewType::from_layout(&DataLayout::new(2, vec![4]).unwrap()).to_string(),
+            "half[4]"
+        );
+
+        assert!(ViewType::parse("long").is_err());
+        assert!(ViewType::parse("word[10").is_err());
+        assert!(ViewType::parse("string[2]").is_err());
+    }
+
+    #[test]
+    fn test_render() {
+        let mut image = vec![0, 1, 0xFF, 0xFE, b'h', b'i', 0];
+        image.extend_from_slice(&1.5f32.to_be_bytes());
+        let memory = Memory::new(image);
+
+        let render = |ty: &str, address| ViewType::parse(ty).unwrap().render(&memory, address);
+        assert_eq!(render("half[2]", 0).unwrap(), "[1, -2]");
+        assert_eq!(render("byte[2][2]", 0).unwrap(), "  0   1\n255 254");
+        assert_eq!(render("string", 4).unwrap(), "\"hi\"");
+        assert_eq!(render("float", 7).unwrap(), "1.5");
+
+        assert!(render("word[4]", 0).is_err());
+    }
+}
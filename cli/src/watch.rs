@@ -0,0 +1,142 @@
+use crate::format::NumberFormat;
+use anyhow::{anyhow, bail};
+use shared::abi::register_index;
+use std::fmt::{Display, Formatter};
+use vm::Machine;
+
+/// Location watched for changes by `.watch`
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Watch {
+    /// Word of memory starting at an address, such as `mem @counter`
+    Memory(usize),
+    /// Register, such as `reg $3`
+    Register(usize),
+}
+
+impl Watch {
+    /// Parses a watch of the form <mem | reg> <location>, evaluating memory addresses with
+    /// `address`
+    pub fn parse(
+        input: &str,
+        address: impl FnOnce(&str) -> anyhow::Result<usize>,
+    ) -> anyhow::Result<Self> {
+        let (kind, location) = input.trim().split_once(' ').unwrap_or((input, ""));
+        let location = location.trim();
+
+        Ok(match kind {
+            "mem" => Watch::Memory(address(location)?),
+            "reg" => {
+                let index = register_index(location.trim_start_matches('$'))
+                    .ok_or_else(|| anyhow!("invalid register '{location}'"))?;
+                Watch::Register(index as usize)
+            }
+            kind => bail!("unknown watch '{kind}', expected mem or reg"),
+        })
+    }
+
+    /// Current value, or None if it can't be read
+    pub fn read(&self, machine: &dyn Machine) -> Option<i32> {
+        match *self {
+            Watch::Memory(address) => {
+                let bytes = machine.memory().read(address, 4).ok()?;
+                Some(i32::from_be_bytes(bytes.try_into().unwrap()))
+            }
+            Watch::Register(index) => machine.registers().get(index).copied(),
+        }
+    }
+}
+
+impl Display for Watch {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Watch::Memory(address) => write!(f, "MEM[{address:#06X}]"),
+            Watch::Register(index) => write!(f, "${index}"),
+        }
+    }
+}
+
+/// Watched location along with its value when last checked
+#[derive(Debug, Clone)]
+pub struct Watchpoint {
+    pub watch: Watch,
+    value: Option<i32>,
+}
+
+impl Watchpoint {
+    pub fn new(watch: Watch, machine: &dyn Machine) -> Self {
+        Self {
+            watch,
+            value: watch.read(machine),
+        }
+    }
+
+    /// Checks the location against its saved value, saving the new value and describing the change
+    /// if there was one
+    pub fn update(&mut self, machine: &dyn Machine, format: &NumberFormat) -> Option<String> {
+        let value = self.watch.read(machine);
+        if value == self.value {
+            return None;
+        }
+
+        let show = |value: Option<i32>| value.map_or("<unreadable>".into(), |v| format.word(v));
+        let change = format!(
+            "{} changed from {} to {}",
+            self.watch,
+            show(self.value),
+            show(value)
+        );
+        self.value = value;
+
+        Some(change)
+    }
+
+    /// Describes the location and its saved value
+    pub fn describe(&self, format: &NumberFormat) -> String {
+        match self.value {
+            Some(value) => format!("{} = {}", self.watch, format.word(value)),
+            None => format!("{} = <unreadable>", self.watch),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use vm::{Memory, VM};
+
+    #[test]
+    fn test_watch() {
+        let address = |location: &str| Ok(location.parse::<usize>()?);
+        assert_eq!(Watch::parse("reg $3", address).unwrap(), Watch::Register(3));
+        assert_eq!(Watch::parse("mem 2", address).unwrap(), Watch::Memory(2));
+        assert!(Watch::parse("reg $32", address).is_err());
+        assert!(Watch::parse("mem x", address).is_err());
+        assert!(Watch::parse("flag", address).is_err());
+
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(vec![0, 0, 0, 0, 7]);
+        let format = NumberFormat::default();
+        let mut register = Watchpoint::new(Watch::Register(3), &vm);
+        let mut memory = Watchpoint::new(Watch::Memory(1), &vm);
+        assert_eq!(memory.describe(&format), "MEM[0x0001] = 00000007");
+        assert_eq!(register.update(&vm, &format), None);
+
+        vm.registers_mut()[3] = 16;
+        vm.poke(4, &[8]).unwrap();
+        assert_eq!(
+            register.update(&vm, &format).unwrap(),
+            "$3 changed from 00000000 to 00000010"
+        );
+        assert_eq!(register.update(&vm, &format), None);
+        assert_eq!(
+            memory.update(&vm, &format).unwrap(),
+            "MEM[0x0001] changed from 00000007 to 00000008"
+        );
+
+        *vm.memory_mut() = Memory::default();
+        assert_eq!(
+            memory.update(&vm, &format).unwrap(),
+            "MEM[0x0001] changed from 00000008 to <unreadable>"
+        );
+    }
+}
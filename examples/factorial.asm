@@ -0,0 +1,19 @@
+; Computes 10! recursively, printing it and leaving it in $a0
+.code
+    main:   ldbi $a0, 10
+            calli @factorial
+            prti $a0
+            hlt
+
+; $a0 <- $a0!
+factorial:  lti $a0, 2
+            jmpnei @recurse
+            ldbi $a0, 1
+            ret
+recurse:    push $s0
+            mov $s0, $a0
+            dec $a0
+            calli @factorial
+            mulr $a0, $s0, $a0
+            pop $s0
+            ret
@@ -0,0 +1,13 @@
+; Prints the first 10 Fibonacci numbers
+.code
+            ldbi $t0, 0             ; current
+            ldbi $t1, 1             ; next
+            ldbi $t2, 10            ; left to print
+    loop:   prti $t0
+            addr $t3, $t0, $t1
+            mov $t0, $t1
+            mov $t1, $t3
+            dec $t2
+            gti $t2, 0
+            jmpei @loop
+            hlt
@@ -0,0 +1,11 @@
+; Reads a name from input and greets it
+.data
+    hello:  .asciiz 'Hello'
+.bss
+    name:   .space 32
+.code
+            ldwd $t0, =@name
+            reads $t0, 32
+            prtsd @hello
+            prtsr $t0
+            hlt
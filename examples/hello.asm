@@ -0,0 +1,6 @@
+; Prints a greeting
+.data
+    greeting: .asciiz 'Hello, world!'
+.code
+            prtsd @greeting
+            hlt
@@ -0,0 +1,184 @@
+//! Derives the tables and conversions of `shared::Opcode` from the enum itself, so each opcode's
+//! mnemonic, encoding, operands and description are only written once.
+
+use proc_macro::TokenStream;
+use quote::quote;
+use syn::punctuated::Punctuated;
+use syn::{
+    parse_macro_input, Data, DeriveInput, Error, Expr, Fields, Ident, Lit, LitStr, Meta, Token,
+};
+
+/// Derives, for an enum of opcodes whose discriminants are their encodings:
+/// - `OPCODES`, an `OpcodeInfo` describing every opcode, and `MNEMONICS`, every mnemonic in lower
+///   case, both in the order the opcodes are declared
+/// - `info`, giving an opcode's entry in `OPCODES`
+/// - `from_encoding` and `from_mnemonic`, finding the opcode with an encoding or mnemonic
+///
+/// Every variant needs an `#[operands(...)]` attribute listing the `OperandKind`s it reads, and can
+/// have `#[alias("name")]` attributes giving other mnemonics it can be written with. Its doc
+/// comment becomes its description. Both `OpcodeInfo` and `OperandKind` must be in scope
+#[proc_macro_derive(Opcode, attributes(operands, alias))]
+pub fn derive_opcode(input: TokenStream) -> TokenStream {
+    let input = parse_macro_input!(input as DeriveInput);
+
+    match expand(&input) {
+        Ok(tokens) => tokens.into(),
+        Err(error) => error.to_compile_error().into(),
+    }
+}
+
+/// Opcode as declared
+struct Variant {
+    name: Ident,
+    encoding: u8,
+    operands: Vec<Ident>,
+    aliases: Vec<LitStr>,
+    doc: String,
+}
+
+fn expand(input: &DeriveInput) -> syn::Result<proc_macro2::TokenStream> {
+    let Data::Enum(data) = &input.data else {
+        return Err(Error::new_spanned(
+            input,
+            "opcodes must be declared as an enum",
+        ));
+    };
+    let variants = data
+        .variants
+        .iter()
+        .map(parse_variant)
+        .collect::<syn::Result<Vec<_>>>()?;
+
+    // each byte can only decode to one opcode
+    for (index, variant) in variants.iter().enumerate() {
+        if let Some(other) = variants[..index]
+            .iter()
+            .find(|other| other.encoding == variant.encoding)
+        {
+            let message = format!("{} has the same encoding as {}", variant.name, other.name);
+            return Err(Error::new_spanned(&variant.name, message));
+        }
+    }
+
+    let ty = &input.ident;
+    let names = variants
+        .iter()
+        .map(|variant| &variant.name)
+        .collect::<Vec<_>>();
+    let mnemonics = names
+        .iter()
+        .map(|name| name.to_string())
+        .collect::<Vec<_>>();
+    let lower = mnemonics
+        .iter()
+        .map(|mnemonic| mnemonic.to_lowercase())
+        .collect::<Vec<_>>();
+    let encodings = variants.iter().map(|variant| variant.encoding);
+    let operands = variants.iter().map(|variant| {
+        let kinds = &variant.operands;
+        quote!(&[#(OperandKind::#kinds),*])
+    });
+    let docs = variants.iter().map(|variant| &variant.doc);
+    let indices = 0..variants.len();
+    let (aliases, aliased): (Vec<_>, Vec<_>) = variants
+        .iter()
+        .flat_map(|variant| {
+            variant
+                .aliases
+                .iter()
+                .map(|alias| (alias.value().to_lowercase(), &variant.name))
+        })
+        .unzip();
+
+    Ok(quote! {
+        /// Every opcode, in the order they're declared
+        pub const OPCODES: &[OpcodeInfo] = &[#(OpcodeInfo {
+            opcode: #ty::#names,
+            mnemonic: #mnemonics,
+            operands: #operands,
+            doc: #docs,
+        }),*];
+
+        /// Mnemonic of every opcode in lower case, in the order they're declared
+        pub const MNEMONICS: &[&str] = &[#(#lower),*];
+
+        impl #ty {
+            /// Description of the opcode from [`OPCODES`]
+            pub fn info(self) -> &'static OpcodeInfo {
+                match self {
+                    #(#ty::#names => &OPCODES[#indices],)*
+                }
+            }
+
+            /// Opcode encoded as the given byte, or None if no opcode is
+            pub fn from_encoding(byte: u8) -> Option<Self> {
+                match byte {
+                    #(#encodings => Some(#ty::#names),)*
+                    _ => None,
+                }
+            }
+
+            /// Opcode written with the given mnemonic or one of its aliases, in any case, or None
+            /// if there isn't one
+            pub fn from_mnemonic(mnemonic: &str) -> Option<Self> {
+                match mnemonic.to_ascii_lowercase().as_str() {
+                    #(#lower => Some(#ty::#names),)*
+                    #(#aliases => Some(#ty::#aliased),)*
+                    _ => None,
+                }
+            }
+        }
+    })
+}
+
+fn parse_variant(variant: &syn::Variant) -> syn::Result<Variant> {
+    if !matches!(variant.fields, Fields::Unit) {
+        return Err(Error::new_spanned(variant, "opcodes can't have fields"));
+    }
+    let encoding = match &variant.discriminant {
+        Some((_, Expr::Lit(literal))) => match &literal.lit {
+            Lit::Int(int) => int.base10_parse::<u8>()?,
+            lit => return Err(Error::new_spanned(lit, "encodings must be integers")),
+        },
+        _ => {
+            return Err(Error::new_spanned(
+                variant,
+                "opcodes must be given their encoding as an integer",
+            ))
+        }
+    };
+
+    let mut operands = None;
+    let mut aliases = Vec::new();
+    let mut doc = Vec::new();
+    for attribute in &variant.attrs {
+        if attribute.path().is_ident("operands") {
+            let kinds = match &attribute.meta {
+                // `#[operands]` would be easy to mistake for operands being inferred
+                Meta::Path(_) => return Err(Error::new_spanned(attribute, "expected operands()")),
+                _ => attribute.parse_args_with(Punctuated::<Ident, Token![,]>::parse_terminated)?,
+            };
+            operands = Some(kinds.into_iter().collect());
+        } else if attribute.path().is_ident("alias") {
+            aliases.push(attribute.parse_args::<LitStr>()?);
+        } else if attribute.path().is_ident("doc") {
+            if let Meta::NameValue(name_value) = &attribute.meta {
+                if let Expr::Lit(literal) = &name_value.value {
+                    if let Lit::Str(line) = &literal.lit {
+                        doc.push(line.value().trim().to_owned());
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Variant {
+        name: variant.ident.clone(),
+        encoding,
+        operands: operands.ok_or_else(|| {
+            Error::new_spanned(&variant.ident, "opcodes need an #[operands(...)] attribute")
+        })?,
+        aliases,
+        doc: doc.join(" "),
+    })
+}
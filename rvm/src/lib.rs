@@ -0,0 +1,90 @@
+//! Assembles and runs programs from a single crate.
+//!
+//! Re-exports the parts of the [`assembler`], [`vm`] and [`shared`] crates meant to be used by other
+//! programs, so they only need to depend on this one. Anything only reachable through those crates
+//! directly may change between versions. [`assemble_and_run`] covers the common case of running a
+//! program from source and checking what it did.
+
+#[cfg(feature = "wasm")]
+mod wasm;
+
+pub use assembler::{
+    disassemble, Assembler, AssemblerError, AssemblerWarning, DisassemblerError, FileSystemLoader,
+    Location, SourceLoader,
+};
+pub use shared::abi;
+pub use shared::{Opcode, OperandKind};
+pub use vm::{
+    validate, Device, Limit, LogLevel, LogRecord, Logger, Memory, Program, RunStatus, SharedBuffer,
+    Snapshot, SnapshotError, Syscall, VMConfig, VerifyError, VmError, VM,
+};
+
+/// Why [`assemble_and_run`] failed
+#[derive(thiserror::Error, Debug, Clone)]
+#[non_exhaustive]
+pub enum Error {
+    #[error("couldn't assemble program: {0}")]
+    Assemble(#[from] AssemblerError),
+    #[error("program faulted: {0}")]
+    Run(#[from] VmError),
+}
+
+/// State of the VM once a program has halted
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct VmState {
+    pub registers: [i32; 32],
+    /// Equality from the last comparison instruction
+    pub equality_flag: bool,
+    /// Status the program exited with, which is zero unless it halted with `EXIT`
+    pub exit_status: i32,
+    /// Number of instructions executed
+    pub steps: u64,
+    /// Everything the program printed
+    pub output: Vec<u8>,
+}
+
+/// Assembles a program and runs it until it halts, with the default limits and nothing to read
+/// from
+pub fn assemble_and_run(source: &str) -> Result<VmState, Error> {
+    let program = Assembler::default().assemble(source)?;
+
+    let mut vm = VM::default();
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+    vm.set_input(std::io::empty());
+    vm.load(Program::parse(program)?);
+    vm.run()?;
+
+    Ok(VmState {
+        registers: *vm.registers(),
+        equality_flag: vm.equality_flag(),
+        exit_status: vm.exit_status(),
+        steps: vm.steps(),
+        output: output.take(),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assemble_and_run() {
+        let state = assemble_and_run(".code\nldbi $0, 42\nprti $0\neqi $0, 42\nhlt").unwrap();
+        assert_eq!(state.registers[0], 42);
+        assert!(state.equality_flag);
+        assert_eq!(state.exit_status, 0);
+        assert_eq!(state.steps, 4);
+        assert_eq!(state.output, b"42\nHalting!\n");
+
+        assert!(matches!(
+            assemble_and_run(".code\nbogus $0"),
+            Err(Error::Assemble(_))
+        ));
+        assert!(matches!(
+            assemble_and_run(".code\njmpi 2"),
+            Err(Error::Run(_))
+        ));
+    }
+}
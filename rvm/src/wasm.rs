@@ -0,0 +1,109 @@
+//! JavaScript bindings, added by the `wasm` feature, for running programs in a browser.
+//!
+//! Build them with `wasm-pack build rvm --features wasm`, or with
+//! `cargo build -p rvm --features wasm --target wasm32-unknown-unknown` and `wasm-bindgen`. Output
+//! is collected for the page to show, and input always reads as the end of input, since there's
+//! no terminal to read from.
+//! ```js
+//! const vm = VM.fromSource(".code\nldbi $0, 42\nprti $0\nhlt");
+//! vm.run();
+//! console.log(vm.takeOutput(), vm.registers());
+//! ```
+
+use crate::{Assembler, Program, SharedBuffer, VM};
+use wasm_bindgen::prelude::*;
+
+/// Assembles a program into bytecode
+#[wasm_bindgen]
+pub fn assemble(source: &str) -> Result<Vec<u8>, JsError> {
+    Ok(Assembler::default().assemble(source)?)
+}
+
+/// VM with a program loaded, ready to run from its entry point
+#[wasm_bindgen(js_name = VM)]
+pub struct WasmVm {
+    vm: VM,
+    output: SharedBuffer,
+}
+
+#[wasm_bindgen(js_class = VM)]
+impl WasmVm {
+    /// Loads assembled bytecode
+    #[wasm_bindgen(constructor)]
+    pub fn new(program: Vec<u8>) -> Result<WasmVm, JsError> {
+        let mut vm = VM::default();
+        let output = SharedBuffer::default();
+        vm.set_output(output.clone());
+        vm.set_input(std::io::empty());
+        vm.load(Program::parse(program)?);
+        vm.start()?;
+
+        Ok(Self { vm, output })
+    }
+
+    /// Assembles and loads a program
+    #[wasm_bindgen(js_name = fromSource)]
+    pub fn from_source(source: &str) -> Result<WasmVm, JsError> {
+        Self::new(assemble(source)?)
+    }
+
+    /// Runs the program from the start until it halts
+    pub fn run(&mut self) -> Result<(), JsError> {
+        Ok(self.vm.run()?)
+    }
+
+    /// Runs at most count instructions from where the program stopped, so a page can run long
+    /// programs in slices without blocking. Returns whether the program is still running
+    #[wasm_bindgen(js_name = runFor)]
+    pub fn run_for(&mut self, count: u32) -> Result<bool, JsError> {
+        for _ in 0..count {
+            if !self.vm.run_once()? {
+                return Ok(false);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Runs a single instruction, returning whether the program is still running
+    pub fn step(&mut self) -> Result<bool, JsError> {
+        Ok(self.vm.run_once()?)
+    }
+
+    /// Moves back to the entry point, ready to run the program again
+    pub fn restart(&mut self) -> Result<(), JsError> {
+        Ok(self.vm.start()?)
+    }
+
+    /// Address of the next instruction to run
+    pub fn pc(&self) -> usize {
+        self.vm.pc()
+    }
+
+    pub fn registers(&self) -> Vec<i32> {
+        self.vm.registers().to_vec()
+    }
+
+    /// Equality from the last comparison instruction
+    #[wasm_bindgen(js_name = equalityFlag)]
+    pub fn equality_flag(&self) -> bool {
+        self.vm.equality_flag()
+    }
+
+    /// Number of instructions executed since the program was started
+    pub fn steps(&self) -> u64 {
+        self.vm.steps()
+    }
+
+    /// Reads len bytes of memory starting at an address
+    #[wasm_bindgen(js_name = readMemory)]
+    pub fn read_memory(&self, address: usize, len: usize) -> Result<Vec<u8>, JsError> {
+        Ok(self.vm.memory().read(address, len)?.to_vec())
+    }
+
+    /// Removes and returns everything the program has printed so far, with invalid UTF-8 replaced
+    #[wasm_bindgen(js_name = takeOutput)]
+    pub fn take_output(&self) -> String {
+        String::from_utf8_lossy(&self.output.take()).into_owned()
+    }
+}
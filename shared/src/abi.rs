@@ -0,0 +1,118 @@
+//! Calling convention shared by the VM, assembler and any code generating assembly.
+//!
+//! Routines are entered with `CALLI`/`CALLR`, which push the return address onto the stack, and
+//! left with `RET`. Arguments are passed in registers, first to last, and results are returned in
+//! the first return register. A routine may freely overwrite caller-saved registers, but must
+//! restore any callee-saved register it writes (usually with `PUSH`/`POP`) before returning.
+//!
+//! The stack grows down from [`STACK_TOP`], and the stack pointer always holds the address of the
+//! most recently pushed word.
+//!
+//! A routine needing space for locals starts with `ENTER n`, which pushes the caller's frame
+//! pointer, points the frame pointer at it and reserves n bytes below it, and ends with `LEAVE`
+//! followed by `RET`. Within the frame, locals are at negative offsets from the frame pointer, the
+//! caller's frame pointer is at the frame pointer itself and the return address directly above it
+//! at [`RETURN_ADDRESS_OFFSET`]. Since the frame pointer is restored by `LEAVE`, it doesn't need
+//! saving like the callee-saved registers.
+
+use std::ops::RangeInclusive;
+
+/// Number of general purpose registers, including the frame and stack pointers
+pub const REGISTER_COUNT: usize = 32;
+/// Registers holding routine arguments, in order
+pub const ARGUMENT_REGISTERS: RangeInclusive<u8> = 0..=5;
+/// Registers holding routine results, in order
+pub const RETURN_REGISTERS: RangeInclusive<u8> = 0..=1;
+/// Registers a routine may overwrite without restoring
+pub const CALLER_SAVED: RangeInclusive<u8> = 0..=15;
+/// Registers a routine must restore before returning if it writes them
+pub const CALLEE_SAVED: RangeInclusive<u8> = 16..=29;
+/// Register holding the base of the current stack frame
+pub const FRAME_POINTER: u8 = 30;
+/// Register holding the address of the top of the stack
+pub const STACK_POINTER: u8 = 31;
+/// Offset from the frame pointer of the return address of a routine that has entered its frame
+pub const RETURN_ADDRESS_OFFSET: usize = 4;
+
+/// Names registers can be written as in assembly, such as `$a0` or `$sp`, indexed by register.
+/// Arguments and results share `$a0`-`$a5`, followed by the other caller-saved temporaries and
+/// then the callee-saved registers. There's deliberately no `$zero` or `$ra`: every register can
+/// be written, so none always reads zero, and `CALLI` pushes the return address onto the stack
+/// rather than leaving it in a register
+pub const REGISTER_NAMES: [&str; REGISTER_COUNT] = [
+    "a0", "a1", "a2", "a3", "a4", "a5", "t0", "t1", "t2", "t3", "t4", "t5", "t6", "t7", "t8", "t9",
+    "s0", "s1", "s2", "s3", "s4", "s5", "s6", "s7", "s8", "s9", "s10", "s11", "s12", "s13", "fp",
+    "sp",
+];
+
+/// Alignment of every stack push and pop, in bytes
+pub const STACK_ALIGNMENT: usize = 4;
+/// Address directly above the stack, which the stack pointer starts at
+pub const STACK_TOP: usize = 0x0010_0000;
+/// Size of the stack, in bytes
+pub const STACK_SIZE: usize = 0x0001_0000;
+/// Size of the guard directly below the stack, in bytes. It's never mapped, so the stack
+/// overflowing faults rather than running into the heap
+pub const STACK_GUARD_SIZE: usize = 0x1000;
+/// Lowest address devices can be mapped at, directly above the stack
+pub const DEVICE_BASE: usize = STACK_TOP;
+/// Address the CLI maps the framebuffer at, with `run --display`
+pub const FRAMEBUFFER_BASE: usize = DEVICE_BASE;
+/// Address the CLI maps the keyboard at, directly above the framebuffer
+pub const KEYBOARD_BASE: usize = FRAMEBUFFER_BASE + 0x1000;
+
+/// Register holding the number of program arguments when the program starts
+pub const ARGC_REGISTER: u8 = 0;
+/// Register holding the address of the program argument pointers when the program starts
+pub const ARGV_REGISTER: u8 = 1;
+/// Register holding the exit status of the program when it halts
+pub const EXIT_STATUS_REGISTER: u8 = 0;
+
+/// Register written as the name or decimal number, without the leading `$`, if it exists
+pub fn register_index(name: &str) -> Option<u8> {
+    let index = match name.parse::<usize>() {
+        Ok(index) => index,
+        Err(_) => REGISTER_NAMES.iter().position(|&known| known == name)?,
+    };
+
+    (index < REGISTER_COUNT).then_some(index as u8)
+}
+
+/// Checks if a routine must restore the register before returning
+pub fn is_callee_saved(register: u8) -> bool {
+    CALLEE_SAVED.contains(&register)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_register_classes() {
+        // every general purpose register is exactly one of caller or callee saved
+        for register in 0..FRAME_POINTER {
+            assert_ne!(CALLER_SAVED.contains(&register), is_callee_saved(register));
+        }
+
+        assert!(ARGUMENT_REGISTERS
+            .clone()
+            .all(|r| CALLER_SAVED.contains(&r)));
+        assert!(RETURN_REGISTERS.clone().all(|r| CALLER_SAVED.contains(&r)));
+        assert_eq!(STACK_TOP % STACK_ALIGNMENT, 0);
+    }
+
+    #[test]
+    fn test_register_names() {
+        assert_eq!(register_index("sp"), Some(STACK_POINTER));
+        assert_eq!(register_index("fp"), Some(FRAME_POINTER));
+        assert_eq!(register_index("s0"), Some(*CALLEE_SAVED.start()));
+        assert_eq!(register_index("a5"), Some(*ARGUMENT_REGISTERS.end()));
+        assert_eq!(register_index("t9"), Some(*CALLER_SAVED.end()));
+        assert_eq!(register_index("7"), Some(7));
+
+        assert_eq!(register_index("32"), None);
+        // no register always reads zero or holds the return address, so neither has a name
+        assert_eq!(register_index("zero"), None);
+        assert_eq!(register_index("ra"), None);
+    }
+}
@@ -1,6 +1,25 @@
+pub mod abi;
+pub mod lines;
 mod opcode;
+pub mod symbols;
 
-pub use opcode::Opcode;
+pub use opcode::{AddressingMode, Opcode, OpcodeInfo, OperandKind, MNEMONICS, OPCODES};
 
 pub const PIE_HEADER_PREFIX: [u8; 4] = *b"EPIE";
+/// Version of the bytecode format, stored in the header directly after the prefix
+pub const PIE_FORMAT_VERSION: u8 = 1;
+/// Width of registers and memory words in bytes, stored in the header directly after the format
+/// version. Programs use 4 byte words unless assembled for 8 byte words, and 0 is read as 4 since
+/// programs assembled before the field existed leave it zeroed
+pub const PIE_WORD_SIZE: u8 = 4;
 pub const PIE_HEADER_LENGTH: usize = 64;
+/// Offset of the header field giving where execution starts, as an offset from the start of the
+/// code section. Programs without an entry point leave it zeroed, starting at the code section
+pub const ENTRY_POINT_FIELD: usize = 32;
+/// Offset of the header fields giving the read-only data section's offset and length, which are
+/// both zero if the program has none
+pub const RODATA_SECTION_FIELD: usize = 36;
+/// Offset of the header fields giving the address and length of the zeroed space reserved when
+/// the program is loaded, which are both zero if the program has none. It's addressed after the
+/// program image rather than stored in it
+pub const BSS_SECTION_FIELD: usize = 44;
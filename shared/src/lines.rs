@@ -0,0 +1,149 @@
+//! Line section, optionally written after the symbol section so instructions can be mapped back to
+//! the source lines they were assembled from when debugging.
+//!
+//! Its offset and length are stored in the header at [`LINE_SECTION_FIELD`], and are both zero if
+//! a program has no line section. It's encoded as the names of the source files followed by an
+//! entry for each instruction, sorted by address
+//! ```text
+//! <file count: 2 bytes> (<name length: 2 bytes> <name>)...
+//! (<address: 4 bytes> <file index: 2 bytes> <line: 4 bytes>)...
+//! ```
+//! with every number big endian.
+
+/// Offset of the line section offset in the header, followed by its length
+pub const LINE_SECTION_FIELD: usize = 52;
+
+/// Source line an instruction was assembled from
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineEntry {
+    /// Address of the instruction
+    pub address: u32,
+    /// Index of the file in [`LineTable::files`]
+    pub file: u16,
+    /// Line number, starting from 1
+    pub line: u32,
+}
+
+/// Source files a program was assembled from, and the line each instruction came from
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct LineTable {
+    /// File names, empty for source assembled without a path
+    pub files: Vec<String>,
+    /// Lines by instruction address, sorted by address
+    pub entries: Vec<LineEntry>,
+}
+
+impl LineTable {
+    /// File name and line the instruction at address was assembled from
+    pub fn lookup(&self, address: u32) -> Option<(&str, u32)> {
+        let index = self
+            .entries
+            .binary_search_by_key(&address, |entry| entry.address)
+            .ok()?;
+        let entry = self.entries[index];
+
+        Some((self.files.get(entry.file as usize)?, entry.line))
+    }
+}
+
+/// Encodes a line table into a line section
+pub fn encode_lines(table: &LineTable) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    out.extend_from_slice(&(table.files.len() as u16).to_be_bytes());
+    for file in &table.files {
+        out.extend_from_slice(&(file.len() as u16).to_be_bytes());
+        out.extend_from_slice(file.as_bytes());
+    }
+
+    for entry in &table.entries {
+        out.extend_from_slice(&entry.address.to_be_bytes());
+        out.extend_from_slice(&entry.file.to_be_bytes());
+        out.extend_from_slice(&entry.line.to_be_bytes());
+    }
+
+    out
+}
+
+/// Decodes a line section, returning None if it's truncated or malformed
+pub fn decode_lines(bytes: &[u8]) -> Option<LineTable> {
+    let count = u16::from_be_bytes(bytes.get(..2)?.try_into().unwrap());
+    let mut bytes = &bytes[2..];
+
+    let mut files = Vec::with_capacity(count as usize);
+    for _ in 0..count {
+        let len = u16::from_be_bytes(bytes.get(..2)?.try_into().unwrap()) as usize;
+        let name = std::str::from_utf8(bytes.get(2..2 + len)?).ok()?;
+
+        files.push(name.to_owned());
+        bytes = &bytes[2 + len..];
+    }
+
+    if !bytes.len().is_multiple_of(10) {
+        return None;
+    }
+    let entries = bytes
+        .chunks_exact(10)
+        .map(|entry| LineEntry {
+            address: u32::from_be_bytes(entry[..4].try_into().unwrap()),
+            file: u16::from_be_bytes(entry[4..6].try_into().unwrap()),
+            line: u32::from_be_bytes(entry[6..].try_into().unwrap()),
+        })
+        .collect();
+
+    Some(LineTable { files, entries })
+}
+
+/// Reads the line section of a program, returning an empty table if it has none, or None if the
+/// section lies outside the program or is malformed
+pub fn read_lines(program: &[u8]) -> Option<LineTable> {
+    let field = |offset: usize| {
+        program
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    let start = field(LINE_SECTION_FIELD)?;
+    let len = field(LINE_SECTION_FIELD + 4)?;
+    if len == 0 {
+        return Some(LineTable::default());
+    }
+
+    decode_lines(program.get(start..start.checked_add(len)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_lines() {
+        let table = LineTable {
+            files: vec!["main.asm".to_owned(), "lib/io.asm".to_owned()],
+            entries: vec![
+                LineEntry {
+                    address: 64,
+                    file: 0,
+                    line: 3,
+                },
+                LineEntry {
+                    address: 68,
+                    file: 1,
+                    line: 12,
+                },
+            ],
+        };
+
+        let bytes = encode_lines(&table);
+        assert_eq!(
+            &bytes[..12],
+            [0, 2, 0, 8, b'm', b'a', b'i', b'n', b'.', b'a', b's', b'm']
+        );
+        assert_eq!(decode_lines(&bytes), Some(table.clone()));
+        assert_eq!(table.lookup(68), Some(("lib/io.asm", 12)));
+        assert_eq!(table.lookup(66), None);
+
+        assert_eq!(decode_lines(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode_lines(&[0, 1, 0, 9, b'a']), None);
+        assert_eq!(read_lines(&[0; 64]), Some(LineTable::default()));
+    }
+}
@@ -2,181 +2,479 @@
 /// Upper 6 bits = opcode\
 /// Lower 2 bits = addressing mode\
 /// 00 => Literal value, 01 => From memory, 10 => From Register
-#[derive(Debug, PartialEq, Copy, Clone, num_derive::FromPrimitive)]
+///
+/// This is the only place opcodes are declared. Deriving `Opcode` generates [`OPCODES`],
+/// [`MNEMONICS`] and the conversions to and from encodings and mnemonics, so the assembler, VM and
+/// disassembler can't disagree about an opcode
+#[derive(Debug, PartialEq, Copy, Clone, opcode_derive::Opcode)]
 #[repr(u8)]
 #[allow(clippy::upper_case_acronyms)]
 pub enum Opcode {
     /// Halt
+    #[operands()]
     HLT = 0b00000000,
+    /// Halts with the exit status read from register
+    #[operands(Register)]
+    EXIT = 0b00000010,
     /// Loads byte value into register
+    #[operands(Register, Value)]
     LDBI = 0b00000100,
     /// Loads byte value from memory into register
+    #[operands(Register, Address)]
     LDBD = 0b00000101,
-    /// Loads byte value from memory specified by register into register,
+    /// Loads byte value from memory specified by register into register
+    #[operands(Register, Register)]
     LDBR = 0b00000110,
     /// Loads half-word value into register
+    #[operands(Register, Value)]
     LDHI = 0b00001000,
     /// Loads half-word from memory into register
+    #[operands(Register, Address)]
     LDHD = 0b00001001,
-    /// Loads byte value from memory specified by register into register,
+    /// Loads half-word value from memory specified by register into register
+    #[operands(Register, Register)]
     LDHR = 0b00001010,
     /// Loads word from memory into register
+    #[operands(Register, Address)]
     LDWD = 0b00001101,
-    /// Loads word value from memory specified by register into register,
+    /// Loads word value from memory specified by register into register
+    #[operands(Register, Register)]
+    #[alias("loadm")]
     LDWR = 0b00001110,
     /// Stores byte from register into memory with address from raw value
+    #[operands(Register, Address)]
     STRBI = 0b00010000,
     /// Stores byte from register into memory with address from register
+    #[operands(Register, Register)]
     STRBR = 0b00010010,
     /// Stores half-word from register into memory with address from raw value
+    #[operands(Register, Address)]
     STRHI = 0b00010100,
     /// Stores half-word from register into memory with address from register
+    #[operands(Register, Register)]
     STRHR = 0b00010110,
     /// Stores word from register into memory with address from raw value
+    #[operands(Register, Address)]
     STRWI = 0b00011000,
     /// Stores word from register into memory with address from register
+    #[operands(Register, Register)]
+    #[alias("setm")]
     STRWR = 0b00011010,
     /// Copies register value
+    #[operands(Register, Register)]
     MOV = 0b00011110,
+    /// Copies a number of bytes read from register between addresses read from registers
+    #[operands(Register, Register, Register)]
+    MEMCPY = 0b00111010,
+    /// Fills a number of bytes read from register, starting at an address read from register, with
+    /// the low byte of a register
+    #[operands(Register, Register, Register)]
+    MEMSET = 0b00111110,
+    /// Loads a double-word from memory into a register pair
+    #[operands(Register, Address)]
+    LD64D = 0b00110001,
+    /// Loads a double-word from memory specified by register into a register pair
+    #[operands(Register, Register)]
+    LD64R = 0b00110010,
+    /// Stores a double-word from a register pair into memory with address from raw value
+    #[operands(Register, Address)]
+    ST64I = 0b00110100,
+    /// Stores a double-word from a register pair into memory with address from register
+    #[operands(Register, Register)]
+    ST64R = 0b00110110,
+    /// Allocates a literal number of bytes, storing the address of the new bytes in a register
+    #[operands(Register, Value)]
+    ALOCI = 0b00100000,
+    /// Allocates a number of bytes read from register, storing the address of the new bytes in a register
+    #[operands(Register, Register)]
+    #[alias("aloc")]
+    ALOCR = 0b00100010,
+    /// Frees an allocation, given its address in a register
+    #[operands(Register)]
+    FREE = 0b00101110,
+    /// Pushes a register onto the stack
+    #[operands(Register)]
+    PUSH = 0b00100110,
+    /// Pops the top of the stack into a register
+    #[operands(Register)]
+    POP = 0b00101010,
     /// Adds two registers
+    #[operands(Register, Register, Register)]
     ADDR = 0b01000010,
     /// Adds a register and a literal
+    #[operands(Register, Value)]
     ADDI = 0b01000000,
     /// Subtracts two registers
+    #[operands(Register, Register, Register)]
     SUBR = 0b01000110,
     /// Subtracts a register and a literal
+    #[operands(Register, Value)]
     SUBI = 0b01000100,
     /// Multiplies two registers
+    #[operands(Register, Register, Register)]
     MULR = 0b01001010,
     /// Multiplies a register and a literal
+    #[operands(Register, Value)]
     MULI = 0b01001000,
     /// Divides two registers
+    #[operands(Register, Register, Register)]
     DIVR = 0b01001110,
     /// Divides a register and a literal
+    #[operands(Register, Value)]
     DIVI = 0b01001100,
+    /// Adds two register pairs
+    #[operands(Register, Register)]
+    ADD64 = 0b01010010,
+    /// Subtracts two register pairs
+    #[operands(Register, Register)]
+    SUB64 = 0b01010110,
+    /// Adds 1 to a register
+    #[operands(Register)]
+    INC = 0b01011010,
+    /// Subtracts 1 from a register
+    #[operands(Register)]
+    DEC = 0b01011110,
     /// Checks for equality between a register and a literal
+    #[operands(Register, Value)]
     EQI = 0b10000000,
     /// Checks for equality between two registers
+    #[operands(Register, Register)]
     EQR = 0b10000010,
     /// Checks for inequality between a register and a literal
+    #[operands(Register, Value)]
     NEQI = 0b10000100,
     /// Checks for inequality between two registers
+    #[operands(Register, Register)]
     NEQR = 0b10000110,
     /// Checks if one register is greater than a literal
+    #[operands(Register, Value)]
     GTI = 0b10001000,
     /// Checks if one register is greater than another
+    #[operands(Register, Register)]
     GTR = 0b10001010,
     /// Checks if one register is greater than or equal to a literal
+    #[operands(Register, Value)]
     GTEI = 0b10001100,
     /// Checks if one register is greater than or equal to another
+    #[operands(Register, Register)]
     GTER = 0b10001110,
     /// Checks if one register is less than a literal
+    #[operands(Register, Value)]
     LTI = 0b10010000,
     /// Checks if one register is less than another
+    #[operands(Register, Register)]
     LTR = 0b10010010,
     /// Checks if one register is less than or equal to a literal
+    #[operands(Register, Value)]
     LTEI = 0b10010100,
     /// Checks if one register is less than or equal to another
+    #[operands(Register, Register)]
     LTER = 0b10010110,
+    /// Checks for equality between strings at addresses read from registers, up to a number of
+    /// bytes read from register
+    #[operands(Register, Register, Register)]
+    STRCMP = 0b10011010,
     /// Jumps to literal location
+    #[operands(Address)]
+    #[alias("djmp")]
     JMPI = 0b10100000,
     /// Jumps to location read from memory
+    #[operands(Address)]
     JMPD = 0b10100001,
     /// Jumps to location read from register
+    #[operands(Register)]
     JMPR = 0b10100010,
     /// Jumps to literal location if equality register true
+    #[operands(Address)]
     JMPEI = 0b10100100,
     /// Jumps to location read from memory if equality register true
+    #[operands(Address)]
     JMPED = 0b10100101,
     /// Jumps to location read from register if equality register true
+    #[operands(Register)]
     JMPER = 0b10100110,
     /// Jumps to literal location if equality register false
+    #[operands(Address)]
     JMPNEI = 0b10101000,
     /// Jumps to location read from memory if equality register false
+    #[operands(Address)]
     JMPNED = 0b10101001,
     /// Jumps to location read from register if equality register false
+    #[operands(Register)]
     JMPNER = 0b10101010,
+    /// Branches by a literal signed offset from the branch
+    #[operands(Offset)]
+    BRA = 0b11100000,
+    /// Branches by a literal signed offset from the branch if equality register true
+    #[operands(Offset)]
+    BRE = 0b11100100,
+    /// Branches by a literal signed offset from the branch if equality register false
+    #[operands(Offset)]
+    BRNE = 0b11101000,
+    /// Pushes the return address and jumps to literal location
+    #[operands(Address)]
+    CALLI = 0b10110000,
+    /// Pushes the return address and jumps to location read from register
+    #[operands(Register)]
+    CALLR = 0b10110010,
+    /// Pops the return address and jumps to it
+    #[operands()]
+    RET = 0b10110100,
+    /// Pushes the frame pointer, points it at the top of the stack and reserves a literal number of
+    /// bytes below it for locals
+    #[operands(Value)]
+    ENTER = 0b10101100,
+    /// Frees the current stack frame, restoring the stack pointer and popping the frame pointer
+    #[operands()]
+    LEAVE = 0b10011100,
+    /// Sets the address jumped to when a device requests an interrupt, or disables interrupts if 0
+    #[operands(Address)]
+    IVECI = 0b10111000,
+    /// Pops the equality flag and return address pushed when an interrupt was taken, and jumps back
+    #[operands()]
+    IRET = 0b10111100,
     /// Prints string from memory location until null byte found
+    #[operands(Address)]
     PRTSD = 0b11000001,
     /// Prints string from memory location specified in register until null byte found
+    #[operands(Register)]
     PRTSR = 0b11000010,
+    /// Logs string from memory location until null byte found, at a literal level
+    #[operands(Byte, Address)]
+    LOGD = 0b11000101,
+    /// Logs string from memory location specified in register until null byte found, at a literal level
+    #[operands(Byte, Register)]
+    LOGR = 0b11000110,
+    /// Prints the integer in a register
+    #[operands(Register)]
+    PRTI = 0b11001110,
+    /// Reads an integer from a line of input into a register
+    #[operands(Register)]
+    READI = 0b11010010,
+    /// Reads a line of input into memory specified by register, up to a literal number of bytes
+    #[operands(Register, Value)]
+    READS = 0b11010100,
+    /// Calls the host function registered with a literal number
+    #[operands(Value)]
+    SYSI = 0b11001000,
+    /// Sends the value in a register to the VM in the same cluster whose id is in a register
+    #[operands(Register, Register)]
+    SEND = 0b11011010,
+    /// Waits for a value from another VM in the same cluster, storing it and the sender's id in
+    /// registers
+    #[operands(Register, Register)]
+    RECV = 0b11011110,
+    /// Loads a random number into a register
+    #[operands(Register)]
+    RAND = 0b11101110,
+    /// Loads the milliseconds since the program started into a register
+    #[operands(Register)]
+    TIME = 0b11110010,
+    /// Opens the file at the path in memory specified by register, in a mode read from register,
+    /// storing its descriptor in a register
+    #[operands(Register, Register, Register)]
+    FOPEN = 0b01100010,
+    /// Reads up to a number of bytes read from register from the file whose descriptor is in a
+    /// register into memory specified by register, storing the number read in the first register
+    #[operands(Register, Register, Register)]
+    FREAD = 0b01100110,
+    /// Writes a number of bytes read from register to the file whose descriptor is in a register
+    /// from memory specified by register, storing the number written in the first register
+    #[operands(Register, Register, Register)]
+    FWRITE = 0b01101010,
+    /// Closes the file whose descriptor is in a register
+    #[operands(Register)]
+    FCLOSE = 0b01101110,
+    /// Creates an object of a literal kind with a number of elements read from register, storing
+    /// its handle in a register
+    #[operands(Register, Register, Byte)]
+    NEWARR = 0b01110010,
+    /// Loads the element of the object whose handle is in a register, at an index read from
+    /// register, into a register
+    #[operands(Register, Register, Register)]
+    GETEL = 0b01110110,
+    /// Stores a register into the element of the object whose handle is in a register, at an index
+    /// read from register
+    #[operands(Register, Register, Register)]
+    SETEL = 0b01111010,
+    /// Does nothing, used to pad code that may be run through
+    #[operands()]
+    NOP = 0b11111000,
     /// Illegal instruction
+    #[operands()]
     IGL = 0b11111111,
 }
 
-impl From<&str> for Opcode {
-    fn from(value: &str) -> Self {
-        match &value.to_lowercase()[..] {
-            "hlt" => Opcode::HLT,
-            "ldbi" => Opcode::LDBI,
-            "ldbd" => Opcode::LDBD,
-            "ldbr" => Opcode::LDBR,
-            "ldhi" => Opcode::LDHI,
-            "ldhd" => Opcode::LDHD,
-            "ldhr" => Opcode::LDHR,
-            "ldwd" => Opcode::LDWD,
-            "ldwr" => Opcode::LDWR,
-            "strbi" => Opcode::STRBI,
-            "strbr" => Opcode::STRBR,
-            "strhi" => Opcode::STRHI,
-            "strhr" => Opcode::STRHR,
-            "strwi" => Opcode::STRWI,
-            "strwr" => Opcode::STRWR,
-            "mov" => Opcode::MOV,
-            "addr" => Opcode::ADDR,
-            "addi" => Opcode::ADDI,
-            "subr" => Opcode::SUBR,
-            "subi" => Opcode::SUBI,
-            "mulr" => Opcode::MULR,
-            "muli" => Opcode::MULI,
-            "divr" => Opcode::DIVR,
-            "divi" => Opcode::DIVI,
-            "eqi" => Opcode::EQI,
-            "eqr" => Opcode::EQR,
-            "neqi" => Opcode::NEQI,
-            "neqr" => Opcode::NEQR,
-            "gti" => Opcode::GTI,
-            "gtr" => Opcode::GTR,
-            "gtei" => Opcode::GTEI,
-            "gter" => Opcode::GTER,
-            "lti" => Opcode::LTI,
-            "ltr" => Opcode::LTR,
-            "ltei" => Opcode::LTEI,
-            "lter" => Opcode::LTER,
-            "jmpi" => Opcode::JMPI,
-            "jmpd" => Opcode::JMPD,
-            "jmpr" => Opcode::JMPR,
-            "jmpei" => Opcode::JMPEI,
-            "jmped" => Opcode::JMPED,
-            "jmper" => Opcode::JMPER,
-            "jmpnei" => Opcode::JMPNEI,
-            "jmpned" => Opcode::JMPNED,
-            "jmpner" => Opcode::JMPNER,
-            "prtsd" => Opcode::PRTSD,
-            "prtsr" => Opcode::PRTSR,
-            _ => Opcode::IGL,
+/// Description of an opcode, read from the table every crate takes opcodes from
+#[derive(Debug, PartialEq)]
+pub struct OpcodeInfo {
+    pub opcode: Opcode,
+    /// Name the opcode is written with in assembly, in upper case
+    pub mnemonic: &'static str,
+    /// Operands read by the opcode, in order
+    pub operands: &'static [OperandKind],
+    /// What the opcode does, from its doc comment
+    pub doc: &'static str,
+}
+
+impl Opcode {
+    /// Byte the opcode is encoded as, with its addressing mode in the lower 2 bits
+    pub fn encoding(self) -> u8 {
+        self as u8
+    }
+
+    /// Name the opcode is written with in assembly, in upper case
+    pub fn mnemonic(self) -> &'static str {
+        self.info().mnemonic
+    }
+
+    /// Operands read by the opcode, in order
+    pub fn operand_kinds(self) -> &'static [OperandKind] {
+        self.info().operands
+    }
+
+    /// Addressing mode, from the lower 2 bits of the opcode's encoding, or None if they don't give
+    /// one
+    pub fn mode(self) -> Option<AddressingMode> {
+        match self.encoding() & 0b11 {
+            0b00 => Some(AddressingMode::Immediate),
+            0b01 => Some(AddressingMode::Direct),
+            0b10 => Some(AddressingMode::Register),
+            _ => None,
+        }
+    }
+
+    /// Opcode doing the same as this one in another addressing mode, which shares its upper 6
+    /// bits, such as LDBR for LDBI. None if there isn't one
+    pub fn with_mode(self, mode: AddressingMode) -> Option<Self> {
+        Opcode::from_encoding(self.encoding() & !0b11 | mode as u8)
+    }
+}
+
+/// Where an opcode's main operand comes from, encoded in the lower 2 bits of the opcode
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum AddressingMode {
+    /// A literal value in the instruction
+    Immediate = 0b00,
+    /// Memory at a literal address in the instruction
+    Direct = 0b01,
+    /// A register
+    Register = 0b10,
+}
+
+/// How an operand is encoded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum OperandKind {
+    /// Single byte register index
+    Register,
+    /// Single byte literal value
+    Byte,
+    /// Two byte literal value
+    Value,
+    /// Two byte address
+    Address,
+    /// Two byte signed offset from the instruction's own address
+    Offset,
+}
+
+impl OperandKind {
+    /// Number of bytes the operand is encoded in
+    pub fn size(self) -> usize {
+        match self {
+            OperandKind::Register | OperandKind::Byte => 1,
+            OperandKind::Value | OperandKind::Address | OperandKind::Offset => 2,
         }
     }
 }
 
+impl std::fmt::Display for OperandKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            OperandKind::Register => "register",
+            OperandKind::Byte => "byte",
+            OperandKind::Value => "value",
+            OperandKind::Address => "address",
+            OperandKind::Offset => "offset",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use num_traits::cast::FromPrimitive;
 
     #[test]
     fn test_create_hlt() {
-        let opcode = Opcode::from_u8(0);
+        let opcode = Opcode::from_encoding(0);
 
         assert_eq!(opcode, Some(Opcode::HLT));
+        assert_eq!(Opcode::from_encoding(0b11111100), None);
     }
 
     #[test]
     fn test_str_to_opcode() {
-        let opcode = Opcode::from("ldbi");
-        assert_eq!(opcode, Opcode::LDBI);
+        assert_eq!(Opcode::from_mnemonic("ldbi"), Some(Opcode::LDBI));
+        assert_eq!(Opcode::from_mnemonic("illegal"), None);
+        assert_eq!(Opcode::from_mnemonic("INC"), Some(Opcode::INC));
+        assert_eq!(Opcode::from_mnemonic("djmp"), Some(Opcode::JMPI));
+    }
+
+    #[test]
+    fn test_table() {
+        for (index, info) in OPCODES.iter().enumerate() {
+            assert_eq!(info.opcode.info(), info);
+            assert_eq!(
+                Opcode::from_encoding(info.opcode.encoding()),
+                Some(info.opcode)
+            );
+            assert_eq!(Opcode::from_mnemonic(info.mnemonic), Some(info.opcode));
+            assert!(OPCODES[..index]
+                .iter()
+                .all(|other| other.mnemonic != info.mnemonic));
+
+            // every operand fits in the 3 bytes after the opcode
+            let size: usize = info.operands.iter().map(|kind| kind.size()).sum();
+            assert!(size <= 3, "{}", info.mnemonic);
+        }
 
-        let opcode = Opcode::from("illegal");
-        assert_eq!(opcode, Opcode::IGL);
+        let info = Opcode::MEMSET.info();
+        assert_eq!(info.mnemonic, "MEMSET");
+        assert!(info
+            .doc
+            .starts_with("Fills a number of bytes read from register, starting"));
+        assert!(!info.doc.contains("  "));
+        assert_eq!(MNEMONICS.len(), OPCODES.len());
+        assert_eq!(MNEMONICS[2], "ldbi");
+    }
+
+    #[test]
+    fn test_addressing_modes() {
+        assert_eq!(Opcode::LDBD.mode(), Some(AddressingMode::Direct));
+        assert_eq!(Opcode::IGL.mode(), None);
+        assert_eq!(
+            Opcode::LDBI.with_mode(AddressingMode::Register),
+            Some(Opcode::LDBR)
+        );
+        assert_eq!(
+            Opcode::JMPR.with_mode(AddressingMode::Direct),
+            Some(Opcode::JMPD)
+        );
+        assert_eq!(Opcode::STRWR.with_mode(AddressingMode::Direct), None);
+    }
+
+    #[test]
+    fn test_readme_lists_every_opcode() {
+        let readme = include_str!("../../README.md");
+
+        for info in OPCODES {
+            let row = format!("\n| {} ", info.mnemonic);
+            assert!(
+                readme.contains(&row),
+                "{} isn't in the README",
+                info.mnemonic
+            );
+        }
     }
 }
@@ -0,0 +1,116 @@
+//! Symbol section, optionally written after the code section so addresses can be resolved back to
+//! label names when debugging.
+//!
+//! Its offset and length are stored in the header directly after the code section's, and are both
+//! zero if a program has no symbols. Each symbol is encoded as
+//! ```text
+//! <kind: 1 byte> <value: 4 bytes> <name length: 2 bytes> <name>
+//! ```
+//! with every number big endian.
+
+/// Offset of the symbol section offset in the header, followed by its length
+pub const SYMBOL_SECTION_FIELD: usize = 24;
+
+/// What a symbol names
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum SymbolKind {
+    /// Label in the data section, whose value is its address
+    Data = 0,
+    /// Label in the code section, whose value is its address
+    Code = 1,
+    /// Constant defined with `.equ`, whose value is the constant
+    Constant = 2,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DebugSymbol {
+    pub name: String,
+    pub kind: SymbolKind,
+    pub value: u32,
+}
+
+/// Encodes symbols into a symbol section
+pub fn encode_symbols(symbols: &[DebugSymbol]) -> Vec<u8> {
+    let mut out = Vec::new();
+
+    for symbol in symbols {
+        out.push(symbol.kind as u8);
+        out.extend_from_slice(&symbol.value.to_be_bytes());
+        out.extend_from_slice(&(symbol.name.len() as u16).to_be_bytes());
+        out.extend_from_slice(symbol.name.as_bytes());
+    }
+
+    out
+}
+
+/// Decodes a symbol section, returning None if it's truncated or malformed
+pub fn decode_symbols(mut bytes: &[u8]) -> Option<Vec<DebugSymbol>> {
+    let mut symbols = Vec::new();
+
+    while let [kind, rest @ ..] = bytes {
+        let kind = match kind {
+            0 => SymbolKind::Data,
+            1 => SymbolKind::Code,
+            2 => SymbolKind::Constant,
+            _ => return None,
+        };
+        let value = u32::from_be_bytes(rest.get(..4)?.try_into().unwrap());
+        let len = u16::from_be_bytes(bytes.get(5..7)?.try_into().unwrap()) as usize;
+        let name = std::str::from_utf8(bytes.get(7..7 + len)?).ok()?;
+
+        symbols.push(DebugSymbol {
+            name: name.to_owned(),
+            kind,
+            value,
+        });
+        bytes = &bytes[7 + len..];
+    }
+
+    Some(symbols)
+}
+
+/// Reads the symbol section of a program, returning an empty list if it has none, or None if the
+/// section lies outside the program or is malformed
+pub fn read_symbols(program: &[u8]) -> Option<Vec<DebugSymbol>> {
+    let field = |offset: usize| {
+        program
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+    };
+    let start = field(SYMBOL_SECTION_FIELD)?;
+    let len = field(SYMBOL_SECTION_FIELD + 4)?;
+    if len == 0 {
+        return Some(Vec::new());
+    }
+
+    decode_symbols(program.get(start..start.checked_add(len)?)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_symbols() {
+        let symbols = vec![
+            DebugSymbol {
+                name: "main".to_owned(),
+                kind: SymbolKind::Code,
+                value: 64,
+            },
+            DebugSymbol {
+                name: "SIZE".to_owned(),
+                kind: SymbolKind::Constant,
+                value: -1i32 as u32,
+            },
+        ];
+
+        let bytes = encode_symbols(&symbols);
+        assert_eq!(&bytes[..11], [1, 0, 0, 0, 64, 0, 4, b'm', b'a', b'i', b'n']);
+        assert_eq!(decode_symbols(&bytes), Some(symbols));
+
+        assert_eq!(decode_symbols(&bytes[..bytes.len() - 1]), None);
+        assert_eq!(decode_symbols(&[3, 0, 0, 0, 0, 0, 0]), None);
+        assert_eq!(read_symbols(&[0; 64]), Some(Vec::new()));
+    }
+}
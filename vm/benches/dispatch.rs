@@ -0,0 +1,39 @@
+//! Instruction dispatch throughput, running a tight counting loop.
+
+use assembler::Assembler;
+use criterion::{criterion_group, criterion_main, Criterion, Throughput};
+use vm::{Program, VM};
+
+/// Counts $0 up to 10000, executing three instructions per iteration
+const COUNT: &str = r#"
+.code
+            ldhi $1, 10000
+    loop:   addi $0, 1
+            ltr $0, $1
+            jmpei @loop
+            hlt
+"#;
+
+fn load(program: &[u8]) -> VM {
+    let mut vm = VM::default();
+    vm.load(Program::parse(program.to_vec()).unwrap());
+    vm.set_output(std::io::sink());
+    vm
+}
+
+fn dispatch(c: &mut Criterion) {
+    let program = Assembler::default().assemble(COUNT).unwrap();
+
+    let mut group = c.benchmark_group("dispatch");
+    group.throughput(Throughput::Elements(30_002));
+    group.bench_function("counting loop", |b| {
+        b.iter(|| load(&program).run().unwrap())
+    });
+    group.bench_function("counting loop, cached", |b| {
+        b.iter(|| load(&program).run_cached().unwrap())
+    });
+    group.finish();
+}
+
+criterion_group!(benches, dispatch);
+criterion_main!(benches);
@@ -0,0 +1,156 @@
+use crate::errors::SnapshotError;
+use crate::snapshot::{Reader, Writer};
+use std::collections::BTreeMap;
+use std::ops::Range;
+
+/// Block of heap memory handed out by `ALOCI`/`ALOCR` and not yet freed
+#[derive(Debug, Clone, PartialEq)]
+pub struct Allocation {
+    pub address: usize,
+    pub size: usize,
+    /// Address of the instruction that allocated the block
+    pub pc: usize,
+}
+
+/// Tracks live allocations and freed blocks of the heap. Allocations reuse the first freed block
+/// large enough to hold them, and only grow the heap if there isn't one
+#[derive(Debug, Default, Clone)]
+pub struct Allocator {
+    /// Live allocations, by address
+    live: BTreeMap<usize, Allocation>,
+    /// Freed address ranges, sorted and with adjacent ranges merged
+    free: Vec<Range<usize>>,
+    /// Bytes held by live allocations
+    live_bytes: usize,
+    /// Most bytes held by live allocations at once
+    peak_bytes: usize,
+}
+
+impl Allocator {
+    /// Live allocations, in address order
+    pub fn allocations(&self) -> impl Iterator<Item = &Allocation> {
+        self.live.values()
+    }
+
+    /// Freed blocks available for reuse, in address order
+    pub fn free_blocks(&self) -> &[Range<usize>] {
+        &self.free
+    }
+
+    /// Most bytes held by live allocations at once
+    pub fn peak_bytes(&self) -> usize {
+        self.peak_bytes
+    }
+
+    /// Takes size bytes from the first freed block that can hold them, returning their address
+    pub(crate) fn reuse(&mut self, size: usize) -> Option<usize> {
+        let index = self.free.iter().position(|block| block.len() >= size)?;
+        let block = &mut self.free[index];
+        let address = block.start;
+
+        block.start += size;
+        if block.start == block.end {
+            self.free.remove(index);
+        }
+
+        Some(address)
+    }
+
+    pub(crate) fn record(&mut self, allocation: Allocation) {
+        self.live_bytes += allocation.size;
+        self.peak_bytes = self.peak_bytes.max(self.live_bytes);
+        self.live.insert(allocation.address, allocation);
+    }
+
+    /// Frees the allocation starting at address, returning it if there was one
+    pub(crate) fn release(&mut self, address: usize) -> Option<Allocation> {
+        let allocation = self.live.remove(&address)?;
+        self.live_bytes -= allocation.size;
+        let mut block = address..address + allocation.size;
+
+        // merge with the neighbouring freed blocks
+        let index = self.free.partition_point(|free| free.start < block.start);
+        if let Some(next) = self.free.get(index).filter(|next| next.start == block.end) {
+            block.end = next.end;
+            self.free.remove(index);
+        }
+        match index
+            .checked_sub(1)
+            .map(|previous| &mut self.free[previous])
+        {
+            Some(previous) if previous.end == block.start => previous.end = block.end,
+            _ => self.free.insert(index, block),
+        }
+
+        Some(allocation)
+    }
+
+    pub(crate) fn save(&self, writer: &mut Writer) {
+        writer.usize(self.live.len());
+        for allocation in self.live.values() {
+            writer.usize(allocation.address);
+            writer.usize(allocation.size);
+            writer.usize(allocation.pc);
+        }
+
+        writer.usize(self.free.len());
+        for block in &self.free {
+            writer.usize(block.start);
+            writer.usize(block.end);
+        }
+    }
+
+    pub(crate) fn load(reader: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut allocator = Self::default();
+
+        for _ in 0..reader.usize()? {
+            allocator.record(Allocation {
+                address: reader.usize()?,
+                size: reader.usize()?,
+                pc: reader.usize()?,
+            });
+        }
+        for _ in 0..reader.usize()? {
+            allocator.free.push(reader.usize()?..reader.usize()?);
+        }
+
+        Ok(allocator)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allocation(address: usize, size: usize) -> Allocation {
+        Allocation {
+            address,
+            size,
+            pc: 0,
+        }
+    }
+
+    #[test]
+    fn test_free_list() {
+        let mut allocator = Allocator::default();
+        for address in [0, 4, 8, 12] {
+            allocator.record(allocation(address, 4));
+        }
+
+        assert!(allocator.release(4).is_some());
+        assert!(allocator.release(12).is_some());
+        assert_eq!(allocator.free_blocks(), [4..8, 12..16]);
+
+        // freeing between two blocks merges all three
+        assert!(allocator.release(8).is_some());
+        assert_eq!(allocator.free_blocks().len(), 1);
+        assert_eq!(allocator.free_blocks().first(), Some(&(4..16)));
+        assert!(allocator.release(8).is_none());
+
+        assert_eq!(allocator.reuse(16), None);
+        assert_eq!(allocator.reuse(8), Some(4));
+        assert_eq!(allocator.free_blocks().first(), Some(&(12..16)));
+        assert_eq!(allocator.allocations().count(), 1);
+        assert_eq!(allocator.peak_bytes(), 16);
+    }
+}
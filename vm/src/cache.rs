@@ -0,0 +1,222 @@
+use crate::instruction::Instruction;
+use shared::Opcode;
+use std::collections::HashMap;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Times a basic block is entered before its instructions are decoded into a block
+const HOT_THRESHOLD: u32 = 8;
+/// Most instructions decoded into a single block
+const MAX_BLOCK_LENGTH: usize = 64;
+
+/// Cache of decoded instructions, keyed by the address they were fetched from, and of the basic
+/// blocks that are entered often enough to be worth decoding as a whole.
+///
+/// Programs may be allowed to write into their own code section, so any store must invalidate the
+/// decodes it overlaps. A store takes effect for every instruction fetched after it completes,
+/// including the one directly after the store itself.
+#[derive(Debug, Default)]
+pub(crate) struct InstructionCache {
+    entries: HashMap<usize, Instruction>,
+    /// Code section the blocks were decoded from
+    code: Range<usize>,
+    /// Decoded block starting at each instruction in the code section, if it's hot
+    blocks: Vec<Option<Rc<[Instruction]>>>,
+    /// Times the block starting at each instruction in the code section has been entered
+    heat: Vec<u32>,
+    /// Incremented whenever a block is dropped, so a block being run can tell it's stale
+    generation: u64,
+}
+
+impl InstructionCache {
+    /// Returns the instruction at `pc`, decoding and caching it if it isn't already cached.
+    /// Returns None if fewer than 4 bytes are available at `pc`
+    pub fn fetch(&mut self, pc: usize, program: &[u8]) -> Option<Instruction> {
+        if let Some(instruction) = self.entries.get(&pc) {
+            return Some(*instruction);
+        }
+
+        let instruction = Instruction::from(program.get(pc..pc + 4)?)?;
+        self.entries.insert(pc, instruction);
+
+        Some(instruction)
+    }
+
+    /// Returns the decoded basic block starting at `pc`, counting it as entered. Returns None if the
+    /// block isn't hot yet, or `pc` isn't an instruction boundary in the code section
+    pub fn block(
+        &mut self,
+        pc: usize,
+        program: &[u8],
+        code: Range<usize>,
+    ) -> Option<Rc<[Instruction]>> {
+        if code != self.code {
+            self.clear();
+            self.blocks = vec![None; code.len() / 4];
+            self.heat = vec![0; code.len() / 4];
+            self.code = code;
+        }
+
+        let offset = pc.checked_sub(self.code.start)?;
+        if !offset.is_multiple_of(4) {
+            return None;
+        }
+        let index = offset / 4;
+        if let Some(block) = self.blocks.get(index)? {
+            return Some(block.clone());
+        }
+
+        self.heat[index] += 1;
+        if self.heat[index] < HOT_THRESHOLD {
+            return None;
+        }
+
+        let mut instructions = Vec::new();
+        for address in (pc..self.code.end).step_by(4).take(MAX_BLOCK_LENGTH) {
+            let Some(instruction) = program
+                .get(address..address + 4)
+                .and_then(Instruction::from)
+            else {
+                break;
+            };
+            instructions.push(instruction);
+
+            if ends_block(instruction.opcode) {
+                break;
+            }
+        }
+
+        // instructions that can't be decoded fault when fetched individually
+        if instructions.is_empty() {
+            return None;
+        }
+
+        let block: Rc<[Instruction]> = instructions.into();
+        self.blocks[index] = Some(block.clone());
+        Some(block)
+    }
+
+    /// Number of times blocks have been dropped
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Drops every cached instruction and block overlapping the written byte range
+    pub fn invalidate(&mut self, written: Range<usize>) {
+        // blocks can start up to a whole block before the write and still overlap it
+        let reach = MAX_BLOCK_LENGTH * 4;
+        if written.start < self.code.end && written.end + reach > self.code.start {
+            let first = written.start.saturating_sub(reach).max(self.code.start);
+            for index in (first - self.code.start) / 4..self.blocks.len() {
+                let start = self.code.start + index * 4;
+                if start >= written.end {
+                    break;
+                }
+
+                let overlaps = self.blocks[index]
+                    .as_ref()
+                    .is_some_and(|block| start + block.len() * 4 > written.start);
+                if overlaps {
+                    self.blocks[index] = None;
+                    self.generation += 1;
+                }
+            }
+        }
+
+        if self.entries.is_empty() {
+            return;
+        }
+
+        // any instruction starting up to 3 bytes before the write overlaps it
+        let start = written.start.saturating_sub(3);
+        for pc in start..written.end {
+            self.entries.remove(&pc);
+        }
+    }
+
+    /// Drops every cached instruction and block, and forgets how hot blocks were
+    pub fn clear(&mut self) {
+        self.entries.clear();
+        self.blocks.fill(None);
+        self.heat.fill(0);
+        self.generation += 1;
+    }
+}
+
+/// Whether an instruction can move the program counter anywhere other than the next instruction,
+/// ending the basic block it's in
+fn ends_block(opcode: Opcode) -> bool {
+    matches!(
+        opcode,
+        Opcode::HLT
+            | Opcode::EXIT
+            | Opcode::JMPI
+            | Opcode::JMPD
+            | Opcode::JMPR
+            | Opcode::JMPEI
+            | Opcode::JMPED
+            | Opcode::JMPER
+            | Opcode::JMPNEI
+            | Opcode::JMPNED
+            | Opcode::JMPNER
+            | Opcode::BRA
+            | Opcode::BRE
+            | Opcode::BRNE
+            | Opcode::CALLI
+            | Opcode::CALLR
+            | Opcode::RET
+            | Opcode::IRET
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::Opcode;
+
+    #[test]
+    fn test_invalidate_overlapping() {
+        let program = [0, 0, 0, 0, 4, 0, 0, 0];
+        let mut cache = InstructionCache::default();
+
+        cache.fetch(0, &program);
+        cache.fetch(4, &program);
+        assert_eq!(cache.entries.len(), 2);
+
+        // a write to the last byte of the first instruction only touches that one
+        cache.invalidate(3..4);
+        assert!(!cache.entries.contains_key(&0));
+        assert!(cache.entries.contains_key(&4));
+
+        assert_eq!(cache.fetch(4, &program).unwrap().opcode, Opcode::LDBI);
+        assert!(cache.fetch(6, &program).is_none());
+    }
+
+    #[test]
+    fn test_blocks() {
+        // addi $0, 1; jmpi 0; hlt
+        let program = [64, 0, 0, 1, 160, 0, 0, 0, 0, 0, 0, 0];
+        let mut cache = InstructionCache::default();
+
+        for _ in 1..HOT_THRESHOLD {
+            assert!(cache.block(0, &program, 0..12).is_none());
+        }
+        let block = cache.block(0, &program, 0..12).unwrap();
+        assert_eq!(block.len(), 2);
+        assert_eq!(block[1].opcode, Opcode::JMPI);
+        assert!(cache.block(2, &program, 0..12).is_none());
+
+        // writes after the block leave it alone
+        let generation = cache.generation();
+        cache.invalidate(8..12);
+        assert_eq!(cache.generation(), generation);
+
+        // the block is still hot, so is decoded again from the new bytes
+        cache.invalidate(7..8);
+        assert_ne!(cache.generation(), generation);
+        assert!(!Rc::ptr_eq(
+            &block,
+            &cache.block(0, &program, 0..12).unwrap()
+        ));
+    }
+}
@@ -0,0 +1,192 @@
+//! Several VMs running on their own threads, passing messages to each other with `SEND` and `RECV`.
+//!
+//! Every VM in a cluster has a mailbox, and `SEND` queues a value in another VM's mailbox without
+//! waiting for it to be received. `RECV` waits for a value to arrive, and faults rather than
+//! waiting forever once every other VM has stopped. VMs that wait on each other at the same time
+//! still deadlock, just as threads would.
+//!
+//! VMs hold their hooks and devices on the thread they're made on, so each one is built on its own
+//! thread by a closure given to [`Cluster::add`].
+
+use crate::errors::VmError;
+use crate::vm::VM;
+use std::sync::mpsc::{channel, Receiver, Sender};
+use std::thread::JoinHandle;
+
+/// Value sent by another VM
+#[derive(Debug, Clone, Copy)]
+struct Message {
+    from: usize,
+    value: i32,
+}
+
+/// A VM's connection to the rest of its cluster
+#[derive(Debug)]
+pub(crate) struct Mailbox {
+    id: usize,
+    /// Senders to every VM by id, except this one so its mailbox closes once every other VM stops
+    senders: Vec<Option<Sender<Message>>>,
+    receiver: Receiver<Message>,
+}
+
+impl Mailbox {
+    /// Queues a value for the VM with the given id, or returns false if it doesn't exist or has
+    /// stopped
+    pub(crate) fn send(&self, to: usize, value: i32) -> bool {
+        let message = Message {
+            from: self.id,
+            value,
+        };
+
+        match self.senders.get(to) {
+            Some(Some(sender)) => sender.send(message).is_ok(),
+            _ => false,
+        }
+    }
+
+    /// Waits for a value, returning it along with the id of the VM that sent it, or None if every
+    /// other VM has stopped without sending one
+    pub(crate) fn receive(&self) -> Option<(i32, usize)> {
+        self.receiver
+            .recv()
+            .ok()
+            .map(|message| (message.value, message.from))
+    }
+}
+
+/// VMs waiting to be spawned together
+#[derive(Default)]
+pub struct Cluster {
+    builders: Vec<Box<dyn FnOnce() -> VM + Send>>,
+}
+
+impl Cluster {
+    /// Adds a VM, built on its own thread by `build` once the cluster is spawned. Returns its id,
+    /// which other VMs send messages to it with
+    pub fn add(&mut self, build: impl FnOnce() -> VM + Send + 'static) -> usize {
+        self.builders.push(Box::new(build));
+        self.builders.len() - 1
+    }
+
+    /// Runs every VM on its own thread, returning handles in order of id
+    pub fn spawn(self) -> Vec<VmHandle> {
+        let (senders, receivers): (Vec<_>, Vec<_>) =
+            self.builders.iter().map(|_| channel()).unzip();
+
+        self.builders
+            .into_iter()
+            .zip(receivers)
+            .enumerate()
+            .map(|(id, (build, receiver))| {
+                let mailbox = Mailbox {
+                    id,
+                    senders: senders
+                        .iter()
+                        .enumerate()
+                        .map(|(to, sender)| (to != id).then(|| sender.clone()))
+                        .collect(),
+                    receiver,
+                };
+
+                let thread = std::thread::spawn(move || {
+                    let mut vm = build();
+                    vm.set_mailbox(mailbox);
+                    vm.run()?;
+
+                    Ok(*vm.registers())
+                });
+                VmHandle { id, thread }
+            })
+            .collect()
+    }
+}
+
+/// VM running on its own thread as part of a [`Cluster`]
+#[derive(Debug)]
+pub struct VmHandle {
+    id: usize,
+    thread: JoinHandle<Result<[i32; 32], VmError>>,
+}
+
+impl VmHandle {
+    pub fn id(&self) -> usize {
+        self.id
+    }
+
+    /// Waits for the VM to stop, returning its final registers or the fault it stopped with.
+    /// Panics on the VM's thread are resumed on this one
+    pub fn join(self) -> Result<[i32; 32], VmError> {
+        self.thread
+            .join()
+            .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::memory::Memory;
+    use shared::{PIE_FORMAT_VERSION, PIE_HEADER_PREFIX};
+
+    /// VM running a program with no data and the given code, and discarding its output
+    fn vm(code: &[u8]) -> VM {
+        let mut image = vec![0; 64];
+        image[..4].copy_from_slice(&PIE_HEADER_PREFIX);
+        image[4] = PIE_FORMAT_VERSION;
+        image[8..12].copy_from_slice(&64u32.to_be_bytes());
+        image[16..20].copy_from_slice(&64u32.to_be_bytes());
+        image[20..24].copy_from_slice(&(code.len() as u32).to_be_bytes());
+        image.extend_from_slice(code);
+
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(image);
+        vm.set_output(std::io::sink());
+        vm
+    }
+
+    #[test]
+    fn test_cluster() {
+        let mut cluster = Cluster::default();
+        // ldbi $1, 1; ldbi $2, 42; send $1, $2; recv $3, $4; hlt
+        cluster.add(|| {
+            vm(&[
+                4, 1, 0, 1, 4, 2, 0, 42, 218, 1, 2, 0, 222, 3, 4, 0, 0, 0, 0, 0,
+            ])
+        });
+        // recv $3, $4; inc $3; send $4, $3; hlt
+        cluster.add(|| vm(&[222, 3, 4, 0, 90, 3, 0, 0, 218, 4, 3, 0, 0, 0, 0, 0]));
+
+        let handles = cluster.spawn();
+        assert_eq!(handles[1].id(), 1);
+        let registers = handles
+            .into_iter()
+            .map(|handle| handle.join().unwrap())
+            .collect::<Vec<_>>();
+
+        // VM 1 got 42 from VM 0 and sent it back incremented
+        assert_eq!(registers[1][3..5], [43, 0]);
+        assert_eq!(registers[0][3..5], [43, 1]);
+    }
+
+    #[test]
+    fn test_cluster_faults() {
+        let mut cluster = Cluster::default();
+        // ldbi $1, 5; send $1, $1
+        cluster.add(|| vm(&[4, 1, 0, 5, 218, 1, 1, 0]));
+        // recv $1, $2
+        cluster.add(|| vm(&[222, 1, 2, 0]));
+
+        let results = cluster
+            .spawn()
+            .into_iter()
+            .map(VmHandle::join)
+            .collect::<Vec<_>>();
+        assert_eq!(
+            results,
+            [
+                Err(VmError::InvalidRecipient { id: 5, pc: 68 }),
+                Err(VmError::MailboxClosed { pc: 64 })
+            ]
+        );
+    }
+}
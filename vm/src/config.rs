@@ -0,0 +1,30 @@
+use std::fmt::{Display, Formatter};
+
+/// Resource limits enforced while running a program. Every limit defaults to unlimited
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct VMConfig {
+    /// Maximum number of bytes the heap can grow to
+    pub max_heap: Option<usize>,
+    /// Maximum size of the program image, in bytes
+    pub max_program_size: Option<usize>,
+    /// Maximum number of instructions executed per run
+    pub max_steps: Option<u64>,
+}
+
+/// Resource limit that can be exceeded
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Limit {
+    Heap,
+    ProgramSize,
+    Steps,
+}
+
+impl Display for Limit {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Limit::Heap => write!(f, "heap size"),
+            Limit::ProgramSize => write!(f, "program size"),
+            Limit::Steps => write!(f, "step"),
+        }
+    }
+}
@@ -0,0 +1,51 @@
+//! Memory-mapped devices.
+//!
+//! A device is a block of registers mapped into the address space at or above
+//! [`DEVICE_BASE`](shared::abi::DEVICE_BASE), which loads and stores within are sent to instead of
+//! memory. Devices are shared with the host through `Rc<RefCell<_>>`, so it can feed them input and
+//! collect their output while the program runs.
+//!
+//! A device can also request an interrupt. Between instructions, if the program has set an
+//! interrupt handler with `IVECI` and isn't already handling an interrupt, the VM pushes the
+//! program counter and the equality flag and jumps to the handler, which returns with `IRET`.
+//! Interrupts are level triggered, so a device keeps requesting one until the handler has dealt
+//! with whatever caused it.
+
+use std::cell::RefCell;
+use std::fmt::Debug;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Peripheral whose registers are mapped into memory. See [`Uart`](crate::Uart) for an example
+pub trait Device: Debug {
+    /// Number of bytes of address space the registers take up
+    fn size(&self) -> usize;
+
+    /// Reads the register byte at offset from the start of the device, which may change the
+    /// device's state
+    fn load(&mut self, offset: usize) -> u8;
+
+    /// Writes the register byte at offset from the start of the device
+    fn store(&mut self, offset: usize, value: u8);
+
+    /// Whether the device is requesting an interrupt
+    fn interrupt_pending(&self) -> bool {
+        false
+    }
+
+    /// State the program can observe, saved in snapshots so a restored program finds the device
+    /// as it left it, including any interrupt it was requesting. Empty for devices without any
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Puts back state saved by `save_state`, ignoring any the device doesn't understand
+    fn restore_state(&mut self, _state: &[u8]) {}
+}
+
+/// Device mapped at a range of addresses
+#[derive(Debug, Clone)]
+pub(crate) struct MappedDevice {
+    pub range: Range<usize>,
+    pub device: Rc<RefCell<dyn Device>>,
+}
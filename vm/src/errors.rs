@@ -0,0 +1,116 @@
+use crate::config::Limit;
+use crate::verify::BadJump;
+use shared::PIE_FORMAT_VERSION;
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum VmError {
+    #[error("program header is missing, truncated or describes sections outside the program")]
+    InvalidHeader,
+    #[error("program doesn't start with the EPIE magic number, so isn't an assembled program")]
+    MissingMagic,
+    #[error(
+        "program uses bytecode format version {version}, but only version {PIE_FORMAT_VERSION} \
+         is supported; reassemble it with this version of the assembler"
+    )]
+    UnsupportedVersion { version: u8 },
+    #[error("program uses {size} byte words, but only 4 and 8 byte words are supported")]
+    UnsupportedWordSize { size: u8 },
+    #[error("program uses {size} byte words, but this VM uses {expected} byte words")]
+    WordSizeMismatch { size: usize, expected: usize },
+    #[error("truncated instruction at {pc:#06X}")]
+    TruncatedInstruction { pc: usize },
+    #[error("illegal instruction at {pc:#06X}")]
+    IllegalOpcode { pc: usize },
+    #[error("register {index} does not exist")]
+    InvalidRegister { index: u8 },
+    #[error("memory address {address:#06X} out of bounds")]
+    InvalidAddress { address: usize },
+    #[error("stack overflow at {address:#06X}, below the stack")]
+    StackOverflow { address: usize },
+    #[error("device at {address:#06X} overlaps memory or another device")]
+    DeviceOverlap { address: usize },
+    #[error("memory address {address:#06X} is read-only")]
+    WriteProtected { address: usize },
+    #[error("{size} byte access at {address:#06X} is not aligned")]
+    UnalignedAccess { address: usize, size: usize },
+    #[error(
+        "immediate jumps to addresses that aren't instructions in the code section: {}",
+        .jumps.iter().map(ToString::to_string).collect::<Vec<_>>().join(", ")
+    )]
+    InvalidJumps { jumps: Vec<BadJump> },
+    #[error("memory at {pc:#06X} is not executable")]
+    NotExecutable { pc: usize },
+    #[error("address {address:#06X} is not the start of a live allocation")]
+    InvalidFree { address: usize },
+    #[error("log level {level} at {pc:#06X} is invalid, expected 0 (error) to 4 (trace)")]
+    InvalidLogLevel { level: u8, pc: usize },
+    #[error("syscall {number} at {pc:#06X} isn't registered")]
+    UnknownSyscall { number: u16, pc: usize },
+    #[error("message passing at {pc:#06X} needs the VM to be running in a cluster")]
+    NotInCluster { pc: usize },
+    #[error("message sent at {pc:#06X} to VM {id}, which isn't another VM in the cluster or has stopped")]
+    InvalidRecipient { id: usize, pc: usize },
+    #[error(
+        "receive at {pc:#06X} can never complete, as every other VM in the cluster has stopped"
+    )]
+    MailboxClosed { pc: usize },
+    #[error("object instruction at {pc:#06X} needs the object heap to be enabled")]
+    NoObjectHeap { pc: usize },
+    #[error("object kind {kind} at {pc:#06X} is invalid, expected 0 (array) or 1 (string)")]
+    InvalidObjectKind { kind: u8, pc: usize },
+    #[error("{handle:#010X} at {pc:#06X} isn't the handle of a live object")]
+    InvalidObject { handle: i32, pc: usize },
+    #[error("index {index} at {pc:#06X} is out of bounds for an object of {len} elements")]
+    InvalidElement { index: i32, len: usize, pc: usize },
+    #[error("division by zero")]
+    DivisionByZero,
+    #[error("failed to write output: {error}")]
+    OutputFailed {
+        error: String,
+        kind: std::io::ErrorKind,
+    },
+    #[error("failed to read input: {error}")]
+    InputFailed { error: String },
+    #[error("expected an integer to be input at {pc:#06X}, got '{input}'")]
+    InvalidInteger { input: String, pc: usize },
+    #[error("{limit} limit exceeded")]
+    ResourceExhausted { limit: Limit },
+}
+
+/// Problem found in a program by [`verify`](crate::verify)
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum VerifyError {
+    #[error(transparent)]
+    Header(#[from] VmError),
+    #[error("{first} section overlaps the {second} section")]
+    SectionOverlap {
+        first: &'static str,
+        second: &'static str,
+    },
+    #[error("code section is {len} bytes long, which isn't a whole number of instructions")]
+    PartialInstruction { len: usize },
+    #[error("illegal instruction at {pc:#06X}")]
+    IllegalOpcode { pc: usize },
+    #[error("register {index} at {pc:#06X} does not exist")]
+    InvalidRegister { index: u8, pc: usize },
+    #[error("immediate jump {jump} doesn't target an instruction in the code section")]
+    InvalidJump { jump: BadJump },
+}
+
+#[derive(thiserror::Error, Debug, Clone, PartialEq)]
+pub enum SnapshotError {
+    #[error("not a VM snapshot")]
+    InvalidMagic,
+    #[error("snapshot format version {found} isn't supported, expected version {expected}")]
+    UnsupportedVersion { found: u16, expected: u16 },
+    #[error("snapshot is truncated or describes invalid state")]
+    Malformed,
+    #[error("snapshot was taken with different devices mapped")]
+    DeviceMismatch,
+    #[error("couldn't reopen {path}, which the program had open when the snapshot was taken")]
+    FileUnavailable { path: String },
+    #[error(
+        "snapshot was taken in a VM with {size} byte words, but this VM uses {expected} byte words"
+    )]
+    WordSizeMismatch { size: usize, expected: usize },
+}
@@ -0,0 +1,281 @@
+//! Files opened by `FOPEN`, and read and written through descriptors by `FREAD`, `FWRITE` and
+//! `FCLOSE`.
+//!
+//! Programs can only open files inside directories the host has allowed with
+//! [`VM::allow_directory`](crate::VM::allow_directory), so none at all by default. Paths are
+//! resolved, following any symlinks, before being checked, so `..` and links can't escape the
+//! allowed directories, and new files are only created where nothing exists yet, so a dangling
+//! link can't be followed out of them either.
+
+use crate::errors::SnapshotError;
+use crate::snapshot::{Reader, Writer};
+use std::fs::{File, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+/// `FOPEN` mode opening an existing file for reading
+pub const FILE_READ: i32 = 0;
+/// `FOPEN` mode creating a file for writing, or truncating it if it exists
+pub const FILE_WRITE: i32 = 1;
+/// `FOPEN` mode creating a file for writing, or appending to it if it exists
+pub const FILE_APPEND: i32 = 2;
+
+#[derive(Debug, Default)]
+pub(crate) struct FileTable {
+    /// Directories files can be opened in, resolved when they were allowed
+    allowed: Vec<PathBuf>,
+    /// Open files by descriptor, with closed descriptors reused by the next file opened
+    open: Vec<Option<OpenFile>>,
+}
+
+#[derive(Debug)]
+pub(crate) struct OpenFile {
+    file: File,
+    /// Resolved path the file was opened at, and the mode it was opened in, so it can be reopened
+    /// when a snapshot is restored
+    path: PathBuf,
+    mode: i32,
+}
+
+impl FileTable {
+    /// Allows files anywhere inside a directory to be opened
+    pub(crate) fn allow(&mut self, directory: &Path) -> io::Result<()> {
+        self.allowed.push(directory.canonicalize()?);
+
+        Ok(())
+    }
+
+    /// Opens the file at path in the given mode, returning its descriptor, or None if the path
+    /// isn't in an allowed directory or the file can't be opened
+    pub(crate) fn open(&mut self, path: &[u8], mode: i32) -> Option<usize> {
+        let path = Path::new(std::str::from_utf8(path).ok()?);
+        let (file, path) = self.open_path(path, mode, mode != FILE_READ, mode == FILE_WRITE)?;
+        let fd = match self.open.iter().position(Option::is_none) {
+            Some(fd) => fd,
+            None => {
+                self.open.push(None);
+                self.open.len() - 1
+            }
+        };
+        self.open[fd] = Some(OpenFile { file, path, mode });
+
+        Some(fd)
+    }
+
+    /// Opens the file at path in the given mode, returning it with its resolved path, or None if
+    /// the path isn't in an allowed directory or the file can't be opened. Existing files are
+    /// opened at their resolved path, and files being created don't exist yet, so only their
+    /// directory is resolved and they're created only if nothing is there, not even a dangling
+    /// symlink which creating the file would follow out of the directory
+    fn open_path(
+        &self,
+        path: &Path,
+        mode: i32,
+        create: bool,
+        truncate: bool,
+    ) -> Option<(File, PathBuf)> {
+        let mut options = options(mode)?;
+        let resolved = match path.canonicalize() {
+            Ok(resolved) => {
+                options.truncate(truncate);
+                resolved
+            }
+            Err(_) if create => {
+                let directory = match path.parent()? {
+                    parent if parent.as_os_str().is_empty() => Path::new("."),
+                    parent => parent,
+                };
+                options.create_new(true);
+                // None for paths ending in `..`, which would otherwise resolve to the directory
+                directory.canonicalize().ok()?.join(path.file_name()?)
+            }
+            Err(_) => return None,
+        };
+        if !self.is_allowed(&resolved) {
+            return None;
+        }
+
+        let file = options.open(&resolved).ok()?;
+        // the path could have been replaced with a link after it was resolved, so it's checked
+        // again now the file is open
+        let opened = resolved.canonicalize().ok()?;
+        (opened == resolved && self.is_allowed(&opened)).then_some((file, resolved))
+    }
+
+    /// Whether a resolved path is inside an allowed directory
+    fn is_allowed(&self, resolved: &Path) -> bool {
+        self.allowed
+            .iter()
+            .any(|directory| resolved.starts_with(directory))
+    }
+
+    /// Reads into buffer from an open file, returning the number of bytes read, which is zero at
+    /// the end of the file
+    pub(crate) fn read(&mut self, fd: usize, buffer: &mut [u8]) -> Option<usize> {
+        self.file(fd)?.read(buffer).ok()
+    }
+
+    /// Writes all of bytes to an open file, returning the number written
+    pub(crate) fn write(&mut self, fd: usize, bytes: &[u8]) -> Option<usize> {
+        self.file(fd)?.write_all(bytes).ok()?;
+
+        Some(bytes.len())
+    }
+
+    /// Closes a file, returning whether it was open
+    pub(crate) fn close(&mut self, fd: usize) -> bool {
+        self.open.get_mut(fd).and_then(Option::take).is_some()
+    }
+
+    /// Closes every open file
+    pub(crate) fn close_all(&mut self) {
+        self.open.clear();
+    }
+
+    fn file(&mut self, fd: usize) -> Option<&mut File> {
+        Some(&mut self.open.get_mut(fd)?.as_mut()?.file)
+    }
+
+    /// Saves where each open file is and how far through it the program is, so it can be reopened
+    pub(crate) fn save(&self, writer: &mut Writer) {
+        writer.usize(self.open.len());
+        for open in &self.open {
+            writer.bool(open.is_some());
+            if let Some(open) = open {
+                writer.bytes(open.path.as_os_str().as_encoded_bytes());
+                writer.u32(open.mode as u32);
+                writer.u64((&open.file).stream_position().unwrap_or(0));
+            }
+        }
+    }
+
+    /// Reopens the files saved by `save` at the same positions, without truncating any. Fails if
+    /// a file is no longer inside an allowed directory or can't be opened
+    pub(crate) fn reopen(
+        &self,
+        reader: &mut Reader,
+    ) -> Result<Vec<Option<OpenFile>>, SnapshotError> {
+        let mut open = Vec::new();
+        for _ in 0..reader.usize()? {
+            if !reader.bool()? {
+                open.push(None);
+                continue;
+            }
+
+            let path =
+                std::str::from_utf8(reader.bytes()?).map_err(|_| SnapshotError::Malformed)?;
+            let mode = reader.u32()? as i32;
+            let position = reader.u64()?;
+
+            let unavailable = || SnapshotError::FileUnavailable {
+                path: path.to_owned(),
+            };
+            if options(mode).is_none() {
+                return Err(SnapshotError::Malformed);
+            }
+            let (mut file, path) = self
+                .open_path(Path::new(path), mode, false, false)
+                .ok_or_else(unavailable)?;
+            file.seek(SeekFrom::Start(position))
+                .map_err(|_| unavailable())?;
+
+            open.push(Some(OpenFile { file, path, mode }));
+        }
+
+        Ok(open)
+    }
+
+    /// Replaces the open files with ones reopened by `reopen`
+    pub(crate) fn restore(&mut self, open: Vec<Option<OpenFile>>) {
+        self.open = open;
+    }
+}
+
+/// Options opening an existing file in a `FOPEN` mode, without creating or truncating it, or None
+/// if the mode doesn't exist
+fn options(mode: i32) -> Option<OpenOptions> {
+    let mut options = OpenOptions::new();
+    match mode {
+        FILE_READ => options.read(true),
+        FILE_WRITE => options.write(true),
+        FILE_APPEND => options.append(true),
+        _ => return None,
+    };
+
+    Some(options)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_file_table() {
+        let directory = std::env::temp_dir().join(format!("rvm-files-{}", std::process::id()));
+        let allowed = directory.join("allowed");
+        std::fs::create_dir_all(&allowed).unwrap();
+        std::fs::write(directory.join("secret"), "secret").unwrap();
+
+        let mut files = FileTable::default();
+        let path = allowed.join("file").into_os_string().into_string().unwrap();
+        assert_eq!(files.open(path.as_bytes(), FILE_WRITE), None);
+
+        files.allow(&allowed).unwrap();
+        let fd = files.open(path.as_bytes(), FILE_WRITE).unwrap();
+        assert_eq!(files.write(fd, b"hello"), Some(5));
+        assert!(files.close(fd));
+        assert!(!files.close(fd));
+        assert_eq!(files.write(fd, b"hello"), None);
+
+        let fd = files.open(path.as_bytes(), FILE_READ).unwrap();
+        let mut buffer = [0; 8];
+        assert_eq!(files.read(fd, &mut buffer), Some(5));
+        assert_eq!(&buffer[..5], b"hello");
+        assert_eq!(files.read(fd, &mut buffer), Some(0));
+
+        // escaping the allowed directory
+        let escape = format!("{}/../secret", allowed.display());
+        assert_eq!(files.open(escape.as_bytes(), FILE_READ), None);
+        let escape = format!("{}/..", allowed.display());
+        assert_eq!(files.open(escape.as_bytes(), FILE_WRITE), None);
+        assert_eq!(files.open(path.as_bytes(), 3), None);
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_dangling_symlink() {
+        let directory = std::env::temp_dir().join(format!("rvm-links-{}", std::process::id()));
+        let allowed = directory.join("allowed");
+        std::fs::create_dir_all(&allowed).unwrap();
+        let outside = directory.join("outside");
+        let link = allowed.join("link");
+        std::os::unix::fs::symlink(&outside, &link).unwrap();
+
+        let mut files = FileTable::default();
+        files.allow(&allowed).unwrap();
+        let link = link.into_os_string().into_string().unwrap();
+        for mode in [FILE_READ, FILE_WRITE, FILE_APPEND] {
+            assert_eq!(files.open(link.as_bytes(), mode), None);
+        }
+        assert!(!outside.exists());
+
+        // a file saved in a snapshot that's since been replaced with a dangling link
+        let path = allowed.join("file");
+        std::fs::write(&path, "file").unwrap();
+        let fd = files
+            .open(path.as_os_str().as_encoded_bytes(), FILE_APPEND)
+            .unwrap();
+        let mut writer = Writer::new();
+        files.save(&mut writer);
+        let snapshot = writer.finish();
+        std::fs::remove_file(&path).unwrap();
+        std::os::unix::fs::symlink(&outside, &path).unwrap();
+        assert!(files.reopen(&mut snapshot.reader()).is_err());
+        assert!(!outside.exists());
+        assert!(files.close(fd));
+
+        std::fs::remove_dir_all(directory).unwrap();
+    }
+}
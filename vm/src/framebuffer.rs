@@ -0,0 +1,103 @@
+//! Framebuffer of [`FRAMEBUFFER_WIDTH`] by [`FRAMEBUFFER_HEIGHT`] pixels, for drawing graphics.
+//!
+//! Each pixel is one byte, in row order from the top left, holding a colour as `RRRGGGBB`. Loads
+//! read pixels back, and stores draw them. The CLI maps it at
+//! [`FRAMEBUFFER_BASE`](shared::abi::FRAMEBUFFER_BASE) with `run --display`.
+
+use crate::device::Device;
+
+pub const FRAMEBUFFER_WIDTH: usize = 64;
+pub const FRAMEBUFFER_HEIGHT: usize = 64;
+
+#[derive(Debug, Clone)]
+pub struct Framebuffer {
+    pixels: Vec<u8>,
+    /// Whether any pixel has been drawn since the host last took a frame
+    changed: bool,
+}
+
+impl Default for Framebuffer {
+    fn default() -> Self {
+        Self {
+            pixels: vec![0; FRAMEBUFFER_WIDTH * FRAMEBUFFER_HEIGHT],
+            changed: true,
+        }
+    }
+}
+
+impl Framebuffer {
+    /// Pixels as the program drew them, one byte each
+    pub fn pixels(&self) -> &[u8] {
+        &self.pixels
+    }
+
+    /// Pixels as `0RGB` words, the layout most windowing libraries take, or None if nothing has
+    /// been drawn since the last frame was taken
+    pub fn take_frame(&mut self) -> Option<Vec<u32>> {
+        if !std::mem::take(&mut self.changed) {
+            return None;
+        }
+
+        Some(self.pixels.iter().map(|&pixel| to_rgb(pixel)).collect())
+    }
+}
+
+/// Expands an `RRRGGGBB` colour into a `0RGB` word, so the brightest value of each channel is 255
+fn to_rgb(pixel: u8) -> u32 {
+    let red = (pixel >> 5) as u32 * 255 / 7;
+    let green = (pixel >> 2 & 0b111) as u32 * 255 / 7;
+    let blue = (pixel & 0b11) as u32 * 255 / 3;
+
+    red << 16 | green << 8 | blue
+}
+
+impl Device for Framebuffer {
+    fn size(&self) -> usize {
+        self.pixels.len()
+    }
+
+    fn load(&mut self, offset: usize) -> u8 {
+        self.pixels[offset]
+    }
+
+    fn store(&mut self, offset: usize, value: u8) {
+        self.pixels[offset] = value;
+        self.changed = true;
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.pixels.clone()
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if state.len() == self.pixels.len() {
+            self.pixels.copy_from_slice(state);
+            self.changed = true;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_framebuffer() {
+        let mut framebuffer = Framebuffer::default();
+        assert_eq!(
+            framebuffer.take_frame().map(|frame| frame.len()),
+            Some(64 * 64)
+        );
+        assert_eq!(framebuffer.take_frame(), None);
+
+        framebuffer.store(65, 0b1110_0000);
+        framebuffer.store(66, 0b0001_1100);
+        framebuffer.store(67, 0b0000_0011);
+        framebuffer.store(68, 0xFF);
+        assert_eq!(framebuffer.load(65), 0b1110_0000);
+
+        let frame = framebuffer.take_frame().unwrap();
+        assert_eq!(frame[64..69], [0, 0xFF0000, 0xFF00, 0xFF, 0xFFFFFF]);
+        assert_eq!(framebuffer.take_frame(), None);
+    }
+}
@@ -0,0 +1,82 @@
+//! Side effects of the most recently executed instructions, recorded so a debugger can step
+//! backwards through a program.
+//!
+//! Before each instruction runs, the registers, flags and program counter are saved, and memory
+//! journals the bytes every store overwrites along with the heap's size and allocator. Once it
+//! has run, only the registers it changed are kept. Steps are kept in a ring buffer, so only the
+//! last few can be undone. Output already printed, files, objects and device state can't be taken
+//! back, so they're left as they are.
+
+use crate::allocator::Allocator;
+use crate::shadow_stack::Frame;
+use std::collections::VecDeque;
+
+/// Memory overwritten while an instruction ran
+#[derive(Debug, Default)]
+pub(crate) struct Journal {
+    /// Address and previous contents of every store, in the order they happened
+    pub(crate) writes: Vec<(usize, Vec<u8>)>,
+    /// Bytes on the heap before the instruction ran
+    pub(crate) heap_size: usize,
+    /// Allocator before the instruction ran, if the instruction used it
+    pub(crate) allocator: Option<Allocator>,
+}
+
+/// State of the VM before an instruction ran, enough to undo it
+#[derive(Debug)]
+pub(crate) struct Step<W> {
+    pub(crate) pc: usize,
+    pub(crate) instruction_pc: usize,
+    /// Registers the instruction changed, with their previous values
+    pub(crate) registers: Vec<(u8, W)>,
+    pub(crate) equality_flag: bool,
+    pub(crate) remainder: W,
+    pub(crate) steps: u64,
+    pub(crate) exit_status: i32,
+    pub(crate) interrupt_vector: Option<usize>,
+    pub(crate) in_interrupt: bool,
+    /// Calls that hadn't returned, if the instruction called or returned from a routine
+    pub(crate) frames: Option<Vec<Frame>>,
+    pub(crate) journal: Journal,
+}
+
+/// Most recent steps, oldest first
+#[derive(Debug)]
+pub(crate) struct History<W> {
+    capacity: usize,
+    steps: VecDeque<Step<W>>,
+}
+
+impl<W> History<W> {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            steps: VecDeque::new(),
+        }
+    }
+
+    /// Records a step, forgetting the oldest if the history is full
+    pub(crate) fn push(&mut self, step: Step<W>) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.steps.len() == self.capacity {
+            self.steps.pop_front();
+        }
+
+        self.steps.push_back(step);
+    }
+
+    /// Removes the most recent step
+    pub(crate) fn pop(&mut self) -> Option<Step<W>> {
+        self.steps.pop_back()
+    }
+
+    pub(crate) fn len(&self) -> usize {
+        self.steps.len()
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.steps.clear();
+    }
+}
@@ -1,58 +1,80 @@
-use num_traits::cast::FromPrimitive;
+use crate::errors::VmError;
 use shared::Opcode;
-use std::collections::VecDeque;
 
 /// Entire instruction for VM
-#[derive(Debug, PartialEq)]
+#[derive(Debug, PartialEq, Clone, Copy)]
 pub struct Instruction {
     pub opcode: Opcode,
-    buffer: VecDeque<u8>,
+    operands: [u8; 3],
+    /// Index of the next operand byte to be read
+    cursor: usize,
 }
 
 impl Instruction {
-    /// Creates an instruction with an internal buffer for reading operand values.
+    /// Decodes an instruction from its first 4 bytes, ready for reading operand values.
     /// Returns None if less than 4 bytes given
     pub fn from<T: AsRef<[u8]>>(slice: T) -> Option<Self> {
-        let slice = slice.as_ref();
-        if slice.len() < 4 {
-            return None;
-        }
+        let bytes: [u8; 4] = slice.as_ref().get(..4)?.try_into().unwrap();
+        let [opcode, operands @ ..] = bytes;
 
-        let opcode = Opcode::from_u8(slice[0]).unwrap_or(Opcode::IGL);
+        Some(Self {
+            opcode: Opcode::from_encoding(opcode).unwrap_or(Opcode::IGL),
+            operands,
+            cursor: 0,
+        })
+    }
 
-        let mut buffer = VecDeque::with_capacity(3);
-        buffer.extend(slice[1..].iter());
+    /// Operand bytes not yet read, padded with zeroes
+    pub fn operands(&self) -> [u8; 3] {
+        let mut operands = [0; 3];
+        operands[..3 - self.cursor].copy_from_slice(&self.operands[self.cursor..]);
 
-        Some(Self { opcode, buffer })
+        operands
     }
 
-    /// Reads u8 from internal buffer.
-    /// Will panic if buffer is empty.
+    /// Reads the next operand byte.
+    /// Will panic if every operand byte has been read.
     pub fn next_u8(&mut self) -> u8 {
-        self.buffer.pop_front().unwrap()
+        let byte = self.operands[self.cursor];
+        self.cursor += 1;
+
+        byte
     }
 
-    /// Reads u16 from internal buffer.
-    /// Will panic if buffer is empty.
+    /// Reads the next two operand bytes as a big endian u16.
+    /// Will panic if fewer than two operand bytes are left.
     pub fn next_u16(&mut self) -> u16 {
-        let bytes = [
-            self.buffer.pop_front().unwrap(),
-            self.buffer.pop_front().unwrap(),
-        ];
+        u16::from_be_bytes([self.next_u8(), self.next_u8()])
+    }
 
-        u16::from_be_bytes(bytes)
+    /// Reads the next two operand bytes as a big endian i16, such as a branch offset.
+    /// Will panic if fewer than two operand bytes are left.
+    pub fn next_i16(&mut self) -> i16 {
+        self.next_u16() as i16
     }
 
-    /// Reads u8 from internal buffer, and returns the value from the register with that index.
-    /// Will panic if buffer is empty.
-    pub fn next_register(&mut self, registers: &[i32]) -> i32 {
-        registers[self.next_u8() as usize]
+    /// Reads the next operand byte, and returns the value from the register with that index.
+    /// Will panic if every operand byte has been read.
+    pub fn next_register<W: Copy>(&mut self, registers: &[W]) -> Result<W, VmError> {
+        let index = self.next_u8();
+
+        registers
+            .get(index as usize)
+            .copied()
+            .ok_or(VmError::InvalidRegister { index })
     }
 
-    /// Reads u8 from internal buffer, and returns a mutable reference to the register with that index.
-    /// Will panic if buffer is empty.
-    pub fn next_register_mut<'a, 'b>(&'a mut self, registers: &'b mut [i32]) -> &'b mut i32 {
-        &mut registers[self.next_u8() as usize]
+    /// Reads the next operand byte, and returns a mutable reference to the register with that index.
+    /// Will panic if every operand byte has been read.
+    pub fn next_register_mut<'a, W>(
+        &mut self,
+        registers: &'a mut [W],
+    ) -> Result<&'a mut W, VmError> {
+        let index = self.next_u8();
+
+        registers
+            .get_mut(index as usize)
+            .ok_or(VmError::InvalidRegister { index })
     }
 }
 
@@ -66,4 +88,15 @@ mod tests {
 
         assert_eq!(instruction.unwrap().opcode, Opcode::HLT);
     }
+
+    #[test]
+    fn test_operands() {
+        let mut instruction = Instruction::from([64, 1, 0x12, 0x34, 0xFF]).unwrap();
+
+        assert_eq!(instruction.opcode, Opcode::ADDI);
+        assert_eq!(instruction.next_u8(), 1);
+        assert_eq!(instruction.operands(), [0x12, 0x34, 0]);
+        assert_eq!(instruction.next_u16(), 0x1234);
+        assert_eq!(Instruction::from([64, 1, 0]), None);
+    }
 }
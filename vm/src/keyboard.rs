@@ -0,0 +1,195 @@
+//! Keyboard queueing key presses for a program to poll, or to take interrupts for.
+//!
+//! | offset | register | access                                                                 |
+//! |--------|----------|------------------------------------------------------------------------|
+//! | 0      | data     | loads take the oldest key pressed that hasn't been read (0 if none)    |
+//! | 1      | status   | bit 0 is set while keys are waiting                                    |
+//! | 2      | last     | loads the last key pressed, whether or not it has been read (0 if none) |
+//! | 3      | control  | setting bit 0 enables the key interrupt                                |
+//!
+//! Keys are ASCII, with the arrow keys given codes from [`KEY_UP`] upwards. The host presses keys
+//! with [`Keyboard::press`], and tests can script them with [`Keyboard::scripted`] so they arrive
+//! at the same point in a program every run. The CLI maps it at
+//! [`KEYBOARD_BASE`](shared::abi::KEYBOARD_BASE).
+
+use crate::device::Device;
+use std::collections::VecDeque;
+
+pub const KEYBOARD_DATA: usize = 0;
+pub const KEYBOARD_STATUS: usize = 1;
+pub const KEYBOARD_LAST: usize = 2;
+pub const KEYBOARD_CONTROL: usize = 3;
+
+/// Status bit set while keys are waiting
+pub const KEYBOARD_WAITING: u8 = 0b01;
+/// Control bit enabling the key interrupt
+pub const KEYBOARD_INTERRUPT: u8 = 0b01;
+
+pub const KEY_UP: u8 = 0x80;
+pub const KEY_DOWN: u8 = 0x81;
+pub const KEY_LEFT: u8 = 0x82;
+pub const KEY_RIGHT: u8 = 0x83;
+
+#[derive(Debug, Default, Clone)]
+pub struct Keyboard {
+    /// Keys pressed that the program hasn't read yet
+    waiting: VecDeque<u8>,
+    last: u8,
+    control: u8,
+    /// Keys still to be pressed, each after the status register has been read a number of times
+    /// since the previous one
+    script: VecDeque<(u32, u8)>,
+    /// Reads of the status register since the last scripted key was pressed
+    polls: u32,
+}
+
+impl Keyboard {
+    /// Creates a keyboard pressing each key once the program has read the status register the
+    /// given number of times since the previous key was pressed, or since it was created. Programs
+    /// waiting for a key poll the status register, so keys arrive at the same point in a program
+    /// every run without depending on how fast it runs
+    pub fn scripted(script: impl IntoIterator<Item = (u32, u8)>) -> Self {
+        let mut keyboard = Self {
+            script: script.into_iter().collect(),
+            ..Self::default()
+        };
+        keyboard.press_due();
+
+        keyboard
+    }
+
+    /// Queues a key for the program to read
+    pub fn press(&mut self, key: u8) {
+        self.waiting.push_back(key);
+        self.last = key;
+    }
+
+    /// Presses the scripted keys whose number of polls has been reached
+    fn press_due(&mut self) {
+        while let Some(&(polls, key)) = self.script.front() {
+            if self.polls < polls {
+                break;
+            }
+
+            self.script.pop_front();
+            self.polls = 0;
+            self.press(key);
+        }
+    }
+}
+
+impl Device for Keyboard {
+    fn size(&self) -> usize {
+        4
+    }
+
+    fn load(&mut self, offset: usize) -> u8 {
+        match offset {
+            KEYBOARD_DATA => self.waiting.pop_front().unwrap_or(0),
+            KEYBOARD_STATUS => {
+                self.polls += 1;
+                self.press_due();
+
+                match self.waiting.is_empty() {
+                    true => 0,
+                    false => KEYBOARD_WAITING,
+                }
+            }
+            KEYBOARD_LAST => self.last,
+            KEYBOARD_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn store(&mut self, offset: usize, value: u8) {
+        if offset == KEYBOARD_CONTROL {
+            self.control = value;
+        }
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.control & KEYBOARD_INTERRUPT != 0 && !self.waiting.is_empty()
+    }
+
+    /// The last key and control registers, the polls since the last scripted key, the keys waiting
+    /// to be read with their count, then the rest of the script
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.last, self.control];
+        state.extend(self.polls.to_be_bytes());
+        state.extend((self.waiting.len() as u32).to_be_bytes());
+        state.extend(&self.waiting);
+        for &(polls, key) in &self.script {
+            state.extend(polls.to_be_bytes());
+            state.push(key);
+        }
+
+        state
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        let word = |bytes: &[u8]| u32::from_be_bytes(bytes.try_into().unwrap());
+        let Some((&[last, control], rest)) = state.split_first_chunk::<2>() else {
+            return;
+        };
+        let Some((polls, rest)) = rest.split_first_chunk::<4>() else {
+            return;
+        };
+        let Some((waiting, rest)) = rest.split_first_chunk::<4>() else {
+            return;
+        };
+        let waiting = word(waiting) as usize;
+        if rest.len() < waiting || !(rest.len() - waiting).is_multiple_of(5) {
+            return;
+        }
+        let (waiting, script) = rest.split_at(waiting);
+
+        self.last = last;
+        self.control = control;
+        self.polls = word(polls);
+        self.waiting = waiting.iter().copied().collect();
+        self.script = script
+            .chunks(5)
+            .map(|entry| (word(&entry[..4]), entry[4]))
+            .collect();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_keyboard() {
+        let mut keyboard = Keyboard::default();
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), 0);
+        assert_eq!(keyboard.load(KEYBOARD_LAST), 0);
+
+        keyboard.press(b'a');
+        keyboard.press(KEY_UP);
+        assert!(!keyboard.interrupt_pending());
+        keyboard.store(KEYBOARD_CONTROL, KEYBOARD_INTERRUPT);
+        assert!(keyboard.interrupt_pending());
+
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), KEYBOARD_WAITING);
+        assert_eq!(keyboard.load(KEYBOARD_DATA), b'a');
+        assert_eq!(keyboard.load(KEYBOARD_LAST), KEY_UP);
+        assert_eq!(keyboard.load(KEYBOARD_DATA), KEY_UP);
+        assert_eq!(keyboard.load(KEYBOARD_DATA), 0);
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), 0);
+        assert!(!keyboard.interrupt_pending());
+        assert_eq!(keyboard.load(KEYBOARD_LAST), KEY_UP);
+    }
+
+    #[test]
+    fn test_scripted() {
+        let mut keyboard = Keyboard::scripted([(0, b'x'), (2, b'y'), (1, b'z')]);
+        assert_eq!(keyboard.load(KEYBOARD_DATA), b'x');
+
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), 0);
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), KEYBOARD_WAITING);
+        assert_eq!(keyboard.load(KEYBOARD_DATA), b'y');
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), KEYBOARD_WAITING);
+        assert_eq!(keyboard.load(KEYBOARD_DATA), b'z');
+        assert_eq!(keyboard.load(KEYBOARD_STATUS), 0);
+    }
+}
@@ -1,4 +1,59 @@
+mod allocator;
+mod cache;
+mod cluster;
+mod config;
+mod device;
+mod errors;
+mod files;
+mod framebuffer;
+mod history;
 mod instruction;
+mod keyboard;
+mod logger;
+mod machine;
+mod memory;
+mod objects;
+mod output;
+mod profile;
+mod program;
+mod shadow_stack;
+mod snapshot;
+mod sources;
+mod syscall;
+#[cfg(feature = "timing")]
+mod timing;
+mod tracer;
+mod uart;
+mod verify;
 mod vm;
+mod word;
 
-pub use vm::VM;
+pub use allocator::{Allocation, Allocator};
+pub use cluster::{Cluster, VmHandle};
+pub use config::{Limit, VMConfig};
+pub use device::Device;
+pub use errors::{SnapshotError, VerifyError, VmError};
+pub use files::{FILE_APPEND, FILE_READ, FILE_WRITE};
+pub use framebuffer::{Framebuffer, FRAMEBUFFER_HEIGHT, FRAMEBUFFER_WIDTH};
+pub use keyboard::{
+    Keyboard, KEYBOARD_CONTROL, KEYBOARD_DATA, KEYBOARD_INTERRUPT, KEYBOARD_LAST, KEYBOARD_STATUS,
+    KEYBOARD_WAITING, KEY_DOWN, KEY_LEFT, KEY_RIGHT, KEY_UP,
+};
+pub use logger::{LogLevel, LogRecord, Logger};
+pub use machine::{Cached, Machine};
+pub use memory::{HighWaterMarks, Memory, Region};
+pub use objects::{Object, ObjectHeap, HANDLE_TAG, OBJECT_ARRAY, OBJECT_STRING};
+pub use output::{SharedBuffer, Tee};
+pub use profile::Profile;
+pub use program::Program;
+pub use shadow_stack::Frame;
+pub use snapshot::{Snapshot, SNAPSHOT_VERSION};
+pub use sources::{Clock, Random, SystemClock, XorShift};
+pub use syscall::Syscall;
+#[cfg(feature = "timing")]
+pub use timing::{OpcodeTiming, OpcodeTimings};
+pub use tracer::{decode_trace, RingTracer, TraceEncoder, TraceEntry, TraceStep, Tracer};
+pub use uart::{Uart, UART_CONTROL, UART_DATA, UART_RECEIVED, UART_RECEIVE_INTERRUPT, UART_STATUS};
+pub use verify::{validate, verify, BadJump, VerifiedImage};
+pub use vm::{RunStatus, VM};
+pub use word::Word;
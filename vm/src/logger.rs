@@ -0,0 +1,90 @@
+use std::fmt::{Display, Formatter};
+use std::str::FromStr;
+
+/// Severity of a message logged by a program with `LOGD`/`LOGR`, most severe first
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum LogLevel {
+    Error = 0,
+    Warn = 1,
+    Info = 2,
+    Debug = 3,
+    Trace = 4,
+}
+
+impl LogLevel {
+    /// Decodes the level operand of a log instruction
+    pub fn from_u8(level: u8) -> Option<Self> {
+        match level {
+            0 => Some(LogLevel::Error),
+            1 => Some(LogLevel::Warn),
+            2 => Some(LogLevel::Info),
+            3 => Some(LogLevel::Debug),
+            4 => Some(LogLevel::Trace),
+            _ => None,
+        }
+    }
+}
+
+impl Display for LogLevel {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            LogLevel::Error => "error",
+            LogLevel::Warn => "warn",
+            LogLevel::Info => "info",
+            LogLevel::Debug => "debug",
+            LogLevel::Trace => "trace",
+        };
+
+        write!(f, "{name}")
+    }
+}
+
+impl FromStr for LogLevel {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match &s.to_lowercase()[..] {
+            "error" => Ok(LogLevel::Error),
+            "warn" => Ok(LogLevel::Warn),
+            "info" => Ok(LogLevel::Info),
+            "debug" => Ok(LogLevel::Debug),
+            "trace" => Ok(LogLevel::Trace),
+            _ => Err(format!(
+                "unknown log level '{s}', expected error, warn, info, debug or trace"
+            )),
+        }
+    }
+}
+
+/// Message logged by a program
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct LogRecord<'a> {
+    pub level: LogLevel,
+    pub message: &'a str,
+    /// Address of the log instruction
+    pub pc: usize,
+    /// Instructions executed since the program was started, including the log instruction
+    pub steps: u64,
+}
+
+impl Display for LogRecord<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "[{} {:#06X} step {}] {}",
+            self.level, self.pc, self.steps, self.message
+        )
+    }
+}
+
+/// Hook receiving messages logged by the program, so they can be routed through the host's own
+/// logging and filtered by level
+pub trait Logger {
+    fn log(&mut self, record: &LogRecord);
+}
+
+impl<F: FnMut(&LogRecord)> Logger for F {
+    fn log(&mut self, record: &LogRecord) {
+        self(record)
+    }
+}
@@ -0,0 +1,203 @@
+//! Backends that can run a program, so the REPL and CLI don't depend on how it's executed.
+//!
+//! [`VM`] interprets one instruction at a time and [`Cached`] runs the same VM from pre-decoded
+//! basic blocks. Other backends only need to load, step and expose their registers and memory.
+//! Debugging features such as breakpoints, snapshots and recording belong to the VM itself, and
+//! are reached through [`Machine::vm`] on backends built on one.
+
+use crate::errors::VmError;
+use crate::memory::Memory;
+use crate::program::Program;
+use crate::vm::VM;
+
+/// Something that runs programs
+pub trait Machine {
+    /// Short name of the backend, for messages
+    fn name(&self) -> &'static str;
+
+    /// Replaces memory with a parsed program, ready to be started
+    fn load_program(&mut self, program: Program);
+
+    /// Moves the program counter to the start of the code section, ready to run the program
+    fn start(&mut self) -> Result<(), VmError>;
+
+    /// Executes a single instruction. Returns a bool indicating if another instruction can be ran
+    /// afterwards
+    fn step(&mut self) -> Result<bool, VmError>;
+
+    /// Runs the program from the start until completion, ignoring breakpoints
+    fn run(&mut self) -> Result<(), VmError>;
+
+    /// CPU registers
+    fn registers(&self) -> &[i32];
+
+    fn registers_mut(&mut self) -> &mut [i32];
+
+    /// Equality from the last comparison instruction
+    fn equality_flag(&self) -> bool;
+
+    /// Memory holding the program, the heap and the stack
+    fn memory(&self) -> &Memory;
+
+    /// Memory, for mapping devices and extending the program. Its bytes can only be changed with
+    /// `poke`, so backends that decode ahead see every change
+    fn memory_mut(&mut self) -> &mut Memory;
+
+    /// Writes bytes to memory regardless of protection, for patching a running program
+    fn poke(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError>;
+
+    /// Address of the next instruction to be executed
+    fn pc(&self) -> usize;
+
+    /// Moves the program counter, for running code appended to the program
+    fn set_pc(&mut self, pc: usize);
+
+    /// Status the program exited with, set by `EXIT`
+    fn exit_status(&self) -> i32;
+
+    /// VM behind the backend, for debugging features it provides, or None if there isn't one
+    fn vm(&self) -> Option<&VM> {
+        None
+    }
+
+    fn vm_mut(&mut self) -> Option<&mut VM> {
+        None
+    }
+}
+
+impl Machine for VM {
+    fn name(&self) -> &'static str {
+        "plain"
+    }
+
+    fn load_program(&mut self, program: Program) {
+        self.load(program);
+    }
+
+    fn start(&mut self) -> Result<(), VmError> {
+        VM::start(self)
+    }
+
+    fn step(&mut self) -> Result<bool, VmError> {
+        self.run_once()
+    }
+
+    fn run(&mut self) -> Result<(), VmError> {
+        VM::run(self)
+    }
+
+    fn registers(&self) -> &[i32] {
+        VM::registers(self)
+    }
+
+    fn registers_mut(&mut self) -> &mut [i32] {
+        VM::registers_mut(self)
+    }
+
+    fn equality_flag(&self) -> bool {
+        VM::equality_flag(self)
+    }
+
+    fn memory(&self) -> &Memory {
+        VM::memory(self)
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory {
+        VM::memory_mut(self)
+    }
+
+    fn poke(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        VM::poke(self, address, bytes)
+    }
+
+    fn pc(&self) -> usize {
+        VM::pc(self)
+    }
+
+    fn set_pc(&mut self, pc: usize) {
+        VM::set_pc(self, pc);
+    }
+
+    fn exit_status(&self) -> i32 {
+        VM::exit_status(self)
+    }
+
+    fn vm(&self) -> Option<&VM> {
+        Some(self)
+    }
+
+    fn vm_mut(&mut self) -> Option<&mut VM> {
+        Some(self)
+    }
+}
+
+/// VM that runs programs from pre-decoded basic blocks with `run_cached`. Stepping still executes
+/// one instruction at a time
+#[derive(Default)]
+pub struct Cached(pub VM);
+
+impl Machine for Cached {
+    fn name(&self) -> &'static str {
+        "cached"
+    }
+
+    fn load_program(&mut self, program: Program) {
+        self.0.load(program);
+    }
+
+    fn start(&mut self) -> Result<(), VmError> {
+        self.0.start()
+    }
+
+    fn step(&mut self) -> Result<bool, VmError> {
+        self.0.run_once()
+    }
+
+    fn run(&mut self) -> Result<(), VmError> {
+        self.0.run_cached()
+    }
+
+    fn registers(&self) -> &[i32] {
+        self.0.registers()
+    }
+
+    fn registers_mut(&mut self) -> &mut [i32] {
+        self.0.registers_mut()
+    }
+
+    fn equality_flag(&self) -> bool {
+        self.0.equality_flag()
+    }
+
+    fn memory(&self) -> &Memory {
+        self.0.memory()
+    }
+
+    fn memory_mut(&mut self) -> &mut Memory {
+        self.0.memory_mut()
+    }
+
+    fn poke(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        self.0.poke(address, bytes)
+    }
+
+    fn pc(&self) -> usize {
+        self.0.pc()
+    }
+
+    fn set_pc(&mut self, pc: usize) {
+        self.0.set_pc(pc);
+    }
+
+    fn exit_status(&self) -> i32 {
+        self.0.exit_status()
+    }
+
+    fn vm(&self) -> Option<&VM> {
+        Some(&self.0)
+    }
+
+    fn vm_mut(&mut self) -> Option<&mut VM> {
+        Some(&mut self.0)
+    }
+}
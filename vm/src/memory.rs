@@ -0,0 +1,1112 @@
+use crate::allocator::Allocator;
+use crate::device::{Device, MappedDevice};
+use crate::errors::{SnapshotError, VmError};
+use crate::history::Journal;
+use crate::program::Program;
+use crate::snapshot::{Reader, Writer};
+use shared::abi::{DEVICE_BASE, STACK_GUARD_SIZE, STACK_SIZE, STACK_TOP};
+use shared::symbols::{read_symbols, DebugSymbol, SymbolKind};
+use shared::{
+    BSS_SECTION_FIELD, ENTRY_POINT_FIELD, PIE_FORMAT_VERSION, PIE_HEADER_PREFIX, PIE_WORD_SIZE,
+    RODATA_SECTION_FIELD,
+};
+use std::cell::RefCell;
+use std::ops::Range;
+use std::rc::Rc;
+
+/// Region of the address space an address belongs to
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Region {
+    /// Program header, read-only
+    Header,
+    /// Data section, read-write
+    Data,
+    /// Read-only data section
+    Rodata,
+    /// Code section, read-only unless code writes are enabled
+    Code,
+    /// Zeroed space reserved after the program image when it's loaded, read-write
+    Bss,
+    /// Heap, addressed directly after the program image and bss, read-write
+    Heap,
+    /// Stack, addressed directly below the stack top, read-write
+    Stack,
+    /// Registers of a memory-mapped device, only accessible by typed loads and stores
+    Device,
+}
+
+/// Furthest each region was used while running, for sizing resource limits
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct HighWaterMarks {
+    /// Most bytes below the stack top written to
+    pub stack_depth: usize,
+    /// Most bytes held by live heap allocations at once
+    pub heap_peak: usize,
+    /// Highest address written in the data section, if any were
+    pub data_written: Option<usize>,
+}
+
+/// Section ranges read from the program header
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) struct Sections {
+    pub data: Range<usize>,
+    pub rodata: Range<usize>,
+    pub code: Range<usize>,
+    /// Addressed after the image rather than stored in it
+    pub bss: Range<usize>,
+}
+
+/// Generates accessors reading and writing an integer type as big endian bytes, with the same
+/// protection as `read` and `write`
+macro_rules! accessors {
+    ($read:ident, $write:ident, $ty:ty) => {
+        #[doc = concat!("Reads a big endian `", stringify!($ty), "` starting at address")]
+        pub fn $read(&self, address: usize) -> Result<$ty, VmError> {
+            self.check_alignment(address, size_of::<$ty>())?;
+            let mut bytes = [0; size_of::<$ty>()];
+            self.read_into(address, &mut bytes)?;
+
+            Ok(<$ty>::from_be_bytes(bytes))
+        }
+
+        #[doc = concat!("Writes a big endian `", stringify!($ty), "` starting at address")]
+        pub(crate) fn $write(&mut self, address: usize, value: $ty) -> Result<(), VmError> {
+            self.check_alignment(address, size_of::<$ty>())?;
+
+            self.write(address, &value.to_be_bytes())
+        }
+    };
+}
+
+/// VM memory, made up of the loaded program image followed by the bss and heap, and a separate
+/// stack.
+///
+/// Until the sections have been mapped from the header, the whole image is treated as writable
+/// code so headerless bytecode (such as instructions entered at the REPL) can still run. Once
+/// mapped, every access must fall entirely within a single region, and stores into the header or
+/// code section fault.
+#[derive(Debug)]
+pub struct Memory {
+    /// Header, data and code sections as loaded
+    image: Vec<u8>,
+    /// Bss section, zeroed when the sections are mapped
+    bss: Vec<u8>,
+    /// Heap memory, starting at the address directly after the image and bss
+    heap: Vec<u8>,
+    /// Stack memory, ending at the stack top
+    stack: Vec<u8>,
+    /// Section ranges, or None if the header hasn't been read yet
+    sections: Option<Sections>,
+    /// Whether stores may write into the code section
+    code_writable: bool,
+    /// Whether typed accesses must be aligned to their size
+    alignment_checked: bool,
+    /// Live allocations and freed blocks of the heap
+    allocator: Allocator,
+    /// Symbols read from the program's symbol section, sorted by value
+    symbols: Vec<DebugSymbol>,
+    /// Devices mapped above the stack, in address order
+    devices: Vec<MappedDevice>,
+    /// Stack depth and highest data address written so far. The heap peak is tracked by the
+    /// allocator
+    high_water: HighWaterMarks,
+    /// Bytes overwritten by stores since the journal was started, if it has been
+    journal: Option<Journal>,
+}
+
+impl Default for Memory {
+    fn default() -> Self {
+        Self::new(Vec::new())
+    }
+}
+
+impl From<Program> for Memory {
+    /// Memory holding a parsed program with its sections mapped and bss zeroed
+    fn from(program: Program) -> Self {
+        let bss = vec![0; program.sections.bss.len()];
+        let (sections, symbols) = (program.sections.clone(), program.symbols.clone());
+
+        Self {
+            bss,
+            sections: Some(sections),
+            symbols,
+            ..Self::new(program.into_image())
+        }
+    }
+}
+
+impl Memory {
+    /// Creates memory holding the given program image, with an empty heap and zeroed stack
+    pub fn new(image: Vec<u8>) -> Self {
+        Self {
+            image,
+            bss: Vec::new(),
+            heap: Vec::new(),
+            stack: vec![0; STACK_SIZE],
+            sections: None,
+            code_writable: false,
+            alignment_checked: false,
+            allocator: Allocator::default(),
+            symbols: Vec::new(),
+            devices: Vec::new(),
+            high_water: HighWaterMarks::default(),
+            journal: None,
+        }
+    }
+
+    /// Reads the section layout from the program header, protecting the header and code section
+    /// and reserving the bss. Fails if the program doesn't start with the EPIE magic, or uses
+    /// another format version or word size
+    pub fn map_sections(&mut self) -> Result<(), VmError> {
+        let (sections, mut symbols) = read_header(&self.image)?;
+        symbols.sort_by_key(|symbol| symbol.value);
+
+        self.bss = vec![0; sections.bss.len()];
+        self.sections = Some(sections);
+        self.symbols = symbols;
+
+        Ok(())
+    }
+
+    /// Zeroes the bss section again, ready for the program to be restarted
+    pub(crate) fn clear_bss(&mut self) {
+        self.bss.fill(0);
+    }
+
+    /// Sets whether stores may write into the code section, allowing self-modifying programs
+    pub fn set_code_writable(&mut self, writable: bool) {
+        self.code_writable = writable;
+    }
+
+    /// Sets whether 2, 4 and 8 byte accesses through the typed accessors, such as `read_u32`, fault
+    /// unless their address is a multiple of their size
+    pub fn set_alignment_checked(&mut self, checked: bool) {
+        self.alignment_checked = checked;
+    }
+
+    pub fn alignment_checked(&self) -> bool {
+        self.alignment_checked
+    }
+
+    /// Address range of the data section, if the sections have been mapped
+    pub fn data_section(&self) -> Option<Range<usize>> {
+        self.sections.as_ref().map(|sections| sections.data.clone())
+    }
+
+    /// Address range of the read-only data section, if the sections have been mapped. Empty if the
+    /// program doesn't have one
+    pub fn rodata_section(&self) -> Option<Range<usize>> {
+        self.sections
+            .as_ref()
+            .map(|sections| sections.rodata.clone())
+    }
+
+    /// Address range of the code section, if the sections have been mapped
+    pub fn code_section(&self) -> Option<Range<usize>> {
+        self.sections.as_ref().map(|sections| sections.code.clone())
+    }
+
+    /// Address range of the bss section, if the sections have been mapped. Empty if the program
+    /// doesn't have one
+    pub fn bss_section(&self) -> Option<Range<usize>> {
+        self.sections.as_ref().map(|sections| sections.bss.clone())
+    }
+
+    /// Address execution starts at, which is the start of the code section unless the header gives
+    /// an entry point. None if the sections haven't been mapped
+    pub fn entry_point(&self) -> Option<usize> {
+        self.code_section()
+            .map(|code| code.start + entry_offset(&self.image))
+    }
+
+    /// Bytes in each of the program's words, as declared by its header, or None if the sections
+    /// haven't been mapped
+    pub fn word_size(&self) -> Option<usize> {
+        self.sections.as_ref().map(|_| match self.image[5] {
+            0 => PIE_WORD_SIZE as usize,
+            size => size as usize,
+        })
+    }
+
+    /// Symbols from the program's symbol section, sorted by value. Empty if the program has none
+    /// or the sections haven't been mapped
+    pub fn symbols(&self) -> &[DebugSymbol] {
+        &self.symbols
+    }
+
+    /// Finds the closest label at or before address, returning its name and how far past it the
+    /// address is
+    pub fn symbolize(&self, address: usize) -> Option<(&str, usize)> {
+        self.symbols
+            .iter()
+            .rev()
+            .filter(|symbol| symbol.kind != SymbolKind::Constant)
+            .find(|symbol| symbol.value as usize <= address)
+            .map(|symbol| (symbol.name.as_str(), address - symbol.value as usize))
+    }
+
+    /// Returns the region containing address, if any
+    pub fn region(&self, address: usize) -> Option<Region> {
+        self.locate(address).map(|(region, _)| region)
+    }
+
+    /// Reads len bytes starting at address. Device registers can't be read as a slice, so fault
+    pub fn read(&self, address: usize, len: usize) -> Result<&[u8], VmError> {
+        let (region, range) = self.checked_range(address, len)?;
+
+        self.translate(region, range)
+    }
+
+    /// Fills bytes with those starting at address, loading them from a device if one is mapped
+    /// there
+    fn read_into(&self, address: usize, bytes: &mut [u8]) -> Result<(), VmError> {
+        let (region, range) = self.checked_range(address, bytes.len())?;
+        if region != Region::Device {
+            bytes.copy_from_slice(self.translate(region, range)?);
+            return Ok(());
+        }
+
+        let mapped = self.device(address).unwrap();
+        let mut device = mapped.device.borrow_mut();
+        for (offset, byte) in range.zip(bytes) {
+            *byte = device.load(offset - mapped.range.start);
+        }
+
+        Ok(())
+    }
+
+    accessors!(read_u8, write_u8, u8);
+    accessors!(read_u16, write_u16, u16);
+    accessors!(read_u32, write_u32, u32);
+    accessors!(read_u64, write_u64, u64);
+
+    /// Faults if alignment is checked and address isn't a multiple of size
+    fn check_alignment(&self, address: usize, size: usize) -> Result<(), VmError> {
+        match self.alignment_checked && !address.is_multiple_of(size) {
+            true => Err(VmError::UnalignedAccess { address, size }),
+            false => Ok(()),
+        }
+    }
+
+    /// Reads from address up to the end of the region containing it
+    pub fn read_to_region_end(&self, address: usize) -> Result<&[u8], VmError> {
+        let (region, range) = self.locate(address).ok_or_else(|| self.unmapped(address))?;
+
+        self.translate(region, address..range.end)
+    }
+
+    /// Reads a null terminated string starting at address, excluding the null byte
+    pub fn read_string(&self, address: usize) -> Result<&[u8], VmError> {
+        let bytes = self.read_to_region_end(address)?;
+        let end = bytes
+            .iter()
+            .position(|&byte| byte == 0)
+            .ok_or(VmError::InvalidAddress {
+                address: address + bytes.len(),
+            })?;
+
+        Ok(&bytes[..end])
+    }
+
+    /// Writes bytes starting at address, faulting if the region is read-only. Only the VM writes
+    /// through this, so it can drop any cached instructions the bytes overlap
+    pub(crate) fn write(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        let (region, range) = self.checked_range(address, bytes.len())?;
+
+        let writable = match region {
+            Region::Header | Region::Rodata => false,
+            Region::Code => self.code_writable || self.sections.is_none(),
+            _ => true,
+        };
+        if !writable {
+            return Err(VmError::WriteProtected { address });
+        }
+
+        match region {
+            Region::Stack => {
+                let depth = STACK_TOP - range.start;
+                self.high_water.stack_depth = self.high_water.stack_depth.max(depth);
+            }
+            Region::Data if !range.is_empty() => {
+                let last = range.end - 1;
+                self.high_water.data_written = self.high_water.data_written.max(Some(last));
+            }
+            _ => {}
+        }
+
+        if region == Region::Device {
+            let mapped = self.device(address).unwrap();
+            let mut device = mapped.device.borrow_mut();
+            for (offset, &byte) in range.zip(bytes) {
+                device.store(offset - mapped.range.start, byte);
+            }
+
+            return Ok(());
+        }
+
+        if self.journal.is_some() {
+            let previous = self.translate(region, range.clone())?.to_vec();
+            if let Some(journal) = &mut self.journal {
+                journal.writes.push((address, previous));
+            }
+        }
+        self.translate_mut(region, range)?.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// Writes bytes starting at address regardless of protection. Debuggers and hosts patching a
+    /// program use `VM::poke`, which also drops any cached instructions the bytes overlap
+    pub(crate) fn poke(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        let (region, range) = self.checked_range(address, bytes.len())?;
+        self.translate_mut(region, range)?.copy_from_slice(bytes);
+
+        Ok(())
+    }
+
+    /// The image up to the end of the code section, which instructions are fetched from
+    pub fn executable(&self) -> &[u8] {
+        match &self.sections {
+            Some(sections) => &self.image[..sections.code.end],
+            None => &self.image,
+        }
+    }
+
+    /// The loaded program image
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Mutable access to the loaded program image, bypassing protection and the instruction cache
+    #[cfg(test)]
+    pub(crate) fn image_mut(&mut self) -> &mut [u8] {
+        &mut self.image
+    }
+
+    /// Appends bytes to the image, growing the code section if it ends at the end of the image.
+    /// The heap is addressed after the image, so moves up by the number of bytes appended
+    pub fn extend(&mut self, bytes: &[u8]) {
+        if let Some(sections) = &mut self.sections {
+            if sections.code.end == self.image.len() {
+                sections.code.end += bytes.len();
+            }
+        }
+
+        self.image.extend_from_slice(bytes);
+    }
+
+    pub(crate) fn save(&self, writer: &mut Writer) {
+        writer.bytes(&self.image);
+        writer.bytes(&self.bss);
+        writer.bytes(&self.heap);
+        writer.bytes(&self.stack);
+
+        writer.bool(self.sections.is_some());
+        if let Some(sections) = &self.sections {
+            for range in [
+                &sections.data,
+                &sections.rodata,
+                &sections.code,
+                &sections.bss,
+            ] {
+                writer.usize(range.start);
+                writer.usize(range.end);
+            }
+        }
+        writer.bool(self.code_writable);
+        self.allocator.save(writer);
+    }
+
+    pub(crate) fn load(reader: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut memory = Self::new(reader.bytes()?.to_vec());
+        memory.bss = reader.bytes()?.to_vec();
+        memory.heap = reader.bytes()?.to_vec();
+        memory.stack = reader.bytes()?.to_vec();
+
+        if reader.bool()? {
+            let mut range = || Ok::<_, SnapshotError>(reader.usize()?..reader.usize()?);
+            let sections = Sections {
+                data: range()?,
+                rodata: range()?,
+                code: range()?,
+                bss: range()?,
+            };
+
+            let image_len = memory.image.len();
+            let bss_misplaced = !sections.bss.is_empty() && sections.bss.start < image_len;
+            if [&sections.data, &sections.rodata, &sections.code]
+                .iter()
+                .any(|range| range.start > range.end || range.end > image_len)
+                || bss_misplaced
+                || sections.bss.len() != memory.bss.len()
+            {
+                return Err(SnapshotError::Malformed);
+            }
+            memory.symbols = read_symbols(&memory.image).ok_or(SnapshotError::Malformed)?;
+            memory.symbols.sort_by_key(|symbol| symbol.value);
+            memory.sections = Some(sections);
+        }
+        memory.code_writable = reader.bool()?;
+        memory.allocator = Allocator::load(reader)?;
+
+        let heap_end = memory.heap_start() + memory.heap.len();
+        if memory.stack.len() != STACK_SIZE || heap_end > memory.stack_guard().start {
+            return Err(SnapshotError::Malformed);
+        }
+
+        Ok(memory)
+    }
+
+    /// Address the heap starts at, directly after the image and bss
+    pub fn heap_start(&self) -> usize {
+        match &self.sections {
+            Some(sections) => self.image.len().max(sections.bss.end),
+            None => self.image.len(),
+        }
+    }
+
+    /// Number of bytes allocated on the heap
+    pub fn heap_size(&self) -> usize {
+        self.heap.len()
+    }
+
+    /// Grows the heap by size zeroed bytes, returning the address of the first new byte. Returns
+    /// None if the heap would grow into the stack. The VM checks `max_heap` before calling this, so
+    /// it isn't public
+    pub(crate) fn grow_heap(&mut self, size: usize) -> Option<usize> {
+        let address = self.heap_start() + self.heap.len();
+        if address.checked_add(size)? > self.stack_guard().start {
+            return None;
+        }
+
+        self.heap.resize(self.heap.len() + size, 0);
+
+        Some(address)
+    }
+
+    /// How far the stack, heap and data section have been used by stores and allocations since
+    /// the memory was created
+    pub fn high_water_marks(&self) -> HighWaterMarks {
+        HighWaterMarks {
+            heap_peak: self.allocator.peak_bytes(),
+            ..self.high_water
+        }
+    }
+
+    /// Live allocations and freed blocks of the heap
+    pub fn allocator(&self) -> &Allocator {
+        &self.allocator
+    }
+
+    pub(crate) fn allocator_mut(&mut self) -> &mut Allocator {
+        if let Some(journal) = &mut self.journal {
+            journal
+                .allocator
+                .get_or_insert_with(|| self.allocator.clone());
+        }
+        &mut self.allocator
+    }
+
+    /// Maps a device's registers into memory starting at address, which must be at or above
+    /// [`DEVICE_BASE`] and clear of every other device
+    pub fn map_device(
+        &mut self,
+        address: usize,
+        device: Rc<RefCell<dyn Device>>,
+    ) -> Result<(), VmError> {
+        let range = address..address + device.borrow().size();
+        let index = self
+            .devices
+            .partition_point(|mapped| mapped.range.start < address);
+
+        let overlaps = |mapped: &MappedDevice| {
+            mapped.range.start < range.end && range.start < mapped.range.end
+        };
+        if address < DEVICE_BASE
+            || self.devices[index.saturating_sub(1)..]
+                .iter()
+                .take(2)
+                .any(overlaps)
+        {
+            return Err(VmError::DeviceOverlap { address });
+        }
+
+        self.devices.insert(index, MappedDevice { range, device });
+
+        Ok(())
+    }
+
+    /// Whether any mapped device is requesting an interrupt
+    pub fn interrupt_pending(&self) -> bool {
+        self.devices
+            .iter()
+            .any(|mapped| mapped.device.borrow().interrupt_pending())
+    }
+
+    /// Finds the device mapped at address
+    fn device(&self, address: usize) -> Option<&MappedDevice> {
+        if address < DEVICE_BASE {
+            return None;
+        }
+
+        let index = self
+            .devices
+            .partition_point(|mapped| mapped.range.end <= address);
+        self.devices
+            .get(index)
+            .filter(|mapped| mapped.range.contains(&address))
+    }
+
+    /// Saves the state of every mapped device, along with where it's mapped
+    pub(crate) fn save_devices(&self, writer: &mut Writer) {
+        writer.usize(self.devices.len());
+        for mapped in &self.devices {
+            writer.usize(mapped.range.start);
+            writer.bytes(&mapped.device.borrow().save_state());
+        }
+    }
+
+    /// Reads the device state saved by `save_devices`, checking the same devices are mapped at the
+    /// same addresses here. Nothing is given to the devices until `restore_devices`
+    pub(crate) fn load_devices<'a>(
+        &self,
+        reader: &mut Reader<'a>,
+    ) -> Result<Vec<&'a [u8]>, SnapshotError> {
+        if reader.usize()? != self.devices.len() {
+            return Err(SnapshotError::DeviceMismatch);
+        }
+
+        self.devices
+            .iter()
+            .map(|mapped| match reader.usize()? == mapped.range.start {
+                true => reader.bytes(),
+                false => Err(SnapshotError::DeviceMismatch),
+            })
+            .collect()
+    }
+
+    /// Gives each mapped device the state read by `load_devices`
+    pub(crate) fn restore_devices(&self, states: &[&[u8]]) {
+        for (mapped, state) in self.devices.iter().zip(states) {
+            mapped.device.borrow_mut().restore_state(state);
+        }
+    }
+
+    /// Carries settings belonging to the host, rather than the program, over from the memory
+    /// this replaces
+    pub(crate) fn keep_host_settings(&mut self, previous: &Memory) {
+        self.alignment_checked = previous.alignment_checked;
+        self.devices = previous.devices.clone();
+    }
+
+    /// Takes the heap and its allocations from the memory of a program this one replaces, if the
+    /// heap starts at the same address so addresses in it stay valid. Returns whether it was taken
+    pub(crate) fn take_heap(&mut self, previous: &mut Memory) -> bool {
+        if self.heap_start() != previous.heap_start() {
+            return false;
+        }
+
+        self.heap = std::mem::take(&mut previous.heap);
+        self.allocator = std::mem::take(&mut previous.allocator);
+        true
+    }
+
+    /// Starts recording the bytes overwritten by stores, and the heap before it changes, so they
+    /// can be put back with `undo`. Stores to devices aren't recorded
+    pub(crate) fn start_journal(&mut self) {
+        self.journal = Some(Journal {
+            heap_size: self.heap.len(),
+            ..Journal::default()
+        });
+    }
+
+    /// Stops recording, returning what was recorded since the journal was started
+    pub(crate) fn take_journal(&mut self) -> Option<Journal> {
+        self.journal.take()
+    }
+
+    /// Shrinks the heap and restores the allocator to how they were when a journal was started.
+    /// Overwritten bytes are put back separately, so cached instructions can be dropped
+    pub(crate) fn undo_heap(&mut self, journal: Journal) {
+        self.heap.truncate(journal.heap_size);
+        if let Some(allocator) = journal.allocator {
+            self.allocator = allocator;
+        }
+    }
+
+    /// Lowest address of the stack
+    pub fn stack_start(&self) -> usize {
+        STACK_TOP - self.stack.len()
+    }
+
+    /// Addresses directly below the stack that are never mapped, so a stack growing into them
+    /// faults with [`VmError::StackOverflow`] instead of overwriting the heap
+    pub fn stack_guard(&self) -> Range<usize> {
+        self.stack_start() - STACK_GUARD_SIZE..self.stack_start()
+    }
+
+    /// Fault for accessing an address outside every region
+    fn unmapped(&self, address: usize) -> VmError {
+        match self.stack_guard().contains(&address) {
+            true => VmError::StackOverflow { address },
+            false => VmError::InvalidAddress { address },
+        }
+    }
+
+    /// Lowest address of the bss section, or zero if the sections haven't been mapped
+    fn bss_start(&self) -> usize {
+        self.sections
+            .as_ref()
+            .map_or(0, |sections| sections.bss.start)
+    }
+
+    /// Finds the region containing address, along with the address range it covers
+    fn locate(&self, address: usize) -> Option<(Region, Range<usize>)> {
+        if let Some(mapped) = self.device(address) {
+            return Some((Region::Device, mapped.range.clone()));
+        }
+
+        let heap = self.heap_start()..self.heap_start() + self.heap.len();
+        if heap.contains(&address) {
+            return Some((Region::Heap, heap));
+        }
+
+        let stack = self.stack_start()..STACK_TOP;
+        if stack.contains(&address) {
+            return Some((Region::Stack, stack));
+        }
+
+        let Some(sections) = &self.sections else {
+            return (address < self.image.len()).then_some((Region::Code, 0..self.image.len()));
+        };
+
+        let header = 0..sections.data.start.min(sections.code.start);
+        [
+            (Region::Code, sections.code.clone()),
+            (Region::Data, sections.data.clone()),
+            (Region::Rodata, sections.rodata.clone()),
+            (Region::Bss, sections.bss.clone()),
+            (Region::Header, header),
+        ]
+        .into_iter()
+        .find(|(_, range)| range.contains(&address))
+    }
+
+    /// Checks len bytes starting at address all lie within a single region
+    pub(crate) fn checked_range(
+        &self,
+        address: usize,
+        len: usize,
+    ) -> Result<(Region, Range<usize>), VmError> {
+        let (region, range) = self.locate(address).ok_or_else(|| self.unmapped(address))?;
+
+        address
+            .checked_add(len)
+            .filter(|&end| end <= range.end)
+            .map(|end| (region, address..end))
+            .ok_or(VmError::InvalidAddress { address })
+    }
+
+    /// Translates an address range within a region to the bytes backing it, faulting for device
+    /// registers which aren't backed by memory
+    fn translate(&self, region: Region, range: Range<usize>) -> Result<&[u8], VmError> {
+        Ok(match region {
+            Region::Bss => &self.bss[range.start - self.bss_start()..][..range.len()],
+            Region::Heap => &self.heap[range.start - self.heap_start()..][..range.len()],
+            Region::Stack => &self.stack[range.start - self.stack_start()..][..range.len()],
+            Region::Device => {
+                return Err(VmError::InvalidAddress {
+                    address: range.start,
+                })
+            }
+            _ => &self.image[range],
+        })
+    }
+
+    fn translate_mut(&mut self, region: Region, range: Range<usize>) -> Result<&mut [u8], VmError> {
+        let (bss_start, heap_start, stack_start) =
+            (self.bss_start(), self.heap_start(), self.stack_start());
+
+        Ok(match region {
+            Region::Bss => &mut self.bss[range.start - bss_start..][..range.len()],
+            Region::Heap => &mut self.heap[range.start - heap_start..][..range.len()],
+            Region::Stack => &mut self.stack[range.start - stack_start..][..range.len()],
+            Region::Device => {
+                return Err(VmError::InvalidAddress {
+                    address: range.start,
+                })
+            }
+            _ => &mut self.image[range],
+        })
+    }
+}
+
+/// Reads the section layout and symbols from a program header, failing if the program doesn't
+/// start with the EPIE magic, uses another format version or word size, or describes sections
+/// outside the program
+pub(crate) fn read_header(image: &[u8]) -> Result<(Sections, Vec<DebugSymbol>), VmError> {
+    if !image.starts_with(&PIE_HEADER_PREFIX) {
+        return Err(VmError::MissingMagic);
+    }
+    match image.get(4) {
+        Some(&PIE_FORMAT_VERSION) => {}
+        Some(&version) => return Err(VmError::UnsupportedVersion { version }),
+        None => return Err(VmError::InvalidHeader),
+    }
+    match image.get(5) {
+        Some(&(0 | PIE_WORD_SIZE | 8)) => {}
+        Some(&size) => return Err(VmError::UnsupportedWordSize { size }),
+        None => return Err(VmError::InvalidHeader),
+    }
+
+    let field = |offset: usize| {
+        image
+            .get(offset..offset + 4)
+            .map(|bytes| u32::from_be_bytes(bytes.try_into().unwrap()) as usize)
+            .ok_or(VmError::InvalidHeader)
+    };
+    let section = |offset: usize| {
+        let start = field(offset)?;
+        let end = start
+            .checked_add(field(offset + 4)?)
+            .filter(|&end| end <= image.len())
+            .ok_or(VmError::InvalidHeader)?;
+
+        Ok(start..end)
+    };
+
+    let sections = Sections {
+        data: section(8)?,
+        rodata: section(RODATA_SECTION_FIELD)?,
+        code: section(16)?,
+        bss: bss_section(image)?,
+    };
+    // the entry point must be an instruction in the code section
+    let entry = entry_offset(image);
+    if entry != 0 && (entry >= sections.code.len() || !entry.is_multiple_of(4)) {
+        return Err(VmError::InvalidHeader);
+    }
+    let symbols = read_symbols(image).ok_or(VmError::InvalidHeader)?;
+
+    Ok((sections, symbols))
+}
+
+/// Reads the bss section's address range from the header, failing unless it lies between the end
+/// of the image and the stack
+fn bss_section(image: &[u8]) -> Result<Range<usize>, VmError> {
+    let field = |offset: usize| {
+        image.get(offset..offset + 4).map_or(0, |bytes| {
+            u32::from_be_bytes(bytes.try_into().unwrap()) as usize
+        })
+    };
+    let (start, len) = (field(BSS_SECTION_FIELD), field(BSS_SECTION_FIELD + 4));
+    if len == 0 {
+        return Ok(0..0);
+    }
+
+    start
+        .checked_add(len)
+        .filter(|&end| start >= image.len() && end <= STACK_TOP - STACK_SIZE - STACK_GUARD_SIZE)
+        .map(|end| start..end)
+        .ok_or(VmError::InvalidHeader)
+}
+
+/// Offset of the entry point from the start of the code section, read from the header
+pub(crate) fn entry_offset(image: &[u8]) -> usize {
+    image
+        .get(ENTRY_POINT_FIELD..ENTRY_POINT_FIELD + 4)
+        .map_or(0, |bytes| {
+            u32::from_be_bytes(bytes.try_into().unwrap()) as usize
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::uart::Uart;
+
+    /// Header describing 4 bytes of data at 64 followed by 4 bytes of code at 68
+    fn get_test_memory() -> Memory {
+        let mut image = vec![0; 72];
+        image[..4].copy_from_slice(&PIE_HEADER_PREFIX);
+        image[4] = PIE_FORMAT_VERSION;
+        image[8..12].copy_from_slice(&64u32.to_be_bytes());
+        image[12..16].copy_from_slice(&4u32.to_be_bytes());
+        image[16..20].copy_from_slice(&68u32.to_be_bytes());
+        image[20..24].copy_from_slice(&4u32.to_be_bytes());
+
+        let mut memory = Memory::new(image);
+        memory.map_sections().unwrap();
+        memory
+    }
+
+    #[test]
+    fn test_regions() {
+        let mut memory = get_test_memory();
+        let heap = memory.grow_heap(8);
+
+        assert_eq!(heap, Some(72));
+        assert_eq!(memory.region(0), Some(Region::Header));
+        assert_eq!(memory.region(64), Some(Region::Data));
+        assert_eq!(memory.region(68), Some(Region::Code));
+        assert_eq!(memory.region(79), Some(Region::Heap));
+        assert_eq!(memory.region(80), None);
+        assert_eq!(memory.region(STACK_TOP - 1), Some(Region::Stack));
+        assert_eq!(memory.region(STACK_TOP), None);
+
+        assert_eq!(memory.grow_heap(STACK_TOP), None);
+    }
+
+    #[test]
+    fn test_stack_guard() {
+        let mut memory = get_test_memory();
+        let guard = memory.stack_guard();
+        assert_eq!(guard.end, memory.stack_start());
+
+        // the heap can grow up to the guard, but not into it
+        let free = guard.start - memory.heap_start();
+        assert_eq!(memory.grow_heap(free + 1), None);
+        assert!(memory.grow_heap(free).is_some());
+        assert_eq!(memory.region(guard.start - 1), Some(Region::Heap));
+
+        for address in [guard.start, guard.end - 4] {
+            assert_eq!(memory.region(address), None);
+            assert_eq!(
+                memory.write_u32(address, 1),
+                Err(VmError::StackOverflow { address })
+            );
+            assert_eq!(
+                memory.read(address, 4),
+                Err(VmError::StackOverflow { address })
+            );
+        }
+    }
+
+    #[test]
+    fn test_protection() {
+        let mut memory = get_test_memory();
+        memory.grow_heap(8).unwrap();
+
+        assert_eq!(memory.write(64, &[1, 2, 3, 4]), Ok(()));
+        assert_eq!(memory.write(76, &[5, 6]), Ok(()));
+        assert_eq!(memory.read(64, 4), Ok(&[1, 2, 3, 4][..]));
+        assert_eq!(memory.read(76, 2), Ok(&[5, 6][..]));
+
+        assert_eq!(
+            memory.write(0, &[1]),
+            Err(VmError::WriteProtected { address: 0 })
+        );
+        assert_eq!(
+            memory.write(68, &[1]),
+            Err(VmError::WriteProtected { address: 68 })
+        );
+
+        memory.set_code_writable(true);
+        assert_eq!(memory.write(68, &[1]), Ok(()));
+
+        assert_eq!(memory.poke(0, &[1]), Ok(()));
+        assert_eq!(memory.read(0, 1), Ok(&[1][..]));
+    }
+
+    #[test]
+    fn test_rodata() {
+        // the 4 bytes at 64 become read-only data rather than data
+        let mut memory = get_test_memory();
+        memory.image_mut()[12..16].fill(0);
+        memory.image_mut()[RODATA_SECTION_FIELD..RODATA_SECTION_FIELD + 8]
+            .copy_from_slice(&[0, 0, 0, 64, 0, 0, 0, 4]);
+        memory.image_mut()[64] = 7;
+        memory.map_sections().unwrap();
+        memory.set_code_writable(true);
+
+        assert_eq!(memory.rodata_section(), Some(64..68));
+        assert_eq!(memory.region(64), Some(Region::Rodata));
+        assert_eq!(memory.read_u8(64), Ok(7));
+        assert_eq!(
+            memory.write(65, &[1]),
+            Err(VmError::WriteProtected { address: 65 })
+        );
+        assert_eq!(memory.poke(64, &[1]), Ok(()));
+
+        // read-only data running past the end of the image
+        memory.image_mut()[RODATA_SECTION_FIELD + 7] = 100;
+        assert_eq!(memory.map_sections(), Err(VmError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_bss() {
+        // 8 bytes of bss at 76, leaving a gap after the 72 byte image
+        let mut memory = get_test_memory();
+        memory.image_mut()[BSS_SECTION_FIELD..BSS_SECTION_FIELD + 8]
+            .copy_from_slice(&[0, 0, 0, 76, 0, 0, 0, 8]);
+        memory.map_sections().unwrap();
+
+        assert_eq!(memory.bss_section(), Some(76..84));
+        assert_eq!(memory.region(72), None);
+        assert_eq!(memory.region(76), Some(Region::Bss));
+        assert_eq!(memory.read(76, 8), Ok(&[0; 8][..]));
+        assert_eq!(memory.write_u32(80, 5), Ok(()));
+        assert_eq!(memory.read_u32(80), Ok(5));
+        assert_eq!(memory.grow_heap(4), Some(84));
+
+        // mapping the sections again zeroes it
+        memory.map_sections().unwrap();
+        assert_eq!(memory.read_u32(80), Ok(0));
+
+        // bss overlapping the image
+        memory.image_mut()[BSS_SECTION_FIELD + 3] = 68;
+        assert_eq!(memory.map_sections(), Err(VmError::InvalidHeader));
+    }
+
+    #[test]
+    fn test_bounds() {
+        let mut memory = get_test_memory();
+        memory.grow_heap(8).unwrap();
+
+        // accesses can't straddle regions
+        assert_eq!(
+            memory.read(66, 4),
+            Err(VmError::InvalidAddress { address: 66 })
+        );
+        assert_eq!(
+            memory.write(70, &[0; 4]),
+            Err(VmError::InvalidAddress { address: 70 })
+        );
+        assert_eq!(
+            memory.read(80, 1),
+            Err(VmError::InvalidAddress { address: 80 })
+        );
+        assert_eq!(memory.read_to_region_end(74), Ok(&[0; 6][..]));
+    }
+
+    #[test]
+    fn test_accessors() {
+        let mut memory = get_test_memory();
+
+        assert_eq!(memory.write_u32(64, 0x01020304), Ok(()));
+        assert_eq!(memory.read_u8(65), Ok(2));
+        assert_eq!(memory.read_u16(65), Ok(0x0203));
+        assert_eq!(memory.read_u32(64), Ok(0x01020304));
+        assert_eq!(
+            memory.write_u16(68, 1),
+            Err(VmError::WriteProtected { address: 68 })
+        );
+        assert_eq!(
+            memory.read_u32(66),
+            Err(VmError::InvalidAddress { address: 66 })
+        );
+
+        memory.set_alignment_checked(true);
+        assert_eq!(memory.read_u8(65), Ok(2));
+        assert_eq!(memory.read_u16(66), Ok(0x0304));
+        assert_eq!(
+            memory.read_u16(65),
+            Err(VmError::UnalignedAccess {
+                address: 65,
+                size: 2
+            })
+        );
+        assert_eq!(
+            memory.write_u32(66, 0),
+            Err(VmError::UnalignedAccess {
+                address: 66,
+                size: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_devices() {
+        let mut memory = get_test_memory();
+        let uart = Rc::new(RefCell::new(Uart::default()));
+        uart.borrow_mut().receive(b"ab");
+
+        assert_eq!(memory.map_device(DEVICE_BASE + 4, uart.clone()), Ok(()));
+        assert_eq!(
+            memory.map_device(DEVICE_BASE + 6, Rc::new(RefCell::new(Uart::default()))),
+            Err(VmError::DeviceOverlap {
+                address: DEVICE_BASE + 6
+            })
+        );
+        assert_eq!(
+            memory.map_device(STACK_TOP - 4, Rc::new(RefCell::new(Uart::default()))),
+            Err(VmError::DeviceOverlap {
+                address: STACK_TOP - 4
+            })
+        );
+        assert_eq!(
+            memory.map_device(DEVICE_BASE, Rc::new(RefCell::new(Uart::default()))),
+            Ok(())
+        );
+
+        // loads and stores go to the device, but it can't be read as a slice
+        assert_eq!(memory.region(DEVICE_BASE + 6), Some(Region::Device));
+        assert_eq!(memory.read_u16(DEVICE_BASE + 4), Ok(0x6101));
+        assert_eq!(memory.write_u8(DEVICE_BASE + 4, b'!'), Ok(()));
+        assert_eq!(uart.borrow_mut().take_transmitted(), b"!");
+        assert_eq!(
+            memory.read(DEVICE_BASE + 4, 1),
+            Err(VmError::InvalidAddress {
+                address: DEVICE_BASE + 4
+            })
+        );
+        assert_eq!(
+            memory.read_u32(DEVICE_BASE + 4),
+            Err(VmError::InvalidAddress {
+                address: DEVICE_BASE + 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_high_water_marks() {
+        let mut memory = get_test_memory();
+        memory.map_sections().unwrap();
+        assert_eq!(memory.high_water_marks(), HighWaterMarks::default());
+
+        memory.write_u16(65, 1).unwrap();
+        memory.write_u8(64, 1).unwrap();
+        memory.write_u32(STACK_TOP - 8, 1).unwrap();
+        memory.write_u32(STACK_TOP - 4, 1).unwrap();
+        assert_eq!(
+            memory.high_water_marks(),
+            HighWaterMarks {
+                stack_depth: 8,
+                heap_peak: 0,
+                data_written: Some(66),
+            }
+        );
+    }
+
+    #[test]
+    fn test_symbols() {
+        let mut memory = get_test_memory();
+        let symbols = ["main", "value"].map(|name| DebugSymbol {
+            name: name.to_owned(),
+            kind: SymbolKind::Code,
+            value: if name == "main" { 68 } else { 64 },
+        });
+        let section = shared::symbols::encode_symbols(&symbols);
+        memory.image_mut()[24..28].copy_from_slice(&72u32.to_be_bytes());
+        memory.image_mut()[28..32].copy_from_slice(&(section.len() as u32).to_be_bytes());
+        memory.extend(&section);
+        memory.map_sections().unwrap();
+
+        assert_eq!(memory.symbols()[0].name, "value");
+        assert_eq!(memory.symbolize(63), None);
+        assert_eq!(memory.symbolize(66), Some(("value", 2)));
+        assert_eq!(memory.symbolize(71), Some(("main", 3)));
+        // the symbol section itself isn't addressable
+        assert_eq!(memory.region(72), None);
+    }
+
+    #[test]
+    fn test_invalid_header() {
+        let mut memory = Memory::new(vec![0; 24]);
+        memory.image_mut()[..4].copy_from_slice(&PIE_HEADER_PREFIX);
+        memory.image_mut()[4] = PIE_FORMAT_VERSION;
+        memory.image_mut()[20..24].copy_from_slice(&100u32.to_be_bytes());
+
+        assert_eq!(memory.map_sections(), Err(VmError::InvalidHeader));
+    }
+}
@@ -0,0 +1,351 @@
+//! Heap of garbage collected objects created by `NEWARR` and accessed with `GETEL` and `SETEL`,
+//! for languages that would rather not free memory by hand.
+//!
+//! Objects are arrays of words or strings of bytes, referred to by handles rather than addresses.
+//! Handles all have [`HANDLE_TAG`] set in their top two bits, so they're rarely confused with other
+//! values, followed by the generation of the slot the object is in and the slot's index. Slots are
+//! reused once their object is freed, but with the next generation, so a handle kept after its
+//! object was freed doesn't refer to whichever object is allocated there next.
+//!
+//! When the heap has grown enough since the last collection, objects are marked from every register,
+//! and every 32 bit word in the data section, bss, heap and stack, that is a handle, and from there
+//! every element of a marked array that is a handle, and the rest are freed. Words that merely look
+//! like handles keep objects alive too, but nothing still referenced from the program is ever
+//! freed.
+
+use crate::errors::SnapshotError;
+use crate::snapshot::{Reader, Writer};
+
+/// `NEWARR` kind creating an array of words
+pub const OBJECT_ARRAY: u8 = 0;
+/// `NEWARR` kind creating a string of bytes
+pub const OBJECT_STRING: u8 = 1;
+
+/// Bits set in the top two bits of every handle
+pub const HANDLE_TAG: u32 = 0x4000_0000;
+const HANDLE_MASK: u32 = 0xC000_0000;
+
+/// Bits below the tag holding the slot's generation, which wraps around after 256 reuses
+const GENERATION_BITS: u32 = 8;
+/// Bits at the bottom of a handle holding the slot's index
+const INDEX_BITS: u32 = 30 - GENERATION_BITS;
+const GENERATION_MASK: u32 = (1 << GENERATION_BITS) - 1;
+const INDEX_MASK: u32 = (1 << INDEX_BITS) - 1;
+
+/// Kind saved in snapshots for slots that have been freed
+const FREED: u32 = u32::MAX;
+
+/// Bytes allocated before the first collection
+const INITIAL_THRESHOLD: usize = 64 * 1024;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Object {
+    Array(Vec<i32>),
+    String(Vec<u8>),
+}
+
+impl Object {
+    /// Creates a zeroed object of the given kind, or None if the kind doesn't exist
+    pub fn new(kind: u8, len: usize) -> Option<Self> {
+        match kind {
+            OBJECT_ARRAY => Some(Object::Array(vec![0; len])),
+            OBJECT_STRING => Some(Object::String(vec![0; len])),
+            _ => None,
+        }
+    }
+
+    /// Bytes taken by each element of an object of the given kind, or None if the kind doesn't
+    /// exist
+    pub fn element_size(kind: u8) -> Option<usize> {
+        match kind {
+            OBJECT_ARRAY => Some(4),
+            OBJECT_STRING => Some(1),
+            _ => None,
+        }
+    }
+
+    /// Number of elements
+    pub fn len(&self) -> usize {
+        match self {
+            Object::Array(elements) => elements.len(),
+            Object::String(bytes) => bytes.len(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Element at index, or None if it's out of bounds
+    pub fn get(&self, index: usize) -> Option<i32> {
+        match self {
+            Object::Array(elements) => elements.get(index).copied(),
+            Object::String(bytes) => bytes.get(index).map(|&byte| byte as i32),
+        }
+    }
+
+    /// Sets the element at index, truncating it to a byte in strings. Returns false if index is out
+    /// of bounds
+    pub fn set(&mut self, index: usize, value: i32) -> bool {
+        match self {
+            Object::Array(elements) => elements.get_mut(index).map(|element| *element = value),
+            Object::String(bytes) => bytes.get_mut(index).map(|byte| *byte = value as u8),
+        }
+        .is_some()
+    }
+
+    /// Bytes taken by the elements
+    pub(crate) fn size(&self) -> usize {
+        match self {
+            Object::Array(elements) => elements.len() * 4,
+            Object::String(bytes) => bytes.len(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ObjectHeap {
+    /// Objects by handle index, with freed slots reused by the next object allocated
+    objects: Vec<Option<Object>>,
+    /// Generation of each slot, advanced whenever its object is freed
+    generations: Vec<u32>,
+    /// Indices of freed slots
+    free: Vec<usize>,
+    /// Bytes taken by allocated objects, whether or not they're still referenced
+    size: usize,
+    /// Size the heap can grow to before it's next collected
+    threshold: usize,
+    /// Collections since the heap was created
+    collections: usize,
+}
+
+impl Default for ObjectHeap {
+    fn default() -> Self {
+        Self {
+            objects: Vec::new(),
+            generations: Vec::new(),
+            free: Vec::new(),
+            size: 0,
+            threshold: INITIAL_THRESHOLD,
+            collections: 0,
+        }
+    }
+}
+
+impl ObjectHeap {
+    /// Object with the given handle, or None if it isn't a live object
+    pub fn get(&self, handle: i32) -> Option<&Object> {
+        self.objects.get(self.index(handle)?)?.as_ref()
+    }
+
+    pub(crate) fn get_mut(&mut self, handle: i32) -> Option<&mut Object> {
+        let index = self.index(handle)?;
+        self.objects.get_mut(index)?.as_mut()
+    }
+
+    /// Index of the slot a value is the handle of, or None if it isn't a handle or its slot has
+    /// been freed since
+    fn index(&self, handle: i32) -> Option<usize> {
+        let (index, generation) = decode(handle)?;
+
+        (self.generations.get(index) == Some(&generation)).then_some(index)
+    }
+
+    /// Number of live objects
+    pub fn len(&self) -> usize {
+        self.objects.len() - self.free.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Bytes taken by live objects
+    pub fn size(&self) -> usize {
+        self.size
+    }
+
+    /// Number of times the heap has been collected
+    pub fn collections(&self) -> usize {
+        self.collections
+    }
+
+    /// Whether the heap has grown enough since the last collection to collect it again before
+    /// allocating
+    pub(crate) fn should_collect(&self) -> bool {
+        self.size >= self.threshold
+    }
+
+    /// Adds an object to the heap, returning its handle, or None if every handle is in use
+    pub(crate) fn allocate(&mut self, object: Object) -> Option<i32> {
+        let index = match self.free.pop() {
+            Some(index) => index,
+            None if self.objects.len() <= INDEX_MASK as usize => {
+                self.objects.push(None);
+                self.generations.push(0);
+                self.objects.len() - 1
+            }
+            None => return None,
+        };
+        self.size += object.size();
+        self.objects[index] = Some(object);
+
+        Some((HANDLE_TAG | (self.generations[index] << INDEX_BITS) | index as u32) as i32)
+    }
+
+    /// Frees every object not reachable from the roots, returning the number freed
+    pub(crate) fn collect(&mut self, roots: impl IntoIterator<Item = i32>) -> usize {
+        let mut marked = vec![false; self.objects.len()];
+        let mut pending: Vec<i32> = roots.into_iter().collect();
+        while let Some(value) = pending.pop() {
+            let Some(index) = self.index(value) else {
+                continue;
+            };
+            if marked.get(index) != Some(&false) {
+                continue;
+            }
+
+            match &self.objects[index] {
+                Some(Object::Array(elements)) => pending.extend(elements),
+                Some(Object::String(_)) => {}
+                None => continue,
+            }
+            marked[index] = true;
+        }
+
+        let mut freed = 0;
+        for (index, object) in self.objects.iter_mut().enumerate() {
+            if !marked[index] {
+                if let Some(object) = object.take() {
+                    self.size -= object.size();
+                    self.generations[index] = (self.generations[index] + 1) & GENERATION_MASK;
+                    self.free.push(index);
+                    freed += 1;
+                }
+            }
+        }
+
+        // grows with the heap, so programs keeping lots of objects alive aren't always collecting
+        self.threshold = INITIAL_THRESHOLD.max(self.size * 2);
+        self.collections += 1;
+
+        freed
+    }
+
+    pub(crate) fn save(&self, writer: &mut Writer) {
+        writer.usize(self.objects.len());
+        for (object, &generation) in self.objects.iter().zip(&self.generations) {
+            writer.u32(generation);
+            match object {
+                Some(Object::Array(elements)) => {
+                    writer.u32(OBJECT_ARRAY as u32);
+                    writer.usize(elements.len());
+                    for &element in elements {
+                        writer.u32(element as u32);
+                    }
+                }
+                Some(Object::String(bytes)) => {
+                    writer.u32(OBJECT_STRING as u32);
+                    writer.bytes(bytes);
+                }
+                None => writer.u32(FREED),
+            }
+        }
+
+        writer.usize(self.threshold);
+        writer.usize(self.collections);
+    }
+
+    pub(crate) fn load(reader: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut heap = Self::default();
+
+        for index in 0..reader.usize()? {
+            let generation = reader.u32()?;
+            if generation > GENERATION_MASK || index > INDEX_MASK as usize {
+                return Err(SnapshotError::Malformed);
+            }
+            heap.generations.push(generation);
+
+            let object = match reader.u32()? {
+                kind if kind == OBJECT_ARRAY as u32 => {
+                    let len = reader.usize()?;
+                    let elements = (0..len)
+                        .map(|_| Ok(reader.u32()? as i32))
+                        .collect::<Result<_, SnapshotError>>()?;
+                    Object::Array(elements)
+                }
+                kind if kind == OBJECT_STRING as u32 => Object::String(reader.bytes()?.to_vec()),
+                FREED => {
+                    heap.objects.push(None);
+                    heap.free.push(index);
+                    continue;
+                }
+                _ => return Err(SnapshotError::Malformed),
+            };
+
+            heap.size += object.size();
+            heap.objects.push(Some(object));
+        }
+
+        heap.threshold = reader.usize()?;
+        heap.collections = reader.usize()?;
+
+        Ok(heap)
+    }
+}
+
+/// Slot index and generation a value would be the handle of, or None if it isn't a handle
+fn decode(handle: i32) -> Option<(usize, u32)> {
+    let handle = handle as u32;
+    let index = (handle & INDEX_MASK) as usize;
+    let generation = (handle >> INDEX_BITS) & GENERATION_MASK;
+
+    (handle & HANDLE_MASK == HANDLE_TAG).then_some((index, generation))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_object() {
+        let mut string = Object::new(OBJECT_STRING, 2).unwrap();
+        assert!(string.set(1, 0x141));
+        assert!(!string.set(2, 0));
+        assert_eq!(string.get(1), Some(0x41));
+        assert_eq!(string.get(2), None);
+        assert_eq!(Object::new(2, 1), None);
+        assert_eq!(Object::element_size(OBJECT_ARRAY), Some(4));
+    }
+
+    #[test]
+    fn test_collect() {
+        let mut heap = ObjectHeap::default();
+        let outer = heap.allocate(Object::Array(vec![0; 2])).unwrap();
+        let inner = heap.allocate(Object::String(b"hi".to_vec())).unwrap();
+        let garbage = heap.allocate(Object::Array(vec![0; 4])).unwrap();
+        heap.get_mut(outer).unwrap().set(0, inner);
+        assert_eq!(outer as u32, HANDLE_TAG);
+        assert_eq!((heap.len(), heap.size()), (3, 26));
+
+        // the inner string is only reachable through the outer array
+        assert_eq!(heap.collect([outer, 5]), 1);
+        assert_eq!((heap.len(), heap.size()), (2, 10));
+        assert_eq!(heap.get(garbage), None);
+        assert_eq!(heap.get(inner), Some(&Object::String(b"hi".to_vec())));
+
+        // freed slots are reused, but not their handles
+        let reused = heap.allocate(Object::Array(Vec::new())).unwrap();
+        assert_eq!(reused, garbage + (1 << INDEX_BITS));
+        assert_eq!(heap.get(garbage), None);
+        assert_eq!(heap.collect([garbage]), 3);
+        assert!(heap.is_empty());
+        assert_eq!(heap.collections(), 2);
+
+        let mut writer = Writer::new();
+        heap.save(&mut writer);
+        let loaded = ObjectHeap::load(&mut writer.finish().reader()).unwrap();
+        assert_eq!(loaded.generations, [1, 1, 2]);
+        assert_eq!(loaded.free.len(), 3);
+    }
+}
@@ -0,0 +1,109 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::rc::Rc;
+
+/// Writes everything to several sinks at once, such as the terminal, a log file and an in-memory
+/// capture
+#[derive(Default)]
+pub struct Tee {
+    sinks: Vec<Box<dyn Write>>,
+}
+
+impl Tee {
+    /// Adds another sink, returning the tee so sinks can be chained
+    pub fn with(mut self, sink: impl Write + 'static) -> Self {
+        self.push(sink);
+        self
+    }
+
+    pub fn push(&mut self, sink: impl Write + 'static) {
+        self.sinks.push(Box::new(sink));
+    }
+
+    pub fn clear(&mut self) {
+        self.sinks.clear();
+    }
+
+    pub fn len(&self) -> usize {
+        self.sinks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+}
+
+impl Write for Tee {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        // every sink gets the whole buffer, so a short write to one can't desync the others
+        for sink in &mut self.sinks {
+            sink.write_all(buf)?;
+        }
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        for sink in &mut self.sinks {
+            sink.flush()?;
+        }
+
+        Ok(())
+    }
+}
+
+/// In-memory sink whose clones all share the same buffer, so output can be read back after it's
+/// been handed to the VM
+#[derive(Debug, Default, Clone)]
+pub struct SharedBuffer {
+    buffer: Rc<RefCell<Vec<u8>>>,
+}
+
+impl SharedBuffer {
+    /// Everything written so far
+    pub fn contents(&self) -> Vec<u8> {
+        self.buffer.borrow().clone()
+    }
+
+    /// Everything written so far, with invalid UTF-8 replaced
+    pub fn to_string_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.buffer.borrow()).into_owned()
+    }
+
+    /// Removes and returns everything written so far
+    pub fn take(&self) -> Vec<u8> {
+        std::mem::take(&mut self.buffer.borrow_mut())
+    }
+}
+
+impl Write for SharedBuffer {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.buffer.borrow_mut().extend_from_slice(buf);
+
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_tee() {
+        let (first, second) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut tee = Tee::default().with(first.clone()).with(second.clone());
+
+        write!(tee, "hello").unwrap();
+        assert_eq!(first.contents(), b"hello");
+        assert_eq!(second.to_string_lossy(), "hello");
+
+        assert_eq!(first.take(), b"hello");
+        writeln!(tee).unwrap();
+        assert_eq!(first.contents(), b"\n");
+        assert_eq!(second.contents(), b"hello\n");
+    }
+}
@@ -0,0 +1,95 @@
+//! Instruction counts recorded while profiling is enabled with
+//! [`VM::enable_profiling`](crate::VM::enable_profiling).
+//!
+//! Unlike the `timing` feature, profiling only counts instructions, so it's available in every
+//! build and costs a single branch per instruction while disabled.
+
+use shared::Opcode;
+use std::collections::HashMap;
+
+/// Executions of each opcode and of the instruction at each address
+#[derive(Debug, Clone)]
+pub struct Profile {
+    /// Executions indexed by opcode byte
+    opcodes: Box<[u64; 256]>,
+    /// Executions of the instruction at each address
+    addresses: HashMap<usize, u64>,
+}
+
+impl Default for Profile {
+    fn default() -> Self {
+        Self {
+            opcodes: Box::new([0; 256]),
+            addresses: HashMap::new(),
+        }
+    }
+}
+
+impl Profile {
+    /// Records an execution of the instruction at pc
+    pub(crate) fn record(&mut self, opcode: Opcode, pc: usize) {
+        self.opcodes[opcode as usize] += 1;
+        *self.addresses.entry(pc).or_default() += 1;
+    }
+
+    /// Number of instructions executed while profiling
+    pub fn total(&self) -> u64 {
+        self.opcodes.iter().sum()
+    }
+
+    /// The `limit` most executed opcodes and how often each ran, most executed first
+    pub fn top_opcodes(&self, limit: usize) -> Vec<(Opcode, u64)> {
+        let counts = self
+            .opcodes
+            .iter()
+            .enumerate()
+            .filter(|(_, &count)| count > 0)
+            .map(|(byte, &count)| (Opcode::from_encoding(byte as u8).unwrap(), count));
+
+        top(counts, limit)
+    }
+
+    /// The `limit` most executed instruction addresses and how often each ran, most executed first
+    /// and lowest address first among equals
+    pub fn hot_spots(&self, limit: usize) -> Vec<(usize, u64)> {
+        let mut counts = self
+            .addresses
+            .iter()
+            .map(|(&pc, &count)| (pc, count))
+            .collect::<Vec<_>>();
+        counts.sort();
+
+        top(counts, limit)
+    }
+}
+
+/// Sorts counts from most to least, keeping the existing order among equals, and keeps the first
+/// `limit`
+fn top<T>(counts: impl IntoIterator<Item = (T, u64)>, limit: usize) -> Vec<(T, u64)> {
+    let mut counts = counts.into_iter().collect::<Vec<_>>();
+    counts.sort_by_key(|&(_, count)| std::cmp::Reverse(count));
+    counts.truncate(limit);
+
+    counts
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_profile() {
+        let mut profile = Profile::default();
+        for pc in [64, 68, 64, 68, 64, 72] {
+            let opcode = match pc {
+                72 => Opcode::HLT,
+                _ => Opcode::INC,
+            };
+            profile.record(opcode, pc);
+        }
+
+        assert_eq!(profile.total(), 6);
+        assert_eq!(profile.top_opcodes(5), [(Opcode::INC, 5), (Opcode::HLT, 1)]);
+        assert_eq!(profile.hot_spots(2), [(64, 3), (68, 2)]);
+    }
+}
@@ -0,0 +1,114 @@
+//! Programs parsed ahead of being run.
+//!
+//! [`Program::parse`] reads and checks an EPIE header once, so a [`VM`](crate::VM) loading the
+//! result doesn't have to find out the header is malformed partway through starting.
+
+use crate::errors::VmError;
+use crate::memory::{entry_offset, read_header, Sections};
+use shared::symbols::DebugSymbol;
+use std::ops::Range;
+
+/// Program whose header has been parsed, with every section checked to lie within it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Program {
+    image: Vec<u8>,
+    pub(crate) sections: Sections,
+    /// Symbols from the symbol section, sorted by value
+    pub(crate) symbols: Vec<DebugSymbol>,
+}
+
+impl Program {
+    /// Parses a program's header. Fails if the program doesn't start with the EPIE magic, uses
+    /// another format version or word size, or describes sections outside the program
+    pub fn parse(image: Vec<u8>) -> Result<Self, VmError> {
+        let (sections, mut symbols) = read_header(&image)?;
+        symbols.sort_by_key(|symbol| symbol.value);
+
+        Ok(Self {
+            image,
+            sections,
+            symbols,
+        })
+    }
+
+    /// The whole program, header included
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Addresses of the data section
+    pub fn data(&self) -> Range<usize> {
+        self.sections.data.clone()
+    }
+
+    /// Addresses of the read-only data section, empty if the program doesn't have one
+    pub fn rodata(&self) -> Range<usize> {
+        self.sections.rodata.clone()
+    }
+
+    /// Addresses of the code section
+    pub fn code(&self) -> Range<usize> {
+        self.sections.code.clone()
+    }
+
+    /// Addresses of the bss section, which lies after the image and is empty if the program
+    /// doesn't have one
+    pub fn bss(&self) -> Range<usize> {
+        self.sections.bss.clone()
+    }
+
+    /// Address execution starts at
+    pub fn entry(&self) -> usize {
+        self.sections.code.start + entry_offset(&self.image)
+    }
+
+    /// Symbols from the program's symbol section, sorted by value. Empty if it has none
+    pub fn symbols(&self) -> &[DebugSymbol] {
+        &self.symbols
+    }
+
+    /// The whole program, header included
+    pub fn into_image(self) -> Vec<u8> {
+        self.image
+    }
+}
+
+impl TryFrom<Vec<u8>> for Program {
+    type Error = VmError;
+
+    fn try_from(image: Vec<u8>) -> Result<Self, Self::Error> {
+        Self::parse(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{ENTRY_POINT_FIELD, PIE_FORMAT_VERSION, PIE_HEADER_PREFIX};
+
+    #[test]
+    fn test_parse() {
+        // 4 bytes of data at 64 followed by 8 bytes of code at 68, starting at the second instruction
+        let mut image = vec![0; 76];
+        image[..4].copy_from_slice(&PIE_HEADER_PREFIX);
+        image[4] = PIE_FORMAT_VERSION;
+        image[8..12].copy_from_slice(&64u32.to_be_bytes());
+        image[12..16].copy_from_slice(&4u32.to_be_bytes());
+        image[16..20].copy_from_slice(&68u32.to_be_bytes());
+        image[20..24].copy_from_slice(&8u32.to_be_bytes());
+        image[ENTRY_POINT_FIELD + 3] = 4;
+
+        let program = Program::parse(image.clone()).unwrap();
+        assert_eq!(program.data(), 64..68);
+        assert_eq!(program.code(), 68..76);
+        assert_eq!(program.rodata(), 0..0);
+        assert_eq!(program.bss(), 0..0);
+        assert_eq!(program.entry(), 72);
+        assert_eq!(program.into_image(), image);
+
+        // code running past the end of the file
+        image[23] = 12;
+        assert_eq!(Program::parse(image), Err(VmError::InvalidHeader));
+        assert_eq!(Program::try_from(vec![1, 2, 3]), Err(VmError::MissingMagic));
+    }
+}
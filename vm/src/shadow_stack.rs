@@ -0,0 +1,112 @@
+use crate::errors::SnapshotError;
+use crate::snapshot::{Reader, Writer};
+
+/// Call recorded on the shadow stack
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Frame {
+    /// Address of the call instruction
+    pub call_site: usize,
+    /// Address of the routine called
+    pub routine: usize,
+    /// Address the routine returns to
+    pub return_address: usize,
+    /// Address the return address was pushed to, which the routine's stack frame is below
+    pub stack_pointer: usize,
+}
+
+/// Calls that haven't returned yet, kept separately from the guest stack so they can be reported
+/// even if the program corrupts its own stack
+#[derive(Debug, Default, Clone)]
+pub(crate) struct ShadowStack {
+    frames: Vec<Frame>,
+}
+
+impl ShadowStack {
+    pub(crate) fn call(&mut self, frame: Frame) {
+        self.frames.push(frame);
+    }
+
+    /// Pops the frame returning to address, along with any frames above it that never returned
+    pub(crate) fn ret(&mut self, address: usize) {
+        if let Some(index) = self
+            .frames
+            .iter()
+            .rposition(|frame| frame.return_address == address)
+        {
+            self.frames.truncate(index);
+        }
+    }
+
+    /// Frames of every call that hasn't returned, outermost first
+    pub(crate) fn frames(&self) -> &[Frame] {
+        &self.frames
+    }
+
+    /// Replaces every frame, such as when a call or return is undone
+    pub(crate) fn set_frames(&mut self, frames: Vec<Frame>) {
+        self.frames = frames;
+    }
+
+    pub(crate) fn clear(&mut self) {
+        self.frames.clear();
+    }
+
+    pub(crate) fn save(&self, writer: &mut Writer) {
+        writer.usize(self.frames.len());
+        for frame in &self.frames {
+            writer.usize(frame.call_site);
+            writer.usize(frame.routine);
+            writer.usize(frame.return_address);
+            writer.usize(frame.stack_pointer);
+        }
+    }
+
+    pub(crate) fn load(reader: &mut Reader) -> Result<Self, SnapshotError> {
+        let mut frames = Vec::new();
+        for _ in 0..reader.usize()? {
+            frames.push(Frame {
+                call_site: reader.usize()?,
+                routine: reader.usize()?,
+                return_address: reader.usize()?,
+                stack_pointer: reader.usize()?,
+            });
+        }
+
+        Ok(Self { frames })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn frame(return_address: usize) -> Frame {
+        Frame {
+            call_site: return_address - 4,
+            routine: 0,
+            return_address,
+            stack_pointer: 0,
+        }
+    }
+
+    #[test]
+    fn test_shadow_stack() {
+        let mut stack = ShadowStack::default();
+        stack.call(frame(8));
+        stack.call(frame(20));
+        stack.call(frame(32));
+
+        stack.ret(32);
+        assert_eq!(stack.frames(), [frame(8), frame(20)]);
+
+        // returning past frames that never returned drops them too
+        stack.call(frame(32));
+        stack.ret(8);
+        assert!(stack.frames().is_empty());
+
+        // returns that don't match a call are ignored
+        stack.call(frame(8));
+        stack.ret(100);
+        assert_eq!(stack.frames(), [frame(8)]);
+    }
+}
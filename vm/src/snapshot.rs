@@ -0,0 +1,285 @@
+//! Snapshots of VM state, so a run can be saved and later continued exactly where it left off.
+//!
+//! A snapshot holds everything executing instructions can observe or change: registers, flags,
+//! interrupt state, the program image, heap, stack, allocator, step count and exit status, the
+//! state of mapped devices (and so the interrupts they're requesting), the random number
+//! generator, the time `TIME` has counted up to, how much input has been read, the files the
+//! program has open and its object heap, along with the shadow stack if it's enabled. Host
+//! configuration such as breakpoints, tracers, output sinks, resource limits, allowed directories
+//! and the devices themselves belongs to the VM being restored into, so isn't included. Devices
+//! and random number generators save their own state through [`Device::save_state`] and
+//! [`Random::save_state`].
+//!
+//! [`Device::save_state`]: crate::Device::save_state
+//! [`Random::save_state`]: crate::Random::save_state
+//!
+//! Snapshots start with the `EVMS` magic and a two byte format version, followed by each field in
+//! a fixed order. Numbers are big endian, and variable length fields are prefixed by their length.
+//! The VM's word size comes first, and registers are saved as 8 bytes whatever it is, but a
+//! snapshot can only be restored into a VM with the same word size.
+
+use crate::errors::SnapshotError;
+
+/// Version of the snapshot format written by this VM, bumped whenever the layout changes
+pub const SNAPSHOT_VERSION: u16 = 7;
+const SNAPSHOT_MAGIC: [u8; 4] = *b"EVMS";
+
+/// Saved VM state, created by [`VM::snapshot`](crate::VM::snapshot) and loaded back with
+/// [`VM::restore`](crate::VM::restore)
+#[derive(Debug, Clone, PartialEq)]
+pub struct Snapshot {
+    bytes: Vec<u8>,
+}
+
+impl Snapshot {
+    /// Reads a snapshot previously written out with [`Snapshot::as_bytes`], checking it was
+    /// written in a format this VM understands. The state itself is validated when restored
+    pub fn from_bytes(bytes: Vec<u8>) -> Result<Self, SnapshotError> {
+        if bytes.get(..4) != Some(&SNAPSHOT_MAGIC) {
+            return Err(SnapshotError::InvalidMagic);
+        }
+
+        let version = bytes
+            .get(4..6)
+            .map(|version| u16::from_be_bytes(version.try_into().unwrap()))
+            .ok_or(SnapshotError::Malformed)?;
+        if version != SNAPSHOT_VERSION {
+            return Err(SnapshotError::UnsupportedVersion {
+                found: version,
+                expected: SNAPSHOT_VERSION,
+            });
+        }
+
+        Ok(Self { bytes })
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes
+    }
+
+    pub(crate) fn reader(&self) -> Reader<'_> {
+        Reader {
+            bytes: &self.bytes[6..],
+        }
+    }
+}
+
+/// Encodes the fields of a snapshot
+pub(crate) struct Writer {
+    out: Vec<u8>,
+}
+
+impl Writer {
+    pub fn new() -> Self {
+        let mut out = SNAPSHOT_MAGIC.to_vec();
+        out.extend_from_slice(&SNAPSHOT_VERSION.to_be_bytes());
+
+        Self { out }
+    }
+
+    pub fn bool(&mut self, value: bool) {
+        self.out.push(value as u8);
+    }
+
+    pub fn u32(&mut self, value: u32) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn u64(&mut self, value: u64) {
+        self.out.extend_from_slice(&value.to_be_bytes());
+    }
+
+    pub fn usize(&mut self, value: usize) {
+        self.u64(value as u64);
+    }
+
+    pub fn bytes(&mut self, bytes: &[u8]) {
+        self.usize(bytes.len());
+        self.out.extend_from_slice(bytes);
+    }
+
+    pub fn finish(self) -> Snapshot {
+        Snapshot { bytes: self.out }
+    }
+}
+
+/// Decodes the fields of a snapshot, in the order they were written
+pub(crate) struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], SnapshotError> {
+        if len > self.bytes.len() {
+            return Err(SnapshotError::Malformed);
+        }
+
+        let (taken, rest) = self.bytes.split_at(len);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+
+    pub fn bool(&mut self) -> Result<bool, SnapshotError> {
+        match self.take(1)? {
+            [0] => Ok(false),
+            [1] => Ok(true),
+            _ => Err(SnapshotError::Malformed),
+        }
+    }
+
+    pub fn u32(&mut self) -> Result<u32, SnapshotError> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    pub fn u64(&mut self) -> Result<u64, SnapshotError> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    pub fn usize(&mut self) -> Result<usize, SnapshotError> {
+        usize::try_from(self.u64()?).map_err(|_| SnapshotError::Malformed)
+    }
+
+    pub fn bytes(&mut self) -> Result<&'a [u8], SnapshotError> {
+        let len = self.usize()?;
+        self.take(len)
+    }
+
+    /// Checks every field has been read
+    pub fn finish(self) -> Result<(), SnapshotError> {
+        match self.bytes.is_empty() {
+            true => Ok(()),
+            false => Err(SnapshotError::Malformed),
+        }
+    }
+}
+
+/// Snapshots serialize as their bytes, and are checked when deserialized just as by
+/// [`Snapshot::from_bytes`]
+#[cfg(feature = "serde")]
+impl serde::Serialize for Snapshot {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_bytes(&self.bytes)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for Snapshot {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct BytesVisitor;
+
+        // formats without a bytes type, such as JSON, write them as a sequence of numbers
+        impl<'de> serde::de::Visitor<'de> for BytesVisitor {
+            type Value = Vec<u8>;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("VM snapshot bytes")
+            }
+
+            fn visit_bytes<E: serde::de::Error>(self, bytes: &[u8]) -> Result<Self::Value, E> {
+                Ok(bytes.to_vec())
+            }
+
+            fn visit_byte_buf<E: serde::de::Error>(self, bytes: Vec<u8>) -> Result<Self::Value, E> {
+                Ok(bytes)
+            }
+
+            fn visit_seq<A: serde::de::SeqAccess<'de>>(
+                self,
+                mut seq: A,
+            ) -> Result<Self::Value, A::Error> {
+                let mut bytes = Vec::with_capacity(seq.size_hint().unwrap_or(0));
+                while let Some(byte) = seq.next_element()? {
+                    bytes.push(byte);
+                }
+
+                Ok(bytes)
+            }
+        }
+
+        let bytes = deserializer.deserialize_byte_buf(BytesVisitor)?;
+        Snapshot::from_bytes(bytes).map_err(serde::de::Error::custom)
+    }
+}
+
+/// VMs serialize as a [`Snapshot`] of their state, so host configuration isn't included and a
+/// deserialized VM has the default configuration
+#[cfg(feature = "serde")]
+impl serde::Serialize for crate::VM {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.snapshot().serialize(serializer)
+    }
+}
+
+#[cfg(feature = "serde")]
+impl<'de> serde::Deserialize<'de> for crate::VM {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        let snapshot = Snapshot::deserialize(deserializer)?;
+        let mut vm = crate::VM::default();
+        vm.restore(&snapshot).map_err(serde::de::Error::custom)?;
+
+        Ok(vm)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_snapshot_format() {
+        let mut writer = Writer::new();
+        writer.bool(true);
+        writer.u32(7);
+        writer.bytes(b"hi");
+        let snapshot = writer.finish();
+
+        let snapshot = Snapshot::from_bytes(snapshot.as_bytes().to_vec()).unwrap();
+        let mut reader = snapshot.reader();
+        assert_eq!(reader.bool(), Ok(true));
+        assert_eq!(reader.u32(), Ok(7));
+        assert_eq!(reader.bytes(), Ok(&b"hi"[..]));
+        assert_eq!(reader.finish(), Ok(()));
+
+        let mut reader = snapshot.reader();
+        reader.bool().unwrap();
+        assert_eq!(reader.finish(), Err(SnapshotError::Malformed));
+
+        assert_eq!(
+            Snapshot::from_bytes(b"EPIE\0\x01".to_vec()),
+            Err(SnapshotError::InvalidMagic)
+        );
+        assert_eq!(
+            Snapshot::from_bytes(b"EVMS\0\x01".to_vec()),
+            Err(SnapshotError::UnsupportedVersion {
+                found: 1,
+                expected: SNAPSHOT_VERSION
+            })
+        );
+        assert_eq!(
+            Snapshot::from_bytes(b"EVMS".to_vec()),
+            Err(SnapshotError::Malformed)
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serde() {
+        use crate::{Memory, VM};
+
+        let mut vm = VM::default();
+        *vm.memory_mut() = Memory::new(vec![1, 2, 3]);
+        vm.registers_mut()[4] = -9;
+        vm.set_clock(|| 0_u64);
+
+        let json = serde_json::to_string(&vm).unwrap();
+        let mut restored: VM = serde_json::from_str(&json).unwrap();
+        // the time is saved too, so both read the same clock
+        restored.set_clock(|| 0_u64);
+        assert_eq!(restored.snapshot(), vm.snapshot());
+        assert_eq!(restored.registers()[4], -9);
+
+        assert!(serde_json::from_str::<Snapshot>("[69, 80, 73, 69, 0, 1]").is_err());
+        assert!(serde_json::from_str::<VM>("[69, 86, 77, 83, 0, 2]").is_err());
+    }
+}
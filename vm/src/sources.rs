@@ -0,0 +1,137 @@
+//! Where `RAND` gets its random numbers and `TIME` gets the time from. Embedders can replace both
+//! with [`VM::set_random`](crate::VM::set_random) and [`VM::set_clock`](crate::VM::set_clock), such
+//! as with a fixed seed and a clock advanced by hand, so tests of programs using them are
+//! deterministic.
+
+use std::collections::hash_map::RandomState;
+use std::hash::BuildHasher;
+#[cfg(not(target_arch = "wasm32"))]
+use std::time::Instant;
+
+/// Source of the random numbers loaded by `RAND`
+pub trait Random {
+    fn next_u32(&mut self) -> u32;
+
+    /// State saved in snapshots, so a restored program carries on with the same numbers. Empty for
+    /// sources that can't be saved
+    fn save_state(&self) -> Vec<u8> {
+        Vec::new()
+    }
+
+    /// Puts back state saved by `save_state`. Sources that can't be saved ignore it and carry on
+    /// with their own numbers
+    fn restore_state(&mut self, _state: &[u8]) {}
+}
+
+impl<F: FnMut() -> u32> Random for F {
+    fn next_u32(&mut self) -> u32 {
+        self()
+    }
+}
+
+/// Source of the time read by `TIME`
+pub trait Clock {
+    /// Milliseconds since some fixed point, which must never go backwards
+    fn now_ms(&mut self) -> u64;
+}
+
+impl<F: FnMut() -> u64> Clock for F {
+    fn now_ms(&mut self) -> u64 {
+        self()
+    }
+}
+
+/// Xorshift generator, which is fast and small but not suitable for anything needing secure
+/// random numbers
+#[derive(Debug, Clone)]
+pub struct XorShift {
+    state: u64,
+}
+
+impl XorShift {
+    /// Creates a generator producing the same numbers every time for a given seed
+    pub fn new(seed: u64) -> Self {
+        // the state must never be zero, since zero only ever produces more zeroes
+        Self { state: seed.max(1) }
+    }
+
+    /// Creates a generator seeded differently every time, from the random keys std generates for
+    /// hash maps
+    pub fn from_entropy() -> Self {
+        Self::new(Self::entropy_seed())
+    }
+
+    /// Seed that's different every time, for generators whose seed needs to be known so their
+    /// numbers can be repeated
+    pub fn entropy_seed() -> u64 {
+        RandomState::new().hash_one(0u64)
+    }
+}
+
+impl Random for XorShift {
+    fn next_u32(&mut self) -> u32 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 7;
+        self.state ^= self.state << 17;
+
+        (self.state >> 32) as u32
+    }
+
+    fn save_state(&self) -> Vec<u8> {
+        self.state.to_be_bytes().to_vec()
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let Ok(state) = state.try_into() {
+            *self = Self::new(u64::from_be_bytes(state));
+        }
+    }
+}
+
+/// Clock counting from when it was created. wasm has no clock to read without JavaScript, so there
+/// it always reads zero unless replaced
+#[derive(Debug, Clone)]
+pub struct SystemClock {
+    #[cfg(not(target_arch = "wasm32"))]
+    created: Instant,
+}
+
+// without a field on wasm, this could be derived there
+#[cfg_attr(target_arch = "wasm32", allow(clippy::derivable_impls))]
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self {
+            #[cfg(not(target_arch = "wasm32"))]
+            created: Instant::now(),
+        }
+    }
+}
+
+impl Clock for SystemClock {
+    #[cfg(not(target_arch = "wasm32"))]
+    fn now_ms(&mut self) -> u64 {
+        self.created.elapsed().as_millis() as u64
+    }
+
+    #[cfg(target_arch = "wasm32")]
+    fn now_ms(&mut self) -> u64 {
+        0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_xorshift() {
+        let numbers = |seed| {
+            let mut random = XorShift::new(seed);
+            (0..4).map(|_| random.next_u32()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(numbers(7), numbers(7));
+        assert_ne!(numbers(7), numbers(8));
+        assert!(numbers(0).iter().any(|&number| number != 0));
+    }
+}
@@ -0,0 +1,16 @@
+use crate::errors::VmError;
+use crate::vm::VM;
+use crate::word::Word;
+
+/// Host function called by the program with `SYSI`, for exposing services of the embedding
+/// application. Arguments and results are passed in registers, by whatever convention the host
+/// chooses
+pub trait Syscall<W: Word = i32> {
+    fn call(&mut self, vm: &mut VM<W>) -> Result<(), VmError>;
+}
+
+impl<W: Word, F: FnMut(&mut VM<W>) -> Result<(), VmError>> Syscall<W> for F {
+    fn call(&mut self, vm: &mut VM<W>) -> Result<(), VmError> {
+        self(vm)
+    }
+}
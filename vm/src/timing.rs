@@ -0,0 +1,114 @@
+//! Wall time spent in each opcode's handler, recorded by builds with the `timing` feature.
+//!
+//! Every instruction is timed with [`Instant`], which costs tens of nanoseconds per instruction,
+//! so timed runs are slower than normal ones. The relative costs are what matter: handlers near the
+//! top are the ones worth giving fast paths or fusing with their neighbours.
+
+use shared::Opcode;
+use std::fmt::{Display, Formatter};
+use std::time::{Duration, Instant};
+
+/// Executions and total time spent in one opcode's handler
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct OpcodeTiming {
+    pub opcode: Opcode,
+    pub count: u64,
+    pub total: Duration,
+}
+
+impl OpcodeTiming {
+    /// Average time per execution
+    pub fn mean(&self) -> Duration {
+        self.total / self.count.max(1) as u32
+    }
+}
+
+impl Display for OpcodeTiming {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{:<8} {:>10} {:>12.3?} {:>10.1?}",
+            format!("{:?}", self.opcode),
+            self.count,
+            self.total,
+            self.mean()
+        )
+    }
+}
+
+/// Time spent in every opcode's handler since the timings were last cleared
+#[derive(Debug, Clone)]
+pub struct OpcodeTimings {
+    /// Executions and nanoseconds spent, indexed by opcode byte
+    entries: Box<[(u64, u64); 256]>,
+}
+
+impl Default for OpcodeTimings {
+    fn default() -> Self {
+        Self {
+            entries: Box::new([(0, 0); 256]),
+        }
+    }
+}
+
+impl OpcodeTimings {
+    /// Records an execution of an opcode's handler that started at `start`
+    pub(crate) fn record(&mut self, opcode: Opcode, start: Instant) {
+        let (count, nanos) = &mut self.entries[opcode as usize];
+        *count += 1;
+        *nanos += start.elapsed().as_nanos() as u64;
+    }
+
+    /// The `limit` opcodes that took the most time in total, most expensive first
+    pub fn top(&self, limit: usize) -> Vec<OpcodeTiming> {
+        let mut timings = self
+            .entries
+            .iter()
+            .enumerate()
+            .filter(|(_, &(count, _))| count > 0)
+            .map(|(byte, &(count, nanos))| OpcodeTiming {
+                opcode: Opcode::from_encoding(byte as u8).unwrap(),
+                count,
+                total: Duration::from_nanos(nanos),
+            })
+            .collect::<Vec<_>>();
+        timings.sort_by_key(|timing| std::cmp::Reverse(timing.total));
+        timings.truncate(limit);
+
+        timings
+    }
+
+    /// Total time spent across every handler
+    pub fn total(&self) -> Duration {
+        Duration::from_nanos(self.entries.iter().map(|&(_, nanos)| nanos).sum())
+    }
+
+    pub fn clear(&mut self) {
+        *self = Self::default();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_timings() {
+        let mut timings = OpcodeTimings::default();
+        let start = Instant::now();
+        std::thread::sleep(Duration::from_millis(2));
+        timings.record(Opcode::ADDR, start);
+        timings.record(Opcode::HLT, Instant::now());
+        timings.record(Opcode::HLT, Instant::now());
+
+        let top = timings.top(5);
+        assert_eq!(top.len(), 2);
+        assert_eq!((top[0].opcode, top[0].count), (Opcode::ADDR, 1));
+        assert_eq!((top[1].opcode, top[1].count), (Opcode::HLT, 2));
+        assert!(timings.total() >= Duration::from_millis(2));
+        assert_eq!(timings.top(1).len(), 1);
+
+        timings.clear();
+        assert!(timings.top(5).is_empty());
+    }
+}
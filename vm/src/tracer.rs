@@ -0,0 +1,245 @@
+//! Hooks for observing every instruction the VM executes, and tracers recording them.
+//!
+//! Traces can be written in a compact binary format, starting with the `EPTR` magic and a one byte
+//! format version. Each instruction is then recorded as its big endian address and its 4 bytes,
+//! followed by a count of the registers that changed since the previous instruction and, for each
+//! of them, its index and big endian value. The registers recorded are those before the instruction
+//! ran, and the first instruction records every register that isn't zero.
+
+use crate::word::Word;
+use shared::Opcode;
+use std::collections::VecDeque;
+use std::fmt::{Display, Formatter};
+use std::io::Write;
+
+const TRACE_MAGIC: [u8; 4] = *b"EPTR";
+/// Version of the binary trace format, bumped whenever the layout changes
+const TRACE_VERSION: u8 = 1;
+
+/// State of the VM directly before an instruction is executed
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceStep<'a, W = i32> {
+    /// Address of the instruction
+    pub pc: usize,
+    pub opcode: Opcode,
+    /// Raw operand bytes following the opcode
+    pub operands: [u8; 3],
+    pub registers: &'a [W; 32],
+}
+
+/// Hook invoked by the VM before every instruction, for building debuggers and profilers
+pub trait Tracer<W: Word = i32> {
+    fn trace(&mut self, step: &TraceStep<W>);
+}
+
+impl<W: Word, F: FnMut(&TraceStep<W>)> Tracer<W> for F {
+    fn trace(&mut self, step: &TraceStep<W>) {
+        self(step)
+    }
+}
+
+/// Executed instruction recorded in a trace, along with the registers before it ran
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TraceEntry {
+    pub pc: usize,
+    pub opcode: Opcode,
+    pub operands: [u8; 3],
+    pub registers: [i32; 32],
+}
+
+impl From<&TraceStep<'_>> for TraceEntry {
+    fn from(step: &TraceStep) -> Self {
+        Self {
+            pc: step.pc,
+            opcode: step.opcode,
+            operands: step.operands,
+            registers: *step.registers,
+        }
+    }
+}
+
+impl Display for TraceEntry {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        let [a, b, c] = self.operands;
+        write!(
+            f,
+            "{:#06X} {:?} {a:02X} {b:02X} {c:02X}",
+            self.pc, self.opcode
+        )
+    }
+}
+
+/// Tracer keeping only the most recent instructions, so long runs can be traced in bounded memory
+/// and the instructions leading up to a fault or halt examined afterwards
+#[derive(Debug, Clone)]
+pub struct RingTracer {
+    capacity: usize,
+    entries: VecDeque<TraceEntry>,
+}
+
+impl RingTracer {
+    /// Creates a tracer keeping the last `capacity` instructions
+    pub fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        }
+    }
+
+    /// Recorded instructions, oldest first
+    pub fn entries(&self) -> impl Iterator<Item = &TraceEntry> {
+        self.entries.iter()
+    }
+
+    /// Writes the recorded instructions in the binary trace format
+    pub fn write_binary(&self, writer: impl Write) -> std::io::Result<()> {
+        let mut encoder = TraceEncoder::new(writer)?;
+        for entry in &self.entries {
+            encoder.encode(entry)?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Tracer for RingTracer {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.capacity == 0 {
+            return;
+        }
+        if self.entries.len() == self.capacity {
+            self.entries.pop_front();
+        }
+
+        self.entries.push_back(step.into());
+    }
+}
+
+/// Writes instructions in the binary trace format as they're executed. Tracers can't fail, so the
+/// first write error is kept and returned by [`TraceEncoder::finish`]
+#[derive(Debug)]
+pub struct TraceEncoder<W: Write> {
+    writer: W,
+    /// Registers of the last instruction written, which the next one only records changes from
+    registers: [i32; 32],
+    error: Option<std::io::Error>,
+}
+
+impl<W: Write> TraceEncoder<W> {
+    /// Writes the trace header, ready for instructions to be written
+    pub fn new(mut writer: W) -> std::io::Result<Self> {
+        writer.write_all(&TRACE_MAGIC)?;
+        writer.write_all(&[TRACE_VERSION])?;
+
+        Ok(Self {
+            writer,
+            registers: [0; 32],
+            error: None,
+        })
+    }
+
+    /// Writes an instruction, with the registers that changed since the last one
+    pub fn encode(&mut self, entry: &TraceEntry) -> std::io::Result<()> {
+        let changed = (0..32)
+            .filter(|&index| entry.registers[index] != self.registers[index])
+            .collect::<Vec<_>>();
+
+        let mut bytes = Vec::with_capacity(9 + changed.len() * 5);
+        bytes.extend_from_slice(&(entry.pc as u32).to_be_bytes());
+        bytes.push(entry.opcode as u8);
+        bytes.extend_from_slice(&entry.operands);
+        bytes.push(changed.len() as u8);
+        for index in changed {
+            bytes.push(index as u8);
+            bytes.extend_from_slice(&entry.registers[index].to_be_bytes());
+        }
+        self.registers = entry.registers;
+
+        self.writer.write_all(&bytes)
+    }
+
+    /// Flushes the trace, returning the writer or the first error writing it
+    pub fn finish(mut self) -> std::io::Result<W> {
+        if let Some(error) = self.error {
+            return Err(error);
+        }
+        self.writer.flush()?;
+
+        Ok(self.writer)
+    }
+}
+
+impl<W: Write> Tracer for TraceEncoder<W> {
+    fn trace(&mut self, step: &TraceStep) {
+        if self.error.is_none() {
+            self.error = self.encode(&step.into()).err();
+        }
+    }
+}
+
+/// Reads a trace written in the binary trace format, or None if it's malformed
+pub fn decode_trace(bytes: &[u8]) -> Option<Vec<TraceEntry>> {
+    let mut bytes = bytes
+        .strip_prefix(&TRACE_MAGIC)?
+        .strip_prefix(&[TRACE_VERSION])?;
+
+    let mut entries = Vec::new();
+    let mut registers = [0; 32];
+    while !bytes.is_empty() {
+        let (instruction, rest) = bytes.split_at_checked(9)?;
+        let (changes, rest) = rest.split_at_checked(instruction[8] as usize * 5)?;
+        bytes = rest;
+
+        for change in changes.chunks_exact(5) {
+            *registers.get_mut(change[0] as usize)? =
+                i32::from_be_bytes(change[1..].try_into().unwrap());
+        }
+        entries.push(TraceEntry {
+            pc: u32::from_be_bytes(instruction[..4].try_into().unwrap()) as usize,
+            opcode: Opcode::from_encoding(instruction[4])?,
+            operands: instruction[5..8].try_into().unwrap(),
+            registers,
+        });
+    }
+
+    Some(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ring_tracer() {
+        let mut registers = [0; 32];
+        let mut tracer = RingTracer::new(2);
+        for pc in [64, 68, 72] {
+            registers[1] = pc as i32;
+            tracer.trace(&TraceStep {
+                pc,
+                opcode: Opcode::ADDI,
+                operands: [1, 0, 4],
+                registers: &registers,
+            });
+        }
+
+        let pcs = tracer.entries().map(|entry| entry.pc).collect::<Vec<_>>();
+        assert_eq!(pcs, [68, 72]);
+        assert_eq!(
+            tracer.entries().next().unwrap().to_string(),
+            "0x0044 ADDI 01 00 04"
+        );
+
+        let mut binary = Vec::new();
+        tracer.write_binary(&mut binary).unwrap();
+        // header, then the first entry with one register set and the second with one changed
+        assert_eq!(binary.len(), 5 + 14 + 14);
+        assert_eq!(
+            decode_trace(&binary),
+            Some(tracer.entries().copied().collect())
+        );
+
+        assert_eq!(decode_trace(&binary[..binary.len() - 1]), None);
+        assert_eq!(decode_trace(b"EVMS"), None);
+    }
+}
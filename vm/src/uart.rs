@@ -0,0 +1,111 @@
+//! Serial port modelled on a UART, and the reference for writing new devices.
+//!
+//! | offset | register | access                                                              |
+//! |--------|----------|---------------------------------------------------------------------|
+//! | 0      | data     | loads take the oldest received byte (0 if none), stores transmit one |
+//! | 1      | status   | bit 0 is set while received bytes are waiting                       |
+//! | 2      | control  | setting bit 0 enables the receive interrupt                         |
+//!
+//! The receive interrupt is requested for as long as it's enabled and received bytes are waiting,
+//! so a handler reading one byte per interrupt is called again until they've all been read.
+
+use crate::device::Device;
+use std::collections::VecDeque;
+
+pub const UART_DATA: usize = 0;
+pub const UART_STATUS: usize = 1;
+pub const UART_CONTROL: usize = 2;
+
+/// Status bit set while received bytes are waiting
+pub const UART_RECEIVED: u8 = 0b01;
+/// Control bit enabling the receive interrupt
+pub const UART_RECEIVE_INTERRUPT: u8 = 0b01;
+
+#[derive(Debug, Default, Clone)]
+pub struct Uart {
+    /// Bytes sent by the host that the program hasn't read yet
+    received: VecDeque<u8>,
+    /// Bytes the program has transmitted that the host hasn't taken yet
+    transmitted: Vec<u8>,
+    control: u8,
+}
+
+impl Uart {
+    /// Queues bytes for the program to read, as if they had arrived on the line
+    pub fn receive(&mut self, bytes: &[u8]) {
+        self.received.extend(bytes);
+    }
+
+    /// Takes every byte the program has transmitted so far
+    pub fn take_transmitted(&mut self) -> Vec<u8> {
+        std::mem::take(&mut self.transmitted)
+    }
+}
+
+impl Device for Uart {
+    fn size(&self) -> usize {
+        3
+    }
+
+    fn load(&mut self, offset: usize) -> u8 {
+        match offset {
+            UART_DATA => self.received.pop_front().unwrap_or(0),
+            UART_STATUS if !self.received.is_empty() => UART_RECEIVED,
+            UART_CONTROL => self.control,
+            _ => 0,
+        }
+    }
+
+    fn store(&mut self, offset: usize, value: u8) {
+        match offset {
+            UART_DATA => self.transmitted.push(value),
+            UART_CONTROL => self.control = value,
+            _ => {}
+        }
+    }
+
+    fn interrupt_pending(&self) -> bool {
+        self.control & UART_RECEIVE_INTERRUPT != 0 && !self.received.is_empty()
+    }
+
+    /// The control register followed by the bytes waiting to be read. Transmitted bytes are the
+    /// host's to take, so aren't saved
+    fn save_state(&self) -> Vec<u8> {
+        let mut state = vec![self.control];
+        state.extend(&self.received);
+
+        state
+    }
+
+    fn restore_state(&mut self, state: &[u8]) {
+        if let Some((&control, received)) = state.split_first() {
+            self.control = control;
+            self.received = received.iter().copied().collect();
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_uart() {
+        let mut uart = Uart::default();
+        uart.receive(b"hi");
+        assert!(!uart.interrupt_pending());
+
+        uart.store(UART_CONTROL, UART_RECEIVE_INTERRUPT);
+        assert!(uart.interrupt_pending());
+        assert_eq!(uart.load(UART_STATUS), UART_RECEIVED);
+        assert_eq!(uart.load(UART_DATA), b'h');
+        assert_eq!(uart.load(UART_DATA), b'i');
+        assert!(!uart.interrupt_pending());
+        assert_eq!(uart.load(UART_STATUS), 0);
+        assert_eq!(uart.load(UART_DATA), 0);
+
+        uart.store(UART_DATA, b'!');
+        assert_eq!(uart.take_transmitted(), b"!");
+        assert!(uart.take_transmitted().is_empty());
+    }
+}
@@ -0,0 +1,314 @@
+//! Checks run over a program before it's executed.
+//!
+//! [`verify`] checks a whole program without running it, so hosts accepting programs from
+//! elsewhere can reject malformed ones up front rather than finding out partway through a run.
+
+use crate::errors::{VerifyError, VmError};
+use crate::memory::{read_header, Memory, Sections};
+use crate::program::Program;
+use shared::abi::REGISTER_COUNT;
+use shared::lines::LINE_SECTION_FIELD;
+use shared::symbols::SYMBOL_SECTION_FIELD;
+use shared::{Opcode, OperandKind, PIE_HEADER_LENGTH};
+use std::fmt::{Display, Formatter};
+use std::ops::Range;
+
+/// Immediate jump or branch whose target isn't an instruction in the code section
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BadJump {
+    /// Address of the jump
+    pub pc: usize,
+    pub target: usize,
+}
+
+impl Display for BadJump {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#06X} -> {:#06X}", self.pc, self.target)
+    }
+}
+
+/// Program that has passed [`verify`]
+#[derive(Debug, Clone, PartialEq)]
+pub struct VerifiedImage {
+    image: Vec<u8>,
+    data: Range<usize>,
+    code: Range<usize>,
+}
+
+impl VerifiedImage {
+    /// The whole program, header included
+    pub fn image(&self) -> &[u8] {
+        &self.image
+    }
+
+    /// Addresses of the data section
+    pub fn data(&self) -> Range<usize> {
+        self.data.clone()
+    }
+
+    /// Addresses of the code section
+    pub fn code(&self) -> Range<usize> {
+        self.code.clone()
+    }
+
+    /// The program with its header parsed, ready to be loaded into a VM
+    pub fn into_program(self) -> Program {
+        Program::parse(self.image).expect("verified programs have valid headers")
+    }
+
+    /// Memory holding the program with its sections mapped, ready to be given to a VM
+    pub fn into_memory(self) -> Memory {
+        Memory::from(self.into_program())
+    }
+}
+
+/// Checks a program's header, that its sections don't overlap each other or the header, and that
+/// every instruction in the code section is legal, only names registers that exist and only jumps
+/// to instructions in the code section. Returns every problem found, except that nothing else is
+/// checked if the header is invalid
+pub fn verify(image: Vec<u8>) -> Result<VerifiedImage, Vec<VerifyError>> {
+    let sections = check(&image)?;
+
+    Ok(VerifiedImage {
+        image,
+        data: sections.data,
+        code: sections.code,
+    })
+}
+
+/// Checks a program like [`verify`] without taking ownership of it, for hosts deciding whether to
+/// run a program and fuzzers feeding in arbitrary bytes. Never panics, whatever the input
+pub fn validate(image: &[u8]) -> Result<(), Vec<VerifyError>> {
+    check(image).map(|_| ())
+}
+
+/// Runs every check made by [`verify`], returning the program's sections if it passes
+fn check(image: &[u8]) -> Result<Sections, Vec<VerifyError>> {
+    let (sections, _) = read_header(image).map_err(|error| vec![error.into()])?;
+    let mut errors = Vec::new();
+
+    // the header has been read, so the symbol and line section fields are present
+    let field =
+        |offset: usize| u32::from_be_bytes(image[offset..offset + 4].try_into().unwrap()) as usize;
+    let symbols = field(SYMBOL_SECTION_FIELD);
+    let lines = field(LINE_SECTION_FIELD);
+    let regions = [
+        ("header", 0..PIE_HEADER_LENGTH),
+        ("data", sections.data.clone()),
+        ("rodata", sections.rodata.clone()),
+        ("code", sections.code.clone()),
+        ("symbol", symbols..symbols + field(SYMBOL_SECTION_FIELD + 4)),
+        ("line", lines..lines + field(LINE_SECTION_FIELD + 4)),
+    ];
+    for (index, (first, a)) in regions.iter().enumerate() {
+        for (second, b) in &regions[index + 1..] {
+            if !a.is_empty() && !b.is_empty() && a.start < b.end && b.start < a.end {
+                errors.push(VerifyError::SectionOverlap { first, second });
+            }
+        }
+    }
+
+    let code = &image[sections.code.clone()];
+    if !code.len().is_multiple_of(4) {
+        errors.push(VerifyError::PartialInstruction { len: code.len() });
+    }
+    for (index, instruction) in code.chunks_exact(4).enumerate() {
+        let pc = sections.code.start + index * 4;
+        let opcode = match Opcode::from_encoding(instruction[0]) {
+            Some(Opcode::IGL) | None => {
+                errors.push(VerifyError::IllegalOpcode { pc });
+                continue;
+            }
+            Some(opcode) => opcode,
+        };
+
+        let mut offset = 1;
+        for kind in opcode.operand_kinds() {
+            let index = instruction[offset];
+            if *kind == OperandKind::Register && index as usize >= REGISTER_COUNT {
+                errors.push(VerifyError::InvalidRegister { index, pc });
+            }
+            offset += kind.size();
+        }
+
+        if let Some(jump) = bad_jump(&sections.code, pc, instruction, true) {
+            errors.push(VerifyError::InvalidJump { jump });
+        }
+    }
+
+    match errors.is_empty() {
+        true => Ok(sections),
+        false => Err(errors),
+    }
+}
+
+/// Checks every immediate jump, call and interrupt vector in the code section targets the code
+/// section. If `aligned`, targets must also be on an instruction boundary. Sections must already
+/// be mapped
+pub(crate) fn verify_jumps(memory: &Memory, aligned: bool) -> Result<(), VmError> {
+    let Some(code) = memory.code_section() else {
+        return Ok(());
+    };
+    let bytes = memory.read(code.start, code.len())?;
+
+    let bad = bytes
+        .chunks_exact(4)
+        .enumerate()
+        .filter_map(|(index, instruction)| {
+            bad_jump(&code, code.start + index * 4, instruction, aligned)
+        })
+        .collect::<Vec<_>>();
+
+    match bad.is_empty() {
+        true => Ok(()),
+        false => Err(VmError::InvalidJumps { jumps: bad }),
+    }
+}
+
+/// The instruction at pc if it's an immediate jump, branch, call or interrupt vector whose target
+/// isn't in the code section, or isn't on an instruction boundary when `aligned`
+fn bad_jump(code: &Range<usize>, pc: usize, instruction: &[u8], aligned: bool) -> Option<BadJump> {
+    let operand = [instruction[1], instruction[2]];
+    let target = match Opcode::from_encoding(instruction[0])? {
+        Opcode::JMPI | Opcode::JMPEI | Opcode::JMPNEI | Opcode::CALLI | Opcode::IVECI => {
+            u16::from_be_bytes(operand) as usize
+        }
+        Opcode::BRA | Opcode::BRE | Opcode::BRNE => {
+            pc.wrapping_add_signed(i16::from_be_bytes(operand) as isize)
+        }
+        _ => return None,
+    };
+    // interrupt vectors of 0 disable interrupts rather than jumping
+    if instruction[0] == Opcode::IVECI as u8 && target == 0 {
+        return None;
+    }
+
+    let on_boundary = target.wrapping_sub(code.start).is_multiple_of(4);
+    (!code.contains(&target) || (aligned && !on_boundary)).then_some(BadJump { pc, target })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use shared::{PIE_FORMAT_VERSION, PIE_HEADER_PREFIX};
+
+    /// Program with no data and the given code
+    fn image(code: &[u8]) -> Vec<u8> {
+        let mut image = vec![0; 64];
+        image[..4].copy_from_slice(&PIE_HEADER_PREFIX);
+        image[4] = PIE_FORMAT_VERSION;
+        image[8..12].copy_from_slice(&64u32.to_be_bytes());
+        image[16..20].copy_from_slice(&64u32.to_be_bytes());
+        image[20..24].copy_from_slice(&(code.len() as u32).to_be_bytes());
+        image.extend_from_slice(code);
+        image
+    }
+
+    /// Maps a program with no data and the given code
+    fn memory(code: &[u8]) -> Memory {
+        let mut memory = Memory::new(image(code));
+        memory.map_sections().unwrap();
+        memory
+    }
+
+    #[test]
+    fn test_verify_jumps() {
+        // jmpi 68; calli 66; jmpei 16; iveci 0; jmpnei 80; bra -4; brne 16
+        let memory = memory(&[
+            160, 0, 68, 0, 176, 0, 66, 0, 164, 0, 16, 0, 184, 0, 0, 0, 168, 0, 80, 0, 224, 255,
+            252, 0, 232, 0, 16, 0,
+        ]);
+
+        let bad = |sites: &[(usize, usize)]| {
+            Err(VmError::InvalidJumps {
+                jumps: sites
+                    .iter()
+                    .map(|&(pc, target)| BadJump { pc, target })
+                    .collect(),
+            })
+        };
+        assert_eq!(verify_jumps(&memory, false), bad(&[(72, 16), (88, 104)]));
+        assert_eq!(
+            verify_jumps(&memory, true),
+            bad(&[(68, 66), (72, 16), (88, 104)])
+        );
+        assert_eq!(verify_jumps(&Memory::default(), true), Ok(()));
+    }
+
+    #[test]
+    fn test_verify() {
+        // jmpi 64; hlt
+        let verified = verify(image(&[160, 0, 64, 0, 0, 0, 0, 0])).unwrap();
+        assert_eq!(verified.code(), 64..72);
+        assert_eq!(verified.into_memory().code_section(), Some(64..72));
+
+        // jmpi 64; addr $1, $40, $2; igl; jmpi 66; then half an instruction
+        let code = [
+            160, 0, 64, 0, 66, 1, 40, 2, 255, 0, 0, 0, 160, 0, 66, 0, 0, 0,
+        ];
+        assert_eq!(
+            verify(image(&code)),
+            Err(vec![
+                VerifyError::PartialInstruction { len: 18 },
+                VerifyError::InvalidRegister { index: 40, pc: 68 },
+                VerifyError::IllegalOpcode { pc: 72 },
+                VerifyError::InvalidJump {
+                    jump: BadJump { pc: 76, target: 66 }
+                },
+            ])
+        );
+
+        // data section starting inside the header and running into the code
+        let mut overlapping = image(&[0; 4]);
+        overlapping[8..16].copy_from_slice(&[0, 0, 0, 60, 0, 0, 0, 8]);
+        assert_eq!(
+            verify(overlapping),
+            Err(vec![
+                VerifyError::SectionOverlap {
+                    first: "header",
+                    second: "data"
+                },
+                VerifyError::SectionOverlap {
+                    first: "data",
+                    second: "code"
+                },
+            ])
+        );
+
+        let mut unsupported = image(&[]);
+        unsupported[4] = 9;
+        assert_eq!(
+            verify(unsupported),
+            Err(vec![VerifyError::Header(VmError::UnsupportedVersion {
+                version: 9
+            })])
+        );
+    }
+
+    #[test]
+    fn test_validate_mutations() {
+        // jmpi 64; ldbi $1, 2; hlt
+        let valid = image(&[160, 0, 64, 0, 4, 1, 0, 2, 0, 0, 0, 0]);
+        assert_eq!(validate(&valid), Ok(()));
+
+        // xorshift, so every run corrupts the same bytes
+        let mut state = 0x2545_F491_u32;
+        let mut next = || {
+            state ^= state << 13;
+            state ^= state >> 17;
+            state ^= state << 5;
+            state as usize
+        };
+        for _ in 0..10_000 {
+            let mut mutated = valid.clone();
+            for _ in 0..next() % 4 + 1 {
+                let index = next() % mutated.len();
+                mutated[index] = next() as u8;
+            }
+            mutated.truncate(next() % (mutated.len() + 1));
+
+            // only checking nothing panics, whatever the bytes
+            let _ = validate(&mutated);
+        }
+    }
+}
@@ -1,334 +1,1277 @@
+use crate::allocator::Allocation;
+use crate::cache::InstructionCache;
+use crate::cluster::Mailbox;
+use crate::config::{Limit, VMConfig};
+use crate::errors::{SnapshotError, VmError};
+use crate::files::FileTable;
+use crate::history::{History, Step};
 use crate::instruction::Instruction;
+use crate::logger::{LogLevel, LogRecord, Logger};
+use crate::memory::{Memory, Region};
+use crate::objects::{Object, ObjectHeap};
+use crate::output::Tee;
+use crate::profile::Profile;
+use crate::program::Program;
+use crate::shadow_stack::{Frame, ShadowStack};
+use crate::snapshot::{Snapshot, Writer};
+use crate::sources::{Clock, Random, SystemClock, XorShift};
+use crate::syscall::Syscall;
+#[cfg(feature = "timing")]
+use crate::timing::OpcodeTimings;
+use crate::tracer::{TraceStep, Tracer};
+use crate::verify::verify_jumps;
+use crate::word::Word;
+use shared::abi::{
+    ARGC_REGISTER, ARGV_REGISTER, FRAME_POINTER, STACK_ALIGNMENT, STACK_POINTER, STACK_TOP,
+};
 use shared::Opcode;
-use shared::PIE_HEADER_PREFIX;
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fmt::Display;
+use std::io::{BufRead, Write};
+use std::path::Path;
+
+/// Why a limited run stopped
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RunStatus {
+    /// The program halted or ran off the end of its code
+    Halted,
+    /// The instruction limit was reached before the program halted
+    LimitReached,
+}
 
-/// Main virtual machine
-#[derive(Default)]
-pub struct VM {
+/// Main virtual machine, with registers and memory words of type `W`
+pub struct VM<W: Word = i32> {
     /// CPU Registers
-    pub registers: [i32; 32],
+    registers: [W; 32],
     /// Program counter - current byte being executed
     pc: usize,
-    /// Program to be executed
-    pub program: Vec<u8>,
+    /// Address of the instruction last executed, or that faulted
+    instruction_pc: usize,
+    /// Memory holding the program to be executed, the heap and the stack
+    memory: Memory,
     /// Start of bytecode section
     code_section_start: usize,
     /// Remainder from previous instruction
-    remainder: u32,
+    remainder: W,
     /// Equality from last comparison instruction
-    pub equality_flag: bool,
+    equality_flag: bool,
+    /// Address jumped to when a device requests an interrupt, or None if interrupts are disabled
+    interrupt_vector: Option<usize>,
+    /// Whether an interrupt is being handled, which masks any others until it returns
+    in_interrupt: bool,
+    /// Decoded instructions, invalidated by stores into the bytes they were decoded from
+    instruction_cache: InstructionCache,
+    /// Resource limits
+    config: VMConfig,
+    /// Instructions executed since the program was started
+    steps: u64,
+    /// Status the program exited with, which is zero unless it halted with `EXIT`
+    exit_status: i32,
+    /// Hook invoked before every instruction
+    tracer: Option<Box<dyn Tracer<W>>>,
+    /// Receives messages logged by the program, which are dropped if None
+    logger: Option<Box<dyn Logger>>,
+    /// Addresses execution stops at when resumed
+    breakpoints: Vec<usize>,
+    /// Where strings printed by the program and the halt message are written, defaulting to stdout
+    output: Tee,
+    /// Where input read by the program comes from, or None to read from stdin
+    input: Option<Box<dyn BufRead>>,
+    /// Bytes of input the program has read since it was started
+    input_read: u64,
+    /// Calls that haven't returned, if enabled
+    shadow_stack: Option<ShadowStack>,
+    /// Executions of each opcode and address, if enabled
+    profile: Option<Profile>,
+    /// Host functions called by `SYSI`, by number
+    syscalls: HashMap<u16, Box<dyn Syscall<W>>>,
+    /// Whether immediate jump targets are checked when the program is started
+    verify_jumps: bool,
+    /// Connection to the other VMs in the cluster running this one, if any
+    mailbox: Option<Mailbox>,
+    /// Where `RAND` gets its numbers from
+    random: Box<dyn Random>,
+    /// Where `TIME` reads the time from. Snapshots read it too, so it's behind a `RefCell`
+    clock: RefCell<Box<dyn Clock>>,
+    /// Clock reading when the program was started, or restored from a snapshot
+    clock_start: u64,
+    /// Milliseconds that had already passed when the program was restored from a snapshot
+    clock_offset: u64,
+    /// Files opened by the program, and the directories it may open them in
+    files: FileTable,
+    /// Garbage collected objects created by the program, if enabled
+    objects: Option<ObjectHeap>,
+    /// Side effects of the most recently executed instructions, if recording
+    history: Option<History<W>>,
+    /// Time spent in each opcode's handler
+    #[cfg(feature = "timing")]
+    timings: OpcodeTimings,
+}
+
+impl Default for VM {
+    fn default() -> Self {
+        Self::with_config(VMConfig::default())
+    }
 }
 
 impl VM {
-    fn verify_header(&self) -> bool {
-        self.program[0..4] == PIE_HEADER_PREFIX
+    /// Creates a VM enforcing the given resource limits
+    pub fn with_config(config: VMConfig) -> Self {
+        Self::new(config)
+    }
+}
+
+impl<W: Word> VM<W> {
+    /// Creates a VM with registers of type `W` enforcing the given resource limits, such as
+    /// `VM::<i64>::new` for running programs assembled for 8 byte words
+    pub fn new(config: VMConfig) -> Self {
+        let mut registers = [W::default(); 32];
+        registers[STACK_POINTER as usize] = W::from_address(STACK_TOP);
+
+        Self {
+            registers,
+            pc: 0,
+            instruction_pc: 0,
+            memory: Memory::default(),
+            code_section_start: 0,
+            remainder: W::default(),
+            equality_flag: false,
+            interrupt_vector: None,
+            in_interrupt: false,
+            instruction_cache: InstructionCache::default(),
+            config,
+            steps: 0,
+            exit_status: 0,
+            tracer: None,
+            logger: None,
+            breakpoints: Vec::new(),
+            output: default_output(),
+            input: None,
+            input_read: 0,
+            shadow_stack: None,
+            profile: None,
+            syscalls: HashMap::new(),
+            verify_jumps: false,
+            mailbox: None,
+            random: Box::new(XorShift::from_entropy()),
+            clock: RefCell::new(Box::new(SystemClock::default())),
+            clock_start: 0,
+            clock_offset: 0,
+            files: FileTable::default(),
+            objects: None,
+            history: None,
+            #[cfg(feature = "timing")]
+            timings: OpcodeTimings::default(),
+        }
+    }
+
+    /// Runs VM until completion, ignoring breakpoints
+    pub fn run(&mut self) -> Result<(), VmError> {
+        self.start()?;
+
+        while self.execute_instruction()? {}
+
+        Ok(())
+    }
+
+    /// Runs VM until completion like `run`, but executes hot loops from pre-decoded basic blocks
+    /// instead of fetching every instruction separately. Behaves exactly like `run`, including
+    /// for programs that modify their own code, just faster for programs that spend most of their
+    /// time in loops
+    pub fn run_cached(&mut self) -> Result<(), VmError> {
+        self.start()?;
+
+        loop {
+            let running = match self.execute_block()? {
+                Some(running) => running,
+                None => self.execute_instruction()?,
+            };
+            if !running {
+                return Ok(());
+            }
+        }
+    }
+
+    /// Runs the VM from the start for at most max_instructions instructions, ignoring breakpoints.
+    /// Unlike the `max_steps` limit, reaching this one isn't a fault, so the run can be continued
+    /// with `resume`
+    pub fn run_with_limit(&mut self, max_instructions: u64) -> Result<RunStatus, VmError> {
+        self.start()?;
+
+        for _ in 0..max_instructions {
+            if !self.execute_instruction()? {
+                return Ok(RunStatus::Halted);
+            }
+        }
+
+        Ok(RunStatus::LimitReached)
+    }
+
+    /// Status the program exited with, set by `EXIT`. Zero if it halted with `HLT` or hasn't
+    /// halted yet
+    pub fn exit_status(&self) -> i32 {
+        self.exit_status
+    }
+
+    /// Number of instructions executed since the program was started
+    pub fn steps(&self) -> u64 {
+        self.steps
+    }
+
+    /// Time spent in each opcode's handler, across every run since the timings were cleared
+    #[cfg(feature = "timing")]
+    pub fn timings(&self) -> &OpcodeTimings {
+        &self.timings
+    }
+
+    #[cfg(feature = "timing")]
+    pub fn clear_timings(&mut self) {
+        self.timings.clear();
+    }
+
+    /// Sets the hook invoked before every instruction, replacing any existing one
+    pub fn set_tracer(&mut self, tracer: impl Tracer<W> + 'static) {
+        self.tracer = Some(Box::new(tracer));
+    }
+
+    /// Removes the tracer, returning it if one was set
+    pub fn take_tracer(&mut self) -> Option<Box<dyn Tracer<W>>> {
+        self.tracer.take()
+    }
+
+    /// Connects the VM to the rest of its cluster
+    pub(crate) fn set_mailbox(&mut self, mailbox: Mailbox) {
+        self.mailbox = Some(mailbox);
+    }
+
+    /// Sets where messages logged by the program are sent, replacing any existing logger
+    pub fn set_logger(&mut self, logger: impl Logger + 'static) {
+        self.logger = Some(Box::new(logger));
+    }
+
+    /// Removes the logger, returning it if one was set
+    pub fn take_logger(&mut self) -> Option<Box<dyn Logger>> {
+        self.logger.take()
+    }
+
+    /// Registers the host function called by `SYSI number`, replacing any existing one
+    pub fn register_syscall(&mut self, number: u16, syscall: impl Syscall<W> + 'static) {
+        self.syscalls.insert(number, Box::new(syscall));
+    }
+
+    /// Sets where strings printed by the program are written, replacing every other sink
+    pub fn set_output(&mut self, output: impl Write + 'static) {
+        self.output.clear();
+        self.output.push(output);
+    }
+
+    /// Adds another sink strings printed by the program are written to, alongside the existing
+    /// ones
+    pub fn add_output(&mut self, output: impl Write + 'static) {
+        self.output.push(output);
+    }
+
+    /// Sets where input read by the program comes from, instead of stdin
+    pub fn set_input(&mut self, input: impl BufRead + 'static) {
+        self.input = Some(Box::new(input));
+    }
+
+    /// Sets where `RAND` gets its numbers from, instead of a generator seeded differently every
+    /// run
+    pub fn set_random(&mut self, random: impl Random + 'static) {
+        self.random = Box::new(random);
+    }
+
+    /// Sets where `TIME` reads the time from, instead of the system's clock. `TIME` counts from the
+    /// clock's current reading until the program is started again
+    pub fn set_clock(&mut self, clock: impl Clock + 'static) {
+        self.clock = RefCell::new(Box::new(clock));
+        self.clock_start = self.clock.get_mut().now_ms();
+        self.clock_offset = 0;
+    }
+
+    /// Allows the program to open files anywhere inside a directory with `FOPEN`. Programs can't
+    /// open any files until a directory is allowed
+    pub fn allow_directory(&mut self, directory: impl AsRef<Path>) -> std::io::Result<()> {
+        self.files.allow(directory.as_ref())
+    }
+
+    /// Sets whether the program can create garbage collected objects with `NEWARR`. Enabling the
+    /// object heap starts an empty one
+    pub fn enable_object_heap(&mut self, enabled: bool) {
+        self.objects = enabled.then(ObjectHeap::default);
+    }
+
+    /// Objects created by the program, if the object heap is enabled
+    pub fn objects(&self) -> Option<&ObjectHeap> {
+        self.objects.as_ref()
+    }
+
+    /// Frees every object that isn't referenced from a register, the data section, bss, heap or
+    /// stack, or another object, returning the number freed. Objects are collected automatically as the heap grows, so this
+    /// is only needed to free them sooner
+    pub fn collect_garbage(&mut self) -> usize {
+        let Some(objects) = &mut self.objects else {
+            return 0;
+        };
+
+        // the stack is only scanned while the stack pointer is within it
+        let stack_pointer = self.registers[STACK_POINTER as usize].to_address();
+        let stack = STACK_TOP
+            .checked_sub(stack_pointer)
+            .map(|len| stack_pointer..stack_pointer + len);
+        let heap_start = self.memory.heap_start();
+        let heap = heap_start..heap_start + self.memory.heap_size();
+        let ranges = [
+            self.memory.data_section(),
+            self.memory.bss_section(),
+            Some(heap),
+            stack,
+        ];
+
+        // handles are 32 bits whatever the word size, so memory is scanned 4 bytes at a time, which
+        // also finds handles in the low half of 8 byte words
+        let memory = ranges
+            .into_iter()
+            .flatten()
+            .filter_map(|range| self.memory.read(range.start, range.len()).ok())
+            .flat_map(|bytes| bytes.chunks_exact(4))
+            .map(|bytes| i32::from_be_bytes(bytes.try_into().unwrap()));
+
+        objects.collect(
+            self.registers
+                .iter()
+                .map(|&register| register.to_i32())
+                .chain(memory),
+        )
+    }
+
+    /// Sets whether calls are recorded on a shadow stack, so a backtrace is available if the
+    /// program faults
+    pub fn enable_shadow_stack(&mut self, enabled: bool) {
+        self.shadow_stack = enabled.then(ShadowStack::default);
+    }
+
+    /// Sets whether the executions of each opcode and instruction address are counted. Enabling
+    /// profiling starts a new profile
+    pub fn enable_profiling(&mut self, enabled: bool) {
+        self.profile = enabled.then(Profile::default);
+    }
+
+    /// Instruction counts since the program was started, if profiling is enabled
+    pub fn profile(&self) -> Option<&Profile> {
+        self.profile.as_ref()
+    }
+
+    /// Sets whether the program is rejected when started if any immediate jump, call or interrupt
+    /// vector targets an address outside the code section, or one that isn't on an instruction
+    /// boundary while alignment is checked
+    pub fn enable_jump_verification(&mut self, enabled: bool) {
+        self.verify_jumps = enabled;
+    }
+
+    /// Calls that haven't returned yet, outermost first, or None if the shadow stack isn't enabled
+    pub fn backtrace(&self) -> Option<&[Frame]> {
+        self.shadow_stack.as_ref().map(ShadowStack::frames)
+    }
+
+    /// Replaces memory with a parsed program, ready to be run. Alignment checking and devices are
+    /// kept
+    pub fn load(&mut self, program: Program) {
+        let previous = std::mem::replace(&mut self.memory, Memory::from(program));
+        self.memory.keep_host_settings(&previous);
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+    }
+
+    /// Replaces the program with a new version of it and starts it, for reloading a program while
+    /// debugging it. With `keep_state` the registers other than the stack pointer, the equality
+    /// flag and the heap are carried over, so the new version continues with the old one's data.
+    /// Allocations are recorded by address, so the heap is only kept if it starts at the same
+    /// address in both versions. Returns whether it was
+    pub fn reload(&mut self, program: Program, keep_state: bool) -> Result<bool, VmError> {
+        let mut previous = std::mem::replace(&mut self.memory, Memory::from(program));
+        self.memory.keep_host_settings(&previous);
+
+        let heap_kept = keep_state && self.memory.take_heap(&mut previous);
+        if !keep_state {
+            self.registers = [W::default(); 32];
+            self.equality_flag = false;
+        }
+        self.start()?;
+
+        Ok(heap_kept)
+    }
+
+    /// Copies program arguments onto the heap as null terminated strings, after a word aligned
+    /// array of their addresses, and sets the argument count and array address registers to
+    /// match. Must be called after the program is loaded, since loading replaces the heap
+    pub fn set_args<S: AsRef<[u8]>>(&mut self, args: &[S]) -> Result<(), VmError> {
+        let heap_end = self.memory.heap_start() + self.memory.heap_size();
+        let padding = heap_end.next_multiple_of(W::BYTES) - heap_end;
+        let strings_len: usize = args.iter().map(|arg| arg.as_ref().len() + 1).sum();
+        let size = padding + args.len() * W::BYTES + strings_len;
+
+        let heap_size = self.memory.heap_size().saturating_add(size);
+        if self.config.max_heap.is_some_and(|max| heap_size > max) {
+            return Err(VmError::ResourceExhausted { limit: Limit::Heap });
+        }
+        let argv = self
+            .memory
+            .grow_heap(size)
+            .ok_or(VmError::ResourceExhausted { limit: Limit::Heap })?
+            + padding;
+
+        let mut address = argv + args.len() * W::BYTES;
+        for (index, arg) in args.iter().enumerate() {
+            let arg = arg.as_ref();
+            self.memory.write(
+                argv + index * W::BYTES,
+                &W::from_address(address).to_be_vec(),
+            )?;
+            self.memory.write(address, arg)?;
+
+            // the heap is zeroed, so the null terminator is already there
+            address += arg.len() + 1;
+        }
+
+        self.registers[ARGC_REGISTER as usize] = W::from_address(args.len());
+        self.registers[ARGV_REGISTER as usize] = W::from_address(argv);
+
+        Ok(())
+    }
+
+    /// Moves the program counter to the start of the code section, ready to run the program
+    pub fn start(&mut self) -> Result<(), VmError> {
+        if self
+            .config
+            .max_program_size
+            .is_some_and(|max| self.memory.image().len() > max)
+        {
+            return Err(VmError::ResourceExhausted {
+                limit: Limit::ProgramSize,
+            });
+        }
+
+        // programs given to `load` were parsed up front, so only raw images are parsed here
+        match self.memory.code_section() {
+            Some(_) => self.memory.clear_bss(),
+            None => self.memory.map_sections()?,
+        }
+        let size = self.memory.word_size().unwrap();
+        if size != W::BYTES {
+            return Err(VmError::WordSizeMismatch {
+                size,
+                expected: W::BYTES,
+            });
+        }
+        if self.verify_jumps {
+            verify_jumps(&self.memory, self.memory.alignment_checked())?;
+        }
+        self.code_section_start = self.memory.code_section().unwrap().start;
+
+        self.pc = self.memory.entry_point().unwrap();
+        self.registers[STACK_POINTER as usize] = W::from_address(STACK_TOP);
+        self.steps = 0;
+        self.exit_status = 0;
+        self.clock_start = self.clock.get_mut().now_ms();
+        self.clock_offset = 0;
+        self.input_read = 0;
+        self.files.close_all();
+        if let Some(objects) = &mut self.objects {
+            *objects = ObjectHeap::default();
+        }
+        self.interrupt_vector = None;
+        self.in_interrupt = false;
+        if let Some(shadow_stack) = &mut self.shadow_stack {
+            shadow_stack.clear();
+        }
+        if let Some(profile) = &mut self.profile {
+            *profile = Profile::default();
+        }
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
+
+        // program may have been replaced since the last run
+        self.instruction_cache.clear();
+
+        Ok(())
+    }
+
+    /// Saves the state of the running program, so it can be continued later with `restore`
+    pub fn snapshot(&self) -> Snapshot {
+        let mut writer = Writer::new();
+
+        writer.usize(W::BYTES);
+        for &register in &self.registers {
+            writer.u64(register.to_i64() as u64);
+        }
+        writer.usize(self.pc);
+        writer.usize(self.instruction_pc);
+        writer.usize(self.code_section_start);
+        writer.u64(self.remainder.to_i64() as u64);
+        writer.bool(self.equality_flag);
+        writer.usize(self.interrupt_vector.unwrap_or(0));
+        writer.bool(self.in_interrupt);
+        writer.u64(self.steps);
+        writer.u32(self.exit_status as u32);
+        self.memory.save(&mut writer);
+
+        writer.bool(self.shadow_stack.is_some());
+        if let Some(shadow_stack) = &self.shadow_stack {
+            shadow_stack.save(&mut writer);
+        }
+
+        self.memory.save_devices(&mut writer);
+        writer.bytes(&self.random.save_state());
+        writer.u64(self.elapsed_ms());
+        writer.u64(self.input_read);
+        self.files.save(&mut writer);
+        writer.bool(self.objects.is_some());
+        if let Some(objects) = &self.objects {
+            objects.save(&mut writer);
+        }
+
+        writer.finish()
     }
 
-    /// Runs VM until completion
-    pub fn run(&mut self) {
-        // test header and then skip to code section
-        if !self.verify_header() {}
-        self.code_section_start =
-            u32::from_be_bytes(self.program[16..20].try_into().unwrap()) as usize;
+    /// Replaces the state of the VM with a snapshot, leaving it unchanged if the snapshot is
+    /// invalid. Breakpoints, the tracer, outputs, limits, alignment checking and the devices
+    /// themselves are kept, and are given the state the devices had when it was taken. Files the
+    /// program had open are reopened, so must still be inside an allowed directory, and input set
+    /// with `set_input` is skipped forward to where the program had read up to
+    pub fn restore(&mut self, snapshot: &Snapshot) -> Result<(), SnapshotError> {
+        let mut reader = snapshot.reader();
+
+        let size = reader.usize()?;
+        if size != W::BYTES {
+            return Err(SnapshotError::WordSizeMismatch {
+                size,
+                expected: W::BYTES,
+            });
+        }
+        let mut registers = [W::default(); 32];
+        for register in &mut registers {
+            *register = W::from_i64(reader.u64()? as i64);
+        }
+        let pc = reader.usize()?;
+        let instruction_pc = reader.usize()?;
+        let code_section_start = reader.usize()?;
+        let remainder = W::from_i64(reader.u64()? as i64);
+        let equality_flag = reader.bool()?;
+        let interrupt_vector = Some(reader.usize()?).filter(|&vector| vector != 0);
+        let in_interrupt = reader.bool()?;
+        let steps = reader.u64()?;
+        let exit_status = reader.u32()? as i32;
+        let mut memory = Memory::load(&mut reader)?;
+        memory.keep_host_settings(&self.memory);
+        let shadow_stack = match reader.bool()? {
+            true => Some(ShadowStack::load(&mut reader)?),
+            false => None,
+        };
+        let devices = self.memory.load_devices(&mut reader)?;
+        let random = reader.bytes()?;
+        let elapsed = reader.u64()?;
+        let input_read = reader.u64()?;
+        let files = self.files.reopen(&mut reader)?;
+        let objects = match reader.bool()? {
+            true => Some(ObjectHeap::load(&mut reader)?),
+            false => None,
+        };
+        reader.finish()?;
+
+        self.registers = registers;
+        self.pc = pc;
+        self.instruction_pc = instruction_pc;
+        self.code_section_start = code_section_start;
+        self.remainder = remainder;
+        self.equality_flag = equality_flag;
+        self.interrupt_vector = interrupt_vector;
+        self.in_interrupt = in_interrupt;
+        self.steps = steps;
+        self.exit_status = exit_status;
+        self.memory = memory;
+        self.shadow_stack = shadow_stack;
+        self.memory.restore_devices(&devices);
+        self.random.restore_state(random);
+        self.clock_start = self.clock.get_mut().now_ms();
+        self.clock_offset = elapsed;
+        if let Some(input) = &mut self.input {
+            skip(input, input_read.saturating_sub(self.input_read));
+        }
+        self.input_read = input_read;
+        self.files.restore(files);
+        self.objects = objects;
+        self.instruction_cache.clear();
+        if let Some(history) = &mut self.history {
+            history.clear();
+        }
 
-        self.pc = self.code_section_start;
+        Ok(())
+    }
 
-        while self.execute_instruction() {}
+    /// Records the side effects of up to the last `capacity` instructions executed, so they can be
+    /// undone with `step_back`, or stops recording if None. Hot loops aren't run from pre-decoded
+    /// blocks while recording
+    pub fn set_recording(&mut self, capacity: Option<usize>) {
+        self.history = capacity.map(History::new);
     }
 
-    /// Runs the VM, executing a single instruction
-    pub fn run_once(&mut self) {
-        self.execute_instruction();
+    /// Number of instructions that can be undone with `step_back`
+    pub fn recorded_steps(&self) -> usize {
+        self.history.as_ref().map_or(0, History::len)
     }
 
-    /// Executes a single instruction, returning a bool indicating if another instruction can be ran
-    /// afterwards
-    fn execute_instruction(&mut self) -> bool {
-        if self.pc >= self.program.len() {
+    /// Undoes the last recorded instruction, putting back the registers, flags, memory, heap and
+    /// calls it changed. Anything it printed, and files, objects and devices it changed, stay
+    /// changed. Returns false if there's no recorded instruction to undo
+    pub fn step_back(&mut self) -> bool {
+        let Some(mut step) = self.history.as_mut().and_then(History::pop) else {
             return false;
+        };
+
+        for (address, bytes) in std::mem::take(&mut step.journal.writes).iter().rev() {
+            // the bytes were stored there, so they can be put back
+            self.poke(*address, bytes).unwrap();
+        }
+        self.memory.undo_heap(step.journal);
+        for (index, value) in step.registers {
+            self.registers[index as usize] = value;
+        }
+        if let (Some(frames), Some(stack)) = (step.frames, &mut self.shadow_stack) {
+            stack.set_frames(frames);
         }
+        self.pc = step.pc;
+        self.instruction_pc = step.instruction_pc;
+        self.equality_flag = step.equality_flag;
+        self.remainder = step.remainder;
+        self.steps = step.steps;
+        self.exit_status = step.exit_status;
+        self.interrupt_vector = step.interrupt_vector;
+        self.in_interrupt = step.in_interrupt;
 
-        // read 4 bytes and advance PC
-        let mut instruction =
-            if let Some(inst) = Instruction::from(&self.program[self.pc..self.pc + 4]) {
-                inst
-            } else {
-                return false;
-            };
+        true
+    }
+
+    /// Runs the VM, executing a single instruction. Returns a bool indicating if another
+    /// instruction can be ran afterwards
+    pub fn run_once(&mut self) -> Result<bool, VmError> {
+        self.execute_instruction()
+    }
+
+    /// Runs the VM until it finishes or reaches a breakpoint, always executing at least one
+    /// instruction. Returns a bool indicating if it stopped at a breakpoint
+    pub fn resume(&mut self) -> Result<bool, VmError> {
+        while self.execute_instruction()? {
+            if self.breakpoints.contains(&self.pc) {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+
+    /// Adds a breakpoint at address, returning false if one already exists there
+    pub fn add_breakpoint(&mut self, address: usize) -> bool {
+        if self.breakpoints.contains(&address) {
+            return false;
+        }
+
+        self.breakpoints.push(address);
+        true
+    }
+
+    /// Removes the breakpoint with the given index, returning its address
+    pub fn remove_breakpoint(&mut self, index: usize) -> Option<usize> {
+        (index < self.breakpoints.len()).then(|| self.breakpoints.remove(index))
+    }
+
+    /// Breakpoint addresses, in the order they were added
+    pub fn breakpoints(&self) -> &[usize] {
+        &self.breakpoints
+    }
+
+    /// Writes bytes to memory regardless of protection, dropping any cached instructions they
+    /// overlap. Used by debuggers to patch a running program
+    pub fn poke(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        self.memory.poke(address, bytes)?;
+        self.instruction_cache
+            .invalidate(address..address + bytes.len());
+
+        Ok(())
+    }
+
+    /// CPU registers
+    pub fn registers(&self) -> &[W; 32] {
+        &self.registers
+    }
+
+    /// CPU registers, for passing values to the program or changing them while debugging
+    pub fn registers_mut(&mut self) -> &mut [W; 32] {
+        &mut self.registers
+    }
+
+    /// Equality from the last comparison instruction
+    pub fn equality_flag(&self) -> bool {
+        self.equality_flag
+    }
+
+    /// Memory holding the program, the heap and the stack
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    /// Memory, for mapping devices and changing how it's checked. Its bytes can only be changed
+    /// with `poke`, so cached instructions are dropped whenever they're overwritten
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    /// Address of the next instruction to be executed
+    pub fn pc(&self) -> usize {
+        self.pc
+    }
+
+    /// Moves the program counter, for running code appended to the program while debugging
+    pub fn set_pc(&mut self, pc: usize) {
+        self.pc = pc;
+    }
+
+    /// Address of the instruction last executed, or of the instruction that faulted
+    pub fn instruction_pc(&self) -> usize {
+        self.instruction_pc
+    }
+
+    /// Executes a single instruction, recording its side effects if recording is enabled. Returns a
+    /// bool indicating if another instruction can be ran afterwards
+    fn execute_instruction(&mut self) -> Result<bool, VmError> {
+        if self.history.is_none() {
+            return self.fetch_and_execute();
+        }
+
+        let registers = self.registers;
+        let frames = self
+            .shadow_stack
+            .as_ref()
+            .map(|stack| stack.frames().to_vec());
+        let mut step = Step {
+            pc: self.pc,
+            instruction_pc: self.instruction_pc,
+            registers: Vec::new(),
+            equality_flag: self.equality_flag,
+            remainder: self.remainder,
+            steps: self.steps,
+            exit_status: self.exit_status,
+            interrupt_vector: self.interrupt_vector,
+            in_interrupt: self.in_interrupt,
+            frames: None,
+            journal: Default::default(),
+        };
+        self.memory.start_journal();
+        let result = self.fetch_and_execute();
+        step.journal = self.memory.take_journal().unwrap();
+
+        // nothing happened if the program had already stopped
+        if step.steps == self.steps && step.pc == self.pc && step.journal.writes.is_empty() {
+            return result;
+        }
+        step.registers = (0..32)
+            .filter(|&index| registers[index] != self.registers[index])
+            .map(|index| (index as u8, registers[index]))
+            .collect();
+        // calls only ever push frames and returns pop them, so they changed if the depth did
+        if let (Some(frames), Some(stack)) = (frames, &self.shadow_stack) {
+            if frames.len() != stack.frames().len() {
+                step.frames = Some(frames);
+            }
+        }
+        self.history.as_mut().unwrap().push(step);
+
+        result
+    }
+
+    /// Fetches and executes the instruction at the program counter. Execution stops once the
+    /// program counter runs off the end of the code section or leaves memory, and faults if it
+    /// moves into memory that isn't code
+    fn fetch_and_execute(&mut self) -> Result<bool, VmError> {
+        self.instruction_pc = self.pc;
+        if self.memory.code_section().map(|code| code.end) == Some(self.pc) {
+            return Ok(false);
+        }
+        self.check_interrupt()?;
+        match self.memory.region(self.pc) {
+            None => return Ok(false),
+            Some(Region::Code) => {}
+            Some(_) => return Err(VmError::NotExecutable { pc: self.pc }),
+        }
+        self.count_step()?;
+
+        let instruction = self
+            .instruction_cache
+            .fetch(self.pc, self.memory.executable())
+            .ok_or(VmError::TruncatedInstruction { pc: self.pc })?;
+
+        self.execute(instruction)
+    }
+
+    /// Runs the hot basic block starting at the program counter from its pre-decoded instructions,
+    /// or returns None if it isn't hot yet. Leaves the block early if anything other than the
+    /// next instruction in it would be executed, or a store invalidates it. Returns a bool
+    /// indicating if another instruction can be ran afterwards
+    fn execute_block(&mut self) -> Result<Option<bool>, VmError> {
+        // every instruction is recorded on its own
+        if self.history.is_some() {
+            return Ok(None);
+        }
+        let Some(block) = self.instruction_cache.block(
+            self.pc,
+            self.memory.executable(),
+            self.memory.code_section().unwrap(),
+        ) else {
+            return Ok(None);
+        };
+        let generation = self.instruction_cache.generation();
+
+        for &instruction in block.iter() {
+            let pc = self.pc;
+            self.instruction_pc = pc;
+            self.check_interrupt()?;
+            if self.pc != pc {
+                break;
+            }
+            self.count_step()?;
+
+            if !self.execute(instruction)? {
+                return Ok(Some(false));
+            }
+            if self.pc != pc + 4 || self.instruction_cache.generation() != generation {
+                break;
+            }
+        }
+
+        Ok(Some(true))
+    }
+
+    /// Jumps to the interrupt handler if a device is requesting an interrupt and interrupts are
+    /// enabled and not masked
+    fn check_interrupt(&mut self) -> Result<(), VmError> {
+        if let Some(vector) = self.interrupt_vector {
+            if !self.in_interrupt && self.memory.interrupt_pending() {
+                self.interrupt(vector)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Counts an instruction towards the step limit, faulting if it has been reached
+    fn count_step(&mut self) -> Result<(), VmError> {
+        if self.config.max_steps.is_some_and(|max| self.steps >= max) {
+            return Err(VmError::ResourceExhausted {
+                limit: Limit::Steps,
+            });
+        }
+        self.steps += 1;
+
+        Ok(())
+    }
+
+    /// Executes a decoded instruction found at the program counter, advancing past it. Returns a
+    /// bool indicating if another instruction can be ran afterwards
+    #[cfg(feature = "timing")]
+    fn execute(&mut self, instruction: Instruction) -> Result<bool, VmError> {
+        let start = std::time::Instant::now();
+        let result = self.dispatch(instruction);
+        self.timings.record(instruction.opcode, start);
+
+        result
+    }
+
+    #[cfg(not(feature = "timing"))]
+    fn execute(&mut self, instruction: Instruction) -> Result<bool, VmError> {
+        self.dispatch(instruction)
+    }
+
+    /// Runs the handler for a decoded instruction
+    fn dispatch(&mut self, mut instruction: Instruction) -> Result<bool, VmError> {
+        if let Some(tracer) = &mut self.tracer {
+            tracer.trace(&TraceStep {
+                pc: self.pc,
+                opcode: instruction.opcode,
+                operands: instruction.operands(),
+                registers: &self.registers,
+            });
+        }
+        if let Some(profile) = &mut self.profile {
+            profile.record(instruction.opcode, self.pc);
+        }
         self.pc += 4;
 
         match instruction.opcode {
             Opcode::HLT => {
-                println!("Halting!");
-                return false;
+                self.print_line("Halting!")?;
+                return Ok(false);
+            }
+            Opcode::EXIT => {
+                self.exit_status = instruction.next_register(&self.registers)?.to_i32();
+                self.print_line("Halting!")?;
+                return Ok(false);
             }
             Opcode::LDBI => {
-                let register = instruction.next_u8() as usize;
-                let value = instruction.next_u16() as u8 as i32;
+                let register = instruction.next_register_mut(&mut self.registers)?;
+                let value = W::from_i32(instruction.next_u16() as u8 as i32);
 
-                self.registers[register] = value;
+                *register = value;
             }
             Opcode::LDBD => {
-                let register = instruction.next_u8() as usize;
+                let register = instruction.next_u8();
                 let address = instruction.next_u16() as usize;
 
-                self.registers[register] = self.program[address] as i32;
+                let byte = self.memory.read_u8(address)?;
+
+                *self.register_mut(register)? = W::from_i32(byte as i32);
             }
             Opcode::LDBR => {
-                let register = instruction.next_u8() as usize;
-                let address = instruction.next_register(&self.registers) as usize;
+                let register = instruction.next_u8();
+                let address = instruction.next_register(&self.registers)?.to_address();
 
-                self.registers[register] = self.program[address] as i32;
+                let byte = self.memory.read_u8(address)?;
+
+                *self.register_mut(register)? = W::from_i32(byte as i32);
             }
             Opcode::LDHI => {
-                let register = instruction.next_u8() as usize;
-                let value = instruction.next_u16() as i32;
+                let register = instruction.next_register_mut(&mut self.registers)?;
+                let value = W::from_i32(instruction.next_u16() as i32);
 
-                self.registers[register] = value;
+                *register = value;
             }
             Opcode::LDHD => {
-                let register = instruction.next_u8() as usize;
+                let register = instruction.next_u8();
                 let address = instruction.next_u16() as usize;
 
-                let bytes = [self.program[address], self.program[address + 1]];
+                let half = self.memory.read_u16(address)?;
 
-                self.registers[register] = i16::from_be_bytes(bytes) as i32;
+                *self.register_mut(register)? = W::from_i32(half as i16 as i32);
             }
             Opcode::LDHR => {
-                let register = instruction.next_u8() as usize;
-                let address = instruction.next_register(&self.registers) as usize;
+                let register = instruction.next_u8();
+                let address = instruction.next_register(&self.registers)?.to_address();
 
-                let bytes = [self.program[address], self.program[address + 1]];
+                let half = self.memory.read_u16(address)?;
 
-                self.registers[register] = i16::from_be_bytes(bytes) as i32;
+                *self.register_mut(register)? = W::from_i32(half as i16 as i32);
             }
             Opcode::LDWD => {
-                let register = instruction.next_u8() as usize;
+                let register = instruction.next_u8();
                 let address = instruction.next_u16() as usize;
 
-                let bytes = [
-                    self.program[address],
-                    self.program[address + 1],
-                    self.program[address + 2],
-                    self.program[address + 3],
-                ];
+                let word = self.load_word(address)?;
 
-                self.registers[register] = i32::from_be_bytes(bytes);
+                *self.register_mut(register)? = word;
             }
             Opcode::LDWR => {
-                let register = instruction.next_u8() as usize;
-                let address = instruction.next_register(&self.registers) as usize;
+                let register = instruction.next_u8();
+                let address = instruction.next_register(&self.registers)?.to_address();
 
-                let bytes = [
-                    self.program[address],
-                    self.program[address + 1],
-                    self.program[address + 2],
-                    self.program[address + 3],
-                ];
+                let word = self.load_word(address)?;
 
-                self.registers[register] = i32::from_be_bytes(bytes);
+                *self.register_mut(register)? = word;
             }
             Opcode::STRBI => {
-                let register = instruction.next_register(&self.registers) as u8;
+                let register = instruction.next_register(&self.registers)?.to_i32() as u8;
                 let address = instruction.next_u16() as usize;
 
-                self.program[address] = register;
+                self.store_u8(address, register)?;
             }
             Opcode::STRBR => {
-                let register = instruction.next_register(&self.registers) as u8;
-                let address = instruction.next_register(&self.registers) as usize;
+                let register = instruction.next_register(&self.registers)?.to_i32() as u8;
+                let address = instruction.next_register(&self.registers)?.to_address();
 
-                self.program[address] = register;
+                self.store_u8(address, register)?;
             }
             Opcode::STRHI => {
-                let register = instruction.next_register(&self.registers) as u16;
-                let mut address = instruction.next_u16() as usize;
+                let register = instruction.next_register(&self.registers)?.to_i32() as u16;
+                let address = instruction.next_u16() as usize;
 
-                for byte in register.to_be_bytes() {
-                    self.program[address] = byte;
-                    address += 1;
-                }
+                self.store_u16(address, register)?;
             }
             Opcode::STRHR => {
-                let register = instruction.next_register(&self.registers) as u16;
-                let mut address = instruction.next_register(&self.registers) as usize;
+                let register = instruction.next_register(&self.registers)?.to_i32() as u16;
+                let address = instruction.next_register(&self.registers)?.to_address();
 
-                for byte in register.to_be_bytes() {
-                    self.program[address] = byte;
-                    address += 1;
-                }
+                self.store_u16(address, register)?;
             }
             Opcode::STRWI => {
-                let register = instruction.next_register(&self.registers) as u32;
-                let mut address = instruction.next_u16() as usize;
+                let register = instruction.next_register(&self.registers)?;
+                let address = instruction.next_u16() as usize;
 
-                for byte in register.to_be_bytes() {
-                    self.program[address] = byte;
-                    address += 1;
-                }
+                self.store_word(address, register)?;
             }
             Opcode::STRWR => {
-                let register = instruction.next_register(&self.registers) as u32;
-                let mut address = instruction.next_register(&self.registers) as usize;
+                let register = instruction.next_register(&self.registers)?;
+                let address = instruction.next_register(&self.registers)?.to_address();
 
-                for byte in register.to_be_bytes() {
-                    self.program[address] = byte;
-                    address += 1;
-                }
+                self.store_word(address, register)?;
+            }
+            Opcode::LD64D => {
+                let register = instruction.next_u8();
+                let address = instruction.next_u16() as usize;
+
+                let value = self.memory.read_u64(address)?;
+                self.set_pair(register, value as i64)?;
+            }
+            Opcode::LD64R => {
+                let register = instruction.next_u8();
+                let address = instruction.next_register(&self.registers)?.to_address();
+
+                let value = self.memory.read_u64(address)?;
+                self.set_pair(register, value as i64)?;
+            }
+            Opcode::ST64I => {
+                let value = self.pair(instruction.next_u8())?;
+                let address = instruction.next_u16() as usize;
+
+                self.store_u64(address, value as u64)?;
+            }
+            Opcode::ST64R => {
+                let value = self.pair(instruction.next_u8())?;
+                let address = instruction.next_register(&self.registers)?.to_address();
+
+                self.store_u64(address, value as u64)?;
             }
             Opcode::MOV => {
-                let register_a = instruction.next_u8() as usize;
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_u8();
+                let register_b = instruction.next_register(&self.registers)?;
+
+                *self.register_mut(register_a)? = register_b;
+            }
+            Opcode::MEMCPY => {
+                let destination = instruction.next_register(&self.registers)?.to_address();
+                let source = instruction.next_register(&self.registers)?.to_address();
+                let len = instruction.next_register(&self.registers)?.to_address();
+
+                // copied out first, so overlapping ranges behave as if copied through a buffer
+                if len > 0 {
+                    let bytes = self.memory.read(source, len)?.to_vec();
+                    self.store_bytes(destination, &bytes)?;
+                }
+            }
+            Opcode::MEMSET => {
+                let destination = instruction.next_register(&self.registers)?.to_address();
+                let value = instruction.next_register(&self.registers)?.to_i32() as u8;
+                let len = instruction.next_register(&self.registers)?.to_address();
+
+                // checked before filling a buffer, since len can be anything a register holds
+                if len > 0 {
+                    self.memory.checked_range(destination, len)?;
+                    self.store_bytes(destination, &vec![value; len])?;
+                }
+            }
+            Opcode::ALOCI => {
+                let register = instruction.next_u8();
+                let size = instruction.next_u16() as usize;
+
+                *self.register_mut(register)? = W::from_address(self.allocate(size)?);
+            }
+            Opcode::ALOCR => {
+                let register = instruction.next_u8();
+                let size = instruction.next_register(&self.registers)?.to_address();
+
+                *self.register_mut(register)? = W::from_address(self.allocate(size)?);
+            }
+            Opcode::FREE => {
+                let address = instruction.next_register(&self.registers)?.to_address();
+
+                self.memory
+                    .allocator_mut()
+                    .release(address)
+                    .ok_or(VmError::InvalidFree { address })?;
+            }
+            Opcode::PUSH => {
+                let value = instruction.next_register(&self.registers)?;
+
+                self.push(value)?;
+            }
+            Opcode::POP => {
+                let register = instruction.next_u8();
+                let value = self.pop()?;
 
-                self.registers[register_a] = register_b;
+                *self.register_mut(register)? = value;
             }
             Opcode::ADDR => {
-                let register_a = instruction.next_u8() as usize;
-                let register_b = instruction.next_register(&self.registers);
-                let register_c = instruction.next_register(&self.registers);
+                let register_a = instruction.next_u8();
+                let register_b = instruction.next_register(&self.registers)?;
+                let register_c = instruction.next_register(&self.registers)?;
 
-                self.registers[register_a] = register_b + register_c;
+                *self.register_mut(register_a)? = register_b.wrapping_add(register_c);
             }
             Opcode::ADDI => {
-                let register_a = instruction.next_u8() as usize;
-                let value = instruction.next_u16() as i32;
+                let register_a = instruction.next_register_mut(&mut self.registers)?;
+                let value = W::from_i32(instruction.next_u16() as i32);
 
-                self.registers[register_a] += value;
+                *register_a = register_a.wrapping_add(value);
             }
             Opcode::SUBR => {
-                let register_a = instruction.next_u8() as usize;
-                let register_b = instruction.next_register(&self.registers);
-                let register_c = instruction.next_register(&self.registers);
+                let register_a = instruction.next_u8();
+                let register_b = instruction.next_register(&self.registers)?;
+                let register_c = instruction.next_register(&self.registers)?;
 
-                self.registers[register_a] = register_b - register_c;
+                *self.register_mut(register_a)? = register_b.wrapping_sub(register_c);
             }
             Opcode::SUBI => {
-                let register_a = instruction.next_u8() as usize;
-                let value = instruction.next_u16() as i32;
+                let register_a = instruction.next_register_mut(&mut self.registers)?;
+                let value = W::from_i32(instruction.next_u16() as i32);
 
-                self.registers[register_a] -= value;
+                *register_a = register_a.wrapping_sub(value);
             }
             Opcode::MULR => {
-                let register_a = instruction.next_u8() as usize;
-                let register_b = instruction.next_register(&self.registers);
-                let register_c = instruction.next_register(&self.registers);
+                let register_a = instruction.next_u8();
+                let register_b = instruction.next_register(&self.registers)?;
+                let register_c = instruction.next_register(&self.registers)?;
 
-                self.registers[register_a] = register_b * register_c;
+                *self.register_mut(register_a)? = register_b.wrapping_mul(register_c);
             }
             Opcode::MULI => {
-                let register_a = instruction.next_u8() as usize;
-                let value = instruction.next_u16() as i32;
+                let register_a = instruction.next_register_mut(&mut self.registers)?;
+                let value = W::from_i32(instruction.next_u16() as i32);
 
-                self.registers[register_a] *= value;
+                *register_a = register_a.wrapping_mul(value);
             }
             Opcode::DIVR => {
-                let register_a = instruction.next_u8() as usize;
-                let register_b = instruction.next_register(&self.registers);
-                let register_c = instruction.next_register(&self.registers);
+                let register_a = instruction.next_u8();
+                let register_b = instruction.next_register(&self.registers)?;
+                let register_c = instruction.next_register(&self.registers)?;
 
-                let (value, remainder) = (register_b / register_c, register_b % register_c);
+                let (value, remainder) = Self::divide(register_b, register_c)?;
 
-                self.registers[register_a] = value;
-                self.remainder = remainder as u32;
+                *self.register_mut(register_a)? = value;
+                self.remainder = remainder;
             }
             Opcode::DIVI => {
-                let register_addr = instruction.next_u8() as usize;
-                let register_value = self.registers[register_addr];
-                let value = instruction.next_u16() as i32;
+                let register_addr = instruction.next_u8();
+                let register_value = *self.register_mut(register_addr)?;
+                let value = W::from_i32(instruction.next_u16() as i32);
+
+                let (value, remainder) = Self::divide(register_value, value)?;
+
+                *self.register_mut(register_addr)? = value;
+                self.remainder = remainder;
+            }
+            Opcode::ADD64 => {
+                let register_a = instruction.next_u8();
+                let value = self.pair(register_a)?;
+                let value_b = self.pair(instruction.next_u8())?;
+
+                self.set_pair(register_a, value.wrapping_add(value_b))?;
+            }
+            Opcode::SUB64 => {
+                let register_a = instruction.next_u8();
+                let value = self.pair(register_a)?;
+                let value_b = self.pair(instruction.next_u8())?;
 
-                let (value, remainder) = (register_value / value, register_value % value);
+                self.set_pair(register_a, value.wrapping_sub(value_b))?;
+            }
+            Opcode::INC => {
+                let register = instruction.next_register_mut(&mut self.registers)?;
+
+                *register = register.wrapping_add(W::from_i32(1));
+            }
+            Opcode::DEC => {
+                let register = instruction.next_register_mut(&mut self.registers)?;
 
-                self.registers[register_addr] = value;
-                self.remainder = remainder as u32;
+                *register = register.wrapping_sub(W::from_i32(1));
             }
             Opcode::EQI => {
-                let register = instruction.next_register(&self.registers);
+                let register = instruction.next_register(&self.registers)?;
                 let value = instruction.next_u16();
 
-                self.equality_flag = register == value as i32;
+                self.equality_flag = register == W::from_i32(value as i32);
             }
             Opcode::EQR => {
-                let register_a = instruction.next_register(&self.registers);
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_register(&self.registers)?;
+                let register_b = instruction.next_register(&self.registers)?;
 
                 self.equality_flag = register_a == register_b;
             }
             Opcode::NEQI => {
-                let register = instruction.next_register(&self.registers);
+                let register = instruction.next_register(&self.registers)?;
                 let value = instruction.next_u16();
 
-                self.equality_flag = register != value as i32;
+                self.equality_flag = register != W::from_i32(value as i32);
             }
             Opcode::NEQR => {
-                let register_a = instruction.next_register(&self.registers);
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_register(&self.registers)?;
+                let register_b = instruction.next_register(&self.registers)?;
 
                 self.equality_flag = register_a != register_b;
             }
             Opcode::GTI => {
-                let register = instruction.next_register(&self.registers);
+                let register = instruction.next_register(&self.registers)?;
                 let value = instruction.next_u16();
 
-                self.equality_flag = register > value as i32;
+                self.equality_flag = register > W::from_i32(value as i32);
             }
             Opcode::GTR => {
-                let register_a = instruction.next_register(&self.registers);
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_register(&self.registers)?;
+                let register_b = instruction.next_register(&self.registers)?;
 
                 self.equality_flag = register_a > register_b;
             }
             Opcode::GTEI => {
-                let register = instruction.next_register(&self.registers);
+                let register = instruction.next_register(&self.registers)?;
                 let value = instruction.next_u16();
 
-                self.equality_flag = register >= value as i32;
+                self.equality_flag = register >= W::from_i32(value as i32);
             }
             Opcode::GTER => {
-                let register_a = instruction.next_register(&self.registers);
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_register(&self.registers)?;
+                let register_b = instruction.next_register(&self.registers)?;
 
                 self.equality_flag = register_a >= register_b;
             }
             Opcode::LTI => {
-                let register = instruction.next_register(&self.registers);
+                let register = instruction.next_register(&self.registers)?;
                 let value = instruction.next_u16();
 
-                self.equality_flag = register < value as i32;
+                self.equality_flag = register < W::from_i32(value as i32);
             }
             Opcode::LTR => {
-                let register_a = instruction.next_register(&self.registers);
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_register(&self.registers)?;
+                let register_b = instruction.next_register(&self.registers)?;
 
                 self.equality_flag = register_a < register_b;
             }
             Opcode::LTEI => {
-                let register = instruction.next_register(&self.registers);
+                let register = instruction.next_register(&self.registers)?;
                 let value = instruction.next_u16();
 
-                self.equality_flag = register <= value as i32;
+                self.equality_flag = register <= W::from_i32(value as i32);
             }
             Opcode::LTER => {
-                let register_a = instruction.next_register(&self.registers);
-                let register_b = instruction.next_register(&self.registers);
+                let register_a = instruction.next_register(&self.registers)?;
+                let register_b = instruction.next_register(&self.registers)?;
 
                 self.equality_flag = register_a <= register_b;
             }
+            Opcode::STRCMP => {
+                let a = instruction.next_register(&self.registers)?.to_address();
+                let b = instruction.next_register(&self.registers)?.to_address();
+                let len = instruction.next_register(&self.registers)?.to_address();
+
+                self.equality_flag = self.strings_equal(a, b, len)?;
+            }
             Opcode::JMPI => {
                 self.pc = instruction.next_u16() as usize;
             }
             Opcode::JMPD => {
                 let address = instruction.next_u16() as usize;
-                let bytes = [
-                    self.program[address],
-                    self.program[address + 1],
-                    self.program[address + 2],
-                    self.program[address + 3],
-                ];
-
-                self.pc = u32::from_be_bytes(bytes) as usize;
+                self.pc = self.load_word(address)?.to_address();
             }
             Opcode::JMPR => {
-                self.pc = instruction.next_register(&self.registers) as usize;
+                self.pc = instruction.next_register(&self.registers)?.to_address();
             }
             Opcode::JMPEI => {
                 if self.equality_flag {
@@ -338,19 +1281,12 @@ impl VM {
             Opcode::JMPED => {
                 if self.equality_flag {
                     let address = instruction.next_u16() as usize;
-                    let bytes = [
-                        self.program[address],
-                        self.program[address + 1],
-                        self.program[address + 2],
-                        self.program[address + 3],
-                    ];
-
-                    self.pc = u32::from_be_bytes(bytes) as usize;
+                    self.pc = self.load_word(address)?.to_address();
                 }
             }
             Opcode::JMPER => {
                 if self.equality_flag {
-                    self.pc = instruction.next_register(&self.registers) as usize;
+                    self.pc = instruction.next_register(&self.registers)?.to_address();
                 }
             }
             Opcode::JMPNEI => {
@@ -361,65 +1297,641 @@ impl VM {
             Opcode::JMPNED => {
                 if !self.equality_flag {
                     let address = instruction.next_u16() as usize;
-                    let bytes = [
-                        self.program[address],
-                        self.program[address + 1],
-                        self.program[address + 2],
-                        self.program[address + 3],
-                    ];
-
-                    self.pc = u32::from_be_bytes(bytes) as usize;
+                    self.pc = self.load_word(address)?.to_address();
                 }
             }
             Opcode::JMPNER => {
                 if !self.equality_flag {
-                    self.pc = instruction.next_register(&self.registers) as usize;
+                    self.pc = instruction.next_register(&self.registers)?.to_address();
                 }
             }
-            Opcode::PRTSD => {
-                let start = instruction.next_u16() as usize;
-                let mut end = start;
-
-                while self.program[end] != 0 {
-                    end += 1;
+            Opcode::BRA => {
+                self.branch(instruction.next_i16());
+            }
+            Opcode::BRE => {
+                if self.equality_flag {
+                    self.branch(instruction.next_i16());
                 }
-
-                let string = std::str::from_utf8(&self.program[start..end]);
-                if let Ok(string) = string {
-                    println!("{string}");
-                } else {
-                    println!("Invalid string!");
+            }
+            Opcode::BRNE => {
+                if !self.equality_flag {
+                    self.branch(instruction.next_i16());
                 }
             }
-            Opcode::PRTSR => {
-                let start = instruction.next_register(&self.registers) as usize;
-                let mut end = start;
+            Opcode::CALLI => {
+                let address = instruction.next_u16() as usize;
 
-                while self.program[end] != 0 {
-                    end += 1;
+                self.call(address)?;
+            }
+            Opcode::CALLR => {
+                let address = instruction.next_register(&self.registers)?.to_address();
+
+                self.call(address)?;
+            }
+            Opcode::RET => {
+                self.pc = self.pop()?.to_address();
+
+                if let Some(shadow_stack) = &mut self.shadow_stack {
+                    shadow_stack.ret(self.pc);
                 }
+            }
+            Opcode::ENTER => {
+                // locals are rounded up to whole words, keeping the stack aligned
+                let alignment = STACK_ALIGNMENT.max(W::BYTES);
+                let locals = (instruction.next_u16() as usize).next_multiple_of(alignment);
+
+                let frame_pointer =
+                    self.registers[STACK_POINTER as usize].wrapping_sub(W::from_address(W::BYTES));
+                let stack_pointer = frame_pointer.to_address().wrapping_sub(locals);
+                if stack_pointer < self.memory.stack_start() {
+                    return Err(VmError::StackOverflow {
+                        address: stack_pointer,
+                    });
+                }
+
+                self.push(self.registers[FRAME_POINTER as usize])?;
+                self.registers[FRAME_POINTER as usize] = frame_pointer;
+                self.registers[STACK_POINTER as usize] = W::from_address(stack_pointer);
+            }
+            Opcode::LEAVE => {
+                self.registers[STACK_POINTER as usize] = self.registers[FRAME_POINTER as usize];
+                self.registers[FRAME_POINTER as usize] = self.pop()?;
+            }
+            Opcode::IVECI => {
+                let address = instruction.next_u16() as usize;
+
+                self.interrupt_vector = (address != 0).then_some(address);
+            }
+            Opcode::IRET => {
+                self.equality_flag = self.pop()? != W::default();
+                self.pc = self.pop()?.to_address();
+                self.in_interrupt = false;
+            }
+            Opcode::PRTSD => {
+                let start = instruction.next_u16() as usize;
+
+                self.print_string(start)?;
+            }
+            Opcode::PRTSR => {
+                let start = instruction.next_register(&self.registers)?.to_address();
 
-                let string = std::str::from_utf8(&self.program[start..end]);
-                if let Ok(string) = string {
-                    println!("{string}");
-                } else {
-                    println!("Invalid string!");
+                self.print_string(start)?;
+            }
+            Opcode::LOGD => {
+                let level = instruction.next_u8();
+                let start = instruction.next_u16() as usize;
+
+                self.log(level, start)?;
+            }
+            Opcode::LOGR => {
+                let level = instruction.next_u8();
+                let start = instruction.next_register(&self.registers)?.to_address();
+
+                self.log(level, start)?;
+            }
+            Opcode::PRTI => {
+                let value = instruction.next_register(&self.registers)?;
+
+                self.print_line(value)?;
+            }
+            Opcode::READI => {
+                let register = instruction.next_u8();
+                let line = self.read_line()?;
+
+                self.equality_flag = line.is_some();
+                if let Some(line) = line {
+                    let value = line.trim().parse().map_err(|_| VmError::InvalidInteger {
+                        input: line.clone(),
+                        pc: self.instruction_pc,
+                    })?;
+                    *self.register_mut(register)? = value;
+                }
+            }
+            Opcode::READS => {
+                let address = instruction.next_register(&self.registers)?.to_address();
+                let capacity = instruction.next_u16() as usize;
+                let line = self.read_line()?;
+                self.equality_flag = line.is_some();
+
+                // truncated to leave room for the null byte
+                let mut bytes = line.unwrap_or_default().into_bytes();
+                bytes.truncate(capacity.saturating_sub(1));
+                bytes.push(0);
+                self.store_bytes(address, &bytes[..capacity.min(bytes.len())])?;
+            }
+            Opcode::SYSI => {
+                let number = instruction.next_u16();
+                let mut syscall = self
+                    .syscalls
+                    .remove(&number)
+                    .ok_or(VmError::UnknownSyscall {
+                        number,
+                        pc: self.pc - 4,
+                    })?;
+
+                // the syscall is taken out while it runs, as it borrows the whole VM
+                let result = syscall.call(self);
+                self.syscalls.entry(number).or_insert(syscall);
+                result?;
+            }
+            Opcode::SEND => {
+                let to = instruction.next_register(&self.registers)?.to_address();
+                let value = instruction.next_register(&self.registers)?;
+                let pc = self.pc - 4;
+
+                let mailbox = self.mailbox.as_ref().ok_or(VmError::NotInCluster { pc })?;
+                if !mailbox.send(to, value.to_i32()) {
+                    return Err(VmError::InvalidRecipient { id: to, pc });
                 }
             }
-            _ => {
-                println!("Unrecognized opcode encountered");
-                return false;
+            Opcode::RECV => {
+                let value_register = instruction.next_u8();
+                let sender_register = instruction.next_u8();
+                let pc = self.pc - 4;
+
+                let mailbox = self.mailbox.as_ref().ok_or(VmError::NotInCluster { pc })?;
+                let (value, sender) = mailbox.receive().ok_or(VmError::MailboxClosed { pc })?;
+                *self.register_mut(value_register)? = W::from_i32(value);
+                *self.register_mut(sender_register)? = W::from_address(sender);
+            }
+            Opcode::RAND => {
+                let register = instruction.next_u8();
+
+                *self.register_mut(register)? = W::from_i32(self.random.next_u32() as i32);
+            }
+            Opcode::TIME => {
+                let register = instruction.next_u8();
+                let elapsed = self.elapsed_ms();
+
+                // wraps after about 24 days with 4 byte words
+                *self.register_mut(register)? = W::from_i64(elapsed as i64);
+            }
+            Opcode::FOPEN => {
+                let register = instruction.next_u8();
+                let path = instruction.next_register(&self.registers)?.to_address();
+                let mode = instruction.next_register(&self.registers)?.to_i32();
+
+                let path = self.memory.read_string(path)?.to_vec();
+                let fd = self.files.open(&path, mode);
+                self.equality_flag = fd.is_some();
+                *self.register_mut(register)? = W::from_i32(fd.map_or(-1, |fd| fd as i32));
+            }
+            Opcode::FREAD => {
+                let register = instruction.next_u8();
+                let fd = instruction.next_register(&self.registers)?.to_address();
+                let buffer = instruction.next_register(&self.registers)?.to_address();
+                let len = self.register_mut(register)?.to_address();
+
+                // checked before filling a buffer, since len can be anything a register holds
+                self.memory.checked_range(buffer, len)?;
+                let mut bytes = vec![0; len];
+                let read = self.files.read(fd, &mut bytes);
+                self.equality_flag = read.is_some();
+                if let Some(read) = read {
+                    self.store_bytes(buffer, &bytes[..read])?;
+                }
+                *self.register_mut(register)? = W::from_i32(read.map_or(-1, |read| read as i32));
+            }
+            Opcode::FWRITE => {
+                let register = instruction.next_u8();
+                let fd = instruction.next_register(&self.registers)?.to_address();
+                let buffer = instruction.next_register(&self.registers)?.to_address();
+                let len = self.register_mut(register)?.to_address();
+
+                let bytes = self.memory.read(buffer, len)?;
+                let written = self.files.write(fd, bytes);
+                self.equality_flag = written.is_some();
+                *self.register_mut(register)? =
+                    W::from_i32(written.map_or(-1, |written| written as i32));
+            }
+            Opcode::FCLOSE => {
+                let fd = instruction.next_register(&self.registers)?.to_address();
+
+                self.equality_flag = self.files.close(fd);
+            }
+            Opcode::NEWARR => {
+                let register = instruction.next_u8();
+                let len = instruction.next_register(&self.registers)?.to_address();
+                let kind = instruction.next_u8();
+                let pc = self.pc - 4;
+
+                let element_size =
+                    Object::element_size(kind).ok_or(VmError::InvalidObjectKind { kind, pc })?;
+                let handle = self.allocate_object(kind, len, len.saturating_mul(element_size))?;
+                *self.register_mut(register)? = W::from_i32(handle);
+            }
+            Opcode::GETEL => {
+                let register = instruction.next_u8();
+                let handle = instruction.next_register(&self.registers)?.to_i32();
+                let index = instruction.next_register(&self.registers)?.to_i32();
+
+                let value = self.object_mut(handle, index, |object, index| object.get(index))?;
+                *self.register_mut(register)? = W::from_i32(value);
+            }
+            Opcode::SETEL => {
+                let handle = instruction.next_register(&self.registers)?.to_i32();
+                let index = instruction.next_register(&self.registers)?.to_i32();
+                let value = instruction.next_register(&self.registers)?.to_i32();
+
+                self.object_mut(handle, index, |object, index| {
+                    object.set(index, value).then_some(())
+                })?;
+            }
+            Opcode::NOP => {}
+            Opcode::IGL => {
+                return Err(VmError::IllegalOpcode { pc: self.pc - 4 });
             }
         }
 
-        true
+        Ok(true)
+    }
+
+    /// Returns a mutable reference to the register with the given index
+    fn register_mut(&mut self, index: u8) -> Result<&mut W, VmError> {
+        self.registers
+            .get_mut(index as usize)
+            .ok_or(VmError::InvalidRegister { index })
+    }
+
+    /// Reads the 64 bit value held in a register pair, with the high word in the register with the
+    /// given index and the low word in the one after it
+    fn pair(&self, index: u8) -> Result<i64, VmError> {
+        let high = *self
+            .registers
+            .get(index as usize)
+            .ok_or(VmError::InvalidRegister { index })?;
+        let low = *self
+            .registers
+            .get(index as usize + 1)
+            .ok_or(VmError::InvalidRegister {
+                index: index.saturating_add(1),
+            })?;
+
+        Ok((high.to_i64() << 32) | low.to_i32() as u32 as i64)
+    }
+
+    /// Writes a 64 bit value to a register pair, high word first
+    fn set_pair(&mut self, index: u8, value: i64) -> Result<(), VmError> {
+        // check both registers exist before writing either
+        self.register_mut(index.saturating_add(1))?;
+
+        *self.register_mut(index)? = W::from_i32((value >> 32) as i32);
+        *self.register_mut(index + 1)? = W::from_i32(value as i32);
+
+        Ok(())
+    }
+
+    /// Stores a byte, dropping any cached instruction it overlaps
+    fn store_u8(&mut self, address: usize, value: u8) -> Result<(), VmError> {
+        self.memory.write_u8(address, value)?;
+        self.instruction_cache.invalidate(address..address + 1);
+
+        Ok(())
+    }
+
+    /// Stores a half-word, dropping any cached instructions it overlaps
+    fn store_u16(&mut self, address: usize, value: u16) -> Result<(), VmError> {
+        self.memory.write_u16(address, value)?;
+        self.instruction_cache.invalidate(address..address + 2);
+
+        Ok(())
+    }
+
+    /// Stores a word, dropping any cached instructions it overlaps
+    fn store_u32(&mut self, address: usize, value: u32) -> Result<(), VmError> {
+        self.memory.write_u32(address, value)?;
+        self.instruction_cache.invalidate(address..address + 4);
+
+        Ok(())
+    }
+
+    /// Reads a word, of however many bytes words are in this VM
+    fn load_word(&self, address: usize) -> Result<W, VmError> {
+        Ok(match W::BYTES {
+            8 => W::from_i64(self.memory.read_u64(address)? as i64),
+            _ => W::from_i32(self.memory.read_u32(address)? as i32),
+        })
+    }
+
+    /// Stores a word, of however many bytes words are in this VM
+    fn store_word(&mut self, address: usize, value: W) -> Result<(), VmError> {
+        match W::BYTES {
+            8 => self.store_u64(address, value.to_i64() as u64),
+            _ => self.store_u32(address, value.to_i32() as u32),
+        }
+    }
+
+    /// Stores a double-word, dropping any cached instructions it overlaps
+    fn store_u64(&mut self, address: usize, value: u64) -> Result<(), VmError> {
+        self.memory.write_u64(address, value)?;
+        self.instruction_cache.invalidate(address..address + 8);
+
+        Ok(())
+    }
+
+    /// Checks if the null terminated strings at a and b are equal, comparing at most len bytes.
+    /// Faults if either string runs out of memory before a difference, its end or len bytes
+    fn strings_equal(&self, a: usize, b: usize, len: usize) -> Result<bool, VmError> {
+        if len == 0 {
+            return Ok(true);
+        }
+        let (bytes_a, bytes_b) = (
+            self.memory.read_to_region_end(a)?,
+            self.memory.read_to_region_end(b)?,
+        );
+
+        for offset in 0..len {
+            let byte = |bytes: &[u8], start: usize| {
+                bytes.get(offset).copied().ok_or(VmError::InvalidAddress {
+                    address: start + offset,
+                })
+            };
+            let byte_a = byte(bytes_a, a)?;
+            if byte_a != byte(bytes_b, b)? {
+                return Ok(false);
+            }
+            if byte_a == 0 {
+                return Ok(true);
+            }
+        }
+
+        Ok(true)
+    }
+
+    /// Stores bytes, dropping any cached instructions they overlap
+    fn store_bytes(&mut self, address: usize, bytes: &[u8]) -> Result<(), VmError> {
+        self.memory.write(address, bytes)?;
+        self.instruction_cache
+            .invalidate(address..address + bytes.len());
+
+        Ok(())
+    }
+
+    /// Allocates size zeroed bytes, reusing freed memory if possible and otherwise growing the heap.
+    /// Returns the address of the new bytes
+    fn allocate(&mut self, size: usize) -> Result<usize, VmError> {
+        // empty allocations still need an address of their own to be freed by
+        let size = size.max(1);
+
+        let address = match self.memory.allocator_mut().reuse(size) {
+            Some(address) => {
+                self.memory.write(address, &vec![0; size])?;
+                address
+            }
+            None => {
+                let heap_size = self.memory.heap_size().saturating_add(size);
+                if self.config.max_heap.is_some_and(|max| heap_size > max) {
+                    return Err(VmError::ResourceExhausted { limit: Limit::Heap });
+                }
+
+                self.memory
+                    .grow_heap(size)
+                    .ok_or(VmError::ResourceExhausted { limit: Limit::Heap })?
+            }
+        };
+
+        // the program counter has already moved past the allocating instruction
+        self.memory.allocator_mut().record(Allocation {
+            address,
+            size,
+            pc: self.pc - 4,
+        });
+
+        Ok(address)
+    }
+
+    /// Adds a zeroed object of size bytes to the object heap, collecting it first if it has grown
+    /// enough since it was last collected, or the object would take it over the heap size limit.
+    /// Returns the object's handle
+    fn allocate_object(&mut self, kind: u8, len: usize, size: usize) -> Result<i32, VmError> {
+        let pc = self.pc - 4;
+        let max_heap = self.config.max_heap;
+        let exceeds_limit = |objects: &ObjectHeap| {
+            max_heap.is_some_and(|max| objects.size().saturating_add(size) > max)
+        };
+
+        let objects = self.objects.as_ref().ok_or(VmError::NoObjectHeap { pc })?;
+        if objects.should_collect() || exceeds_limit(objects) {
+            self.collect_garbage();
+        }
+
+        // checked before creating the object, since len can be anything a register holds
+        let objects = self.objects.as_mut().unwrap();
+        if exceeds_limit(objects) {
+            return Err(VmError::ResourceExhausted { limit: Limit::Heap });
+        }
+
+        objects
+            .allocate(Object::new(kind, len).unwrap())
+            .ok_or(VmError::ResourceExhausted { limit: Limit::Heap })
+    }
+
+    /// Runs f on an object and the index of one of its elements, faulting if the object doesn't
+    /// exist or f returns None because the index is out of bounds
+    fn object_mut<T>(
+        &mut self,
+        handle: i32,
+        index: i32,
+        f: impl FnOnce(&mut Object, usize) -> Option<T>,
+    ) -> Result<T, VmError> {
+        let pc = self.pc - 4;
+        let object = self
+            .objects
+            .as_mut()
+            .ok_or(VmError::NoObjectHeap { pc })?
+            .get_mut(handle)
+            .ok_or(VmError::InvalidObject { handle, pc })?;
+        let len = object.len();
+
+        f(object, index as u32 as usize).ok_or(VmError::InvalidElement { index, len, pc })
+    }
+
+    /// Moves the program counter by a branch's offset, which is from the branch itself rather than
+    /// the instruction after it
+    fn branch(&mut self, offset: i16) {
+        self.pc = (self.pc - 4).wrapping_add_signed(offset as isize);
+    }
+
+    /// Pushes the return address and jumps to a routine
+    fn call(&mut self, address: usize) -> Result<(), VmError> {
+        self.push(W::from_address(self.pc))?;
+
+        if let Some(shadow_stack) = &mut self.shadow_stack {
+            shadow_stack.call(Frame {
+                call_site: self.instruction_pc,
+                routine: address,
+                return_address: self.pc,
+                stack_pointer: self.registers[STACK_POINTER as usize].to_address(),
+            });
+        }
+        self.pc = address;
+
+        Ok(())
+    }
+
+    /// Enters the interrupt handler at vector, saving the program counter and equality flag so
+    /// `IRET` can return to the interrupted instruction
+    fn interrupt(&mut self, vector: usize) -> Result<(), VmError> {
+        self.push(W::from_address(self.pc))?;
+        self.push(W::from_i32(self.equality_flag as i32))?;
+        self.in_interrupt = true;
+        self.pc = vector;
+        self.instruction_pc = vector;
+
+        Ok(())
+    }
+
+    /// Pushes a word onto the stack
+    fn push(&mut self, value: W) -> Result<(), VmError> {
+        let stack_pointer =
+            self.registers[STACK_POINTER as usize].wrapping_sub(W::from_address(W::BYTES));
+        self.store_word(stack_pointer.to_address(), value)?;
+        self.registers[STACK_POINTER as usize] = stack_pointer;
+
+        Ok(())
+    }
+
+    /// Pops a word from the top of the stack
+    fn pop(&mut self) -> Result<W, VmError> {
+        let stack_pointer = self.registers[STACK_POINTER as usize];
+        let value = self.load_word(stack_pointer.to_address())?;
+        self.registers[STACK_POINTER as usize] =
+            stack_pointer.wrapping_add(W::from_address(W::BYTES));
+
+        Ok(value)
+    }
+
+    /// Prints the null terminated string starting at address, which must end in the same region
+    fn print_string(&mut self, start: usize) -> Result<(), VmError> {
+        let bytes = self.memory.read_string(start)?;
+
+        match std::str::from_utf8(bytes) {
+            Ok(string) => {
+                let string = string.to_owned();
+                self.print_line(string)
+            }
+            Err(_) => self.print_line("Invalid string!"),
+        }
+    }
+
+    /// Writes a line to the program's output
+    fn print_line(&mut self, line: impl Display) -> Result<(), VmError> {
+        writeln!(self.output, "{line}").map_err(output_failed)
+    }
+
+    /// Flushes the program's output, so anything written after it appears after the output
+    pub fn flush_output(&mut self) -> Result<(), VmError> {
+        self.output.flush().map_err(output_failed)
+    }
+
+    /// Milliseconds since the program was started, as read by `TIME`
+    fn elapsed_ms(&self) -> u64 {
+        let now = self.clock.borrow_mut().now_ms();
+
+        now.saturating_sub(self.clock_start) + self.clock_offset
+    }
+
+    /// Reads a line of input without its line ending, or None at the end of input
+    fn read_line(&mut self) -> Result<Option<String>, VmError> {
+        let mut line = String::new();
+        let read = match &mut self.input {
+            Some(input) => input.read_line(&mut line),
+            None => read_stdin(&mut line),
+        }
+        .map_err(|error| VmError::InputFailed {
+            error: error.to_string(),
+        })?;
+        self.input_read += read as u64;
+
+        let trimmed = line.trim_end_matches(['\n', '\r']).len();
+        line.truncate(trimmed);
+
+        Ok((read > 0).then_some(line))
+    }
+
+    /// Sends a null terminated string to the logger, if there is one
+    fn log(&mut self, level: u8, start: usize) -> Result<(), VmError> {
+        let pc = self.instruction_pc;
+        let level = LogLevel::from_u8(level).ok_or(VmError::InvalidLogLevel { level, pc })?;
+        let message = String::from_utf8_lossy(self.memory.read_string(start)?).into_owned();
+
+        if let Some(logger) = &mut self.logger {
+            logger.log(&LogRecord {
+                level,
+                message: &message,
+                pc,
+                steps: self.steps,
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Divides two values, returning the quotient and remainder
+    fn divide(a: W, b: W) -> Result<(W, W), VmError> {
+        if b == W::default() {
+            return Err(VmError::DivisionByZero);
+        }
+
+        Ok((a.wrapping_div(b), a.wrapping_rem(b)))
+    }
+}
+
+/// Discards up to count bytes of input, stopping early at the end of input or if it can't be read
+fn skip(input: &mut dyn BufRead, mut count: u64) {
+    while count > 0 {
+        let available = match input.fill_buf() {
+            Ok(buffer) if !buffer.is_empty() => buffer.len().min(count as usize),
+            _ => return,
+        };
+
+        input.consume(available);
+        count -= available as u64;
+    }
+}
+
+/// Converts an error writing the program's output, keeping its kind so hosts can tell a closed
+/// pipe apart from other failures
+fn output_failed(error: std::io::Error) -> VmError {
+    VmError::OutputFailed {
+        error: error.to_string(),
+        kind: error.kind(),
     }
 }
 
+/// Output written to until another is set
+#[cfg(not(target_arch = "wasm32"))]
+fn default_output() -> Tee {
+    Tee::default().with(std::io::stdout())
+}
+
+/// Output written to until another is set, which is nowhere since wasm has no stdout
+#[cfg(target_arch = "wasm32")]
+fn default_output() -> Tee {
+    Tee::default()
+}
+
+/// Reads a line of input when no other input is set, returning the number of bytes read
+#[cfg(not(target_arch = "wasm32"))]
+fn read_stdin(line: &mut String) -> std::io::Result<usize> {
+    std::io::stdin().read_line(line)
+}
+
+/// Reads a line of input when no other input is set, which is always the end of input since wasm
+/// has no stdin
+#[cfg(target_arch = "wasm32")]
+fn read_stdin(_line: &mut String) -> std::io::Result<usize> {
+    Ok(0)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
-    use shared::PIE_HEADER_LENGTH;
+    use crate::output::SharedBuffer;
+    use crate::verify::BadJump;
+    use shared::{ENTRY_POINT_FIELD, PIE_FORMAT_VERSION, PIE_HEADER_LENGTH, PIE_HEADER_PREFIX};
+    use std::cell::RefCell;
+    use std::rc::Rc;
 
     fn get_test_vm(program: Vec<u8>) -> VM {
         let mut registers = [0; 32];
@@ -428,52 +1940,40 @@ mod tests {
 
         VM {
             registers,
-            program,
+            memory: Memory::new(program),
             ..Default::default()
         }
     }
 
+    /// Adds a header describing the program as the code section, followed by 8 bytes of data
     fn prepend_header(vm: &mut VM) {
-        let mut out = Vec::with_capacity(PIE_HEADER_LENGTH);
+        let code = vm.memory.image();
+        let data_start = PIE_HEADER_LENGTH + code.len();
+        let mut out = Vec::with_capacity(data_start + 8);
 
         out.extend_from_slice(&PIE_HEADER_PREFIX);
-        out.extend_from_slice(&[
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            PIE_HEADER_LENGTH as u8,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            0,
-            PIE_HEADER_LENGTH as u8,
-            0,
-            0,
-            0,
-            0,
-        ]);
+        out.extend_from_slice(&[PIE_FORMAT_VERSION, 0, 0, 0]);
+        out.extend_from_slice(&(data_start as u32).to_be_bytes());
+        out.extend_from_slice(&8u32.to_be_bytes());
+        out.extend_from_slice(&(PIE_HEADER_LENGTH as u32).to_be_bytes());
+        out.extend_from_slice(&(code.len() as u32).to_be_bytes());
         if out.len() < PIE_HEADER_LENGTH {
             out.resize(PIE_HEADER_LENGTH, 0);
         }
 
-        out.extend_from_slice(&vm.program);
-        vm.program = out;
+        out.extend_from_slice(code);
+        out.resize(data_start + 8, 0);
+        vm.memory = Memory::new(out);
     }
 
     #[test]
     fn test_create_vm() {
         let test_vm = VM::default();
 
-        assert_eq!(test_vm.registers, [0; 32]);
+        assert_eq!(test_vm.registers[..31], [0; 31]);
+        assert_eq!(test_vm.registers[31], STACK_TOP as i32);
         assert_eq!(test_vm.pc, 0);
-        assert_eq!(test_vm.program, vec![]);
+        assert_eq!(test_vm.memory.image(), &[0u8; 0]);
     }
 
     macro_rules! opcode_test {
@@ -491,7 +1991,7 @@ mod tests {
                     $prep_left = $prep_right;
                 )?);*
 
-                $vm_name.run();
+                $vm_name.run().unwrap();
 
                 $(
                     assert_eq!($test_left, $test_right)
@@ -502,26 +2002,47 @@ mod tests {
 
     // misc instructions
     opcode_test!(test_opcode_hlt; vm; [0, 0, 0, 0, 1, 0, 0, 0], vm.pc => 68);
-    opcode_test!(test_opcode_igl; vm; [0x3F, 0, 0, 0, 0, 0, 0, 0], vm.pc => 68);
+    opcode_test!(test_opcode_nop; vm; [248, 0, 0, 0, 248, 0, 0, 0], vm.pc => 72, vm.registers[0] => 5);
+
+    #[test]
+    fn test_opcode_igl() {
+        let mut vm = get_test_vm(vec![0x3F, 0, 0, 0, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+
+        assert_eq!(vm.run(), Err(VmError::IllegalOpcode { pc: 64 }));
+        assert_eq!(vm.pc, 68);
+    }
 
     // load instructions
     opcode_test!(test_opcode_ldbi; vm; [4, 0, 255, 255], vm.registers[0] => 0xFF);
     opcode_test!(test_opcode_ldbd; vm; [5, 0, 0, 0], vm.registers[0] => 0x45);
-    opcode_test!(test_opcode_ldbr; vm; [6, 0, 0, 0], vm.registers[0] => 0xAB; vm.program[5] => 0xAB);
+    opcode_test!(test_opcode_ldbr; vm; [6, 0, 0, 0], vm.registers[0] => 0xAB; vm.registers[0] => 6, vm.memory.image_mut()[6] => 0xAB);
     opcode_test!(test_opcode_ldhi; vm; [8, 0, 255, 255], vm.registers[0] => 0xFFFF);
     opcode_test!(test_opcode_ldhd; vm; [9, 0, 0, 0], vm.registers[0] => 0x4550);
-    opcode_test!(test_opcode_ldhr; vm; [10, 0, 0, 0], vm.registers[0] => -21555; vm.program[5] => 0xAB, vm.program[6] => 0xCD);
+    opcode_test!(test_opcode_ldhr; vm; [10, 0, 0, 0], vm.registers[0] => -21555; vm.registers[0] => 6, vm.memory.image_mut()[6] => 0xAB, vm.memory.image_mut()[7] => 0xCD);
     opcode_test!(test_opcode_ldwd; vm; [13, 0, 0, 0], vm.registers[0] => 0x45504945);
-    opcode_test!(test_opcode_ldwr; vm; [14, 0, 0, 0], vm.registers[0] => 0x40ABCDEF; vm.program[5] => 0x40, vm.program[6] => 0xAB, vm.program[7] => 0xCD, vm.program[8] => 0xEF);
+    opcode_test!(test_opcode_ldwr; vm; [14, 0, 0, 0], vm.registers[0] => 0x40ABCDEF; vm.registers[0] => 68, vm.memory.image_mut()[68] => 0x40, vm.memory.image_mut()[69] => 0xAB, vm.memory.image_mut()[70] => 0xCD, vm.memory.image_mut()[71] => 0xEF);
 
     // store/move instructions
-    opcode_test!(test_opcode_strbi; vm; [16, 1, 0, 0], &vm.program[0..4] => [10, 0x50, 0x49, 0x45]);
-    opcode_test!(test_opcode_strbr; vm; [18, 1, 0, 0], &vm.program[5..9] => [10, 0, 0, 0]);
-    opcode_test!(test_opcode_strhi; vm; [20, 1, 0, 0], &vm.program[0..4] => [0, 10, 0x49, 0x45]);
-    opcode_test!(test_opcode_strhr; vm; [22, 1, 0, 0], &vm.program[5..9] => [0, 10, 0, 0]);
-    opcode_test!(test_opcode_strwi; vm; [24, 1, 0, 0], &vm.program[0..4] => [0, 0, 0, 10]);
-    opcode_test!(test_opcode_strwr; vm; [26, 1, 0, 0], &vm.program[5..9] => [0, 0, 0, 10]);
+    opcode_test!(test_opcode_strbi; vm; [16, 1, 0, 68], &vm.memory.image()[68..72] => [10, 0, 0, 0]);
+    opcode_test!(test_opcode_strbr; vm; [18, 1, 0, 0], &vm.memory.image()[69..73] => [10, 0, 0, 0]; vm.registers[0] => 69);
+    opcode_test!(test_opcode_strhi; vm; [20, 1, 0, 68], &vm.memory.image()[68..72] => [0, 10, 0, 0]);
+    opcode_test!(test_opcode_strhr; vm; [22, 1, 0, 0], &vm.memory.image()[69..73] => [0, 10, 0, 0]; vm.registers[0] => 69);
+    opcode_test!(test_opcode_strwi; vm; [24, 1, 0, 68], &vm.memory.image()[68..72] => [0, 0, 0, 10]);
+    opcode_test!(test_opcode_strwr; vm; [26, 1, 0, 0], &vm.memory.image()[69..73] => [0, 0, 0, 10]; vm.registers[0] => 69);
     opcode_test!(test_opcode_mov; vm; [30, 0, 1, 0], vm.registers[0] => 10);
+    opcode_test!(test_opcode_memcpy; vm; [58, 2, 3, 4], vm.memory.image()[68..74] => [1, 2, 1, 2, 3, 4]; vm.registers[2] => 70, vm.registers[3] => 68, vm.registers[4] => 4, vm.memory.image_mut()[68] => 1, vm.memory.image_mut()[69] => 2, vm.memory.image_mut()[70] => 3, vm.memory.image_mut()[71] => 4);
+    opcode_test!(test_opcode_memset; vm; [62, 2, 3, 4], vm.memory.image()[68..73] => [0, 0xAB, 0xAB, 0xAB, 0]; vm.registers[2] => 69, vm.registers[3] => 0x1AB, vm.registers[4] => 3);
+    opcode_test!(test_opcode_memcpy_empty; vm; [58, 2, 3, 4], vm.pc => 68; vm.registers[2] => -1, vm.registers[3] => -1);
+
+    // heap instructions
+    opcode_test!(test_opcode_aloci; vm; [32, 2, 0, 8, 32, 3, 0, 4], vm.registers[2] => 80, vm.registers[3] => 88, vm.memory.heap_size() => 12);
+    opcode_test!(test_opcode_alocr; vm; [34, 2, 0, 0], vm.registers[2] => 76, vm.memory.heap_size() => 5);
+    opcode_test!(test_opcode_free; vm; [32, 2, 0, 8, 46, 2, 0, 0, 32, 3, 0, 4], vm.registers[3] => 84, vm.memory.heap_size() => 8, vm.memory.allocator().free_blocks().first() => Some(&(88..92)));
+
+    // stack instructions
+    opcode_test!(test_opcode_push_pop; vm; [38, 1, 0, 0, 42, 2, 0, 0], vm.registers[2] => 10, vm.registers[31] => STACK_TOP as i32);
+    opcode_test!(test_opcode_push; vm; [38, 1, 0, 0], vm.memory.read(STACK_TOP - 4, 4).unwrap() => [0, 0, 0, 10], vm.registers[31] => STACK_TOP as i32 - 4);
 
     // arithmetic instructions
     opcode_test!(test_opcode_adr; vm; [66, 2, 0, 1], vm.registers[2] => 15);
@@ -532,10 +2053,16 @@ mod tests {
     opcode_test!(test_opcode_mli; vm; [72, 0, 0, 4], vm.registers[0] => 20);
     opcode_test!(test_opcode_dvr; vm; [78, 2, 1, 0], vm.registers[2] => 2, vm.remainder => 0);
     opcode_test!(test_opcode_dvi; vm; [76, 0, 0, 4], vm.registers[0] => 1, vm.remainder => 1);
+    opcode_test!(test_opcode_inc; vm; [90, 0, 0, 0, 90, 0, 0, 0], vm.registers[0] => 7);
+    opcode_test!(test_opcode_dec; vm; [94, 1, 0, 0], vm.registers[1] => 9);
+    opcode_test!(test_opcode_dec_negative; vm; [94, 2, 0, 0], vm.registers[2] => -1);
 
     // comparison instructions
     opcode_test!(test_opcode_eqi; vm; [128, 0, 0, 5], vm.equality_flag => true);
     opcode_test!(test_opcode_eqr; vm; [130, 0, 1, 0], vm.equality_flag => false);
+    opcode_test!(test_opcode_strcmp; vm; [154, 2, 3, 4], vm.equality_flag => true; vm.registers[2] => 68, vm.registers[3] => 72, vm.registers[4] => 8, vm.memory.image_mut()[68] => b'h', vm.memory.image_mut()[72] => b'h', vm.memory.image_mut()[70] => b'x');
+    opcode_test!(test_opcode_strcmp_differs; vm; [154, 2, 3, 4], vm.equality_flag => false; vm.registers[2] => 68, vm.registers[3] => 72, vm.registers[4] => 8, vm.memory.image_mut()[68] => b'h', vm.memory.image_mut()[72] => b'o');
+    opcode_test!(test_opcode_strcmp_len; vm; [154, 2, 3, 4], vm.equality_flag => true; vm.registers[2] => 68, vm.registers[3] => 72, vm.registers[4] => 1, vm.memory.image_mut()[68] => b'h', vm.memory.image_mut()[69] => b'i', vm.memory.image_mut()[72] => b'h', vm.memory.image_mut()[73] => b'o');
     opcode_test!(test_opcode_neqi; vm; [132, 0, 0, 5], vm.equality_flag => false);
     opcode_test!(test_opcode_neqr; vm; [134, 0, 1, 0], vm.equality_flag => true);
 
@@ -567,4 +2094,614 @@ mod tests {
     opcode_test!(test_opcode_jmpned_b; vm; [169, 0, 0, 0], vm.pc => u32::from_be_bytes(PIE_HEADER_PREFIX) as usize; vm.equality_flag => false);
     opcode_test!(test_opcode_jmpner_a; vm; [8, 1, 1, 0, 170, 1, 0, 0], vm.pc => 72; vm.equality_flag => true);
     opcode_test!(test_opcode_jmpner_b; vm; [8, 1, 1, 0, 170, 1, 0, 0], vm.pc => 256; vm.equality_flag => false);
+
+    // branch instructions
+    opcode_test!(test_opcode_bra; vm; [224, 0, 8, 0, 255, 0, 0, 0, 0, 0, 0, 0], vm.pc => 76);
+    opcode_test!(test_opcode_bra_back; vm; [228, 0, 8, 0, 0, 0, 0, 0, 224, 255, 252, 0], vm.pc => 72; vm.equality_flag => true);
+    opcode_test!(test_opcode_bre_a; vm; [228, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0], vm.pc => 72; vm.equality_flag => false);
+    opcode_test!(test_opcode_bre_b; vm; [228, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0], vm.pc => 76; vm.equality_flag => true);
+    opcode_test!(test_opcode_brne_a; vm; [232, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0], vm.pc => 72; vm.equality_flag => true);
+    opcode_test!(test_opcode_brne_b; vm; [232, 0, 8, 0, 0, 0, 0, 0, 0, 0, 0, 0], vm.pc => 76; vm.equality_flag => false);
+
+    // call instructions
+    opcode_test!(test_opcode_calli; vm;
+        [176, 0, 76, 0, 64, 0, 0, 1, 0, 0, 0, 0, 64, 0, 0, 2, 180, 0, 0, 0],
+        vm.registers[0] => 8, vm.pc => 76);
+    opcode_test!(test_opcode_callr; vm; [178, 2, 0, 0, 0, 0, 0, 0, 180, 0, 0, 0], vm.pc => 72; vm.registers[2] => 72);
+
+    // self-modifying code
+    macro_rules! patch_test {
+        ($name:ident; [$( $program:expr ),*], $patch:expr, $register:expr => $value:expr) => {
+            #[test]
+            fn $name() {
+                let mut vm = get_test_vm(vec![$($program),*]);
+                prepend_header(&mut vm);
+                vm.registers[1] = $patch;
+                vm.memory.set_code_writable(true);
+
+                vm.run().unwrap();
+
+                assert_eq!(vm.registers[$register], $value);
+            }
+        };
+    }
+
+    patch_test!(test_patch_ahead_of_pc; [24, 1, 0, 68, 0, 0, 0, 0], 0x040200FF, 2 => 0xFF);
+    patch_test!(test_patch_cached_instruction;
+        [64, 3, 0, 1, 136, 3, 0, 2, 164, 0, 84, 0, 24, 1, 0, 64, 160, 0, 64, 0, 0, 0, 0, 0],
+        0x40030005, 3 => 6);
+
+    #[test]
+    fn test_poke_code() {
+        let mut vm = get_test_vm(vec![64, 3, 0, 1, 160, 0, 64, 0]);
+        prepend_header(&mut vm);
+        vm.start().unwrap();
+        vm.run_once().unwrap();
+        vm.run_once().unwrap();
+
+        // patch the cached loop body, then replace the jump back with a halt
+        vm.poke(64, &[64, 3, 0, 5]).unwrap();
+        vm.poke(68, &[0, 0, 0, 0]).unwrap();
+        vm.resume().unwrap();
+
+        assert_eq!(vm.registers[3], 6);
+        assert_eq!(vm.memory.read(64, 4), Ok(&[64, 3, 0, 5][..]));
+    }
+
+    // faults
+    macro_rules! fault_test {
+        ($name:ident; [$( $program:expr ),*], $error:expr) => {
+            #[test]
+            fn $name() {
+                let mut vm = get_test_vm(vec![$($program),*]);
+                prepend_header(&mut vm);
+
+                assert_eq!(vm.run(), Err($error));
+            }
+        };
+    }
+
+    fault_test!(test_fault_register; [4, 32, 0, 0], VmError::InvalidRegister { index: 32 });
+    fault_test!(test_fault_address; [5, 0, 1, 0], VmError::InvalidAddress { address: 256 });
+    fault_test!(test_fault_store; [24, 0, 0, 60], VmError::WriteProtected { address: 60 });
+    fault_test!(test_fault_store_code; [24, 0, 0, 64], VmError::WriteProtected { address: 64 });
+    fault_test!(test_fault_store_straddle; [24, 0, 0, 74], VmError::InvalidAddress { address: 74 });
+    fault_test!(test_fault_execute_data; [160, 0, 70, 0], VmError::NotExecutable { pc: 70 });
+    fault_test!(test_fault_stack_underflow; [180, 0, 0, 0], VmError::InvalidAddress { address: STACK_TOP });
+    fault_test!(test_fault_divide; [76, 0, 0, 0], VmError::DivisionByZero);
+    fault_test!(test_fault_truncated; [4, 0, 0], VmError::TruncatedInstruction { pc: 64 });
+    fault_test!(test_fault_string; [193, 0, 67, 65], VmError::InvalidAddress { address: 68 });
+    fault_test!(test_fault_send; [218, 0, 1, 0], VmError::NotInCluster { pc: 64 });
+    fault_test!(test_fault_memcpy; [58, 0, 1, 1], VmError::WriteProtected { address: 5 });
+
+    #[test]
+    fn test_fault_block_bounds() {
+        // memset $2, $0, $3
+        let mut vm = get_test_vm(vec![62, 2, 0, 3]);
+        prepend_header(&mut vm);
+        (vm.registers[2], vm.registers[3]) = (72, 8);
+        assert_eq!(vm.run(), Err(VmError::InvalidAddress { address: 72 }));
+        assert_eq!(vm.memory.image()[72..], [0; 4]);
+
+        // strcmp $2, $2, $3, over data with no null byte
+        let mut vm = get_test_vm(vec![154, 2, 2, 3]);
+        prepend_header(&mut vm);
+        vm.memory.image_mut()[68..].fill(b'a');
+        (vm.registers[2], vm.registers[3]) = (68, 100);
+        assert_eq!(vm.run(), Err(VmError::InvalidAddress { address: 76 }));
+    }
+
+    #[test]
+    fn test_breakpoints() {
+        // loops three times adding to $0, then halts
+        let mut vm = get_test_vm(vec![
+            64, 0, 0, 1, 64, 2, 0, 1, 128, 2, 0, 3, 168, 0, 64, 0, 0, 0, 0, 0,
+        ]);
+        prepend_header(&mut vm);
+        assert!(vm.add_breakpoint(68));
+        assert!(!vm.add_breakpoint(68));
+
+        vm.start().unwrap();
+        for expected in [6, 7, 8] {
+            assert_eq!(vm.resume(), Ok(true));
+            assert_eq!(vm.pc, 68);
+            assert_eq!(vm.registers[0], expected);
+        }
+
+        assert_eq!(vm.remove_breakpoint(0), Some(68));
+        assert_eq!(vm.remove_breakpoint(0), None);
+        assert_eq!(vm.resume(), Ok(false));
+        assert_eq!(vm.registers[2], 3);
+    }
+
+    #[test]
+    fn test_tracer() {
+        let steps = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = get_test_vm(vec![64, 0, 0, 1, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+
+        let recorded = steps.clone();
+        vm.set_tracer(move |step: &TraceStep| {
+            recorded
+                .borrow_mut()
+                .push((step.pc, step.opcode, step.operands, step.registers[0]))
+        });
+        vm.run().unwrap();
+
+        assert_eq!(
+            *steps.borrow(),
+            [
+                (64, Opcode::ADDI, [0, 0, 1], 5),
+                (68, Opcode::HLT, [0, 0, 0], 6)
+            ]
+        );
+        assert!(vm.take_tracer().is_some());
+    }
+
+    #[test]
+    fn test_output() {
+        let (output, log) = (SharedBuffer::default(), SharedBuffer::default());
+        let mut vm = get_test_vm(vec![193, 0, 72, 0, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+        vm.memory.poke(72, b"hi\0").unwrap();
+
+        vm.set_output(output.clone());
+        vm.add_output(log.clone());
+        vm.run().unwrap();
+
+        assert_eq!(output.contents(), b"hi\nHalting!\n");
+        assert_eq!(log.contents(), b"hi\nHalting!\n");
+    }
+
+    #[test]
+    fn test_console() {
+        // readi $2; prti $2; reads $4, 4; readi $3
+        let output = SharedBuffer::default();
+        let mut vm = get_test_vm(vec![210, 2, 0, 0, 206, 2, 0, 0, 212, 4, 0, 4, 210, 3, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(output.clone());
+        vm.set_input(&b" -42\r\nhello\n"[..]);
+        vm.registers[4] = 80;
+
+        // the string is truncated to fit, and the last read finds the end of input
+        vm.run().unwrap();
+        assert_eq!(output.contents(), b"-42\n");
+        assert_eq!(vm.memory.read(80, 4), Ok(&b"hel\0"[..]));
+        assert_eq!(vm.registers[3], 0);
+        assert!(!vm.equality_flag);
+
+        vm.set_input(&b"forty two\n"[..]);
+        assert_eq!(
+            vm.run(),
+            Err(VmError::InvalidInteger {
+                input: "forty two".to_owned(),
+                pc: 64
+            })
+        );
+    }
+
+    #[test]
+    fn test_snapshot() {
+        // allocates, then loops incrementing $3
+        let mut vm = get_test_vm(vec![32, 0, 0, 4, 64, 3, 0, 1, 160, 0, 68, 0]);
+        prepend_header(&mut vm);
+        vm.enable_shadow_stack(true);
+        vm.set_clock(|| 0_u64);
+        vm.start().unwrap();
+        for _ in 0..5 {
+            vm.run_once().unwrap();
+        }
+
+        let snapshot = vm.snapshot();
+        for _ in 0..3 {
+            vm.run_once().unwrap();
+        }
+
+        let mut restored = VM::default();
+        restored.set_clock(|| 0_u64);
+        let snapshot = Snapshot::from_bytes(snapshot.as_bytes().to_vec()).unwrap();
+        restored.restore(&snapshot).unwrap();
+        assert_eq!(restored.registers[3], 2);
+        for _ in 0..3 {
+            restored.run_once().unwrap();
+        }
+
+        assert_eq!(restored.registers, vm.registers);
+        assert_eq!(restored.pc(), vm.pc());
+        assert_eq!(restored.steps, vm.steps);
+        assert_eq!(restored.memory.allocator().allocations().count(), 1);
+        assert_eq!(restored.memory.region(64), Some(Region::Code));
+        assert_eq!(restored.backtrace(), Some(&[][..]));
+        assert_eq!(restored.snapshot(), vm.snapshot());
+
+        // invalid snapshots leave the VM untouched
+        let mut bytes = snapshot.as_bytes().to_vec();
+        bytes.pop();
+        let truncated = Snapshot::from_bytes(bytes).unwrap();
+        assert_eq!(restored.restore(&truncated), Err(SnapshotError::Malformed));
+        assert_eq!(restored.registers, vm.registers);
+    }
+
+    #[test]
+    fn test_logger() {
+        let records = Rc::new(RefCell::new(Vec::new()));
+        let mut vm = get_test_vm(vec![197, 1, 0, 72, 198, 9, 0, 0]);
+        prepend_header(&mut vm);
+        vm.memory.poke(72, b"hi\0").unwrap();
+
+        let logged = records.clone();
+        vm.set_logger(move |record: &LogRecord| logged.borrow_mut().push(record.to_string()));
+        vm.registers[0] = 72;
+
+        // the second log has an invalid level
+        assert_eq!(vm.run(), Err(VmError::InvalidLogLevel { level: 9, pc: 68 }));
+        assert_eq!(*records.borrow(), ["[warn 0x0040 step 1] hi"]);
+    }
+
+    #[test]
+    fn test_syscalls() {
+        // sysi 7; sysi 8
+        let mut vm = get_test_vm(vec![200, 0, 7, 0, 200, 0, 8, 0]);
+        prepend_header(&mut vm);
+        vm.register_syscall(7, |vm: &mut VM| {
+            vm.registers[2] = vm.registers[0] + vm.registers[1];
+            Ok(())
+        });
+
+        assert_eq!(vm.run(), Err(VmError::UnknownSyscall { number: 8, pc: 68 }));
+        assert_eq!(vm.registers[2], 15);
+
+        // the syscall is still registered after being called
+        vm.register_syscall(8, |_: &mut VM| Err(VmError::DivisionByZero));
+        assert_eq!(vm.run(), Err(VmError::DivisionByZero));
+        assert_eq!(vm.registers[2], 15);
+    }
+
+    #[test]
+    fn test_rand_time() {
+        // rand $2; time $3; rand $4; time $5
+        let mut vm = get_test_vm(vec![238, 2, 0, 0, 242, 3, 0, 0, 238, 4, 0, 0, 242, 5, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+
+        let mut next = 0;
+        vm.set_random(move || {
+            next += 1;
+            u32::MAX - next + 1
+        });
+        let mut now = 1000;
+        vm.set_clock(move || {
+            now += 5;
+            now
+        });
+
+        // the clock is read once when the program starts, then by each TIME
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.registers[2..6], [-1, 5, -2, 10]);
+
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.registers[2..6], [-3, 5, -4, 10]);
+    }
+
+    #[test]
+    fn test_alignment() {
+        // stores a half-word at 73, then loads a word from 74
+        let mut vm = get_test_vm(vec![20, 1, 0, 73, 13, 2, 0, 74]);
+        prepend_header(&mut vm);
+        vm.registers[1] = 0x1234;
+        vm.run().unwrap();
+        assert_eq!(vm.registers[2], 0x34000000);
+
+        vm.memory.set_alignment_checked(true);
+        assert_eq!(
+            vm.run(),
+            Err(VmError::UnalignedAccess {
+                address: 73,
+                size: 2
+            })
+        );
+    }
+
+    #[test]
+    fn test_register_pairs() {
+        // add64 $2, $4; sub64 $2, $6; st64i $2, 84; ld64d $8, 84; add64 $31, $2
+        let mut vm = get_test_vm(vec![
+            82, 2, 4, 0, 86, 2, 6, 0, 52, 2, 0, 84, 49, 8, 0, 84, 82, 31, 2, 0,
+        ]);
+        prepend_header(&mut vm);
+        vm.registers[2..8].copy_from_slice(&[0, -1, 0, 1, 0, 2]);
+
+        // the low word carries into the high word, then borrows back out of it
+        assert_eq!(vm.run(), Err(VmError::InvalidRegister { index: 32 }));
+        assert_eq!(vm.registers[2..4], [0, -2]);
+        assert_eq!(vm.registers[8..10], [0, -2]);
+        assert_eq!(
+            vm.memory.read(84, 8),
+            Ok(&[0, 0, 0, 0, 255, 255, 255, 254][..])
+        );
+    }
+
+    #[test]
+    fn test_register_pair_overflow() {
+        let mut vm = get_test_vm(vec![82, 0, 2, 0]);
+        prepend_header(&mut vm);
+        vm.registers[0..4].copy_from_slice(&[i32::MAX, -1, 0, 1]);
+
+        vm.run().unwrap();
+        assert_eq!(vm.registers[0..2], [i32::MIN, 0]);
+    }
+
+    #[test]
+    fn test_limits() {
+        let limited = |config: VMConfig, program: Vec<u8>| {
+            let mut vm = VM::with_config(config);
+            vm.memory = Memory::new(program);
+            prepend_header(&mut vm);
+
+            vm.run()
+        };
+        let exhausted = |limit| Err(VmError::ResourceExhausted { limit });
+
+        let config = VMConfig {
+            max_heap: Some(8),
+            ..Default::default()
+        };
+        assert_eq!(limited(config, vec![32, 0, 0, 8]), Ok(()));
+        assert_eq!(limited(config, vec![32, 0, 0, 9]), exhausted(Limit::Heap));
+
+        let config = VMConfig {
+            max_steps: Some(100),
+            ..Default::default()
+        };
+        assert_eq!(
+            limited(config, vec![160, 0, 64, 0]),
+            exhausted(Limit::Steps)
+        );
+
+        let config = VMConfig {
+            max_program_size: Some(64),
+            ..Default::default()
+        };
+        assert_eq!(
+            limited(config, vec![0, 0, 0, 0]),
+            exhausted(Limit::ProgramSize)
+        );
+    }
+
+    #[test]
+    fn test_run_with_limit() {
+        let mut vm = get_test_vm(vec![160, 0, 64, 0]);
+        prepend_header(&mut vm);
+        assert_eq!(vm.run_with_limit(10), Ok(RunStatus::LimitReached));
+        assert_eq!(vm.steps(), 10);
+
+        let mut vm = get_test_vm(vec![64, 3, 0, 1, 0, 0, 0, 0, 64, 3, 0, 1]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+        assert_eq!(vm.run_with_limit(2), Ok(RunStatus::Halted));
+        assert_eq!(vm.steps(), 2);
+        assert_eq!(vm.registers[3], 1);
+    }
+
+    #[test]
+    fn test_run_cached() {
+        // counts $3 up by 1, patching itself to count by 5 once it reaches 10, until $3 >= 52
+        let program = vec![
+            64, 3, 0, 1, 128, 3, 0, 10, 168, 0, 80, 0, 24, 1, 0, 64, 144, 3, 0, 52, 164, 0, 64, 0,
+            0, 0, 0, 0,
+        ];
+
+        let mut results = Vec::new();
+        for cached in [false, true] {
+            let mut vm = get_test_vm(program.clone());
+            prepend_header(&mut vm);
+            vm.registers[1] = 0x40030005;
+            vm.memory.set_code_writable(true);
+            vm.set_output(std::io::sink());
+
+            match cached {
+                true => vm.run_cached().unwrap(),
+                false => vm.run().unwrap(),
+            }
+            results.push((vm.registers[3], vm.steps()));
+        }
+
+        assert_eq!(results[0].0, 55);
+        assert_eq!(results[0], results[1]);
+    }
+
+    #[cfg(feature = "timing")]
+    #[test]
+    fn test_timings() {
+        // addr $2, $0, $1; addr $2, $2, $1; hlt
+        let mut vm = get_test_vm(vec![66, 2, 0, 1, 66, 2, 2, 1, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+        vm.run().unwrap();
+
+        let counts = vm
+            .timings()
+            .top(10)
+            .iter()
+            .map(|timing| (timing.opcode, timing.count))
+            .collect::<Vec<_>>();
+        assert_eq!(counts.len(), 2);
+        assert!(counts.contains(&(Opcode::ADDR, 2)));
+        assert!(counts.contains(&(Opcode::HLT, 1)));
+
+        vm.clear_timings();
+        assert!(vm.timings().top(10).is_empty());
+    }
+
+    #[test]
+    fn test_jump_verification() {
+        // jmpi 256, which is outside the code section but never reached
+        let mut vm = get_test_vm(vec![0, 0, 0, 0, 160, 1, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+        assert_eq!(vm.run(), Ok(()));
+
+        vm.enable_jump_verification(true);
+        assert_eq!(
+            vm.run(),
+            Err(VmError::InvalidJumps {
+                jumps: vec![BadJump {
+                    pc: 68,
+                    target: 256
+                }]
+            })
+        );
+    }
+
+    #[test]
+    fn test_entry_point() {
+        // ldbi $0, 1; ldbi $1, 2; hlt
+        let mut vm = get_test_vm(vec![4, 0, 0, 1, 4, 1, 0, 2, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+        vm.memory.image_mut()[ENTRY_POINT_FIELD + 3] = 4;
+
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.registers[..2], [5, 2]);
+
+        // entry points must be an instruction in the code section. The header is only read when
+        // memory is first started, so each is given fresh memory
+        for offset in [2, 12] {
+            let mut image = vm.memory.image().to_vec();
+            image[ENTRY_POINT_FIELD + 3] = offset;
+            vm.memory = Memory::new(image);
+            assert_eq!(vm.run(), Err(VmError::InvalidHeader));
+        }
+    }
+
+    #[test]
+    fn test_exit() {
+        // ldbi $2, 3; exit $2
+        let mut vm = get_test_vm(vec![4, 2, 0, 3, 2, 2, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.exit_status(), 3);
+        assert_eq!(vm.pc(), 72);
+
+        // restarting clears it, and HLT leaves it zeroed
+        vm.poke(68, &[0, 0, 0, 0]).unwrap();
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.exit_status(), 0);
+    }
+
+    #[test]
+    fn test_fault_header() {
+        let mut vm = get_test_vm(vec![0, 0, 0, 0]);
+        assert_eq!(vm.run(), Err(VmError::MissingMagic));
+
+        let mut vm = get_test_vm(b"EPIE".to_vec());
+        assert_eq!(vm.run(), Err(VmError::InvalidHeader));
+
+        let mut vm = get_test_vm(vec![0; 4]);
+        prepend_header(&mut vm);
+        vm.memory.image_mut()[4] = PIE_FORMAT_VERSION + 1;
+        assert_eq!(
+            vm.run(),
+            Err(VmError::UnsupportedVersion {
+                version: PIE_FORMAT_VERSION + 1
+            })
+        );
+
+        // a zeroed word size is from before the field existed, so is read as 4 bytes
+        let mut vm = get_test_vm(vec![0; 4]);
+        prepend_header(&mut vm);
+        assert_eq!(vm.run(), Ok(()));
+        let mut image = vm.memory.image().to_vec();
+        image[5] = 2;
+        vm.memory = Memory::new(image.clone());
+        assert_eq!(vm.run(), Err(VmError::UnsupportedWordSize { size: 2 }));
+        image[5] = 8;
+        vm.memory = Memory::new(image);
+        assert_eq!(
+            vm.run(),
+            Err(VmError::WordSizeMismatch {
+                size: 8,
+                expected: 4
+            })
+        );
+    }
+
+    #[test]
+    fn test_set_args() {
+        // ldwr $2, $1; ldbr $3, $2; hlt
+        let mut vm = get_test_vm(vec![0x0E, 2, 1, 0, 0x06, 3, 2, 0, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+        vm.load(Program::parse(vm.memory.image().to_vec()).unwrap());
+        vm.set_args(&["ab", "c"]).unwrap();
+
+        // the image is 84 bytes, so the address array starts at the next word
+        assert_eq!(vm.registers[..2], [2, 84]);
+        assert_eq!(vm.memory.read_u32(84), Ok(92));
+        assert_eq!(vm.memory.read_u32(88), Ok(95));
+        assert_eq!(vm.memory.read_string(92), Ok(&b"ab"[..]));
+        assert_eq!(vm.memory.read_string(95), Ok(&b"c"[..]));
+
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.registers[2..4], [92, b'a' as i32]);
+    }
+
+    #[test]
+    fn test_load() {
+        // ldbi $0, 1; ldbi $1, 2; hlt, starting at the second instruction
+        let mut vm = get_test_vm(vec![4, 0, 0, 1, 4, 1, 0, 2, 0, 0, 0, 0]);
+        prepend_header(&mut vm);
+        vm.set_output(std::io::sink());
+        vm.memory.set_alignment_checked(true);
+        let mut image = vm.memory.image().to_vec();
+        image[ENTRY_POINT_FIELD + 3] = 4;
+
+        let program = Program::parse(image).unwrap();
+        assert_eq!(program.entry(), 68);
+        vm.load(program);
+        assert_eq!(vm.memory.code_section(), Some(64..76));
+        assert!(vm.memory.alignment_checked());
+
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.registers[..2], [5, 2]);
+    }
+
+    #[test]
+    fn test_reload() {
+        let program = |code: Vec<u8>| {
+            let mut vm = get_test_vm(code);
+            prepend_header(&mut vm);
+            Program::parse(vm.memory.image().to_vec()).unwrap()
+        };
+
+        // aloci $2, 8; hlt
+        let mut vm = VM::default();
+        vm.set_output(std::io::sink());
+        vm.load(program(vec![0x20, 2, 0, 8, 0, 0, 0, 0]));
+        vm.start().unwrap();
+        assert_eq!(vm.run(), Ok(()));
+        let address = vm.registers[2];
+        vm.memory.write(address as usize, &[7]).unwrap();
+
+        // ldbi $3, 1; hlt, which is the same size so the heap stays where it was
+        assert_eq!(
+            vm.reload(program(vec![4, 3, 0, 1, 0, 0, 0, 0]), true),
+            Ok(true)
+        );
+        assert_eq!(vm.run(), Ok(()));
+        assert_eq!(vm.registers[2..4], [address, 1]);
+        assert_eq!(vm.memory.read(address as usize, 1), Ok(&[7][..]));
+        assert_eq!(vm.memory.allocator().allocations().count(), 1);
+
+        // a longer program moves the heap, so only the registers are kept
+        let longer = vec![4, 3, 0, 2, 0, 0, 0, 0, 0, 0, 0, 0];
+        assert_eq!(vm.reload(program(longer.clone()), true), Ok(false));
+        assert_eq!(vm.registers[2], address);
+        assert_eq!(vm.memory.allocator().allocations().count(), 0);
+
+        assert_eq!(vm.reload(program(longer), false), Ok(false));
+        assert_eq!(vm.registers[2], 0);
+    }
 }
@@ -0,0 +1,125 @@
+//! Register words the VM can be built with.
+//!
+//! Registers and memory words are 4 bytes by default, and 8 bytes in VMs built with `VM<i64>`. The
+//! program header declares which its program was assembled for, and a VM only runs programs
+//! declaring its own word size. Loads and stores of words, the stack, return addresses and
+//! address tables are all word sized, while half-words, bytes, immediates, register pairs, `RAND`
+//! values and the elements of objects are the same size in either.
+
+use std::fmt::{Debug, Display};
+use std::str::FromStr;
+
+/// Signed integer held in each register
+pub trait Word:
+    Copy + Default + Eq + Ord + Debug + Display + FromStr + Send + Sync + 'static
+{
+    /// Bytes in a word, and in the program header's word size field
+    const BYTES: usize;
+
+    /// Sign extends a 32 bit value
+    fn from_i32(value: i32) -> Self;
+
+    /// Truncates a 64 bit value to the width of a word
+    fn from_i64(value: i64) -> Self;
+
+    /// Truncates the word to 32 bits
+    fn to_i32(self) -> i32;
+
+    /// Sign extends the word to 64 bits
+    fn to_i64(self) -> i64;
+
+    /// Address held in the word, read as unsigned
+    fn to_address(self) -> usize;
+
+    /// Address converted to a word, truncating it if the word is narrower than a pointer
+    fn from_address(address: usize) -> Self {
+        Self::from_i64(address as i64)
+    }
+
+    /// Word from its big endian bytes, given exactly `BYTES` of them
+    fn from_be_slice(bytes: &[u8]) -> Self;
+
+    /// Big endian bytes of the word, which are `BYTES` long
+    fn to_be_vec(self) -> Vec<u8>;
+
+    // arithmetic wraps on overflow, as it does for registers of either size
+    fn wrapping_add(self, other: Self) -> Self;
+    fn wrapping_sub(self, other: Self) -> Self;
+    fn wrapping_mul(self, other: Self) -> Self;
+    fn wrapping_div(self, other: Self) -> Self;
+    fn wrapping_rem(self, other: Self) -> Self;
+}
+
+macro_rules! word {
+    ($ty:ty, $unsigned:ty) => {
+        impl Word for $ty {
+            const BYTES: usize = size_of::<$ty>();
+
+            fn from_i32(value: i32) -> Self {
+                value as $ty
+            }
+
+            fn from_i64(value: i64) -> Self {
+                value as $ty
+            }
+
+            fn to_i32(self) -> i32 {
+                self as i32
+            }
+
+            fn to_i64(self) -> i64 {
+                self as i64
+            }
+
+            fn to_address(self) -> usize {
+                self as $unsigned as usize
+            }
+
+            fn from_be_slice(bytes: &[u8]) -> Self {
+                <$ty>::from_be_bytes(bytes.try_into().unwrap())
+            }
+
+            fn to_be_vec(self) -> Vec<u8> {
+                self.to_be_bytes().to_vec()
+            }
+
+            fn wrapping_add(self, other: Self) -> Self {
+                <$ty>::wrapping_add(self, other)
+            }
+
+            fn wrapping_sub(self, other: Self) -> Self {
+                <$ty>::wrapping_sub(self, other)
+            }
+
+            fn wrapping_mul(self, other: Self) -> Self {
+                <$ty>::wrapping_mul(self, other)
+            }
+
+            fn wrapping_div(self, other: Self) -> Self {
+                <$ty>::wrapping_div(self, other)
+            }
+
+            fn wrapping_rem(self, other: Self) -> Self {
+                <$ty>::wrapping_rem(self, other)
+            }
+        }
+    };
+}
+
+word!(i32, u32);
+word!(i64, u64);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_words() {
+        assert_eq!(i32::from_i64(0x1_0000_0002), 2);
+        assert_eq!(i64::from_i32(-1), -1);
+        assert_eq!((-1_i32).to_address(), 0xFFFF_FFFF);
+        assert_eq!(i64::from_be_slice(&7_i64.to_be_bytes()), 7);
+        assert_eq!(Word::wrapping_add(i32::MAX, 1), i32::MIN);
+        assert_eq!(Word::wrapping_add(i32::MAX as i64, 1), 1 << 31);
+    }
+}
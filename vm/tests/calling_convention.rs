@@ -0,0 +1,104 @@
+//! The calling convention from `shared::abi`, followed by a routine calling another: arguments
+//! and results are passed in `$a` registers, callee-saved registers survive calls, and `ENTER`
+//! and `LEAVE` build a chain of frames that can be walked through the frame pointer.
+
+use assembler::Assembler;
+use shared::abi::{
+    CALLEE_SAVED, FRAME_POINTER, RETURN_ADDRESS_OFFSET, RETURN_REGISTERS, STACK_POINTER, STACK_TOP,
+};
+use vm::{Program, VmError, VM};
+
+const PROGRAM: &str = r#"
+.code
+    main:   ldbi $a0, 3
+            ldbi $a1, 4
+            ldbi $s0, 99
+            calli @sumsquares
+    aftersum:
+            hlt
+
+    ; returns $a0 * $a0 + $a1 * $a1, keeping the first square in a local
+    sumsquares:
+            enter 4
+            push $s0
+            mov $s0, $a1
+            calli @square
+    aftersquare:
+            mov $t0, $fp
+            subi $t0, 4
+            strwr $a0, $t0
+            mov $a0, $s0
+            calli @square
+            mov $t0, $fp
+            subi $t0, 4
+            ldwr $t1, $t0
+            addr $a0, $a0, $t1
+            pop $s0
+            leave
+            ret
+
+    ; returns $a0 * $a0
+    square: enter 0
+            mulr $a0, $a0, $a0
+    squared:
+            leave
+            ret
+"#;
+
+fn load(source: &str) -> (VM, Assembler) {
+    let mut assembler = Assembler::default();
+    let program = assembler.assemble(source).unwrap();
+
+    let mut vm = VM::default();
+    vm.load(Program::parse(program).unwrap());
+    vm.start().unwrap();
+
+    (vm, assembler)
+}
+
+fn register(vm: &VM, register: u8) -> usize {
+    vm.registers()[register as usize] as u32 as usize
+}
+
+#[test]
+fn test_two_level_calls() {
+    let (mut vm, assembler) = load(PROGRAM);
+    let label = |name| assembler.label_address(name).unwrap() as usize;
+    let word = |vm: &VM, address| vm.memory().read_u32(address).unwrap() as usize;
+
+    // stopped in the innermost routine, each frame holds the caller's frame pointer with the
+    // return address above it
+    vm.add_breakpoint(label("squared"));
+    assert_eq!(vm.resume(), Ok(true));
+    let inner = register(&vm, FRAME_POINTER);
+    assert_eq!(
+        word(&vm, inner + RETURN_ADDRESS_OFFSET),
+        label("aftersquare")
+    );
+
+    let outer = word(&vm, inner);
+    assert_eq!(word(&vm, outer + RETURN_ADDRESS_OFFSET), label("aftersum"));
+    assert_eq!(word(&vm, outer), 0);
+    assert_eq!(outer, STACK_TOP - 8);
+
+    // locals and saved registers of the outer routine are below its frame pointer
+    assert_eq!(word(&vm, outer - 8), 99);
+    assert!(register(&vm, STACK_POINTER) < outer - 8);
+
+    vm.remove_breakpoint(0);
+    assert_eq!(vm.resume(), Ok(false));
+
+    // the result is in the first return register, and everything the caller relies on survives
+    assert_eq!(vm.registers()[*RETURN_REGISTERS.start() as usize], 25);
+    assert_eq!(vm.registers()[*CALLEE_SAVED.start() as usize], 99);
+    assert_eq!(register(&vm, STACK_POINTER), STACK_TOP);
+    assert_eq!(register(&vm, FRAME_POINTER), 0);
+}
+
+#[test]
+fn test_enter_overflow() {
+    let (mut vm, _) = load(".code\nenter 0xFFFF\nhlt");
+
+    assert!(matches!(vm.run(), Err(VmError::StackOverflow { .. })));
+    assert_eq!(register(&vm, STACK_POINTER), STACK_TOP);
+}
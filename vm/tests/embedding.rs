@@ -0,0 +1,98 @@
+//! Embedding the VM in another application: assembling a program, giving it host functions to
+//! call, running it within an instruction budget and reading back what it did.
+
+use assembler::Assembler;
+use std::cell::RefCell;
+use std::collections::VecDeque;
+use std::rc::Rc;
+use vm::{verify, Limit, SharedBuffer, VMConfig, VmError, VM};
+
+/// Syscall returning the next input value in $0, or 0 once there are none left
+const READ: u16 = 1;
+/// Syscall reporting the value in $0 back to the host
+const REPORT: u16 = 2;
+
+const SUM: &str = r#"
+.data
+    banner: .asciiz 'summing'
+.code
+            prtsd @banner
+    loop:   sysi 1
+            eqi $0, 0
+            jmpei @done
+            addr $1, $1, $0
+            jmpi @loop
+    done:   mov $0, $1
+            sysi 2
+            hlt
+"#;
+
+/// VM with the program verified and loaded and output captured, limited to max_steps instructions
+fn load(source: &str, max_steps: u64) -> (VM, SharedBuffer) {
+    let program = Assembler::default().assemble(source).unwrap();
+
+    let mut vm = VM::with_config(VMConfig {
+        max_steps: Some(max_steps),
+        ..Default::default()
+    });
+    // programs from elsewhere would be rejected here, before anything runs
+    vm.load(verify(program).unwrap().into_program());
+
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+
+    (vm, output)
+}
+
+#[test]
+fn test_embedding() {
+    let (mut vm, output) = load(SUM, 100);
+
+    let mut input = VecDeque::from([3, 4, 5]);
+    vm.register_syscall(READ, move |vm: &mut VM| {
+        vm.registers_mut()[0] = input.pop_front().unwrap_or(0);
+        Ok(())
+    });
+
+    let reported = Rc::new(RefCell::new(Vec::new()));
+    let report = reported.clone();
+    vm.register_syscall(REPORT, move |vm: &mut VM| {
+        report.borrow_mut().push(vm.registers()[0]);
+        Ok(())
+    });
+
+    vm.run().unwrap();
+
+    assert_eq!(output.to_string_lossy(), "summing\nHalting!\n");
+    assert_eq!(*reported.borrow(), [12]);
+    assert_eq!(vm.registers()[1], 12);
+}
+
+#[test]
+fn test_budget() {
+    // input never runs out, so the program only stops when the budget does
+    let (mut vm, _) = load(SUM, 101);
+    vm.register_syscall(READ, |vm: &mut VM| {
+        vm.registers_mut()[0] = 1;
+        Ok(())
+    });
+
+    assert_eq!(
+        vm.run(),
+        Err(VmError::ResourceExhausted {
+            limit: Limit::Steps
+        })
+    );
+    // the banner then 20 trips round the loop, of 5 instructions each
+    assert_eq!(vm.registers()[1], 20);
+}
+
+#[test]
+fn test_missing_syscall() {
+    let (mut vm, _) = load(SUM, 100);
+
+    assert!(matches!(
+        vm.run(),
+        Err(VmError::UnknownSyscall { number: READ, .. })
+    ));
+}
@@ -0,0 +1,73 @@
+//! Copies a file through the sandboxed file opcodes, as a utility program would.
+
+use assembler::Assembler;
+use std::path::Path;
+use vm::{Program, VM};
+
+/// Copies `in.txt` to `out.txt` in the given directory, four bytes at a time, halting with the
+/// exit status 1 if either can't be opened
+fn copy_program(directory: &Path) -> String {
+    format!(
+        r#"
+.data
+    input:  .asciiz '{0}/in.txt'
+    output: .asciiz '{0}/out.txt'
+.bss
+    buffer: .space 4
+.code
+            ldwd $1, =@input
+            ldbi $2, 0              ; read
+            fopen $10, $1, $2
+            jmpnei @fail
+            ldwd $1, =@output
+            ldbi $2, 1              ; write
+            fopen $11, $1, $2
+            jmpnei @fail
+            ldwd $1, =@buffer
+    copy:   ldbi $0, 4
+            fread $0, $10, $1
+            eqi $0, 0
+            jmpei @done
+            fwrite $0, $11, $1
+            jmpi @copy
+    done:   fclose $10
+            fclose $11
+            hlt
+    fail:   ldbi $0, 1
+            exit $0
+"#,
+        directory.display()
+    )
+}
+
+fn run(source: &str, directory: Option<&Path>) -> i32 {
+    let program = Assembler::default().assemble(source).unwrap();
+
+    let mut vm = VM::default();
+    vm.set_output(std::io::sink());
+    if let Some(directory) = directory {
+        vm.allow_directory(directory).unwrap();
+    }
+    vm.load(Program::parse(program).unwrap());
+    assert_eq!(vm.run(), Ok(()));
+
+    vm.exit_status()
+}
+
+#[test]
+fn test_copy_file() {
+    let directory = std::env::temp_dir().join(format!("rvm-copy-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("in.txt"), "copied by a program\n").unwrap();
+    let source = copy_program(&directory);
+
+    // nothing can be opened until the directory is allowed
+    assert_eq!(run(&source, None), 1);
+    assert!(!directory.join("out.txt").exists());
+
+    assert_eq!(run(&source, Some(&directory)), 0);
+    let copied = std::fs::read_to_string(directory.join("out.txt")).unwrap();
+    assert_eq!(copied, "copied by a program\n");
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,41 @@
+//! Draws to the framebuffer, as `run --display` shows it.
+
+use assembler::Assembler;
+use shared::abi::FRAMEBUFFER_BASE;
+use std::cell::RefCell;
+use std::rc::Rc;
+use vm::{Framebuffer, Program, FRAMEBUFFER_WIDTH, VM};
+
+/// Draws a red diagonal line from the top left corner
+const DIAGONAL: &str = r#"
+.code
+            ldwd $10, =0x100000     ; framebuffer
+            ldbi $0, 0b11100000     ; red
+            ldbi $1, 0
+    draw:   strbr $0, $10
+            addi $10, 65            ; one row down and one pixel right
+            inc $1
+            lti $1, 64
+            jmpei @draw
+            hlt
+"#;
+
+#[test]
+fn test_diagonal() {
+    let program = Assembler::default().assemble(DIAGONAL).unwrap();
+    let framebuffer = Rc::new(RefCell::new(Framebuffer::default()));
+
+    let mut vm = VM::default();
+    vm.set_output(std::io::sink());
+    vm.load(Program::parse(program).unwrap());
+    vm.memory_mut()
+        .map_device(FRAMEBUFFER_BASE, framebuffer.clone())
+        .unwrap();
+    assert_eq!(vm.run(), Ok(()));
+
+    let frame = framebuffer.borrow_mut().take_frame().unwrap();
+    for (index, pixel) in frame.into_iter().enumerate() {
+        let on_diagonal = index % FRAMEBUFFER_WIDTH == index / FRAMEBUFFER_WIDTH;
+        assert_eq!(pixel, if on_diagonal { 0xFF0000 } else { 0 });
+    }
+}
@@ -0,0 +1,93 @@
+//! Steps backwards through a program one instruction at a time, as a debugger would.
+
+use assembler::Assembler;
+use vm::{Program, VM};
+
+/// Allocates a buffer, fills it from a routine, frees it and halts, changing registers, memory,
+/// the heap, the stack and the calls that haven't returned along the way
+const PROGRAM: &str = r#"
+.data
+    total:  .word 0
+.code
+            aloci $1, 16
+            ldbi $2, 0
+    fill:   calli @store
+            inc $2
+            lti $2, 4
+            jmpei @fill
+            ldwd $3, =@total
+            strwr $2, $3
+            divr $4, $2, $2
+            free $1
+            hlt
+
+    store:  push $2
+            addr $5, $1, $2
+            strbr $2, $5
+            pop $2
+            ret
+"#;
+
+fn load() -> VM {
+    let program = Assembler::default().assemble(PROGRAM).unwrap();
+
+    let mut vm = VM::default();
+    vm.set_output(std::io::sink());
+    vm.enable_shadow_stack(true);
+    // snapshots include the time, so it mustn't move on while they're compared
+    vm.set_clock(|| 0_u64);
+    vm.load(Program::parse(program).unwrap());
+    vm.start().unwrap();
+
+    vm
+}
+
+#[test]
+fn test_step_back() {
+    let mut vm = load();
+    vm.set_recording(Some(1000));
+
+    let mut snapshots = vec![vm.snapshot()];
+    loop {
+        let running = vm.run_once().unwrap();
+        snapshots.push(vm.snapshot());
+        if !running {
+            break;
+        }
+    }
+    assert_eq!(vm.recorded_steps(), snapshots.len() - 1);
+
+    // every instruction is undone exactly, back to the start
+    while let Some(snapshot) = snapshots.pop() {
+        assert_eq!(vm.snapshot(), snapshot);
+        assert_eq!(vm.step_back(), !snapshots.is_empty());
+    }
+    assert_eq!(vm.recorded_steps(), 0);
+
+    // and can be run forwards again from anywhere
+    for _ in 0..20 {
+        vm.run_once().unwrap();
+    }
+    vm.step_back();
+    assert_eq!(vm.resume(), Ok(false));
+    assert_eq!(
+        vm.memory().read(vm.registers()[1] as usize, 4),
+        Ok(&[0, 1, 2, 3][..])
+    );
+}
+
+#[test]
+fn test_recording_limit() {
+    let mut vm = load();
+    vm.set_recording(Some(5));
+    assert_eq!(vm.resume(), Ok(false));
+    assert_eq!(vm.recorded_steps(), 5);
+
+    let steps = vm.steps();
+    while vm.step_back() {}
+    assert_eq!(vm.steps(), steps - 5);
+
+    vm.set_recording(None);
+    vm.run_once().unwrap();
+    assert!(!vm.step_back());
+}
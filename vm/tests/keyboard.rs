@@ -0,0 +1,43 @@
+//! Polls the keyboard for scripted keys, as an interactive program would for real ones.
+
+use assembler::Assembler;
+use shared::abi::KEYBOARD_BASE;
+use std::cell::RefCell;
+use std::rc::Rc;
+use vm::{Keyboard, Program, SharedBuffer, VMConfig, VM};
+
+/// Prints the code of every key pressed, until Enter
+const ECHO_KEYS: &str = r#"
+.code
+            ldwd $10, =0x101000     ; keyboard data register
+            mov $11, $10
+            inc $11                 ; keyboard status register
+    wait:   ldbr $0, $11
+            eqi $0, 0
+            jmpei @wait
+            ldbr $1, $10
+            prti $1
+            neqi $1, 10
+            jmpei @wait
+            hlt
+"#;
+
+#[test]
+fn test_scripted_keys() {
+    let program = Assembler::default().assemble(ECHO_KEYS).unwrap();
+    let keyboard = Keyboard::scripted([(0, b'h'), (50, b'i'), (3, b'\n')]);
+
+    let mut vm = VM::with_config(VMConfig {
+        max_steps: Some(1000),
+        ..Default::default()
+    });
+    let output = SharedBuffer::default();
+    vm.set_output(output.clone());
+    vm.load(Program::parse(program).unwrap());
+    vm.memory_mut()
+        .map_device(KEYBOARD_BASE, Rc::new(RefCell::new(keyboard)))
+        .unwrap();
+
+    assert_eq!(vm.run(), Ok(()));
+    assert_eq!(output.to_string_lossy(), "104\n105\n10\nHalting!\n");
+}
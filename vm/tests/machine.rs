@@ -0,0 +1,67 @@
+//! Runs the same program on every backend through the `Machine` trait, which should leave each one
+//! in the same state.
+
+use assembler::Assembler;
+use vm::{Cached, Machine, Program, VM};
+
+/// Sums 1 to 100 into a global in a loop, then exits with the sum
+const PROGRAM: &str = r#"
+.data
+    total: .word 0
+.code
+            ldbi $0, 100
+    loop:   ldwd $1, @total
+            addr $1, $1, $0
+            strwi $1, @total
+            dec $0
+            gti $0, 0
+            jmpei @loop
+            exit $1
+"#;
+
+fn backends() -> Vec<Box<dyn Machine>> {
+    vec![Box::new(VM::default()), Box::new(Cached::default())]
+}
+
+fn load(machine: &mut dyn Machine) -> usize {
+    let mut assembler = Assembler::default();
+    let program = assembler.assemble(PROGRAM).unwrap();
+    machine.load_program(Program::parse(program).unwrap());
+
+    assembler.label_address("total").unwrap() as usize
+}
+
+#[test]
+fn test_run() {
+    for mut machine in backends() {
+        let total = load(machine.as_mut());
+        machine.run().unwrap();
+
+        let name = machine.name();
+        assert_eq!(
+            machine.memory().read(total, 4),
+            Ok(&5050_i32.to_be_bytes()[..]),
+            "{name}"
+        );
+        assert_eq!(machine.registers()[0], 0, "{name}");
+        assert_eq!(machine.exit_status(), 5050, "{name}");
+    }
+}
+
+#[test]
+fn test_step() {
+    for mut machine in backends() {
+        load(machine.as_mut());
+        machine.start().unwrap();
+        let start = machine.pc();
+
+        assert_eq!(machine.step(), Ok(true));
+        assert_eq!(machine.registers()[0], 100);
+        assert_eq!(machine.pc(), start + 4);
+
+        // changes made through the trait are seen by the program
+        machine.registers_mut()[0] = 1;
+        while machine.step().unwrap() {}
+        assert_eq!(machine.exit_status(), 1, "{}", machine.name());
+    }
+}
@@ -0,0 +1,151 @@
+//! Builds objects on the garbage collected heap, as a scripting language frontend would.
+
+use assembler::Assembler;
+use vm::{Object, Program, VmError, VM};
+
+/// Allocates 1000 arrays of 100 words, only keeping each until the next is allocated, then
+/// builds an array holding a string and leaves it in $5
+const CHURN: &str = r#"
+.code
+            ldbi $0, 0
+            ldbi $1, 100
+    loop:   newarr $2, $1, 0
+            setel $2, $7, $0        ; $7 is still 0
+            inc $0
+            lti $0, 1000
+            jmpei @loop
+
+            ldbi $1, 2
+            newarr $3, $1, 1
+            ldbi $0, 0
+            ldbi $4, 'h'
+            setel $3, $0, $4
+            inc $0
+            ldbi $4, 'i'
+            setel $3, $0, $4
+            ldbi $1, 1
+            newarr $5, $1, 0
+            ldbi $0, 0
+            setel $5, $0, $3
+            ldbi $3, 0
+            getel $6, $5, $0
+            hlt
+"#;
+
+fn load(source: &str) -> VM {
+    let program = Assembler::default().assemble(source).unwrap();
+
+    let mut vm = VM::default();
+    vm.set_output(std::io::sink());
+    vm.load(Program::parse(program).unwrap());
+
+    vm
+}
+
+#[test]
+fn test_collection() {
+    let mut vm = load(CHURN);
+    vm.enable_object_heap(true);
+    assert_eq!(vm.run(), Ok(()));
+
+    let objects = vm.objects().unwrap();
+    assert!(objects.collections() > 0);
+    assert!(objects.len() < 1000);
+    let string = Object::String(b"hi".to_vec());
+    assert_eq!(objects.get(vm.registers()[6]), Some(&string));
+
+    // only the last array, and the array in $5 and the string it holds, are still referenced
+    vm.registers_mut()[6] = 0;
+    vm.collect_garbage();
+    let objects = vm.objects().unwrap();
+    assert_eq!(objects.len(), 3);
+    let handle = objects.get(vm.registers()[5]).unwrap().get(0).unwrap();
+    assert_eq!(objects.get(handle), Some(&string));
+}
+
+#[test]
+fn test_object_faults() {
+    let mut vm = load(CHURN);
+    assert_eq!(vm.run(), Err(VmError::NoObjectHeap { pc: 72 }));
+
+    let mut vm = load(
+        r#"
+.code
+            ldbi $1, 2
+            newarr $2, $1, 0
+            getel $3, $2, $1
+"#,
+    );
+    vm.enable_object_heap(true);
+    assert_eq!(
+        vm.run(),
+        Err(VmError::InvalidElement {
+            index: 2,
+            len: 2,
+            pc: 72
+        })
+    );
+}
+
+#[test]
+fn test_memory_roots() {
+    // arrays only referenced from the data section, bss and heap once the registers are cleared
+    let mut vm = load(
+        r#"
+.data
+    kept:   .word 0
+.bss
+    slot:   .space 4
+.code
+            ldbi $1, 1
+            newarr $2, $1, 0
+            strwi $2, @kept
+            newarr $2, $1, 0
+            strwi $2, @slot
+            aloci $3, 4
+            newarr $2, $1, 0
+            strwr $2, $3
+            newarr $2, $1, 0
+            ldbi $2, 0
+            ldbi $3, 0
+            hlt
+"#,
+    );
+    vm.enable_object_heap(true);
+    assert_eq!(vm.run(), Ok(()));
+
+    assert_eq!(vm.collect_garbage(), 1);
+    assert_eq!(vm.objects().unwrap().len(), 3);
+}
+
+#[test]
+fn test_stale_handle() {
+    // the handle is hidden by adding 1 to it, so its array is freed once the heap is collected and
+    // its slot reused by the arrays allocated after it
+    let mut vm = load(
+        r#"
+.code
+            ldbi $1, 1
+            newarr $2, $1, 0
+            mov $3, $2
+            addi $3, 1
+            ldbi $2, 0
+            ldbi $0, 0
+            ldbi $1, 100
+    loop:   newarr $2, $1, 0
+            inc $0
+            lti $0, 1000
+            jmpei @loop
+
+            subi $3, 1
+            ldbi $0, 0
+            getel $4, $3, $0
+"#,
+    );
+    vm.enable_object_heap(true);
+    let result = vm.run();
+
+    let handle = vm.registers()[3];
+    assert_eq!(result, Err(VmError::InvalidObject { handle, pc: 116 }));
+    assert!(vm.objects().unwrap().collections() > 0);
+}
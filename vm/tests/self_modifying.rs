@@ -0,0 +1,70 @@
+//! Programs allowed to write into their own code section, patching instructions they haven't run
+//! yet. Every store has to be seen by the next fetch, whether instructions are fetched one at a
+//! time or run from pre-decoded blocks.
+
+use assembler::Assembler;
+use vm::{Program, VM};
+
+/// Counts $2 up by one for ten iterations of a loop, then replaces the `inc` with the instruction
+/// in `replacement` for the other ten, once the loop is hot enough to be cached as a block
+const PROGRAM: &str = r#"
+.data
+    replacement: .word PATCH
+.code
+            ldbi $0, 20
+    loop:   eqi $0, 10
+            jmpnei @body
+            ldwd $1, @replacement
+            strwi $1, @target
+    body:   dec $0
+    target: inc $2
+            gti $0, 0
+            jmpei @loop
+            hlt
+"#;
+
+/// Encoding of a single instruction
+fn encode(instruction: &str) -> i32 {
+    let program = Program::parse(Assembler::default().assemble(instruction).unwrap()).unwrap();
+    let code = &program.image()[program.code()];
+
+    i32::from_be_bytes(code.try_into().unwrap())
+}
+
+/// VM with the program loaded and its code section writable, patching in `instruction`
+fn load(instruction: &str) -> VM {
+    let source = PROGRAM.replace("PATCH", &encode(instruction).to_string());
+    let program = Assembler::default().assemble(&source).unwrap();
+
+    let mut vm = VM::default();
+    vm.set_output(std::io::sink());
+    vm.load(Program::parse(program).unwrap());
+    vm.memory_mut().set_code_writable(true);
+
+    vm
+}
+
+#[test]
+fn test_patch_ahead_of_pc() {
+    let mut vm = load("addi $2, 100");
+    vm.run().unwrap();
+
+    assert_eq!(vm.registers()[2], 10 + 10 * 100);
+}
+
+#[test]
+fn test_patch_cached_block() {
+    let mut vm = load("addi $2, 100");
+    vm.run_cached().unwrap();
+
+    assert_eq!(vm.registers()[2], 10 + 10 * 100);
+}
+
+#[test]
+fn test_patch_while_stepping() {
+    let mut vm = load("nop");
+    vm.start().unwrap();
+    while vm.run_once().unwrap() {}
+
+    assert_eq!(vm.registers()[2], 10);
+}
@@ -0,0 +1,133 @@
+//! Saves a program partway through and carries it on in another VM, as a host suspending programs
+//! between sessions would. Everything the program can observe, from its open files to the devices
+//! it talks to, should carry on where it left off.
+
+use assembler::Assembler;
+use shared::abi::DEVICE_BASE;
+use std::cell::{Cell, RefCell};
+use std::path::Path;
+use std::rc::Rc;
+use vm::{Device, Program, SnapshotError, Uart, XorShift, UART_DATA, VM};
+
+/// Reads half of `in.txt`, a line of input and builds an array, then at `half` reads everything
+/// again: a random number, the time, another line of input, the rest of the file, a byte from the
+/// UART and the array's element
+fn source(directory: &Path) -> String {
+    format!(
+        r#"
+.data
+    path:   .asciiz '{}/in.txt'
+.bss
+    buffer: .space 4
+.code
+            ldwd $1, =@path
+            ldbi $2, 0
+            fopen $10, $1, $2
+            ldwd $1, =@buffer
+            ldbi $0, 2
+            fread $0, $10, $1
+            readi $11
+            ldbi $1, 3
+            newarr $12, $1, 0
+            ldbi $0, 1
+            ldbi $1, 42
+            setel $12, $0, $1
+    half:   rand $3
+            time $4
+            readi $5
+            ldwd $1, =@buffer
+            ldbi $0, 2
+            fread $0, $10, $1
+            ldwd $6, @buffer
+            ldwd $9, =0x100000      ; UART data register
+            ldbr $7, $9
+            ldbi $0, 1
+            getel $8, $12, $0
+            exit $5
+"#,
+        directory.display()
+    )
+}
+
+struct Host {
+    vm: VM,
+    uart: Rc<RefCell<Uart>>,
+    clock: Rc<Cell<u64>>,
+}
+
+/// VM loaded with the program and given the same input, directory and devices each time, but its
+/// own clock
+fn host(directory: &Path, half: &mut usize) -> Host {
+    let mut assembler = Assembler::default();
+    let program = assembler.assemble(&source(directory)).unwrap();
+    *half = assembler.label_address("half").unwrap() as usize;
+
+    let uart = Rc::new(RefCell::new(Uart::default()));
+    let clock = Rc::new(Cell::new(0));
+    let mut vm = VM::default();
+    vm.set_output(std::io::sink());
+    vm.set_input(&b"5\n6\n"[..]);
+    vm.allow_directory(directory).unwrap();
+    vm.memory_mut()
+        .map_device(DEVICE_BASE, uart.clone())
+        .unwrap();
+    let time = clock.clone();
+    vm.set_clock(move || time.get());
+    vm.load(Program::parse(program).unwrap());
+
+    Host { vm, uart, clock }
+}
+
+#[test]
+fn test_continue_in_another_vm() {
+    let directory = std::env::temp_dir().join(format!("rvm-snapshot-{}", std::process::id()));
+    std::fs::create_dir_all(&directory).unwrap();
+    std::fs::write(directory.join("in.txt"), "abcd").unwrap();
+
+    let mut half = 0;
+    let mut original = host(&directory, &mut half);
+    original.vm.set_random(XorShift::new(7));
+    original.vm.enable_object_heap(true);
+    original.uart.borrow_mut().receive(b"xy");
+    original.vm.add_breakpoint(half);
+    original.vm.start().unwrap();
+    assert_eq!(original.vm.resume(), Ok(true));
+
+    original.clock.set(100);
+    let snapshot = original.vm.snapshot();
+
+    // a different clock reading, random seed and input position, without any UART input or heap
+    let mut restored = host(&directory, &mut half);
+    restored.clock.set(5000);
+    restored.vm.restore(&snapshot).unwrap();
+    assert_eq!(restored.vm.pc(), half);
+    assert_eq!(restored.vm.resume(), Ok(false));
+    assert_eq!(original.vm.resume(), Ok(false));
+
+    let registers = restored.vm.registers();
+    assert_eq!(registers[4], 100);
+    assert_eq!(registers[5], 6);
+    assert_eq!(registers[6].to_be_bytes(), *b"cd\0\0");
+    assert_eq!(registers[7], b'x' as i32);
+    assert_eq!(registers[8], 42);
+    assert_eq!(registers[..], original.vm.registers()[..]);
+    assert_eq!(restored.vm.exit_status(), 6);
+    assert_eq!(restored.uart.borrow_mut().load(UART_DATA), b'y');
+
+    // the snapshot can't be restored without the same devices, or access to the open file
+    let mut vm = VM::default();
+    vm.allow_directory(&directory).unwrap();
+    assert_eq!(vm.restore(&snapshot), Err(SnapshotError::DeviceMismatch));
+
+    let mut vm = VM::default();
+    vm.memory_mut()
+        .map_device(DEVICE_BASE, Rc::new(RefCell::new(Uart::default())))
+        .unwrap();
+    assert!(matches!(
+        vm.restore(&snapshot),
+        Err(SnapshotError::FileUnavailable { .. })
+    ));
+    assert_eq!(vm.pc(), 0);
+
+    std::fs::remove_dir_all(directory).unwrap();
+}
@@ -0,0 +1,45 @@
+//! Overflows the stack with unbounded recursion, which faults at the guard below the stack rather
+//! than running into the heap.
+
+use assembler::Assembler;
+use shared::abi::STACK_POINTER;
+use vm::{Program, VmError, VM};
+
+const PROGRAM: &str = r#"
+.code
+            aloci $s0, 16
+            ldbi $t0, 42
+            strbr $t0, $s0
+            calli @recurse
+            hlt
+
+    recurse: push $t0
+            calli @recurse
+            ret
+"#;
+
+#[test]
+fn test_stack_overflow() {
+    let program = Assembler::default().assemble(PROGRAM).unwrap();
+
+    let mut vm = VM::default();
+    vm.enable_shadow_stack(true);
+    vm.load(Program::parse(program).unwrap());
+
+    let stack_start = vm.memory().stack_start();
+    assert_eq!(
+        vm.run(),
+        Err(VmError::StackOverflow {
+            address: stack_start - 4
+        })
+    );
+
+    // the push that overflowed didn't move the stack pointer or write anything
+    assert_eq!(vm.registers()[STACK_POINTER as usize], stack_start as i32);
+    assert_eq!(vm.memory().read_u8(vm.registers()[16] as usize), Ok(42));
+
+    // each call records where its frame starts
+    let frames = vm.backtrace().unwrap();
+    assert_eq!(frames[0].stack_pointer, stack_start + frames.len() * 8 - 4);
+    assert_eq!(frames.last().unwrap().stack_pointer, stack_start + 4);
+}
@@ -0,0 +1,76 @@
+//! Interrupt driven echo over the UART, showing how a program drives a memory-mapped device.
+
+use assembler::Assembler;
+use shared::abi::DEVICE_BASE;
+use std::cell::RefCell;
+use std::rc::Rc;
+use vm::{Program, Uart, VMConfig, VM};
+
+/// Enables the receive interrupt then waits, while the handler transmits every byte it receives
+/// straight back. Halts once a newline has been echoed
+const ECHO: &str = r#"
+.data
+    done: .byte 0
+.code
+            iveci @echo
+            ldwd $10, =0x100000     ; UART data register
+            mov $11, $10
+            addi $11, 2             ; UART control register
+            ldbi $0, 1
+            strbr $0, $11
+    wait:   ldbd $0, @done
+            eqi $0, 0
+            jmpei @wait
+            hlt
+    echo:   ldbr $1, $10
+            strbr $1, $10
+            eqi $1, 10
+            jmpnei @return
+            ldbi $2, 1
+            strbi $2, @done
+    return: iret
+"#;
+
+fn load(uart: &Rc<RefCell<Uart>>) -> VM {
+    let program = Assembler::default().assemble(ECHO).unwrap();
+
+    let mut vm = VM::with_config(VMConfig {
+        max_steps: Some(1000),
+        ..Default::default()
+    });
+    vm.load(Program::parse(program).unwrap());
+    vm.memory_mut()
+        .map_device(DEVICE_BASE, uart.clone())
+        .unwrap();
+
+    vm
+}
+
+#[test]
+fn test_echo() {
+    let uart = Rc::new(RefCell::new(Uart::default()));
+    let mut vm = load(&uart);
+
+    uart.borrow_mut().receive(b"hello\n");
+    vm.run().unwrap();
+
+    assert_eq!(uart.borrow_mut().take_transmitted(), b"hello\n");
+}
+
+#[test]
+fn test_echo_as_input_arrives() {
+    let uart = Rc::new(RefCell::new(Uart::default()));
+    let mut vm = load(&uart);
+    vm.start().unwrap();
+
+    let mut running = true;
+    for chunk in [&b"ab"[..], b"", b"c\n"] {
+        uart.borrow_mut().receive(chunk);
+        for _ in 0..50 {
+            running = running && vm.run_once().unwrap();
+        }
+
+        assert_eq!(uart.borrow_mut().take_transmitted(), chunk);
+    }
+    assert!(!running);
+}
@@ -0,0 +1,70 @@
+//! Runs a program assembled for 8 byte words in a VM with 64 bit registers, where arithmetic, word
+//! loads and stores, the stack and calls all work on whole 8 byte words.
+
+use assembler::Assembler;
+use vm::{Program, SnapshotError, VMConfig, VmError, VM};
+
+const PROGRAM: &str = r#"
+.data
+    minus:  .word -2
+.bss
+    slot:   .space 8
+.code
+            ldhi $2, 4096
+            mulr $3, $2, $2
+            mulr $3, $3, $2     ; 2^36, too wide for 4 byte words
+            strwi $3, @slot
+            ldwd $4, @slot
+            push $3
+            calli @double
+            pop $5
+            ldwd $6, @minus
+            hlt
+    double: addr $3, $3, $3
+            ret
+"#;
+
+fn program() -> Program {
+    let program = Assembler::default().word_size(8).assemble(PROGRAM).unwrap();
+
+    Program::parse(program).unwrap()
+}
+
+#[test]
+fn test_wide_words() {
+    let mut vm = VM::<i64>::new(VMConfig::default());
+    vm.set_output(std::io::sink());
+    vm.load(program());
+    vm.run().unwrap();
+
+    assert_eq!(vm.registers()[3], 1 << 37);
+    assert_eq!(vm.registers()[4], 1 << 36);
+    assert_eq!(vm.registers()[5], 1 << 36);
+    assert_eq!(vm.registers()[6], -2);
+    assert_eq!(vm.memory().word_size(), Some(8));
+}
+
+#[test]
+fn test_word_size_mismatch() {
+    let mut vm = VM::default();
+    vm.load(program());
+    assert_eq!(
+        vm.run(),
+        Err(VmError::WordSizeMismatch {
+            size: 8,
+            expected: 4
+        })
+    );
+
+    // snapshots can't move between word sizes either
+    let mut wide = VM::<i64>::new(VMConfig::default());
+    wide.load(program());
+    wide.start().unwrap();
+    assert_eq!(
+        vm.restore(&wide.snapshot()),
+        Err(SnapshotError::WordSizeMismatch {
+            size: 8,
+            expected: 4
+        })
+    );
+}